@@ -15,7 +15,10 @@ pub type AppResult<T> = Result<T, Error>;
 async fn main() -> std::io::Result<()> {
     let level = std::env::args().nth(1);
     let level = level.as_deref().unwrap_or("debug");
-    hextacy::logger::init(level);
+    hextacy::logger::Logger::new()
+        .with_filter(level)
+        .init()
+        .expect("could not install logger");
 
     env::load_from_file("examples/template/.env").unwrap();
 
@@ -1,5 +1,4 @@
-use crate::driver::{Driver, DriverError};
-use async_trait::async_trait;
+use crate::driver::Driver;
 use std::{
     any::Any,
     collections::{hash_map::DefaultHasher, HashMap},
@@ -88,6 +87,30 @@ impl InMemConnection {
         Some(*actual)
     }
 
+    /// Atomically replaces the value under the key with the one returned from `f`. `f` receives the current value,
+    /// if any, and returns the new value alongside an arbitrary result.
+    pub fn update<K, V, R>(&mut self, key: K, f: impl FnOnce(Option<V>) -> (V, R)) -> R
+    where
+        K: Hash,
+        V: Clone + Any + Send + Sync + 'static,
+    {
+        let mut map = self.cache.lock().unwrap();
+
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let hashed = hasher.finish();
+
+        let current = map.remove(&hashed).map(|r| {
+            *r.downcast::<V>()
+                .expect("Invalid type provided for `value`")
+        });
+
+        let (value, result) = f(current);
+        map.insert(hashed, Box::new(value));
+
+        result
+    }
+
     pub fn remove<K, V>(&mut self, key: K) -> Option<V>
     where
         K: Hash,
@@ -106,11 +129,11 @@ impl InMemConnection {
     }
 }
 
-#[async_trait]
 impl Driver for InMemCache {
     type Connection = InMemConnection;
+    type Error = std::convert::Infallible;
 
-    async fn connect(&self) -> Result<Self::Connection, DriverError> {
+    async fn connect(&self) -> Result<Self::Connection, Self::Error> {
         Ok(InMemConnection::new(self))
     }
 }
@@ -1,4 +1,6 @@
 use crate::driver::Driver;
+use crate::health::{HealthCheck, HealthError};
+use crate::shutdown::{Shutdown, ShutdownError};
 use deadpool_redis::redis::{AsyncCommands, FromRedisValue, ToRedisArgs};
 use deadpool_redis::{Connection, Pool};
 use serde::{de::DeserializeOwned, Serialize};
@@ -110,3 +112,21 @@ pub trait RedisExt {
         }
     }
 }
+
+impl HealthCheck for Pool {
+    async fn check(&self) -> Result<(), HealthError> {
+        let mut conn = self.get().await.map_err(HealthError::new)?;
+        deadpool_redis::redis::cmd("PING")
+            .query_async::<_, String>(&mut conn)
+            .await
+            .map(|_| ())
+            .map_err(HealthError::new)
+    }
+}
+
+impl Shutdown for Pool {
+    async fn shutdown(&self) -> Result<(), ShutdownError> {
+        self.close();
+        Ok(())
+    }
+}
@@ -1,4 +1,6 @@
 use crate::driver::{Atomic, Driver};
+use crate::health::{HealthCheck, HealthError};
+use crate::shutdown::{Shutdown, ShutdownError};
 use mongodb::{Client, ClientSession};
 
 impl Driver for Client {
@@ -29,3 +31,21 @@ impl Atomic for ClientSession {
         Ok(())
     }
 }
+
+impl HealthCheck for Client {
+    async fn check(&self) -> Result<(), HealthError> {
+        self.database("admin")
+            .run_command(mongodb::bson::doc! { "ping": 1 }, None)
+            .await
+            .map(|_| ())
+            .map_err(HealthError::new)
+    }
+}
+
+impl Shutdown for Client {
+    async fn shutdown(&self) -> Result<(), ShutdownError> {
+        // Clones share the topology, shutting one down shuts down all of them
+        Client::shutdown(self.clone()).await;
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 use crate::driver::{Atomic, Driver};
+use crate::health::{HealthCheck, HealthError};
 use cfg_if::cfg_if;
 use diesel::{
     connection::TransactionManager,
@@ -48,3 +49,21 @@ impl Atomic for DieselConnection {
         Ok(())
     }
 }
+
+impl HealthCheck for DieselPool {
+    async fn check(&self) -> Result<(), HealthError> {
+        use diesel::RunQueryDsl;
+
+        // r2d2 blocks, so the check runs on the blocking pool
+        let pool = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = pool.get().map_err(HealthError::new)?;
+            diesel::sql_query("SELECT 1")
+                .execute(&mut conn)
+                .map(|_| ())
+                .map_err(HealthError::new)
+        })
+        .await
+        .map_err(HealthError::new)?
+    }
+}
@@ -1,4 +1,6 @@
 use crate::driver::{Atomic, Driver};
+use crate::health::{HealthCheck, HealthError};
+use crate::shutdown::{Shutdown, ShutdownError};
 use sea_orm::DatabaseTransaction;
 use sea_orm::TransactionTrait;
 
@@ -39,3 +41,16 @@ impl Atomic for DatabaseConnection {
         DatabaseTransaction::rollback(tx).await
     }
 }
+
+impl HealthCheck for DatabaseConnection {
+    async fn check(&self) -> Result<(), HealthError> {
+        self.ping().await.map_err(HealthError::new)
+    }
+}
+
+impl Shutdown for DatabaseConnection {
+    async fn shutdown(&self) -> Result<(), ShutdownError> {
+        // Clones share the pool, closing one closes all of them
+        self.clone().close().await.map_err(ShutdownError::new)
+    }
+}
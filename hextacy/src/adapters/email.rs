@@ -1,3 +1,4 @@
+use crate::health::{HealthCheck, HealthError};
 use crate::Constructor;
 use lettre::transport;
 use lettre::transport::smtp::authentication::Credentials;
@@ -324,6 +325,23 @@ struct TemplatePlaceholder {
     end_i: usize,
 }
 
+impl HealthCheck for SimpleTemplateMailer {
+    async fn check(&self) -> Result<(), HealthError> {
+        // The SMTP transport blocks, so the check runs on the blocking pool
+        let smtp = self.smtp.clone();
+        let connected = tokio::task::spawn_blocking(move || smtp.test_connection())
+            .await
+            .map_err(HealthError::new)?
+            .map_err(HealthError::new)?;
+
+        if connected {
+            Ok(())
+        } else {
+            Err(HealthError("SMTP server did not respond".to_string()))
+        }
+    }
+}
+
 #[derive(Debug, Constructor)]
 pub struct SenderInfo {
     /// Represents the actual sender
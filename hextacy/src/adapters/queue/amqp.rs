@@ -8,7 +8,9 @@ use lapin::{
 use serde::{de::DeserializeOwned, Serialize};
 use std::{fmt::Debug, sync::Arc};
 
+use crate::health::{HealthCheck, HealthError};
 use crate::queue::{Consumer, Producer, QueueError};
+use crate::shutdown::{Shutdown, ShutdownError};
 
 #[derive(Clone)]
 pub struct AmqpDriver {
@@ -71,6 +73,29 @@ impl AmqpDriver {
     }
 }
 
+impl HealthCheck for AmqpDriver {
+    async fn check(&self) -> Result<(), HealthError> {
+        let status = self.conn.status();
+        if status.connected() {
+            Ok(())
+        } else {
+            Err(HealthError(format!("Connection is {:?}", status.state())))
+        }
+    }
+}
+
+impl Shutdown for AmqpDriver {
+    async fn shutdown(&self) -> Result<(), ShutdownError> {
+        if !self.conn.status().connected() {
+            return Ok(());
+        }
+        self.conn
+            .close(200, "Shutdown")
+            .await
+            .map_err(ShutdownError::new)
+    }
+}
+
 #[derive(Debug)]
 pub struct AmqpPublisher {
     queue: String,
@@ -102,6 +127,7 @@ where
     M: DeserializeOwned + Send + Sync + 'static,
 {
     async fn poll_queue(&mut self) -> Result<Option<M>, QueueError> {
+        // Cancel safe, nothing is awaited after a delivery is taken from the stream
         let Some(msg) = self.next().await else {
             return Ok(None);
         };
@@ -6,6 +6,7 @@ use futures::{Stream, StreamExt};
 use serde::{de::DeserializeOwned, Serialize};
 use std::{fmt::Debug, pin::Pin, sync::Arc};
 
+use crate::health::{HealthCheck, HealthError};
 use crate::queue::{Consumer, Producer, QueueError};
 
 /// A wrapper around a [redis client][deadpool_redis::redis::Client] with simple functionality
@@ -73,6 +74,21 @@ impl RedisMessageQueue {
     }
 }
 
+impl HealthCheck for RedisMessageQueue {
+    async fn check(&self) -> Result<(), HealthError> {
+        let mut conn = self
+            .client
+            .get_async_connection()
+            .await
+            .map_err(HealthError::new)?;
+        deadpool_redis::redis::cmd("PING")
+            .query_async::<_, String>(&mut conn)
+            .await
+            .map(|_| ())
+            .map_err(HealthError::new)
+    }
+}
+
 #[derive(Clone)]
 pub struct RedisPublisher {
     channel: String,
@@ -112,6 +128,7 @@ where
     M: DeserializeOwned + Send + 'static,
 {
     async fn poll_queue(&mut self) -> Result<Option<M>, QueueError> {
+        // Cancel safe, nothing is awaited after a message is taken from the stream
         let Some(message) = self.stream.next().await else {
             return Ok(None);
         };
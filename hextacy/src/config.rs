@@ -0,0 +1,516 @@
+//! Layered configuration loaded from TOML, YAML or JSON files.
+//!
+//! A [ConfigLoader] merges the following layers, each one overriding the keys of the previous:
+//!
+//! 1. The base file, `base.{toml,yaml,yml,json}` in the configuration directory
+//! 2. The profile file, e.g. `production.toml`, if a profile is set
+//! 3. Environment variables with the prefix, e.g. `APP__DATABASE__URL` overrides `database.url`
+//!
+//! Values are accessed with dotted paths and deserialized to the requested type:
+//!
+//! ```ignore
+//! let config = ConfigLoader::new("config")
+//!     .with_profile("production")
+//!     .with_env_prefix("APP")
+//!     .load()?;
+//!
+//! let url: String = config.get("database.url")?;
+//! let pool_size: Option<u32> = config.get_opt("database.pool_size")?;
+//! ```
+//!
+//! The `#[config]` loader of `State` reads from the [global_config], which `#[reload]` fields load again with
+//! [reload_global_config].
+
+use once_cell::sync::OnceCell;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+use thiserror::Error;
+
+/// Extensions of config files, in the order they are looked up.
+const EXTENSIONS: [&str; 4] = ["toml", "yaml", "yml", "json"];
+
+/// Separates the path segments of keys in override environment variables.
+const ENV_SEPARATOR: &str = "__";
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Could not read {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("Could not parse {0}: {1}")]
+    Parse(String, String),
+    #[error("Unsupported config format: {0}")]
+    UnsupportedFormat(String),
+    #[error("Key {0} not found in config")]
+    Missing(String),
+    #[error("Could not deserialize {0}: {1}")]
+    Deserialize(String, String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl Format {
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        match ext {
+            "toml" => Some(Self::Toml),
+            "yaml" | "yml" => Some(Self::Yaml),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+}
+
+/// A tree of configuration values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    values: Value,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            values: Value::Object(Map::new()),
+        }
+    }
+}
+
+impl Config {
+    /// Parses a config from a string in the given format.
+    pub fn parse(source: &str, format: Format) -> Result<Self, ConfigError> {
+        let values: Value = match format {
+            Format::Toml => toml::from_str(source).map_err(|e| e.to_string()),
+            Format::Yaml => serde_yaml::from_str(source).map_err(|e| e.to_string()),
+            Format::Json => serde_json::from_str(source).map_err(|e| e.to_string()),
+        }
+        .map_err(|e| ConfigError::Parse(format!("{format:?}"), e))?;
+
+        // Empty YAML files parse to null
+        if values.is_null() {
+            return Ok(Self::default());
+        }
+
+        Ok(Self { values })
+    }
+
+    /// Reads and parses a file, the format is determined by its extension.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let ext = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+        let format = Format::from_extension(ext)
+            .ok_or_else(|| ConfigError::UnsupportedFormat(path.display().to_string()))?;
+        let source =
+            std::fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+        Self::parse(&source, format).map_err(|e| match e {
+            ConfigError::Parse(_, e) => ConfigError::Parse(path.display().to_string(), e),
+            e => e,
+        })
+    }
+
+    /// Deep merges `other` into this config, values from `other` take precedence.
+    pub fn merge(&mut self, other: Config) {
+        merge(&mut self.values, other.values)
+    }
+
+    /// Sets the value at the dotted path, creating intermediate tables as necessary.
+    pub fn set(&mut self, key: &str, value: impl Into<Value>) {
+        let mut current = &mut self.values;
+        for segment in key.split('.') {
+            if !current.is_object() {
+                *current = Value::Object(Map::new());
+            }
+            current = current
+                .as_object_mut()
+                .unwrap()
+                .entry(segment)
+                .or_insert(Value::Null);
+        }
+        *current = value.into();
+    }
+
+    /// Returns the raw value at the dotted path. Array elements can be accessed with their index.
+    pub fn value(&self, key: &str) -> Option<&Value> {
+        key.split('.')
+            .try_fold(&self.values, |value, segment| match value {
+                Value::Object(map) => map.get(segment),
+                Value::Array(arr) => arr.get(segment.parse::<usize>().ok()?),
+                _ => None,
+            })
+    }
+
+    /// Deserializes the value at the dotted path.
+    ///
+    /// Strings are also parsed as JSON when the value cannot be deserialized directly, so overrides from the
+    /// env such as `"8080"` or `"true"` can be read as numbers or booleans.
+    pub fn get<T>(&self, key: &str) -> Result<T, ConfigError>
+    where
+        T: DeserializeOwned,
+    {
+        self.get_opt(key)?
+            .ok_or_else(|| ConfigError::Missing(key.to_string()))
+    }
+
+    /// Returns the scalar value at the dotted path as a string, the same as it would be read from the env, e.g.
+    /// `8080` for a number and `true` for a boolean. Returns `None` for missing and null values.
+    pub fn get_string(&self, key: &str) -> Result<Option<String>, ConfigError> {
+        match self.value(key) {
+            Some(Value::Null) | None => Ok(None),
+            Some(Value::String(s)) => Ok(Some(s.clone())),
+            Some(value @ (Value::Number(_) | Value::Bool(_))) => Ok(Some(value.to_string())),
+            Some(_) => Err(ConfigError::Deserialize(
+                key.to_string(),
+                "expected a string, number or boolean".to_string(),
+            )),
+        }
+    }
+
+    /// Same as [get][Self::get], but returns `None` for missing and null values.
+    pub fn get_opt<T>(&self, key: &str) -> Result<Option<T>, ConfigError>
+    where
+        T: DeserializeOwned,
+    {
+        let value = match self.value(key) {
+            Some(Value::Null) | None => return Ok(None),
+            Some(value) => value,
+        };
+
+        match serde_json::from_value(value.clone()) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) => match value {
+                Value::String(s) => serde_json::from_str(s)
+                    .map(Some)
+                    .map_err(|_| ConfigError::Deserialize(key.to_string(), e.to_string())),
+                _ => Err(ConfigError::Deserialize(key.to_string(), e.to_string())),
+            },
+        }
+    }
+}
+
+fn merge(base: &mut Value, other: Value) {
+    match (base, other) {
+        (Value::Object(base), Value::Object(other)) => {
+            for (key, value) in other {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, other) => *base = other,
+    }
+}
+
+/// Loads a [Config] from the base file, the profile file and environment overrides.
+#[derive(Debug, Clone)]
+pub struct ConfigLoader {
+    dir: PathBuf,
+    base: String,
+    profile: Option<String>,
+    env_prefix: Option<String>,
+}
+
+impl ConfigLoader {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            base: "base".to_string(),
+            profile: None,
+            env_prefix: None,
+        }
+    }
+
+    /// Configures the loader from the env:
+    ///
+    /// - `CONFIG_DIR` sets the directory, `config` by default
+    /// - `APP_PROFILE` sets the profile
+    /// - Overrides are read from variables prefixed with `APP__`
+    pub fn from_env() -> Self {
+        let dir = crate::env::get_or_default("CONFIG_DIR", "config");
+        let loader = Self::new(dir).with_env_prefix("APP");
+        match crate::env::get("APP_PROFILE") {
+            Ok(profile) => loader.with_profile(profile),
+            Err(_) => loader,
+        }
+    }
+
+    /// The name of the base file without the extension, `base` by default.
+    pub fn with_base(mut self, base: impl Into<String>) -> Self {
+        self.base = base.into();
+        self
+    }
+
+    /// The name of the file overlaid on the base, e.g. `production`.
+    pub fn with_profile(mut self, profile: impl Into<String>) -> Self {
+        self.profile = Some(profile.into());
+        self
+    }
+
+    /// Enables overriding keys with env variables, e.g. with the prefix `APP`, `APP__DATABASE__URL`
+    /// overrides `database.url`.
+    pub fn with_env_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.env_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Loads and merges all the layers. Missing files are skipped.
+    pub fn load(&self) -> Result<Config, ConfigError> {
+        let mut config = Config::default();
+
+        for name in std::iter::once(&self.base).chain(self.profile.as_ref()) {
+            if let Some(path) = self.find(name) {
+                config.merge(Config::from_file(path)?);
+            }
+        }
+
+        if let Some(ref prefix) = self.env_prefix {
+            let prefix = format!("{prefix}{ENV_SEPARATOR}");
+            for (key, value) in std::env::vars() {
+                let Some(key) = key.strip_prefix(&prefix) else {
+                    continue;
+                };
+                let key = key.to_lowercase().replace(ENV_SEPARATOR, ".");
+                config.set(&key, value);
+            }
+        }
+
+        Ok(config)
+    }
+
+    fn find(&self, name: &str) -> Option<PathBuf> {
+        EXTENSIONS
+            .iter()
+            .map(|ext| self.dir.join(format!("{name}.{ext}")))
+            .find(|path| path.is_file())
+    }
+}
+
+/// The config read by `State`, along with the loader it came from so it can be reloaded.
+struct GlobalConfig {
+    loader: Option<ConfigLoader>,
+    config: RwLock<Arc<Config>>,
+}
+
+static GLOBAL_CONFIG: OnceCell<GlobalConfig> = OnceCell::new();
+
+/// Sets the config used by the `#[config]` loader of `State`. Can only be called once, if the config
+/// was already set the given one is returned. Configs set this way are not affected by [reload_global_config].
+pub fn set_global_config(config: Config) -> Result<(), Config> {
+    GLOBAL_CONFIG
+        .set(GlobalConfig {
+            loader: None,
+            config: RwLock::new(Arc::new(config)),
+        })
+        .map_err(|global| {
+            let config = global
+                .config
+                .into_inner()
+                .unwrap_or_else(|e| e.into_inner());
+            Arc::try_unwrap(config).unwrap_or_else(|config| (*config).clone())
+        })
+}
+
+/// Returns the config used by the `#[config]` loader of `State`. If it was not set with [set_global_config],
+/// it is loaded with [ConfigLoader::from_env] on first use.
+pub fn global_config() -> Result<Arc<Config>, ConfigError> {
+    let global = GLOBAL_CONFIG.get_or_try_init(|| {
+        let loader = ConfigLoader::from_env();
+        Ok::<_, ConfigError>(GlobalConfig {
+            config: RwLock::new(Arc::new(loader.load()?)),
+            loader: Some(loader),
+        })
+    })?;
+    Ok(global
+        .config
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone())
+}
+
+/// Loads the global config again so `#[reload]` fields of `State` see changes to the files. If loading
+/// fails the current config is kept.
+pub fn reload_global_config() -> Result<(), ConfigError> {
+    let Some(global) = GLOBAL_CONFIG.get() else {
+        return global_config().map(|_| ());
+    };
+    let Some(ref loader) = global.loader else {
+        return Ok(());
+    };
+    let config = loader.load()?;
+    *global.config.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(config);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hextacy-config-{name}"));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for (file, contents) in files {
+            std::fs::write(dir.join(file), contents).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn layers() {
+        let dir = dir(
+            "layers",
+            &[
+                (
+                    "base.toml",
+                    "[database]\nurl = \"postgres://localhost\"\npool_size = 8\n\n[server]\nport = 8080\n",
+                ),
+                ("production.yaml", "database:\n  url: postgres://prod\n"),
+            ],
+        );
+
+        std::env::set_var("HXTC_LAYERS__SERVER__PORT", "9000");
+        std::env::set_var("HXTC_LAYERS__SERVER__HOSTS", "[\"a\", \"b\"]");
+
+        let config = ConfigLoader::new(&dir)
+            .with_profile("production")
+            .with_env_prefix("HXTC_LAYERS")
+            .load()
+            .unwrap();
+
+        assert_eq!(
+            config.get::<String>("database.url").unwrap(),
+            "postgres://prod"
+        );
+        assert_eq!(config.get::<u32>("database.pool_size").unwrap(), 8);
+        assert_eq!(config.get::<u16>("server.port").unwrap(), 9000);
+        assert_eq!(
+            config.get::<Vec<String>>("server.hosts").unwrap(),
+            ["a", "b"]
+        );
+        assert_eq!(config.get_opt::<u32>("server.workers").unwrap(), None);
+        assert_eq!(config.get_string("server.port").unwrap().unwrap(), "9000");
+        assert_eq!(
+            config.get_string("database.pool_size").unwrap().unwrap(),
+            "8"
+        );
+        assert_eq!(config.get_string("server.workers").unwrap(), None);
+        assert!(matches!(
+            config.get_string("database"),
+            Err(ConfigError::Deserialize(..))
+        ));
+        assert!(matches!(
+            config.get::<u32>("server.workers"),
+            Err(ConfigError::Missing(_))
+        ));
+        assert!(matches!(
+            config.get::<u32>("database.url"),
+            Err(ConfigError::Deserialize(..))
+        ));
+
+        // Missing profiles only use the base
+        let config = ConfigLoader::new(&dir)
+            .with_profile("staging")
+            .load()
+            .unwrap();
+        assert_eq!(
+            config.get::<String>("database.url").unwrap(),
+            "postgres://localhost"
+        );
+    }
+
+    #[test]
+    fn formats() {
+        let json = Config::parse(r#"{"a": {"b": [1, 2]}}"#, Format::Json).unwrap();
+        let yaml = Config::parse("a:\n  b:\n    - 1\n    - 2\n", Format::Yaml).unwrap();
+        let toml = Config::parse("[a]\nb = [1, 2]\n", Format::Toml).unwrap();
+        assert_eq!(json, yaml);
+        assert_eq!(json, toml);
+        assert_eq!(json.get::<u8>("a.b.1").unwrap(), 2);
+
+        assert!(matches!(
+            Config::parse("a = ", Format::Toml),
+            Err(ConfigError::Parse(..))
+        ));
+        assert!(matches!(
+            Config::from_file("config.ini"),
+            Err(ConfigError::UnsupportedFormat(_))
+        ));
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Database {
+        host: String,
+        port: u16,
+        pool_size: Option<u16>,
+    }
+
+    impl Database {
+        fn new(host: &str, port: u16, pool_size: Option<u16>) -> Self {
+            Self {
+                host: host.to_string(),
+                port,
+                pool_size,
+            }
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Server {
+        port: String,
+        tls: String,
+    }
+
+    impl Server {
+        fn new(port: &str, tls: &str) -> Self {
+            Self {
+                port: port.to_string(),
+                tls: tls.to_string(),
+            }
+        }
+    }
+
+    #[derive(Debug, crate::State)]
+    struct AppState {
+        #[config(
+            "database.host",
+            "database.port" as u16,
+            "database.pool_size" as Option<u16>
+        )]
+        db: Database,
+
+        #[config("replica.host", "replica.port" as u16, "replica.pool_size" as Option<u16>)]
+        #[env("HXTC_REPLICA_HOST", "HXTC_REPLICA_PORT" as u16, "HXTC_REPLICA_POOL" as Option<u16>)]
+        replica: Database,
+
+        // Scalars are passed as strings when not converted
+        #[config("server.port", "server.tls")]
+        server: Server,
+    }
+
+    #[test]
+    fn state() {
+        let config = Config::parse(
+            "[database]\nhost = \"localhost\"\nport = 5432\n\n[server]\nport = 8080\ntls = false\n",
+            Format::Toml,
+        )
+        .unwrap();
+        set_global_config(config).unwrap();
+
+        std::env::set_var("HXTC_REPLICA_HOST", "replica");
+        std::env::set_var("HXTC_REPLICA_PORT", "5433");
+
+        let state = AppState::load().unwrap();
+        assert_eq!(state.db, Database::new("localhost", 5432, None));
+        // The replica is missing from the config and falls back to the env
+        assert_eq!(state.replica, Database::new("replica", 5433, None));
+        assert_eq!(state.server, Server::new("8080", "false"));
+    }
+}
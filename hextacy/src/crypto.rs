@@ -1,8 +1,10 @@
 //! Common crypto functionalities used in web apps. Can be utilised to reduce the amount of imports.
 
+pub mod cipher;
 pub mod hmac;
 pub mod jwt;
 pub mod otp;
+pub mod signed;
 
 use bcrypt;
 pub use bcrypt::BcryptError;
@@ -67,5 +69,25 @@ pub enum CryptoError {
     #[error("{0}")]
     Thotp(#[from] thotp::ThotpError),
     #[error("{0}")]
+    QrCode(#[from] qrcode::types::QrError),
+    #[error("{0}")]
+    Image(#[from] image::ImageError),
+    #[error("{0}")]
     FromUtf8(#[from] std::string::FromUtf8Error),
+    #[error("Encryption or decryption failed")]
+    Aead,
+    #[error("Invalid key length, expected 32 bytes, got {0}")]
+    KeyLength(usize),
+    #[error("Invalid envelope: {0}")]
+    Envelope(&'static str),
+    #[error("Unknown key: {0}")]
+    UnknownKey(String),
+    #[error("The TOTP period must be greater than 0")]
+    TotpPeriod,
+}
+
+impl From<aes_gcm::aead::Error> for CryptoError {
+    fn from(_: aes_gcm::aead::Error) -> Self {
+        Self::Aead
+    }
 }
@@ -0,0 +1,478 @@
+//! Authenticated symmetric encryption intended for encrypting values at rest, e.g. OTP secrets
+//! and API credentials stored in a database.
+//!
+//! Values are encrypted with a [Keyring] and stored as versioned envelopes in the form
+//!
+//! `v1$<key_id>$<algorithm>$<base64url(nonce || ciphertext)>`
+//!
+//! Since the envelope carries the id of the key used to seal it, keys can be rotated by adding
+//! a new primary key to the keyring while keeping the old ones around for decryption.
+//!
+//! Nonces are always randomly generated using the OS RNG. AES-256-GCM uses 96 bit nonces which
+//! are safe for around 2^32 encryptions per key, if you expect more than that use
+//! XChaCha20-Poly1305 whose 192 bit nonces can safely be generated at random.
+
+use super::CryptoError;
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm,
+};
+use chacha20poly1305::XChaCha20Poly1305;
+use data_encoding::BASE64URL_NOPAD;
+use once_cell::sync::OnceCell;
+use rand::RngCore;
+use std::{collections::HashMap, fmt::Debug};
+
+/// The current envelope version.
+const ENVELOPE_VERSION: &str = "v1";
+
+/// Separates the envelope segments. Key IDs must not contain it.
+const ENVELOPE_SEPARATOR: char = '$';
+
+/// Size of the keys in bytes for all supported algorithms.
+pub const KEY_LEN: usize = 32;
+
+/// Supported AEAD algorithms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Aes256Gcm,
+    XChaCha20Poly1305,
+}
+
+impl Algorithm {
+    /// The identifier of the algorithm used in envelopes.
+    pub fn id(&self) -> &'static str {
+        match self {
+            Algorithm::Aes256Gcm => "A256GCM",
+            Algorithm::XChaCha20Poly1305 => "XC20P",
+        }
+    }
+
+    /// The length of the nonce in bytes.
+    pub fn nonce_len(&self) -> usize {
+        match self {
+            Algorithm::Aes256Gcm => 12,
+            Algorithm::XChaCha20Poly1305 => 24,
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        match id {
+            "A256GCM" => Some(Algorithm::Aes256Gcm),
+            "XC20P" => Some(Algorithm::XChaCha20Poly1305),
+            _ => None,
+        }
+    }
+}
+
+/// A symmetric key identified by an ID. The ID gets embedded in every envelope sealed with the key.
+///
+/// The secret is never printed in the `Debug` output.
+#[derive(Clone)]
+pub struct Key {
+    id: String,
+    algorithm: Algorithm,
+    secret: [u8; KEY_LEN],
+}
+
+impl Debug for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Key")
+            .field("id", &self.id)
+            .field("algorithm", &self.algorithm)
+            .field("secret", &"{ ... }")
+            .finish()
+    }
+}
+
+impl Key {
+    /// Creates a key from the given secret. The secret must be exactly [KEY_LEN] bytes long
+    /// and the ID must be non empty and must not contain a `$`.
+    pub fn new(id: &str, algorithm: Algorithm, secret: &[u8]) -> Result<Self, CryptoError> {
+        validate_key_id(id)?;
+        let secret: [u8; KEY_LEN] = secret
+            .try_into()
+            .map_err(|_| CryptoError::KeyLength(secret.len()))?;
+        Ok(Self {
+            id: id.to_string(),
+            algorithm,
+            secret,
+        })
+    }
+
+    /// Creates a key from a secret encoded with the given encoding, e.g. when loading keys from the env.
+    pub fn from_encoded(
+        id: &str,
+        algorithm: Algorithm,
+        secret: &str,
+        encoding: data_encoding::Encoding,
+    ) -> Result<Self, CryptoError> {
+        let secret = encoding.decode(secret.as_bytes())?;
+        Self::new(id, algorithm, &secret)
+    }
+
+    /// Generates a random key using the OS RNG.
+    pub fn generate(id: &str, algorithm: Algorithm) -> Result<Self, CryptoError> {
+        let mut secret = [0_u8; KEY_LEN];
+        OsRng.fill_bytes(&mut secret);
+        Self::new(id, algorithm, &secret)
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// Encrypts the plaintext with a random nonce and returns `nonce || ciphertext`.
+    ///
+    /// The associated data is authenticated, but not encrypted. The same data must be
+    /// provided when decrypting.
+    pub fn encrypt(&self, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let payload = Payload {
+            msg: plaintext,
+            aad,
+        };
+
+        let (nonce, ciphertext) = match self.algorithm {
+            Algorithm::Aes256Gcm => {
+                let cipher = Aes256Gcm::new_from_slice(&self.secret)?;
+                let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+                (nonce.to_vec(), cipher.encrypt(&nonce, payload)?)
+            }
+            Algorithm::XChaCha20Poly1305 => {
+                let cipher = XChaCha20Poly1305::new_from_slice(&self.secret)?;
+                let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+                (nonce.to_vec(), cipher.encrypt(&nonce, payload)?)
+            }
+        };
+
+        let mut sealed = nonce;
+        sealed.extend(ciphertext);
+        Ok(sealed)
+    }
+
+    /// Decrypts data obtained from [encrypt][Key::encrypt].
+    pub fn decrypt(&self, sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let nonce_len = self.algorithm.nonce_len();
+        if sealed.len() < nonce_len {
+            return Err(CryptoError::Envelope("ciphertext shorter than nonce"));
+        }
+
+        let (nonce, ciphertext) = sealed.split_at(nonce_len);
+        let payload = Payload {
+            msg: ciphertext,
+            aad,
+        };
+
+        match self.algorithm {
+            Algorithm::Aes256Gcm => Aes256Gcm::new_from_slice(&self.secret)?
+                .decrypt(nonce.into(), payload)
+                .map_err(Into::into),
+            Algorithm::XChaCha20Poly1305 => XChaCha20Poly1305::new_from_slice(&self.secret)?
+                .decrypt(nonce.into(), payload)
+                .map_err(Into::into),
+        }
+    }
+}
+
+/// A parsed encryption envelope.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Envelope {
+    pub key_id: String,
+    pub algorithm: Algorithm,
+    /// `nonce || ciphertext`
+    pub sealed: Vec<u8>,
+}
+
+impl Envelope {
+    pub fn parse(envelope: &str) -> Result<Self, CryptoError> {
+        let mut segments = envelope.split(ENVELOPE_SEPARATOR);
+
+        let (Some(version), Some(key_id), Some(algorithm), Some(sealed), None) = (
+            segments.next(),
+            segments.next(),
+            segments.next(),
+            segments.next(),
+            segments.next(),
+        ) else {
+            return Err(CryptoError::Envelope("malformed envelope"));
+        };
+
+        if version != ENVELOPE_VERSION {
+            return Err(CryptoError::Envelope("unsupported envelope version"));
+        }
+
+        let algorithm =
+            Algorithm::from_id(algorithm).ok_or(CryptoError::Envelope("unknown algorithm"))?;
+
+        Ok(Self {
+            key_id: key_id.to_string(),
+            algorithm,
+            sealed: BASE64URL_NOPAD.decode(sealed.as_bytes())?,
+        })
+    }
+}
+
+impl std::fmt::Display for Envelope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sep = ENVELOPE_SEPARATOR;
+        write!(
+            f,
+            "{ENVELOPE_VERSION}{sep}{}{sep}{}{sep}{}",
+            self.key_id,
+            self.algorithm.id(),
+            BASE64URL_NOPAD.encode(&self.sealed)
+        )
+    }
+}
+
+/// A set of keys with one primary key used for encryption. All keys in the ring can be used for decryption.
+///
+/// To rotate keys, [rotate][Keyring::rotate] the ring with a new key and re-encrypt stored values
+/// for which [needs_reencryption][Keyring::needs_reencryption] returns `true`.
+#[derive(Debug, Clone)]
+pub struct Keyring {
+    primary: String,
+    keys: HashMap<String, Key>,
+}
+
+impl Keyring {
+    /// Creates a keyring with the given key as the primary.
+    pub fn new(primary: Key) -> Self {
+        let id = primary.id.clone();
+        Self {
+            primary: id.clone(),
+            keys: HashMap::from([(id, primary)]),
+        }
+    }
+
+    /// Adds a key used only for decryption. Replaces the key with the same ID, if any,
+    /// unless it is the primary key.
+    pub fn with_key(mut self, key: Key) -> Self {
+        if key.id != self.primary {
+            self.keys.insert(key.id.clone(), key);
+        }
+        self
+    }
+
+    /// Sets the given key as the primary, keeping the previous one for decryption.
+    pub fn rotate(&mut self, key: Key) {
+        self.primary = key.id.clone();
+        self.keys.insert(key.id.clone(), key);
+    }
+
+    /// Returns the key currently used for encryption.
+    pub fn primary(&self) -> &Key {
+        &self.keys[&self.primary]
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Key> {
+        self.keys.get(id)
+    }
+
+    /// Encrypts the plaintext with the primary key and returns the envelope string.
+    pub fn encrypt(&self, plaintext: &[u8], aad: &[u8]) -> Result<String, CryptoError> {
+        let key = self.primary();
+        let envelope = Envelope {
+            key_id: key.id.clone(),
+            algorithm: key.algorithm,
+            sealed: key.encrypt(plaintext, aad)?,
+        };
+        Ok(envelope.to_string())
+    }
+
+    /// Decrypts an envelope obtained from [encrypt][Keyring::encrypt] with the key whose ID is in the envelope.
+    pub fn decrypt(&self, envelope: &str, aad: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let envelope = Envelope::parse(envelope)?;
+
+        let key = self
+            .get(&envelope.key_id)
+            .ok_or_else(|| CryptoError::UnknownKey(envelope.key_id.clone()))?;
+
+        if key.algorithm != envelope.algorithm {
+            return Err(CryptoError::Envelope("algorithm does not match key"));
+        }
+
+        key.decrypt(&envelope.sealed, aad)
+    }
+
+    /// Same as [encrypt][Keyring::encrypt], but for strings.
+    pub fn encrypt_str(&self, plaintext: &str, aad: &[u8]) -> Result<String, CryptoError> {
+        self.encrypt(plaintext.as_bytes(), aad)
+    }
+
+    /// Same as [decrypt][Keyring::decrypt], but converts the plaintext to a string.
+    pub fn decrypt_str(&self, envelope: &str, aad: &[u8]) -> Result<String, CryptoError> {
+        String::from_utf8(self.decrypt(envelope, aad)?).map_err(Into::into)
+    }
+
+    /// Returns `true` if the envelope was not sealed with the current primary key.
+    pub fn needs_reencryption(&self, envelope: &str) -> Result<bool, CryptoError> {
+        Ok(Envelope::parse(envelope)?.key_id != self.primary)
+    }
+
+    /// Decrypts the envelope and encrypts it again with the primary key.
+    pub fn reencrypt(&self, envelope: &str, aad: &[u8]) -> Result<String, CryptoError> {
+        self.encrypt(&self.decrypt(envelope, aad)?, aad)
+    }
+}
+
+fn validate_key_id(id: &str) -> Result<(), CryptoError> {
+    if id.is_empty() || id.contains(ENVELOPE_SEPARATOR) {
+        return Err(CryptoError::Envelope(
+            "key IDs must be non empty and must not contain '$'",
+        ));
+    }
+    Ok(())
+}
+
+static GLOBAL_KEYRING: OnceCell<Keyring> = OnceCell::new();
+
+/// Sets the keyring used by the [encrypted] serde adapter. Can only be called once, if the keyring
+/// was already set the given one is returned.
+pub fn set_global_keyring(keyring: Keyring) -> Result<(), Keyring> {
+    GLOBAL_KEYRING.set(keyring)
+}
+
+/// Returns the keyring used by the [encrypted] serde adapter, if set.
+pub fn global_keyring() -> Option<&'static Keyring> {
+    GLOBAL_KEYRING.get()
+}
+
+/// Serde adapter for encrypting individual struct fields with the keyring set via [set_global_keyring].
+///
+/// The field is serialized to JSON, encrypted and serialized as an envelope string. Using the adapter
+/// without a global keyring results in a serialization error.
+///
+/// ### Example
+///
+/// ```ignore
+/// #[derive(Serialize, Deserialize)]
+/// struct User {
+///     id: Uuid,
+///     #[serde(with = "hextacy::crypto::cipher::encrypted")]
+///     otp_secret: Option<String>,
+/// }
+/// ```
+pub mod encrypted {
+    use super::global_keyring;
+    use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Serialize,
+        S: Serializer,
+    {
+        use serde::ser::Error;
+
+        let keyring = global_keyring().ok_or_else(|| S::Error::custom("keyring not set"))?;
+        let json = serde_json::to_vec(value).map_err(S::Error::custom)?;
+        let envelope = keyring.encrypt(&json, &[]).map_err(S::Error::custom)?;
+        serializer.serialize_str(&envelope)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: DeserializeOwned,
+        D: Deserializer<'de>,
+    {
+        use serde::de::Error;
+
+        let keyring = global_keyring().ok_or_else(|| D::Error::custom("keyring not set"))?;
+        let envelope = String::deserialize(deserializer)?;
+        let json = keyring.decrypt(&envelope, &[]).map_err(D::Error::custom)?;
+        serde_json::from_slice(&json).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+
+    #[test]
+    fn encrypt_decrypt() {
+        for algorithm in [Algorithm::Aes256Gcm, Algorithm::XChaCha20Poly1305] {
+            let keyring = Keyring::new(Key::generate("k1", algorithm).unwrap());
+
+            let envelope = keyring.encrypt_str("secret", b"user:1").unwrap();
+            assert!(envelope.starts_with(&format!("v1$k1${}$", algorithm.id())));
+
+            let decrypted = keyring.decrypt_str(&envelope, b"user:1").unwrap();
+            assert_eq!(decrypted, "secret");
+
+            // Wrong associated data
+            assert!(matches!(
+                keyring.decrypt(&envelope, b"user:2"),
+                Err(CryptoError::Aead)
+            ));
+
+            // Nonces are random
+            assert_ne!(envelope, keyring.encrypt_str("secret", b"user:1").unwrap());
+        }
+    }
+
+    #[test]
+    fn tampering() {
+        let keyring = Keyring::new(Key::generate("k1", Algorithm::Aes256Gcm).unwrap());
+        let envelope = keyring.encrypt_str("secret", &[]).unwrap();
+
+        let mut parsed = Envelope::parse(&envelope).unwrap();
+        let last = parsed.sealed.len() - 1;
+        parsed.sealed[last] ^= 1;
+        assert!(matches!(
+            keyring.decrypt(&parsed.to_string(), &[]),
+            Err(CryptoError::Aead)
+        ));
+
+        assert!(keyring.decrypt("v1$k1$A256GCM", &[]).is_err());
+        assert!(keyring.decrypt("v2$k1$A256GCM$AAAA", &[]).is_err());
+        assert!(matches!(
+            keyring.decrypt(&envelope.replacen("k1", "k2", 1), &[]),
+            Err(CryptoError::UnknownKey(id)) if id == "k2"
+        ));
+    }
+
+    #[test]
+    fn rotation() {
+        let mut keyring = Keyring::new(Key::generate("k1", Algorithm::Aes256Gcm).unwrap());
+        let old = keyring.encrypt_str("secret", &[]).unwrap();
+
+        keyring.rotate(Key::generate("k2", Algorithm::XChaCha20Poly1305).unwrap());
+        assert_eq!(keyring.primary().id(), "k2");
+        assert!(keyring.needs_reencryption(&old).unwrap());
+
+        let new = keyring.reencrypt(&old, &[]).unwrap();
+        assert!(!keyring.needs_reencryption(&new).unwrap());
+        assert_eq!(keyring.decrypt_str(&old, &[]).unwrap(), "secret");
+        assert_eq!(keyring.decrypt_str(&new, &[]).unwrap(), "secret");
+    }
+
+    #[test]
+    fn serde_adapter() {
+        #[derive(Debug, Serialize, Deserialize, PartialEq)]
+        struct Credentials {
+            user: String,
+            #[serde(with = "encrypted")]
+            api_key: Option<String>,
+        }
+
+        let _ = set_global_keyring(Keyring::new(
+            Key::generate("serde", Algorithm::XChaCha20Poly1305).unwrap(),
+        ));
+
+        let creds = Credentials {
+            user: "foo".to_string(),
+            api_key: Some("bar".to_string()),
+        };
+
+        let json = serde_json::to_string(&creds).unwrap();
+        assert!(!json.contains("bar"));
+
+        let deserialized: Credentials = serde_json::from_str(&json).unwrap();
+        assert_eq!(creds, deserialized);
+    }
+}
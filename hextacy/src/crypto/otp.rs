@@ -1,11 +1,33 @@
+//! One time passwords as per [RFC 4226 (HOTP)](https://www.rfc-editor.org/rfc/rfc4226) and
+//! [RFC 6238 (TOTP)](https://www.rfc-editor.org/rfc/rfc6238), as well as one time recovery codes.
+//!
+//! Secrets are always passed as raw bytes to the generation and verification functions, use
+//! [decode_secret] to obtain them from their encoded representation.
+
 use super::CryptoError;
-use data_encoding::Encoding;
+use data_encoding::{Encoding, BASE32_NOPAD, HEXLOWER};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use sha2::{Digest, Sha256};
+use std::io::Cursor;
+use subtle::ConstantTimeEq;
+use thotp::custom::{otp_custom, Sha1, Sha512};
+use thotp::ThotpError;
 
 /// Generates an OTP secret
 pub fn generate_secret(size: usize, encoding: Encoding) -> String {
     thotp::encoding::encode(&thotp::generate_secret(size), encoding)
 }
 
+/// Generates a base32 encoded secret, the encoding expected by authenticator apps.
+pub fn generate_base32_secret(size: usize) -> String {
+    generate_secret(size, BASE32_NOPAD)
+}
+
+/// Decodes an OTP secret obtained from [generate_secret].
+pub fn decode_secret(secret: &str, encoding: Encoding) -> Result<Vec<u8>, CryptoError> {
+    encoding.decode(secret.as_bytes()).map_err(Into::into)
+}
+
 /// Generates a QR code svg with the given secret
 pub fn generate_totp_qr_code(
     secret: &str,
@@ -13,20 +35,451 @@ pub fn generate_totp_qr_code(
     label: &str,
     issuer: &str,
 ) -> Result<String, CryptoError> {
-    let uri = thotp::qr::otp_uri(
-        "totp",
-        secret,
-        &format!("{label}:{user_email}"),
-        issuer,
-        None,
-    )?;
-    thotp::qr::generate_code_svg(&uri, None, None, thotp::qr::EcLevel::M).map_err(Into::into)
+    let uri = Totp::default().uri(secret, &format!("{label}:{user_email}"), issuer)?;
+    generate_qr_code_svg(&uri)
+}
+
+/// Generates a QR code PNG with the given secret
+pub fn generate_totp_qr_code_png(
+    secret: &str,
+    user_email: &str,
+    label: &str,
+    issuer: &str,
+) -> Result<Vec<u8>, CryptoError> {
+    let uri = Totp::default().uri(secret, &format!("{label}:{user_email}"), issuer)?;
+    generate_qr_code_png(&uri)
+}
+
+/// Generates a QR code SVG from an OTP uri obtained from [Totp::uri] or [Hotp::uri].
+pub fn generate_qr_code_svg(uri: &str) -> Result<String, CryptoError> {
+    thotp::qr::generate_code_svg(uri, None, None, thotp::qr::EcLevel::M).map_err(Into::into)
+}
+
+/// Generates a QR code PNG from an OTP uri obtained from [Totp::uri] or [Hotp::uri].
+pub fn generate_qr_code_png(uri: &str) -> Result<Vec<u8>, CryptoError> {
+    let code = qrcode::QrCode::with_error_correction_level(uri, qrcode::EcLevel::M)?;
+
+    let image = code
+        .render::<image::Luma<u8>>()
+        .min_dimensions(200, 200)
+        .build();
+
+    let mut png = Cursor::new(vec![]);
+    image.write_to(&mut png, image::ImageFormat::Png)?;
+
+    Ok(png.into_inner())
 }
 
-/// Verifies a timed OTP against the given secret
+/// Verifies a timed OTP against the given secret.
+///
+/// Only the current time step is checked and the matched step is discarded, use [Totp::verify]
+/// for drift windows and replay protection.
 pub fn verify_otp(password: &str, secret: &str, encoding: Encoding) -> Result<bool, CryptoError> {
-    let secret = encoding.decode(secret.as_bytes())?;
-    thotp::verify_totp(password, &secret, 0)
+    let secret = decode_secret(secret, encoding)?;
+    let totp = Totp {
+        skew: 0,
+        ..Default::default()
+    };
+    Ok(totp.verify(password, &secret, None)?.is_some())
+}
+
+/// The HMAC algorithm used for generating passwords.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OtpAlgorithm {
+    #[default]
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl OtpAlgorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OtpAlgorithm::Sha1 => "SHA1",
+            OtpAlgorithm::Sha256 => "SHA256",
+            OtpAlgorithm::Sha512 => "SHA512",
+        }
+    }
+
+    /// Generates a password for the given counter value.
+    fn otp(&self, secret: &[u8], counter: u64, digits: u8) -> Result<String, CryptoError> {
+        // thotp computes `10^digits` in a u32 so anything above 9 overflows
+        if !(6..=9).contains(&digits) {
+            return Err(ThotpError::InvalidDigits.into());
+        }
+
+        match self {
+            OtpAlgorithm::Sha1 => otp_custom::<Sha1>(secret, counter, digits),
+            OtpAlgorithm::Sha256 => otp_custom::<Sha256>(secret, counter, digits),
+            OtpAlgorithm::Sha512 => otp_custom::<Sha512>(secret, counter, digits),
+        }
         .map_err(Into::into)
-        .map(|(res, _)| res)
+    }
+}
+
+/// Time based OTP configuration.
+///
+/// The defaults are the ones used by most authenticator apps, i.e. 6 digits, a 30 second period
+/// and SHA1, with a skew of 1 step in each direction to account for clock drift.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Totp {
+    /// Amount of digits in the password, between 6 and 9.
+    pub digits: u8,
+    /// Time step in seconds, must be greater than 0.
+    pub period: u64,
+    pub algorithm: OtpAlgorithm,
+    /// Amount of time steps before and after the current one to accept.
+    pub skew: u8,
+}
+
+impl Default for Totp {
+    fn default() -> Self {
+        Self {
+            digits: 6,
+            period: 30,
+            algorithm: OtpAlgorithm::Sha1,
+            skew: 1,
+        }
+    }
+}
+
+impl Totp {
+    /// Returns the time step for the given unix timestamp. Fails if the period is 0.
+    pub fn step(&self, timestamp: u64) -> Result<u64, CryptoError> {
+        timestamp
+            .checked_div(self.period)
+            .ok_or(CryptoError::TotpPeriod)
+    }
+
+    /// Generates the password for the current time.
+    pub fn generate(&self, secret: &[u8]) -> Result<String, CryptoError> {
+        self.generate_at(secret, now())
+    }
+
+    /// Generates the password for the given unix timestamp.
+    pub fn generate_at(&self, secret: &[u8], timestamp: u64) -> Result<String, CryptoError> {
+        self.algorithm
+            .otp(secret, self.step(timestamp)?, self.digits)
+    }
+
+    /// Verifies the password for the current time. See [verify_at][Totp::verify_at].
+    pub fn verify(
+        &self,
+        password: &str,
+        secret: &[u8],
+        last_step: Option<u64>,
+    ) -> Result<Option<u64>, CryptoError> {
+        self.verify_at(password, secret, now(), last_step)
+    }
+
+    /// Verifies the password against every time step in the `[-skew, skew]` window around the
+    /// given timestamp and returns the step that matched.
+    ///
+    /// The matched step should be persisted and passed as `last_step` on subsequent verifications.
+    /// Any step lesser or equal to it will be rejected, preventing the same password from being used twice.
+    pub fn verify_at(
+        &self,
+        password: &str,
+        secret: &[u8],
+        timestamp: u64,
+        last_step: Option<u64>,
+    ) -> Result<Option<u64>, CryptoError> {
+        let current = self.step(timestamp)?;
+        let start = current.saturating_sub(self.skew as u64);
+        let end = current.saturating_add(self.skew as u64);
+
+        for step in start..=end {
+            if last_step.is_some_and(|last| step <= last) {
+                continue;
+            }
+            let expected = self.algorithm.otp(secret, step, self.digits)?;
+            if bool::from(expected.as_bytes().ct_eq(password.as_bytes())) {
+                return Ok(Some(step));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Creates an `otpauth://` uri for the given encoded secret. Parameters other than the
+    /// defaults are appended to the uri.
+    pub fn uri(&self, secret: &str, label: &str, issuer: &str) -> Result<String, CryptoError> {
+        if self.period == 0 {
+            return Err(CryptoError::TotpPeriod);
+        }
+        let mut uri = thotp::qr::otp_uri("totp", secret, label, issuer, None)?;
+        let defaults = Self::default();
+        append_params(
+            &mut uri,
+            (self.algorithm != defaults.algorithm).then_some(self.algorithm),
+            (self.digits != defaults.digits).then_some(self.digits),
+        )?;
+        if self.period != defaults.period {
+            uri.push_str(&format!("&period={}", self.period));
+        }
+        Ok(uri)
+    }
+}
+
+/// Counter based OTP configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hotp {
+    /// Amount of digits in the password, between 6 and 9.
+    pub digits: u8,
+    pub algorithm: OtpAlgorithm,
+    /// Amount of counter values after the current one to accept, used to resynchronise
+    /// counters when the client generated passwords that were never verified.
+    pub lookahead: u8,
+}
+
+impl Default for Hotp {
+    fn default() -> Self {
+        Self {
+            digits: 6,
+            algorithm: OtpAlgorithm::Sha1,
+            lookahead: 5,
+        }
+    }
+}
+
+impl Hotp {
+    /// Generates the password for the given counter.
+    pub fn generate(&self, secret: &[u8], counter: u64) -> Result<String, CryptoError> {
+        self.algorithm.otp(secret, counter, self.digits)
+    }
+
+    /// Verifies the password against the counter values in `[counter, counter + lookahead]`.
+    ///
+    /// Returns the next counter value, i.e. the matched one incremented by 1, which must be
+    /// persisted to prevent the password from being used again.
+    pub fn verify(
+        &self,
+        password: &str,
+        secret: &[u8],
+        counter: u64,
+    ) -> Result<Option<u64>, CryptoError> {
+        for offset in 0..=self.lookahead as u64 {
+            let Some(current) = counter.checked_add(offset) else {
+                break;
+            };
+            let expected = self.algorithm.otp(secret, current, self.digits)?;
+            if bool::from(expected.as_bytes().ct_eq(password.as_bytes())) {
+                return Ok(Some(current.saturating_add(1)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Creates an `otpauth://` uri for the given encoded secret and initial counter.
+    pub fn uri(
+        &self,
+        secret: &str,
+        label: &str,
+        issuer: &str,
+        counter: u64,
+    ) -> Result<String, CryptoError> {
+        let mut uri = thotp::qr::otp_uri("hotp", secret, label, issuer, Some(counter))?;
+        let defaults = Self::default();
+        append_params(
+            &mut uri,
+            (self.algorithm != defaults.algorithm).then_some(self.algorithm),
+            (self.digits != defaults.digits).then_some(self.digits),
+        )?;
+        Ok(uri)
+    }
+}
+
+fn append_params(
+    uri: &mut String,
+    algorithm: Option<OtpAlgorithm>,
+    digits: Option<u8>,
+) -> Result<(), CryptoError> {
+    thotp::qr::uri_append_params(uri, algorithm.map(|a| a.as_str()), digits, None)
+        .map_err(Into::into)
+}
+
+/// Alphabet for recovery codes. Excludes characters that are easily confused with one another.
+const RECOVERY_ALPHABET: &[u8] = b"23456789ABCDEFGHJKLMNPQRSTUVWXYZ";
+
+/// One time recovery codes to use when the user loses access to their authenticator.
+///
+/// Codes are formatted as groups of 5 characters separated by dashes, e.g. `ABCDE-FGHJK`.
+/// Only their SHA256 hashes should be stored since the codes themselves are high entropy.
+pub struct RecoveryCodes;
+
+impl RecoveryCodes {
+    /// Generates `count` codes with `groups` groups of 5 characters each. Returns the plain codes to show
+    /// to the user as the first element and their hashes to store as the second.
+    pub fn generate(count: usize, groups: usize) -> (Vec<String>, Vec<String>) {
+        let mut rng = StdRng::from_entropy();
+
+        let codes = (0..count)
+            .map(|_| {
+                (0..groups)
+                    .map(|_| {
+                        (0..5)
+                            .map(|_| {
+                                RECOVERY_ALPHABET[rng.gen_range(0..RECOVERY_ALPHABET.len())] as char
+                            })
+                            .collect::<String>()
+                    })
+                    .collect::<Vec<_>>()
+                    .join("-")
+            })
+            .collect::<Vec<_>>();
+
+        let hashes = codes.iter().map(|code| Self::hash(code)).collect();
+
+        (codes, hashes)
+    }
+
+    /// Hashes the code after normalising it, i.e. ignoring dashes, whitespace and case.
+    pub fn hash(code: &str) -> String {
+        let normalised = code
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != '-')
+            .map(|c| c.to_ascii_uppercase())
+            .collect::<String>();
+        HEXLOWER.encode(&Sha256::digest(normalised.as_bytes()))
+    }
+
+    /// Returns the index of the hash matching the code. The caller must remove
+    /// the matched hash from storage so the code cannot be used again.
+    pub fn verify(code: &str, hashes: &[impl AsRef<str>]) -> Option<usize> {
+        let hashed = Self::hash(code);
+        hashes
+            .iter()
+            .position(|h| bool::from(h.as_ref().as_bytes().ct_eq(hashed.as_bytes())))
+    }
+}
+
+fn now() -> u64 {
+    crate::time::now() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 6238 Appendix B
+    const SECRET_SHA1: &[u8] = b"12345678901234567890";
+    const SECRET_SHA256: &[u8] = b"12345678901234567890123456789012";
+
+    #[test]
+    fn totp_rfc_vectors() {
+        let totp = Totp {
+            digits: 8,
+            ..Default::default()
+        };
+        assert_eq!(totp.generate_at(SECRET_SHA1, 59).unwrap(), "94287082");
+        assert_eq!(
+            totp.generate_at(SECRET_SHA1, 1111111109).unwrap(),
+            "07081804"
+        );
+
+        let totp = Totp {
+            algorithm: OtpAlgorithm::Sha256,
+            ..totp
+        };
+        assert_eq!(totp.generate_at(SECRET_SHA256, 59).unwrap(), "46119246");
+    }
+
+    #[test]
+    fn totp_window_and_replay() {
+        let totp = Totp::default();
+        let timestamp = 1_700_000_000;
+        let step = totp.step(timestamp).unwrap();
+
+        let previous = totp.generate_at(SECRET_SHA1, timestamp - 30).unwrap();
+        assert_eq!(
+            totp.verify_at(&previous, SECRET_SHA1, timestamp, None)
+                .unwrap(),
+            Some(step - 1)
+        );
+
+        let too_old = totp.generate_at(SECRET_SHA1, timestamp - 60).unwrap();
+        assert_eq!(
+            totp.verify_at(&too_old, SECRET_SHA1, timestamp, None)
+                .unwrap(),
+            None
+        );
+
+        let current = totp.generate_at(SECRET_SHA1, timestamp).unwrap();
+        let matched = totp
+            .verify_at(&current, SECRET_SHA1, timestamp, None)
+            .unwrap();
+        assert_eq!(matched, Some(step));
+
+        // Replaying the same password is rejected
+        assert_eq!(
+            totp.verify_at(&current, SECRET_SHA1, timestamp, matched)
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn totp_zero_period() {
+        let totp = Totp {
+            period: 0,
+            ..Default::default()
+        };
+        assert!(matches!(totp.step(59), Err(CryptoError::TotpPeriod)));
+        assert!(matches!(
+            totp.generate_at(SECRET_SHA1, 59),
+            Err(CryptoError::TotpPeriod)
+        ));
+        assert!(matches!(
+            totp.verify("123456", SECRET_SHA1, None),
+            Err(CryptoError::TotpPeriod)
+        ));
+        assert!(matches!(
+            totp.uri("GEZDGNBV", "app:user", "app"),
+            Err(CryptoError::TotpPeriod)
+        ));
+    }
+
+    #[test]
+    fn hotp() {
+        // RFC 4226 Appendix D
+        let hotp = Hotp::default();
+        assert_eq!(hotp.generate(SECRET_SHA1, 0).unwrap(), "755224");
+        assert_eq!(hotp.generate(SECRET_SHA1, 9).unwrap(), "520489");
+
+        assert_eq!(hotp.verify("520489", SECRET_SHA1, 5).unwrap(), Some(10));
+        assert_eq!(hotp.verify("520489", SECRET_SHA1, 10).unwrap(), None);
+        assert_eq!(hotp.verify("755224", SECRET_SHA1, 1).unwrap(), None);
+    }
+
+    #[test]
+    fn recovery_codes() {
+        let (codes, mut hashes) = RecoveryCodes::generate(10, 2);
+        assert_eq!(codes.len(), 10);
+        assert_eq!(codes[0].len(), 11);
+
+        let idx = RecoveryCodes::verify(&codes[3].to_lowercase(), &hashes).unwrap();
+        assert_eq!(idx, 3);
+        hashes.remove(idx);
+        assert!(RecoveryCodes::verify(&codes[3], &hashes).is_none());
+    }
+
+    #[test]
+    fn uri_and_qr() {
+        let secret = generate_base32_secret(20);
+        let totp = Totp {
+            algorithm: OtpAlgorithm::Sha256,
+            period: 60,
+            ..Default::default()
+        };
+        let uri = totp.uri(&secret, "hextacy:foo@bar.baz", "hextacy").unwrap();
+        assert!(uri.starts_with("otpauth://totp/"));
+        assert!(uri.contains("&algorithm=SHA256"));
+        assert!(uri.contains("&period=60"));
+        assert!(!uri.contains("&digits"));
+
+        let png = generate_qr_code_png(&uri).unwrap();
+        assert_eq!(&png[1..4], b"PNG");
+        assert!(generate_qr_code_svg(&uri).unwrap().starts_with("<?xml"));
+    }
 }
@@ -0,0 +1,340 @@
+//! Tamper proof, expiring tokens for email verification, password resets, magic links and the like.
+//!
+//! A token consists of a payload, a purpose and an expiration time and is signed with HMAC-SHA256.
+//! Tokens are URL safe and have the form `<base64url(body)>.<base64url(signature)>`. When the signer
+//! is configured with a [Keyring] the body is additionally encrypted so the payload cannot be read by the client.
+//!
+//! The purpose prevents tokens issued for one flow from being used in another, e.g. an email verification
+//! token cannot be used to reset a password even though both are signed with the same secret.
+//!
+//! ### Example
+//!
+//! ```ignore
+//! let signer = TokenSigner::new(b"super secret key");
+//!
+//! let token = signer.sign(&user_id, "password-reset", chrono::Duration::minutes(15))?;
+//! // ... send it via email
+//!
+//! let claims = signer.verify::<Uuid>(&token, "password-reset")?;
+//! ```
+
+use super::cipher::Keyring;
+use super::CryptoError;
+use data_encoding::BASE64URL_NOPAD;
+use hmac::{Hmac, Mac};
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::Sha256;
+use std::fmt::Debug;
+use std::future::Future;
+use thiserror::Error;
+
+/// The verified contents of a token.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenClaims<T> {
+    /// Unique ID of the token, used for single use enforcement.
+    #[serde(rename = "jti")]
+    pub id: String,
+    #[serde(rename = "pur")]
+    pub purpose: String,
+    /// Unix timestamp
+    #[serde(rename = "iat")]
+    pub issued_at: i64,
+    /// Unix timestamp
+    #[serde(rename = "exp")]
+    pub expires_at: i64,
+    #[serde(rename = "dat")]
+    pub payload: T,
+}
+
+/// Signs and verifies tokens. Cheap to clone if the secret is small.
+#[derive(Clone)]
+pub struct TokenSigner {
+    secret: Vec<u8>,
+    keyring: Option<Keyring>,
+}
+
+impl Debug for TokenSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenSigner")
+            .field("secret", &"{ ... }")
+            .field("keyring", &self.keyring)
+            .finish()
+    }
+}
+
+impl TokenSigner {
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            secret: secret.to_vec(),
+            keyring: None,
+        }
+    }
+
+    /// Encrypt token bodies with the given keyring before signing them.
+    pub fn with_encryption(mut self, keyring: Keyring) -> Self {
+        self.keyring = Some(keyring);
+        self
+    }
+
+    /// Creates a token for the given payload and purpose that expires after `ttl`.
+    pub fn sign<T: Serialize>(
+        &self,
+        payload: &T,
+        purpose: &str,
+        ttl: chrono::Duration,
+    ) -> Result<String, TokenError> {
+        let now = crate::time::now();
+
+        let mut id = [0_u8; 16];
+        StdRng::from_entropy().fill_bytes(&mut id);
+
+        let claims = TokenClaims {
+            id: BASE64URL_NOPAD.encode(&id),
+            purpose: purpose.to_string(),
+            issued_at: now,
+            expires_at: now + ttl.num_seconds(),
+            payload,
+        };
+
+        let json = serde_json::to_vec(&claims)?;
+
+        let body = match self.keyring {
+            Some(ref keyring) => keyring.encrypt(&json, purpose.as_bytes())?.into_bytes(),
+            None => json,
+        };
+
+        let body = BASE64URL_NOPAD.encode(&body);
+        let signature = BASE64URL_NOPAD.encode(&self.mac(body.as_bytes())?.finalize().into_bytes());
+
+        Ok(format!("{body}.{signature}"))
+    }
+
+    /// Verifies the token's signature, purpose and expiration and returns its claims.
+    pub fn verify<T: DeserializeOwned>(
+        &self,
+        token: &str,
+        purpose: &str,
+    ) -> Result<TokenClaims<T>, TokenError> {
+        let (body, signature) = token.split_once('.').ok_or(TokenError::Malformed)?;
+
+        let signature = BASE64URL_NOPAD
+            .decode(signature.as_bytes())
+            .map_err(|_| TokenError::Malformed)?;
+
+        self.mac(body.as_bytes())?
+            .verify_slice(&signature)
+            .map_err(|_| TokenError::Signature)?;
+
+        let body = BASE64URL_NOPAD
+            .decode(body.as_bytes())
+            .map_err(|_| TokenError::Malformed)?;
+
+        let json = match self.keyring {
+            Some(ref keyring) => {
+                let envelope = String::from_utf8(body).map_err(|_| TokenError::Malformed)?;
+                // The purpose is bound as associated data so a wrong purpose fails decryption,
+                // report it as such
+                keyring
+                    .decrypt(&envelope, purpose.as_bytes())
+                    .map_err(|e| match e {
+                        CryptoError::Aead => TokenError::Purpose,
+                        e => TokenError::Crypto(e),
+                    })?
+            }
+            None => body,
+        };
+
+        let claims: TokenClaims<T> = serde_json::from_slice(&json)?;
+
+        if claims.purpose != purpose {
+            return Err(TokenError::Purpose);
+        }
+
+        if claims.expires_at <= crate::time::now() {
+            return Err(TokenError::Expired);
+        }
+
+        Ok(claims)
+    }
+
+    /// Same as [verify][TokenSigner::verify], but additionally marks the token as used in the given store
+    /// and returns [TokenError::Used] if it was already used.
+    pub async fn verify_once<T, S>(
+        &self,
+        token: &str,
+        purpose: &str,
+        store: &S,
+    ) -> Result<TokenClaims<T>, TokenError>
+    where
+        T: DeserializeOwned,
+        S: TokenStore,
+    {
+        let claims = self.verify::<T>(token, purpose)?;
+        let ttl = (claims.expires_at - crate::time::now()).max(1) as u64;
+
+        let key = format!("hextacy:token:{}:{}", claims.purpose, claims.id);
+        if !store.consume(&key, ttl).await? {
+            return Err(TokenError::Used);
+        }
+
+        Ok(claims)
+    }
+
+    fn mac(&self, body: &[u8]) -> Result<Hmac<Sha256>, TokenError> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).map_err(CryptoError::from)?;
+        mac.update(body);
+        Ok(mac)
+    }
+}
+
+/// Used to keep track of consumed tokens for single use enforcement.
+pub trait TokenStore {
+    /// Marks the key as used for `ttl` seconds. Returns `true` if the key was not previously used.
+    /// This operation must be atomic.
+    fn consume(&self, key: &str, ttl: u64)
+        -> impl Future<Output = Result<bool, TokenError>> + Send;
+}
+
+#[cfg(feature = "cache-redis")]
+impl TokenStore for deadpool_redis::Pool {
+    async fn consume(&self, key: &str, ttl: u64) -> Result<bool, TokenError> {
+        let mut conn = self
+            .get()
+            .await
+            .map_err(|e| TokenError::Store(e.to_string()))?;
+
+        let result: Option<String> = deadpool_redis::redis::cmd("SET")
+            .arg(key)
+            .arg(1)
+            .arg("NX")
+            .arg("EX")
+            .arg(ttl)
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| TokenError::Store(e.to_string()))?;
+
+        Ok(result.is_some())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum TokenError {
+    #[error("Malformed token")]
+    Malformed,
+    #[error("Invalid token signature")]
+    Signature,
+    #[error("Token issued for a different purpose")]
+    Purpose,
+    #[error("Token expired")]
+    Expired,
+    #[error("Token already used")]
+    Used,
+    #[error("Token store: {0}")]
+    Store(String),
+    #[error("{0}")]
+    Crypto(#[from] CryptoError),
+    #[error("{0}")]
+    Serde(#[from] serde_json::Error),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::cipher::{Algorithm, Key};
+    use super::*;
+    use std::{collections::HashSet, sync::Mutex};
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Reset {
+        user_id: u64,
+    }
+
+    #[derive(Default)]
+    struct MemStore(Mutex<HashSet<String>>);
+
+    impl TokenStore for MemStore {
+        async fn consume(&self, key: &str, _: u64) -> Result<bool, TokenError> {
+            Ok(self.0.lock().unwrap().insert(key.to_string()))
+        }
+    }
+
+    #[test]
+    fn sign_verify() {
+        let signer = TokenSigner::new(b"secret");
+        let token = signer
+            .sign(&Reset { user_id: 1 }, "reset", chrono::Duration::minutes(5))
+            .unwrap();
+
+        assert!(!token.contains(['+', '/', '=']));
+
+        let claims = signer.verify::<Reset>(&token, "reset").unwrap();
+        assert_eq!(claims.payload, Reset { user_id: 1 });
+        assert_eq!(claims.purpose, "reset");
+
+        assert!(matches!(
+            signer.verify::<Reset>(&token, "verify-email"),
+            Err(TokenError::Purpose)
+        ));
+        assert!(matches!(
+            TokenSigner::new(b"other").verify::<Reset>(&token, "reset"),
+            Err(TokenError::Signature)
+        ));
+        assert!(matches!(
+            signer.verify::<Reset>(&token.replacen('.', "", 1), "reset"),
+            Err(TokenError::Malformed)
+        ));
+
+        let expired = signer
+            .sign(
+                &Reset { user_id: 1 },
+                "reset",
+                chrono::Duration::seconds(-1),
+            )
+            .unwrap();
+        assert!(matches!(
+            signer.verify::<Reset>(&expired, "reset"),
+            Err(TokenError::Expired)
+        ));
+    }
+
+    #[test]
+    fn encrypted() {
+        let keyring = Keyring::new(Key::generate("k1", Algorithm::XChaCha20Poly1305).unwrap());
+        let signer = TokenSigner::new(b"secret").with_encryption(keyring);
+
+        let token = signer
+            .sign(&"foo@bar.baz", "magic-link", chrono::Duration::minutes(5))
+            .unwrap();
+
+        let (body, _) = token.split_once('.').unwrap();
+        let body = String::from_utf8(BASE64URL_NOPAD.decode(body.as_bytes()).unwrap()).unwrap();
+        assert!(!body.contains("foo@bar.baz"));
+
+        let claims = signer.verify::<String>(&token, "magic-link").unwrap();
+        assert_eq!(claims.payload, "foo@bar.baz");
+        assert!(matches!(
+            signer.verify::<String>(&token, "reset"),
+            Err(TokenError::Purpose)
+        ));
+    }
+
+    #[tokio::test]
+    async fn single_use() {
+        let signer = TokenSigner::new(b"secret");
+        let store = MemStore::default();
+        let token = signer
+            .sign(&1, "verify-email", chrono::Duration::minutes(5))
+            .unwrap();
+
+        signer
+            .verify_once::<u64, _>(&token, "verify-email", &store)
+            .await
+            .unwrap();
+        assert!(matches!(
+            signer
+                .verify_once::<u64, _>(&token, "verify-email", &store)
+                .await,
+            Err(TokenError::Used)
+        ));
+    }
+}
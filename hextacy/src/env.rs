@@ -1,7 +1,65 @@
+//! Reading typed values from the env and loading dotenv files.
+//!
+//! A [DotenvLoader] loads the following files from a directory, each one overriding the keys of the previous:
+//!
+//! 1. `.env`
+//! 2. `.env.{profile}`, e.g. `.env.production`, if a profile is set
+//! 3. `.env.local`
+//! 4. `.env.{profile}.local`, if a profile is set
+//!
+//! The `.local` files hold machine specific overrides and are usually not committed.
+//!
+//! Variables already set in the process env are never overridden. Values can reference other variables with
+//! `$VAR`, `${VAR}` or `${VAR:-default}`, which are resolved after all the files are merged:
+//!
+//! ```ignore
+//! DotenvLoader::from_env().load()?;
+//! env::require(&["DATABASE_URL", "REDIS_URL"])?;
+//!
+//! let timeout = env::get_duration("REQUEST_TIMEOUT")?; // 30s
+//! let max_body = env::get_bytes("MAX_BODY_SIZE")?; // 10MiB
+//! let origins: Vec<String> = env::get_list("ALLOWED_ORIGINS")?; // a.com, b.com
+//! ```
+
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     env::{self, VarError},
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
 };
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum EnvError {
+    #[error("Env variable {0} not found")]
+    Missing(String),
+    #[error("Missing env variables: {}", .0.join(", "))]
+    MissingKeys(Vec<String>),
+    #[error("Could not parse env variable {key} as {expected}: {message}")]
+    Parse {
+        key: String,
+        expected: &'static str,
+        message: String,
+    },
+    #[error("Could not read {0}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("Invalid syntax in {0} on line {1}")]
+    Syntax(PathBuf, usize),
+    #[error("Env variable {0} references itself")]
+    Cycle(String),
+}
+
+impl EnvError {
+    fn parse(key: &str, expected: &'static str, message: impl Display) -> Self {
+        Self::Parse {
+            key: key.to_string(),
+            expected,
+            message: message.to_string(),
+        }
+    }
+}
 
 /// Gets an environment variable for the given key
 pub fn get(key: &str) -> Result<String, VarError> {
@@ -48,7 +106,560 @@ pub fn get_or_default_multiple<'a>(keys: &'a [(&'a str, &str)]) -> HashMap<&'a s
     results
 }
 
+/// The same as [get_multiple], but returns an error listing all the keys not set in the env.
+pub fn require<'a>(keys: &[&'a str]) -> Result<HashMap<&'a str, String>, EnvError> {
+    let results = get_multiple(keys);
+    let missing: Vec<_> = keys
+        .iter()
+        .filter(|key| !results.contains_key(*key))
+        .map(|key| key.to_string())
+        .collect();
+
+    if missing.is_empty() {
+        Ok(results)
+    } else {
+        Err(EnvError::MissingKeys(missing))
+    }
+}
+
 /// Reads a file and sets all of its declared variables in the shell environment
 pub fn load_from_file(path: &str) -> Result<(), dotenv::Error> {
     dotenv::from_path(path)
 }
+
+fn get_checked(key: &str) -> Result<String, EnvError> {
+    env::var(key).map_err(|e| match e {
+        VarError::NotPresent => EnvError::Missing(key.to_string()),
+        VarError::NotUnicode(_) => EnvError::parse(key, "string", e),
+    })
+}
+
+/// Parses the variable with its [FromStr] implementation.
+pub fn get_parsed<T>(key: &str) -> Result<T, EnvError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    get_checked(key)?
+        .trim()
+        .parse()
+        .map_err(|e| EnvError::parse(key, std::any::type_name::<T>(), e))
+}
+
+/// Parses the variable as a bool, see [parse_bool].
+pub fn get_bool(key: &str) -> Result<bool, EnvError> {
+    let value = get_checked(key)?;
+    parse_bool(&value)
+        .ok_or_else(|| EnvError::parse(key, "bool", format!("invalid value `{value}`")))
+}
+
+/// Parses the variable as a duration, see [parse_duration].
+pub fn get_duration(key: &str) -> Result<Duration, EnvError> {
+    let value = get_checked(key)?;
+    parse_duration(&value)
+        .ok_or_else(|| EnvError::parse(key, "duration", format!("invalid value `{value}`")))
+}
+
+/// Parses the variable as a number of bytes, see [parse_bytes].
+pub fn get_bytes(key: &str) -> Result<u64, EnvError> {
+    let value = get_checked(key)?;
+    parse_bytes(&value)
+        .ok_or_else(|| EnvError::parse(key, "byte size", format!("invalid value `{value}`")))
+}
+
+/// Parses the variable as a comma separated list. Items are trimmed and empty ones are skipped.
+pub fn get_list<T>(key: &str) -> Result<Vec<T>, EnvError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    get_checked(key)?
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            item.parse()
+                .map_err(|e| EnvError::parse(key, std::any::type_name::<T>(), e))
+        })
+        .collect()
+}
+
+/// Accepts `true`, `1`, `yes` and `on`, or `false`, `0`, `no` and `off`, in any case.
+pub fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_lowercase().as_str() {
+        "true" | "1" | "yes" | "on" => Some(true),
+        "false" | "0" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+/// Parses durations such as `250ms`, `30s`, `5m`, `2h`, `1d` or combinations like `1h30m`.
+/// A number without a unit is in seconds.
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let mut total = Duration::ZERO;
+    let mut rest = value;
+
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        let amount: u64 = rest[..digits].parse().ok()?;
+        rest = &rest[digits..];
+
+        let unit = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let duration = match rest[..unit].trim() {
+            "ns" => Duration::from_nanos(amount),
+            "us" => Duration::from_micros(amount),
+            "ms" => Duration::from_millis(amount),
+            "s" => Duration::from_secs(amount),
+            "m" => Duration::from_secs(amount.checked_mul(60)?),
+            "h" => Duration::from_secs(amount.checked_mul(60 * 60)?),
+            "d" => Duration::from_secs(amount.checked_mul(60 * 60 * 24)?),
+            _ => return None,
+        };
+        total = total.checked_add(duration)?;
+        rest = rest[unit..].trim_start();
+    }
+
+    Some(total)
+}
+
+/// Parses sizes such as `512`, `64KB` or `10MiB`, case insensitive. `K`, `M`, `G` and `T` with an optional `B`
+/// are powers of 1000, while `Ki`, `Mi`, `Gi` and `Ti` with an optional `B` are powers of 1024.
+pub fn parse_bytes(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let digits = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let amount: u64 = value[..digits].parse().ok()?;
+
+    let unit = value[digits..].trim().to_lowercase();
+    let unit = unit.strip_suffix('b').unwrap_or(&unit);
+    let multiplier: u64 = match unit {
+        "" => 1,
+        "k" => 1000,
+        "m" => 1000u64.pow(2),
+        "g" => 1000u64.pow(3),
+        "t" => 1000u64.pow(4),
+        "ki" => 1 << 10,
+        "mi" => 1 << 20,
+        "gi" => 1 << 30,
+        "ti" => 1 << 40,
+        _ => return None,
+    };
+
+    amount.checked_mul(multiplier)
+}
+
+/// Loads `.env`, `.env.local` and the profile's dotenv files into the process env.
+#[derive(Debug, Clone)]
+pub struct DotenvLoader {
+    dir: PathBuf,
+    profile: Option<String>,
+}
+
+impl DotenvLoader {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            profile: None,
+        }
+    }
+
+    /// Configures the loader from the env:
+    ///
+    /// - `DOTENV_DIR` sets the directory, the current one by default
+    /// - `APP_PROFILE` sets the profile
+    pub fn from_env() -> Self {
+        let loader = Self::new(get_or_default("DOTENV_DIR", "."));
+        match get("APP_PROFILE") {
+            Ok(profile) => loader.with_profile(profile),
+            Err(_) => loader,
+        }
+    }
+
+    /// Loads `.env.{profile}` on top of `.env` and `.env.{profile}.local` on top of `.env.local`.
+    pub fn with_profile(mut self, profile: impl Into<String>) -> Self {
+        self.profile = Some(profile.into());
+        self
+    }
+
+    /// Reads and merges the files and sets the variables not already present in the env.
+    pub fn load(&self) -> Result<(), EnvError> {
+        for (key, value) in self.read()? {
+            if env::var_os(&key).is_none() {
+                env::set_var(key, value);
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads and merges the files without touching the env. Missing files are skipped. Variables
+    /// present in the env take precedence over the files, both in the result and when interpolating.
+    pub fn read(&self) -> Result<HashMap<String, String>, EnvError> {
+        let mut names = vec![".env".to_string()];
+        if let Some(ref profile) = self.profile {
+            names.push(format!(".env.{profile}"));
+        }
+        names.push(".env.local".to_string());
+        if let Some(ref profile) = self.profile {
+            names.push(format!(".env.{profile}.local"));
+        }
+
+        let mut vars = HashMap::new();
+        for name in names {
+            let path = self.dir.join(name);
+            if !path.is_file() {
+                continue;
+            }
+            let contents =
+                std::fs::read_to_string(&path).map_err(|e| EnvError::Io(path.clone(), e))?;
+            vars.extend(parse(&path, &contents)?);
+        }
+
+        let mut interpolator = Interpolator {
+            vars: &vars,
+            resolved: HashMap::new(),
+            visiting: HashSet::new(),
+        };
+        for key in vars.keys() {
+            interpolator.resolve(key)?;
+        }
+
+        Ok(interpolator.resolved)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Text(String),
+    Var {
+        name: String,
+        default: Option<String>,
+    },
+}
+
+/// Parses the `KEY=value` lines of a dotenv file. Values can be unquoted, single quoted which disables
+/// escapes and interpolation, or double quoted which can span multiple lines.
+fn parse(path: &Path, contents: &str) -> Result<Vec<(String, Vec<Segment>)>, EnvError> {
+    let mut lines = contents.lines().enumerate();
+    let mut vars = vec![];
+
+    while let Some((i, line)) = lines.next() {
+        let error = || EnvError::Syntax(path.to_path_buf(), i + 1);
+
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+
+        let (key, value) = line.split_once('=').ok_or_else(error)?;
+        let key = key.trim();
+        if key.is_empty()
+            || !key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        {
+            return Err(error());
+        }
+
+        let value = value.trim_start();
+        let segments = if let Some(value) = value.strip_prefix('"') {
+            let mut value = value.to_string();
+            let end = loop {
+                if let Some(end) = closing_quote(&value) {
+                    break end;
+                }
+                let (_, next) = lines.next().ok_or_else(error)?;
+                value.push('\n');
+                value.push_str(next);
+            };
+            if !is_comment(&value[end + 1..]) {
+                return Err(error());
+            }
+            segments(&value[..end], true).ok_or_else(error)?
+        } else if let Some(value) = value.strip_prefix('\'') {
+            let end = value.find('\'').ok_or_else(error)?;
+            if !is_comment(&value[end + 1..]) {
+                return Err(error());
+            }
+            vec![Segment::Text(value[..end].to_string())]
+        } else {
+            let end = value.find(" #").unwrap_or(value.len());
+            segments(value[..end].trim_end(), false).ok_or_else(error)?
+        };
+
+        vars.push((key.to_string(), segments));
+    }
+
+    Ok(vars)
+}
+
+fn is_comment(rest: &str) -> bool {
+    let rest = rest.trim();
+    rest.is_empty() || rest.starts_with('#')
+}
+
+/// Returns the index of the first double quote not escaped with a backslash.
+fn closing_quote(value: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in value.char_indices() {
+        match c {
+            '"' if !escaped => return Some(i),
+            '\\' => escaped = !escaped,
+            _ => escaped = false,
+        }
+    }
+    None
+}
+
+/// Splits the value into text and variable references. Returns `None` if a `${` is not closed.
+fn segments(value: &str, escapes: bool) -> Option<Vec<Segment>> {
+    let mut segments = vec![];
+    let mut text = String::new();
+    let mut chars = value.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' if escapes => match chars.next() {
+                Some((_, 'n')) => text.push('\n'),
+                Some((_, 'r')) => text.push('\r'),
+                Some((_, 't')) => text.push('\t'),
+                Some((_, c @ ('"' | '\\' | '$'))) => text.push(c),
+                Some((_, c)) => {
+                    text.push('\\');
+                    text.push(c);
+                }
+                None => text.push('\\'),
+            },
+            '$' => {
+                let is_name = |c: &char| c.is_ascii_alphanumeric() || *c == '_';
+                let (name, default) = if let Some((_, '{')) = chars.peek() {
+                    let end = value[i..].find('}')? + i;
+                    let reference = &value[i + 2..end];
+                    while chars.next_if(|(j, _)| *j <= end).is_some() {}
+                    match reference.split_once(":-") {
+                        Some((name, default)) => (name.to_string(), Some(default.to_string())),
+                        None => (reference.to_string(), None),
+                    }
+                } else {
+                    let mut name = String::new();
+                    while let Some((_, c)) = chars.next_if(|(_, c)| is_name(c)) {
+                        name.push(c);
+                    }
+                    (name, None)
+                };
+
+                if name.is_empty() {
+                    text.push('$');
+                    continue;
+                }
+                if !text.is_empty() {
+                    segments.push(Segment::Text(std::mem::take(&mut text)));
+                }
+                segments.push(Segment::Var { name, default });
+            }
+            c => text.push(c),
+        }
+    }
+
+    if !text.is_empty() {
+        segments.push(Segment::Text(text));
+    }
+
+    Some(segments)
+}
+
+/// Resolves the references of the merged variables. References to unknown variables resolve to an empty
+/// string, or to the default if one is given.
+struct Interpolator<'a> {
+    vars: &'a HashMap<String, Vec<Segment>>,
+    resolved: HashMap<String, String>,
+    visiting: HashSet<&'a str>,
+}
+
+impl<'a> Interpolator<'a> {
+    fn resolve(&mut self, key: &str) -> Result<Option<String>, EnvError> {
+        if let Ok(value) = env::var(key) {
+            if self.vars.contains_key(key) {
+                self.resolved.insert(key.to_string(), value.clone());
+            }
+            return Ok(Some(value));
+        }
+
+        if let Some(value) = self.resolved.get(key) {
+            return Ok(Some(value.clone()));
+        }
+
+        let Some((key, segments)) = self.vars.get_key_value(key) else {
+            return Ok(None);
+        };
+
+        if !self.visiting.insert(key.as_str()) {
+            return Err(EnvError::Cycle(key.clone()));
+        }
+
+        let mut value = String::new();
+        for segment in segments {
+            match segment {
+                Segment::Text(text) => value.push_str(text),
+                Segment::Var { name, default } => match (self.resolve(name)?, default) {
+                    (Some(resolved), _) if !resolved.is_empty() => value.push_str(&resolved),
+                    (_, Some(default)) => value.push_str(default),
+                    (resolved, None) => value.push_str(&resolved.unwrap_or_default()),
+                },
+            }
+        }
+
+        self.visiting.remove(key.as_str());
+        self.resolved.insert(key.clone(), value.clone());
+        Ok(Some(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typed() {
+        set("HXTC_ENV_PORT", " 8080 ");
+        set("HXTC_ENV_DEBUG", "Yes");
+        set("HXTC_ENV_TIMEOUT", "1m30s");
+        set("HXTC_ENV_BODY", "10MiB");
+        set("HXTC_ENV_HOSTS", "a.com, b.com,");
+
+        assert_eq!(get_parsed::<u16>("HXTC_ENV_PORT").unwrap(), 8080);
+        assert!(get_bool("HXTC_ENV_DEBUG").unwrap());
+        assert_eq!(
+            get_duration("HXTC_ENV_TIMEOUT").unwrap(),
+            Duration::from_secs(90)
+        );
+        assert_eq!(get_bytes("HXTC_ENV_BODY").unwrap(), 10 * 1024 * 1024);
+        assert_eq!(
+            get_list::<String>("HXTC_ENV_HOSTS").unwrap(),
+            ["a.com", "b.com"]
+        );
+
+        assert!(matches!(
+            get_parsed::<u8>("HXTC_ENV_PORT"),
+            Err(EnvError::Parse { expected: "u8", .. })
+        ));
+        assert!(matches!(
+            get_bool("HXTC_ENV_HOSTS"),
+            Err(EnvError::Parse { .. })
+        ));
+        assert!(matches!(
+            get_duration("HXTC_ENV_MISSING"),
+            Err(EnvError::Missing(_))
+        ));
+    }
+
+    #[test]
+    fn units() {
+        assert_eq!(parse_duration("250ms"), Some(Duration::from_millis(250)));
+        assert_eq!(parse_duration("30"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_duration("1d 2h"), Some(Duration::from_secs(93600)));
+        assert_eq!(parse_duration("5x"), None);
+        assert_eq!(parse_duration("s"), None);
+
+        assert_eq!(parse_bytes("512"), Some(512));
+        assert_eq!(parse_bytes("64kb"), Some(64_000));
+        assert_eq!(parse_bytes("1 Gi"), Some(1 << 30));
+        assert_eq!(parse_bytes("1.5MB"), None);
+    }
+
+    #[test]
+    fn required() {
+        set("HXTC_ENV_REQUIRED", "1");
+        let error =
+            require(&["HXTC_ENV_REQUIRED", "HXTC_ENV_NOPE_A", "HXTC_ENV_NOPE_B"]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Missing env variables: HXTC_ENV_NOPE_A, HXTC_ENV_NOPE_B"
+        );
+        assert_eq!(
+            require(&["HXTC_ENV_REQUIRED"]).unwrap()["HXTC_ENV_REQUIRED"],
+            "1"
+        );
+    }
+
+    fn dotenv_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hextacy-env-{name}"));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for (file, contents) in files {
+            std::fs::write(dir.join(file), contents).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn profiles() {
+        let dir = dotenv_dir(
+            "profiles",
+            &[
+                (
+                    ".env",
+                    "# Defaults\n\
+                    HXTC_DOT_HOST=localhost\n\
+                    HXTC_DOT_PORT=5432 # inline comment\n\
+                    export HXTC_DOT_USER=app\n\
+                    HXTC_DOT_URL=postgres://${HXTC_DOT_USER}@$HXTC_DOT_HOST:${HXTC_DOT_PORT}/${HXTC_DOT_DB:-app}\n\
+                    HXTC_DOT_SHELL=overridden\n",
+                ),
+                (".env.local", "HXTC_DOT_HOST=local\nHXTC_DOT_PORT=5433\n"),
+                (".env.production.local", "HXTC_DOT_HOST=replica.prod\n"),
+                (
+                    ".env.production",
+                    "HXTC_DOT_HOST=db.prod\n\
+                    HXTC_DOT_PORT=6432\n\
+                    HXTC_DOT_RAW='${HXTC_DOT_HOST}'\n\
+                    HXTC_DOT_KEY=\"first\n\\$second\"\n",
+                ),
+            ],
+        );
+        set("HXTC_DOT_SHELL", "shell");
+
+        let local = DotenvLoader::new(&dir).read().unwrap();
+        assert_eq!(local["HXTC_DOT_URL"], "postgres://app@local:5433/app");
+
+        DotenvLoader::new(&dir)
+            .with_profile("production")
+            .load()
+            .unwrap();
+        // .env.local overrides .env.production, .env.production.local overrides both
+        assert_eq!(
+            get("HXTC_DOT_URL").unwrap(),
+            "postgres://app@replica.prod:5433/app"
+        );
+        assert_eq!(get("HXTC_DOT_RAW").unwrap(), "${HXTC_DOT_HOST}");
+        assert_eq!(get("HXTC_DOT_KEY").unwrap(), "first\n$second");
+        assert_eq!(get("HXTC_DOT_SHELL").unwrap(), "shell");
+    }
+
+    #[test]
+    fn invalid() {
+        let dir = dotenv_dir(
+            "cycle",
+            &[(".env", "HXTC_CYC_A=$HXTC_CYC_B\nHXTC_CYC_B=${HXTC_CYC_A}\n")],
+        );
+        assert!(matches!(
+            DotenvLoader::new(&dir).read(),
+            Err(EnvError::Cycle(_))
+        ));
+
+        let dir = dotenv_dir(
+            "syntax",
+            &[(".env", "HXTC_SYN_A=1\nHXTC_SYN_B=\"unclosed\n")],
+        );
+        let error = DotenvLoader::new(&dir).read().unwrap_err();
+        assert!(matches!(error, EnvError::Syntax(_, 2)));
+    }
+}
@@ -0,0 +1,490 @@
+//! Health checks for the dependencies of an application, e.g. for Kubernetes liveness and readiness probes.
+//!
+//! The adapters implement [HealthCheck] by pinging their backing service. Register them in [HealthChecks]
+//! as critical, i.e. the application cannot serve requests without them, or non-critical:
+//!
+//! ```ignore
+//! let checks = HealthChecks::new()
+//!     .critical("postgres", state.db.clone())
+//!     .critical("redis", state.cache.clone())
+//!     .non_critical("smtp", state.mailer.clone());
+//!
+//! // GET /health/live
+//! HealthReport::live().into_json_response()
+//!
+//! // GET /health/ready
+//! checks.run().await.into_json_response()
+//! ```
+//!
+//! Reports are serialized following <https://datatracker.ietf.org/doc/html/draft-inadarei-api-health-check>
+//! and responded with the `application/health+json` content type. Each check is reported as a single element
+//! array with its duration as the observed value in milliseconds.
+
+use futures::future::{join_all, BoxFuture};
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+use std::{
+    collections::{btree_map, BTreeMap},
+    fmt::{Debug, Display},
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+
+/// The content type of health reports.
+pub const APPLICATION_HEALTH_JSON: &str = "application/health+json";
+
+#[derive(Debug, Error)]
+#[error("{0}")]
+pub struct HealthError(pub String);
+
+impl HealthError {
+    pub fn new(error: impl Display) -> Self {
+        Self(error.to_string())
+    }
+}
+
+/// Implement on drivers and clients to check whether their backing service is reachable.
+pub trait HealthCheck: Send + Sync {
+    fn check(&self) -> impl Future<Output = Result<(), HealthError>> + Send;
+}
+
+impl<T> HealthCheck for Arc<T>
+where
+    T: HealthCheck,
+{
+    fn check(&self) -> impl Future<Output = Result<(), HealthError>> + Send {
+        T::check(self)
+    }
+}
+
+/// Object safe [HealthCheck] so different checks can be stored together.
+trait DynHealthCheck: Send + Sync {
+    fn check_boxed(&self) -> BoxFuture<'_, Result<(), HealthError>>;
+}
+
+impl<T> DynHealthCheck for T
+where
+    T: HealthCheck,
+{
+    fn check_boxed(&self) -> BoxFuture<'_, Result<(), HealthError>> {
+        Box::pin(self.check())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Pass,
+    /// A non-critical check failed, the application can still serve requests.
+    Warn,
+    Fail,
+}
+
+/// The outcome of a single check. Whether the check is critical is only reflected in its status.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckResult {
+    pub status: Status,
+    pub critical: bool,
+    pub duration_ms: u64,
+    pub output: Option<String>,
+}
+
+impl Serialize for CheckResult {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let len = if self.output.is_some() { 4 } else { 3 };
+        let mut state = serializer.serialize_struct("CheckResult", len)?;
+        state.serialize_field("status", &self.status)?;
+        state.serialize_field("observedValue", &self.duration_ms)?;
+        state.serialize_field("observedUnit", "ms")?;
+        if let Some(ref output) = self.output {
+            state.serialize_field("output", output)?;
+        }
+        state.end()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HealthReport {
+    pub status: Status,
+    #[serde(
+        skip_serializing_if = "BTreeMap::is_empty",
+        serialize_with = "serialize_checks"
+    )]
+    pub checks: BTreeMap<String, CheckResult>,
+}
+
+/// The draft maps every check name to an array of results.
+fn serialize_checks<S: Serializer>(
+    checks: &BTreeMap<String, CheckResult>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(checks.iter().map(|(name, check)| (name, [check])))
+}
+
+impl HealthReport {
+    /// A passing report without checks, for liveness probes which only ensure the process responds.
+    pub fn live() -> Self {
+        Self {
+            status: Status::Pass,
+            checks: BTreeMap::new(),
+        }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.status != Status::Fail
+    }
+
+    /// `200` unless a critical check failed, then `503`.
+    #[cfg(feature = "web")]
+    pub fn status_code(&self) -> http::StatusCode {
+        if self.is_healthy() {
+            http::StatusCode::OK
+        } else {
+            http::StatusCode::SERVICE_UNAVAILABLE
+        }
+    }
+}
+
+#[cfg(feature = "web")]
+impl crate::web::xhttp::response::RestResponse<'_> for HealthReport {
+    fn default_status(&self) -> http::StatusCode {
+        self.status_code()
+    }
+
+    fn default_headers(&self) -> Vec<(http::HeaderName, http::HeaderValue)> {
+        vec![
+            (
+                http::header::CONTENT_TYPE,
+                http::HeaderValue::from_static(APPLICATION_HEALTH_JSON),
+            ),
+            (
+                http::header::CACHE_CONTROL,
+                http::HeaderValue::from_static("no-store"),
+            ),
+        ]
+    }
+}
+
+#[derive(Clone)]
+struct Entry {
+    name: String,
+    critical: bool,
+    timeout: Option<Duration>,
+    check: Arc<dyn DynHealthCheck>,
+}
+
+/// Runs health checks concurrently, each with a timeout.
+#[derive(Clone)]
+pub struct HealthChecks {
+    entries: Vec<Entry>,
+    timeout: Duration,
+}
+
+impl Debug for HealthChecks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HealthChecks")
+            .field(
+                "entries",
+                &self.entries.iter().map(|e| &e.name).collect::<Vec<_>>(),
+            )
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl Default for HealthChecks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HealthChecks {
+    pub fn new() -> Self {
+        Self {
+            entries: vec![],
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// The timeout of checks registered without their own.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// A failing critical check fails the whole report.
+    ///
+    /// # Panics
+    ///
+    /// Like the other registering methods, panics if a check with the same name is already registered.
+    pub fn critical(self, name: impl Into<String>, check: impl HealthCheck + 'static) -> Self {
+        self.add(name.into(), true, None, check)
+    }
+
+    /// A failing non-critical check only degrades the report to a warning.
+    pub fn non_critical(self, name: impl Into<String>, check: impl HealthCheck + 'static) -> Self {
+        self.add(name.into(), false, None, check)
+    }
+
+    /// Registers a check with its own timeout.
+    pub fn with_check(
+        self,
+        name: impl Into<String>,
+        critical: bool,
+        timeout: Duration,
+        check: impl HealthCheck + 'static,
+    ) -> Self {
+        self.add(name.into(), critical, Some(timeout), check)
+    }
+
+    fn add(
+        mut self,
+        name: String,
+        critical: bool,
+        timeout: Option<Duration>,
+        check: impl HealthCheck + 'static,
+    ) -> Self {
+        assert!(
+            self.entries.iter().all(|e| e.name != name),
+            "health check `{name}` is already registered"
+        );
+        self.entries.push(Entry {
+            name,
+            critical,
+            timeout,
+            check: Arc::new(check),
+        });
+        self
+    }
+
+    /// Runs all checks concurrently.
+    pub async fn run(&self) -> HealthReport {
+        self.run_with([]).await
+    }
+
+    /// Runs the registered checks together with checks borrowed for a single run, e.g. from the application state.
+    /// If a name is used more than once, the worst result is reported under it.
+    pub async fn run_with<'a>(
+        &'a self,
+        borrowed: impl IntoIterator<Item = BorrowedCheck<'a>>,
+    ) -> HealthReport {
+        let registered = self.entries.iter().map(|entry| BorrowedCheck {
+            name: &entry.name,
+            critical: entry.critical,
+            timeout: entry.timeout,
+            check: &*entry.check,
+        });
+
+        let results = join_all(registered.chain(borrowed).map(|entry| async move {
+            let start = Instant::now();
+            let timeout = entry.timeout.unwrap_or(self.timeout);
+
+            let result = match tokio::time::timeout(timeout, entry.check.check_boxed()).await {
+                Ok(result) => result,
+                Err(_) => Err(HealthError(format!(
+                    "Timed out after {}ms",
+                    timeout.as_millis()
+                ))),
+            };
+
+            let status = match result {
+                Ok(_) => Status::Pass,
+                Err(_) if entry.critical => Status::Fail,
+                Err(_) => Status::Warn,
+            };
+
+            if let Err(ref e) = result {
+                tracing::warn!("Health check {} failed: {e}", entry.name);
+            }
+
+            let check = CheckResult {
+                status,
+                critical: entry.critical,
+                duration_ms: start.elapsed().as_millis() as u64,
+                output: result.err().map(|e| e.to_string()),
+            };
+
+            (entry.name.to_string(), check)
+        }))
+        .await;
+
+        let status = results
+            .iter()
+            .map(|(_, check)| check.status)
+            .max()
+            .unwrap_or(Status::Pass);
+
+        let mut checks = BTreeMap::new();
+        for (name, check) in results {
+            match checks.entry(name) {
+                btree_map::Entry::Vacant(entry) => {
+                    entry.insert(check);
+                }
+                btree_map::Entry::Occupied(mut entry) => {
+                    tracing::warn!("Health check {} is registered more than once", entry.key());
+                    if check.status > entry.get().status {
+                        entry.insert(check);
+                    }
+                }
+            }
+        }
+
+        HealthReport { status, checks }
+    }
+}
+
+/// A check which is not owned by [HealthChecks], see [HealthChecks::run_with].
+pub struct BorrowedCheck<'a> {
+    name: &'a str,
+    critical: bool,
+    timeout: Option<Duration>,
+    check: &'a dyn DynHealthCheck,
+}
+
+impl<'a> BorrowedCheck<'a> {
+    pub fn new(name: &'a str, critical: bool, check: &'a impl HealthCheck) -> Self {
+        Self {
+            name,
+            critical,
+            timeout: None,
+            check,
+        }
+    }
+}
+
+impl Debug for BorrowedCheck<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BorrowedCheck")
+            .field("name", &self.name)
+            .field("critical", &self.critical)
+            .field("timeout", &self.timeout)
+            .field("check", &"{ ... }")
+            .finish()
+    }
+}
+
+/// Autoref specialization used by `#[derive(State)]` to only check fields implementing [HealthCheck].
+#[doc(hidden)]
+pub mod probe {
+    use super::{BorrowedCheck, HealthCheck};
+
+    pub struct Probe<'a, T>(pub &'a T);
+
+    pub trait Checked<'a> {
+        fn health_check(&self, name: &'a str, critical: bool) -> Option<BorrowedCheck<'a>>;
+    }
+
+    impl<'a, T: HealthCheck> Checked<'a> for Probe<'a, T> {
+        fn health_check(&self, name: &'a str, critical: bool) -> Option<BorrowedCheck<'a>> {
+            Some(BorrowedCheck::new(name, critical, self.0))
+        }
+    }
+
+    pub trait Unchecked<'a> {
+        fn health_check(&self, _name: &'a str, _critical: bool) -> Option<BorrowedCheck<'a>> {
+            None
+        }
+    }
+
+    impl<'a, T> Unchecked<'a> for &Probe<'a, T> {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fixed(Result<(), &'static str>);
+
+    impl HealthCheck for Fixed {
+        async fn check(&self) -> Result<(), HealthError> {
+            self.0.map_err(HealthError::new)
+        }
+    }
+
+    struct Slow;
+
+    impl HealthCheck for Slow {
+        async fn check(&self) -> Result<(), HealthError> {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn aggregate() {
+        let checks = HealthChecks::new()
+            .critical("db", Fixed(Ok(())))
+            .non_critical("smtp", Fixed(Err("refused")));
+
+        let report = checks.run().await;
+        assert_eq!(report.status, Status::Warn);
+        assert!(report.is_healthy());
+        assert_eq!(report.checks["db"].status, Status::Pass);
+        assert_eq!(report.checks["smtp"].output.as_deref(), Some("refused"));
+
+        let checks = checks
+            .clone()
+            .with_check("cache", true, Duration::from_millis(10), Slow);
+        let report = checks.run().await;
+        assert_eq!(report.status, Status::Fail);
+        assert_eq!(
+            report.checks["cache"].output.as_deref(),
+            Some("Timed out after 10ms")
+        );
+
+        assert_eq!(HealthChecks::new().run().await, HealthReport::live());
+
+        let db = Fixed(Err("down"));
+        let report = HealthChecks::new()
+            .non_critical("smtp", Fixed(Ok(())))
+            .run_with([BorrowedCheck::new("db", true, &db)])
+            .await;
+        assert_eq!(report.status, Status::Fail);
+        assert_eq!(report.checks.len(), 2);
+
+        let report = HealthChecks::new()
+            .non_critical("db", Fixed(Ok(())))
+            .run_with([BorrowedCheck::new("db", true, &db)])
+            .await;
+        assert_eq!(report.checks.len(), 1);
+        assert_eq!(report.checks["db"].status, Status::Fail);
+    }
+
+    #[test]
+    #[should_panic(expected = "health check `db` is already registered")]
+    fn duplicate() {
+        let _ = HealthChecks::new()
+            .critical("db", Fixed(Ok(())))
+            .non_critical("db", Fixed(Ok(())));
+    }
+
+    #[cfg(feature = "web")]
+    #[tokio::test]
+    async fn response() {
+        use crate::web::xhttp::response::RestResponse;
+
+        let report = HealthChecks::new()
+            .critical("db", Fixed(Err("down")))
+            .run()
+            .await;
+        let res = report.into_json_response().unwrap();
+        assert_eq!(res.status(), http::StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            res.headers()[http::header::CONTENT_TYPE],
+            APPLICATION_HEALTH_JSON
+        );
+        let json: serde_json::Value = serde_json::from_str(res.body()).unwrap();
+        assert_eq!(json["status"], "fail");
+        let db = &json["checks"]["db"][0];
+        assert_eq!(db["status"], "fail");
+        assert_eq!(db["output"], "down");
+        assert_eq!(db["observedUnit"], "ms");
+        assert!(db["observedValue"].is_u64());
+        assert!(db.get("critical").is_none());
+
+        let res = HealthReport::live().into_json_response().unwrap();
+        assert_eq!(res.status(), http::StatusCode::OK);
+        assert_eq!(res.body(), r#"{"status":"pass"}"#);
+    }
+}
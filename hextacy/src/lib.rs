@@ -1,3 +1,7 @@
+// Allows using the derive macros, which refer to `hextacy::`, in the crate's tests
+#[cfg(test)]
+extern crate self as hextacy;
+
 /// Core traits for implementing on data sources.
 mod driver;
 
@@ -25,12 +29,25 @@ pub mod queue;
 /// Cryptographic utilities
 pub mod crypto;
 
-/// Utilities for loading dotenv and grabbing stuff from the env.
+#[cfg(feature = "config")]
+pub mod config;
+
+#[cfg(feature = "reload")]
+pub mod reload;
+
+pub mod secrets;
+
 pub mod env;
 
-/// A logger that can be set up to use stdout or a file.
 pub mod logger;
 
+pub mod health;
+
+pub mod shutdown;
+
+#[cfg(feature = "pagination")]
+pub mod pagination;
+
 /// Utilities for time related stuff.
 pub mod time;
 
@@ -40,7 +57,7 @@ pub mod time;
 pub mod web;
 
 #[cfg(feature = "web")]
-pub use hextacy_macros::RestResponse;
+pub use hextacy_macros::{IntoProblem, RestResponse};
 
 /// Quality of life macros.
 pub use hextacy_macros::{component, contract, Constructor, State};
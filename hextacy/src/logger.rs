@@ -1,3 +1,25 @@
+//! Logging for hextacy services.
+//!
+//! A [Logger] installs a `tracing` subscriber which writes every event with the fields of the spans it occurred
+//! in, so the crate's own events and the application's end up in the same place. Records emitted with the `log`
+//! crate are forwarded to it as well.
+//!
+//! ```ignore
+//! Logger::new()
+//!     .with_format(Format::Json)
+//!     .with_filter("info,hextacy=debug,sqlx=warn")
+//!     .init()?;
+//! ```
+//!
+//! With [Format::Json] every line is a JSON object, which log aggregators can parse:
+//!
+//! ```json
+//! {"timestamp":"...","level":"INFO","target":"app::auth","message":"Logged in","user_id":42,"span":{"name":"request","request_id":"a1b2"},"spans":[...]}
+//! ```
+//!
+//! [init] and [init_file] configure the `log` crate directly and are kept for applications that do not use
+//! `tracing`.
+
 use env_logger::fmt::Color;
 use log::{Level, LevelFilter};
 use log4rs::{
@@ -6,7 +28,14 @@ use log4rs::{
     encode::pattern::PatternEncoder,
     Config,
 };
-use std::{env, io::Write};
+use std::{env, fs::OpenOptions, io::Write, path::PathBuf, str::FromStr, sync::Mutex};
+use thiserror::Error;
+use tracing_subscriber::{
+    filter::ParseError,
+    fmt::{self, writer::BoxMakeWriter},
+    layer::SubscriberExt,
+    EnvFilter, Layer, Registry,
+};
 
 /// Errors and warns are always logged.
 pub fn init(level: &str) {
@@ -69,3 +98,251 @@ pub fn init_file(level: &str, path: &str) {
 
     log4rs::init_config(config).expect("Couldn't load log4rs");
 }
+
+#[derive(Debug, Error)]
+pub enum LoggerError {
+    #[error("Invalid filter directives: {0}")]
+    Filter(#[from] ParseError),
+    #[error("Could not open log file {0}: {1}")]
+    File(PathBuf, std::io::Error),
+    #[error("Unknown log format: {0}")]
+    Format(String),
+    #[error("Could not install logger: {0}")]
+    Init(String),
+}
+
+/// How each line of the [Logger] is formatted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    /// Multi line, human readable output for development.
+    Pretty,
+    /// Single line text output.
+    #[default]
+    Compact,
+    /// A JSON object per line, with the event's fields at the top level.
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = LoggerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "pretty" => Ok(Self::Pretty),
+            "compact" => Ok(Self::Compact),
+            "json" => Ok(Self::Json),
+            _ => Err(LoggerError::Format(s.to_string())),
+        }
+    }
+}
+
+/// Builds and installs the global `tracing` subscriber.
+#[derive(Debug, Clone)]
+pub struct Logger {
+    format: Format,
+    directives: Vec<String>,
+    file: Option<PathBuf>,
+    log_bridge: bool,
+}
+
+impl Default for Logger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Logger {
+    /// Creates a compact logger for the `info` level which writes to stdout.
+    pub fn new() -> Self {
+        Self {
+            format: Format::default(),
+            directives: vec!["info".to_string()],
+            file: None,
+            log_bridge: true,
+        }
+    }
+
+    /// Configures the logger from the env:
+    ///
+    /// - `LOG_FORMAT` sets the format, `pretty`, `compact` or `json`
+    /// - `RUST_LOG` sets the filter directives
+    /// - `LOG_FILE` makes it write to the file instead of stdout
+    pub fn from_env() -> Result<Self, LoggerError> {
+        let mut logger = Self::new();
+        if let Ok(format) = env::var("LOG_FORMAT") {
+            logger = logger.with_format(format.parse()?);
+        }
+        if let Ok(directives) = env::var("RUST_LOG") {
+            logger = logger.with_filter(&directives);
+        }
+        if let Ok(path) = env::var("LOG_FILE") {
+            logger = logger.with_file(path);
+        }
+        Ok(logger)
+    }
+
+    pub fn with_format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Replaces the filter with comma separated directives in the `RUST_LOG` syntax, e.g.
+    /// `info,hextacy=debug,sqlx=warn`.
+    pub fn with_filter(mut self, directives: &str) -> Self {
+        self.directives = vec![directives.to_string()];
+        self
+    }
+
+    /// Adds a directive for a single target, e.g. `with_target("sqlx", Level::WARN)`. Targets match themselves
+    /// and their submodules.
+    pub fn with_target(mut self, target: &str, level: tracing::Level) -> Self {
+        self.directives.push(format!("{target}={level}"));
+        self
+    }
+
+    /// Appends the lines to the file instead of writing them to stdout.
+    pub fn with_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.file = Some(path.into());
+        self
+    }
+
+    /// Does not forward records of the `log` crate, e.g. when another logger is already installed for it.
+    pub fn without_log_bridge(mut self) -> Self {
+        self.log_bridge = false;
+        self
+    }
+
+    /// Installs the logger. Fails if the directives are invalid, the file cannot be opened, or a subscriber was
+    /// already installed. If another logger is already installed for the `log` crate, its records are not
+    /// forwarded and a warning is logged instead of failing, since the subscriber is already in place.
+    pub fn init(self) -> Result<(), LoggerError> {
+        let (writer, ansi) = match self.file {
+            Some(ref path) => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|e| LoggerError::File(path.clone(), e))?;
+                (BoxMakeWriter::new(Mutex::new(file)), false)
+            }
+            None => (BoxMakeWriter::new(std::io::stdout), true),
+        };
+
+        let subscriber = self.subscriber(writer, ansi)?;
+        tracing::subscriber::set_global_default(subscriber)
+            .map_err(|e| LoggerError::Init(e.to_string()))?;
+
+        if self.log_bridge {
+            if let Err(e) = tracing_log::LogTracer::init() {
+                tracing::warn!("Records of the `log` crate are not forwarded: {e}");
+            }
+        }
+
+        Ok(())
+    }
+
+    fn subscriber(
+        &self,
+        writer: BoxMakeWriter,
+        ansi: bool,
+    ) -> Result<impl tracing::Subscriber + Send + Sync, LoggerError> {
+        let filter = EnvFilter::try_new(self.directives.join(","))?;
+
+        let layer = fmt::layer().with_writer(writer).with_ansi(ansi);
+        let layer = match self.format {
+            Format::Pretty => layer.pretty().boxed(),
+            Format::Compact => layer.compact().boxed(),
+            Format::Json => layer
+                .json()
+                .flatten_event(true)
+                .with_current_span(true)
+                .with_span_list(true)
+                .boxed(),
+        };
+
+        Ok(Registry::default().with(layer.with_filter(filter)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use std::sync::Arc;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Buffer {
+        fn lines(&self) -> Vec<Value> {
+            let buf = self.0.lock().unwrap();
+            String::from_utf8_lossy(&buf)
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        }
+    }
+
+    #[test]
+    fn json() {
+        let buffer = Buffer::default();
+        let b = buffer.clone();
+        let subscriber = Logger::new()
+            .with_format(Format::Json)
+            .with_target("hxtc_noisy", tracing::Level::WARN)
+            .subscriber(BoxMakeWriter::new(move || b.clone()), false)
+            .unwrap();
+
+        // Installed once per process, another test may have done so already
+        let _ = tracing_log::LogTracer::init();
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("request", request_id = "a1b2");
+            let _guard = span.enter();
+            tracing::info!(user_id = 42, "Logged in");
+            tracing::info!(target: "hxtc_noisy::pool", "Filtered out");
+            tracing::warn!(target: "hxtc_noisy", "Kept");
+            tracing::debug!("Below the level");
+            log::warn!(target: "legacy", "From log");
+        });
+
+        let lines = buffer.lines();
+        assert_eq!(lines.len(), 3);
+
+        assert_eq!(lines[0]["level"], "INFO");
+        assert_eq!(lines[0]["message"], "Logged in");
+        assert_eq!(lines[0]["user_id"], 42);
+        assert_eq!(lines[0]["span"]["name"], "request");
+        assert_eq!(lines[0]["span"]["request_id"], "a1b2");
+        assert_eq!(lines[0]["spans"][0]["request_id"], "a1b2");
+
+        assert_eq!(lines[1]["target"], "hxtc_noisy");
+
+        assert_eq!(lines[2]["message"], "From log");
+        assert_eq!(lines[2]["span"]["request_id"], "a1b2");
+    }
+
+    #[test]
+    fn options() {
+        assert_eq!("JSON".parse::<Format>().unwrap(), Format::Json);
+        assert!(matches!(
+            "xml".parse::<Format>(),
+            Err(LoggerError::Format(_))
+        ));
+
+        let invalid = Logger::new()
+            .with_filter("info,hextacy=loud")
+            .subscriber(BoxMakeWriter::new(std::io::sink), false);
+        assert!(matches!(invalid, Err(LoggerError::Filter(_))));
+    }
+}
@@ -0,0 +1,578 @@
+//! Offset and keyset pagination shared between repositories and HTTP responses.
+//!
+//! Requests carry either [OffsetParams] (`?page=2&per_page=20`) or [CursorParams] (`?cursor=..&limit=20`),
+//! both of which can be deserialized directly from the query string. Repositories fetch the items and
+//! wrap them in a [Page], which serializes to the response body and produces the `Link` header.
+//!
+//! Keyset cursors are opaque to clients and signed with HMAC-SHA256 by a [CursorSigner] so they cannot be
+//! forged to walk the table by arbitrary keys.
+//!
+//! ```ignore
+//! let keyset = params.resolve::<i64>(&signer, 20, 100)?;
+//! let items = users::Entity::find().keyset(users::Column::Id, &keyset).all(&db).await?;
+//! let page = Page::keyset(items, &keyset, &signer, |user| user.id)?;
+//! ```
+
+#[cfg(any(
+    feature = "db-postgres-diesel",
+    feature = "db-mysql-diesel",
+    feature = "db-sqlite-diesel"
+))]
+pub mod diesel;
+
+#[cfg(any(
+    feature = "db-postgres-seaorm",
+    feature = "db-mysql-seaorm",
+    feature = "db-sqlite-seaorm"
+))]
+pub mod seaorm;
+
+use data_encoding::BASE64URL_NOPAD;
+use hmac::{Hmac, Mac};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::Sha256;
+use std::fmt::Debug;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum PaginationError {
+    #[error("Invalid cursor")]
+    InvalidCursor,
+    #[error("Invalid cursor signature")]
+    Signature,
+    #[error("Serde: {0}")]
+    Serde(#[from] serde_json::Error),
+}
+
+/// Page based pagination parameters. Pages start at 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OffsetParams {
+    #[serde(default = "default_page")]
+    pub page: u64,
+    #[serde(default = "default_per_page")]
+    pub per_page: u64,
+}
+
+fn default_page() -> u64 {
+    1
+}
+
+fn default_per_page() -> u64 {
+    20
+}
+
+impl Default for OffsetParams {
+    fn default() -> Self {
+        Self {
+            page: default_page(),
+            per_page: default_per_page(),
+        }
+    }
+}
+
+impl OffsetParams {
+    pub fn new(page: u64, per_page: u64) -> Self {
+        Self { page, per_page }.clamp(u64::MAX)
+    }
+
+    /// Caps `per_page` to `max` and ensures neither parameter is 0.
+    pub fn clamp(mut self, max: u64) -> Self {
+        self.page = self.page.max(1);
+        self.per_page = self.per_page.clamp(1, max.max(1));
+        self
+    }
+
+    /// The amount of rows to skip.
+    pub fn offset(&self) -> u64 {
+        self.page.saturating_sub(1).saturating_mul(self.per_page)
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.per_page
+    }
+}
+
+/// Keyset pagination parameters as received from the client.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CursorParams {
+    pub cursor: Option<String>,
+    pub limit: Option<u64>,
+}
+
+impl CursorParams {
+    /// Verifies and decodes the cursor. The limit defaults to `default` and is capped to `max`.
+    pub fn resolve<K>(
+        &self,
+        signer: &CursorSigner,
+        default: u64,
+        max: u64,
+    ) -> Result<Keyset<K>, PaginationError>
+    where
+        K: DeserializeOwned,
+    {
+        let cursor = self
+            .cursor
+            .as_deref()
+            .filter(|c| !c.is_empty())
+            .map(|c| signer.decode(c))
+            .transpose()?;
+
+        Ok(Keyset {
+            cursor,
+            limit: self.limit.unwrap_or(default).clamp(1, max.max(1)),
+        })
+    }
+}
+
+/// The direction in which a keyset cursor walks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    #[serde(rename = "n")]
+    Next,
+    #[serde(rename = "p")]
+    Prev,
+}
+
+/// A decoded keyset cursor, pointing at the last item seen in its direction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cursor<K> {
+    #[serde(rename = "k")]
+    pub key: K,
+    #[serde(rename = "d")]
+    pub direction: Direction,
+}
+
+/// Resolved keyset pagination parameters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keyset<K> {
+    /// `None` for the first page.
+    pub cursor: Option<Cursor<K>>,
+    pub limit: u64,
+}
+
+impl<K> Keyset<K> {
+    pub fn first(limit: u64) -> Self {
+        Self {
+            cursor: None,
+            limit,
+        }
+    }
+
+    /// The amount of rows to fetch. One more than the limit to know whether there is another page.
+    pub fn fetch_limit(&self) -> u64 {
+        self.limit.saturating_add(1)
+    }
+
+    /// Whether the rows are fetched in descending order of the key and must be reversed.
+    pub fn is_backwards(&self) -> bool {
+        self.cursor
+            .as_ref()
+            .is_some_and(|c| c.direction == Direction::Prev)
+    }
+}
+
+/// Signs and verifies keyset cursors. Cursors have the form `<base64url(json)>.<base64url(signature)>`.
+#[derive(Clone)]
+pub struct CursorSigner {
+    secret: Vec<u8>,
+}
+
+impl Debug for CursorSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CursorSigner")
+            .field("secret", &"{ ... }")
+            .finish()
+    }
+}
+
+impl CursorSigner {
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            secret: secret.into(),
+        }
+    }
+
+    pub fn encode<K: Serialize>(&self, cursor: &Cursor<K>) -> Result<String, PaginationError> {
+        let body = BASE64URL_NOPAD.encode(&serde_json::to_vec(cursor)?);
+        let signature = BASE64URL_NOPAD.encode(&self.mac(body.as_bytes()).finalize().into_bytes());
+        Ok(format!("{body}.{signature}"))
+    }
+
+    pub fn decode<K: DeserializeOwned>(&self, cursor: &str) -> Result<Cursor<K>, PaginationError> {
+        let (body, signature) = cursor
+            .split_once('.')
+            .ok_or(PaginationError::InvalidCursor)?;
+
+        let signature = BASE64URL_NOPAD
+            .decode(signature.as_bytes())
+            .map_err(|_| PaginationError::InvalidCursor)?;
+
+        self.mac(body.as_bytes())
+            .verify_slice(&signature)
+            .map_err(|_| PaginationError::Signature)?;
+
+        let body = BASE64URL_NOPAD
+            .decode(body.as_bytes())
+            .map_err(|_| PaginationError::InvalidCursor)?;
+
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    fn mac(&self, data: &[u8]) -> Hmac<Sha256> {
+        // HMAC accepts keys of any length
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).expect("any key length");
+        mac.update(data);
+        mac
+    }
+}
+
+/// A page of items and the information needed to navigate to the other pages.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    #[serde(flatten)]
+    pub meta: PageMeta,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum PageMeta {
+    Offset {
+        page: u64,
+        per_page: u64,
+        /// Known only if the rows were counted.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        total: Option<u64>,
+    },
+    Keyset {
+        limit: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        next_cursor: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        prev_cursor: Option<String>,
+    },
+}
+
+impl<T> Page<T> {
+    pub fn offset(items: Vec<T>, params: &OffsetParams, total: Option<u64>) -> Self {
+        Self {
+            items,
+            meta: PageMeta::Offset {
+                page: params.page,
+                per_page: params.per_page,
+                total,
+            },
+        }
+    }
+
+    /// Creates a page from rows fetched with [Keyset::fetch_limit], in the order of the keyset.
+    /// The extra row, if any, is dropped and backwards pages are reversed to ascending order.
+    pub fn keyset<K, F>(
+        mut items: Vec<T>,
+        keyset: &Keyset<K>,
+        signer: &CursorSigner,
+        key: F,
+    ) -> Result<Self, PaginationError>
+    where
+        K: Serialize,
+        F: Fn(&T) -> K,
+    {
+        let limit = usize::try_from(keyset.limit).unwrap_or(usize::MAX);
+        let has_more = items.len() > limit;
+        items.truncate(limit);
+
+        let backwards = keyset.is_backwards();
+        if backwards {
+            items.reverse();
+        }
+
+        // Going forwards there is a previous page if we came from a cursor,
+        // going backwards there is always a next page, the one we came from
+        let (has_next, has_prev) = if backwards {
+            (true, has_more)
+        } else {
+            (has_more, keyset.cursor.is_some())
+        };
+
+        let cursor = |item: Option<&T>, direction| {
+            item.map(|item| {
+                signer.encode(&Cursor {
+                    key: key(item),
+                    direction,
+                })
+            })
+            .transpose()
+        };
+
+        let next_cursor = if has_next {
+            cursor(items.last(), Direction::Next)?
+        } else {
+            None
+        };
+
+        let prev_cursor = if has_prev {
+            cursor(items.first(), Direction::Prev)?
+        } else {
+            None
+        };
+
+        Ok(Self {
+            items,
+            meta: PageMeta::Keyset {
+                limit: keyset.limit,
+                next_cursor,
+                prev_cursor,
+            },
+        })
+    }
+
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            meta: self.meta,
+        }
+    }
+
+    /// The query parameters of the related pages, keyed by their link relation, i.e.
+    /// `first`, `prev`, `next` and `last`.
+    pub fn relations(&self) -> Vec<(&'static str, Vec<(&'static str, String)>)> {
+        let mut rels = vec![];
+
+        match self.meta {
+            PageMeta::Offset {
+                page,
+                per_page,
+                total,
+            } => {
+                let params = |page: u64| {
+                    vec![
+                        ("page", page.to_string()),
+                        ("per_page", per_page.to_string()),
+                    ]
+                };
+                let last = total.map(|total| total.div_ceil(per_page.max(1)).max(1));
+
+                rels.push(("first", params(1)));
+                if page > 1 {
+                    rels.push(("prev", params(page - 1)));
+                }
+                match last {
+                    Some(last) if page < last => rels.push(("next", params(page + 1))),
+                    // Without a total assume there is more if the page is full
+                    None if self.items.len() as u64 >= per_page => {
+                        rels.push(("next", params(page + 1)))
+                    }
+                    _ => {}
+                }
+                if let Some(last) = last {
+                    rels.push(("last", params(last)));
+                }
+            }
+            PageMeta::Keyset {
+                limit,
+                ref next_cursor,
+                ref prev_cursor,
+            } => {
+                let limit = limit.to_string();
+                rels.push(("first", vec![("limit", limit.clone())]));
+                if let Some(cursor) = prev_cursor {
+                    rels.push((
+                        "prev",
+                        vec![("cursor", cursor.clone()), ("limit", limit.clone())],
+                    ));
+                }
+                if let Some(cursor) = next_cursor {
+                    rels.push(("next", vec![("cursor", cursor.clone()), ("limit", limit)]));
+                }
+            }
+        }
+
+        rels
+    }
+
+    /// Creates the [RFC 8288](https://www.rfc-editor.org/rfc/rfc8288) `Link` header value for the page.
+    /// The links are relative to the request URI, keeping its query parameters other than the pagination ones.
+    #[cfg(feature = "web")]
+    pub fn link_header(&self, uri: &http::Uri) -> Option<String> {
+        let rels = self.relations();
+        if rels.is_empty() {
+            return None;
+        }
+
+        let managed = ["page", "per_page", "cursor", "limit"];
+        let base = uri
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .filter(|pair| {
+                let key = pair.split_once('=').map_or(*pair, |(key, _)| key);
+                !managed.contains(&key)
+            })
+            .collect::<Vec<_>>();
+
+        let prefix = match (uri.scheme_str(), uri.authority()) {
+            (Some(scheme), Some(authority)) => format!("{scheme}://{authority}{}", uri.path()),
+            _ => uri.path().to_string(),
+        };
+
+        let links = rels
+            .into_iter()
+            .map(|(rel, params)| {
+                let query = base
+                    .iter()
+                    .map(|pair| pair.to_string())
+                    .chain(params.into_iter().map(|(k, v)| format!("{k}={v}")))
+                    .collect::<Vec<_>>()
+                    .join("&");
+                format!("<{prefix}?{query}>; rel=\"{rel}\"")
+            })
+            .collect::<Vec<_>>();
+
+        Some(links.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offset() {
+        let params = OffsetParams::new(3, 10);
+        assert_eq!(params.offset(), 20);
+        assert_eq!(params.limit(), 10);
+        assert_eq!(OffsetParams::new(0, 0), OffsetParams::new(1, 1));
+        assert_eq!(OffsetParams::new(1, 500).clamp(100).per_page, 100);
+
+        let params: OffsetParams = serde_json::from_str("{}").unwrap();
+        assert_eq!(params, OffsetParams::default());
+
+        let page = Page::offset(vec![1, 2], &OffsetParams::new(2, 2), Some(5));
+        let rels = page.relations();
+        let names = rels.iter().map(|(rel, _)| *rel).collect::<Vec<_>>();
+        assert_eq!(names, ["first", "prev", "next", "last"]);
+        assert_eq!(rels[3].1[0], ("page", "3".to_string()));
+
+        let json = serde_json::to_value(&page).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({ "items": [1, 2], "page": 2, "per_page": 2, "total": 5 })
+        );
+
+        let page = Page::offset(vec![1], &OffsetParams::new(1, 2), None);
+        let names = page
+            .relations()
+            .into_iter()
+            .map(|(rel, _)| rel)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["first"]);
+    }
+
+    #[test]
+    fn cursor_signing() {
+        let signer = CursorSigner::new("secret");
+        let cursor = Cursor {
+            key: 42_i64,
+            direction: Direction::Next,
+        };
+
+        let encoded = signer.encode(&cursor).unwrap();
+        assert_eq!(signer.decode::<i64>(&encoded).unwrap(), cursor);
+
+        let forged = CursorSigner::new("other").encode(&cursor).unwrap();
+        assert!(matches!(
+            signer.decode::<i64>(&forged),
+            Err(PaginationError::Signature)
+        ));
+        assert!(matches!(
+            signer.decode::<i64>("garbage"),
+            Err(PaginationError::InvalidCursor)
+        ));
+
+        let params = CursorParams {
+            cursor: Some(encoded),
+            limit: Some(1000),
+        };
+        let keyset = params.resolve::<i64>(&signer, 20, 100).unwrap();
+        assert_eq!(keyset.cursor, Some(cursor));
+        assert_eq!(keyset.limit, 100);
+    }
+
+    #[test]
+    fn keyset() {
+        let signer = CursorSigner::new("secret");
+
+        // First page, one extra row fetched
+        let keyset = Keyset::<i64>::first(2);
+        let page = Page::keyset(vec![1, 2, 3], &keyset, &signer, |i| *i).unwrap();
+        assert_eq!(page.items, [1, 2]);
+        let PageMeta::Keyset {
+            next_cursor: Some(ref next),
+            prev_cursor: None,
+            ..
+        } = page.meta
+        else {
+            panic!("expected only a next cursor");
+        };
+
+        // Next page, rows fetched ascending after 2
+        let keyset = CursorParams {
+            cursor: Some(next.clone()),
+            limit: Some(2),
+        }
+        .resolve::<i64>(&signer, 2, 2)
+        .unwrap();
+        assert_eq!(keyset.cursor.as_ref().unwrap().key, 2);
+        let page = Page::keyset(vec![3, 4], &keyset, &signer, |i| *i).unwrap();
+        let PageMeta::Keyset {
+            next_cursor: None,
+            prev_cursor: Some(ref prev),
+            ..
+        } = page.meta
+        else {
+            panic!("expected only a prev cursor");
+        };
+
+        // Previous page, rows fetched descending before 3
+        let keyset = signer.decode::<i64>(prev).unwrap();
+        assert_eq!(keyset.key, 3);
+        assert_eq!(keyset.direction, Direction::Prev);
+        let keyset = Keyset {
+            cursor: Some(keyset),
+            limit: 2,
+        };
+        let page = Page::keyset(vec![2, 1], &keyset, &signer, |i| *i).unwrap();
+        assert_eq!(page.items, [1, 2]);
+        let PageMeta::Keyset {
+            next_cursor: Some(_),
+            prev_cursor: None,
+            ..
+        } = page.meta
+        else {
+            panic!("expected only a next cursor");
+        };
+    }
+
+    #[cfg(feature = "web")]
+    #[test]
+    fn link_header() {
+        let page = Page::offset(vec![1, 2], &OffsetParams::new(2, 2), Some(6));
+        let uri: http::Uri = "/users?sort=name&page=2&per_page=2".parse().unwrap();
+        assert_eq!(
+            page.link_header(&uri).unwrap(),
+            "</users?sort=name&page=1&per_page=2>; rel=\"first\", \
+             </users?sort=name&page=1&per_page=2>; rel=\"prev\", \
+             </users?sort=name&page=3&per_page=2>; rel=\"next\", \
+             </users?sort=name&page=3&per_page=2>; rel=\"last\""
+        );
+
+        let uri: http::Uri = "https://api.example.com/users".parse().unwrap();
+        let page = Page::offset(Vec::<u8>::new(), &OffsetParams::default(), Some(0));
+        assert_eq!(
+            page.link_header(&uri).unwrap(),
+            "<https://api.example.com/users?page=1&per_page=20>; rel=\"first\", \
+             <https://api.example.com/users?page=1&per_page=20>; rel=\"last\""
+        );
+    }
+}
@@ -0,0 +1,126 @@
+//! Applies pagination parameters to Diesel queries.
+
+use super::{Keyset, OffsetParams};
+use diesel::{
+    dsl,
+    expression::AsExpression,
+    query_dsl::methods::{FilterDsl, LimitDsl, OffsetDsl, OrderDsl},
+    sql_types::SqlType,
+    ExpressionMethods,
+};
+
+/// Applies the offset and limit of the page.
+pub trait OffsetPaginate: OffsetDsl + Sized
+where
+    dsl::Offset<Self>: LimitDsl,
+{
+    fn offset_page(self, params: &OffsetParams) -> dsl::Limit<dsl::Offset<Self>> {
+        let offset = i64::try_from(params.offset()).unwrap_or(i64::MAX);
+        let limit = i64::try_from(params.limit()).unwrap_or(i64::MAX);
+        self.offset(offset).limit(limit)
+    }
+}
+
+impl<Q> OffsetPaginate for Q
+where
+    Q: OffsetDsl,
+    dsl::Offset<Q>: LimitDsl,
+{
+}
+
+/// Filters the rows after (or before, when going backwards) the cursor, orders them by the column
+/// and limits them to [Keyset::fetch_limit]. The column must be unique, e.g. the primary key.
+///
+/// Since the filter and order depend on the cursor, this is available on boxed queries, i.e.
+/// those created with `into_boxed()`.
+pub trait KeysetPaginate<C, K>: Sized
+where
+    C: ExpressionMethods + Clone,
+    C::SqlType: SqlType,
+    K: AsExpression<C::SqlType> + Clone,
+    Self: FilterDsl<dsl::Gt<C, K>, Output = Self>
+        + FilterDsl<dsl::Lt<C, K>, Output = Self>
+        + OrderDsl<dsl::Asc<C>, Output = Self>
+        + OrderDsl<dsl::Desc<C>, Output = Self>
+        + LimitDsl<Output = Self>,
+{
+    fn keyset(self, column: C, keyset: &Keyset<K>) -> Self {
+        let query = match keyset.cursor {
+            Some(ref cursor) if keyset.is_backwards() => {
+                let query = FilterDsl::filter(self, column.clone().lt(cursor.key.clone()));
+                OrderDsl::order(query, column.desc())
+            }
+            Some(ref cursor) => {
+                let query = FilterDsl::filter(self, column.clone().gt(cursor.key.clone()));
+                OrderDsl::order(query, column.asc())
+            }
+            None => OrderDsl::order(self, column.asc()),
+        };
+        let limit = i64::try_from(keyset.fetch_limit()).unwrap_or(i64::MAX);
+        LimitDsl::limit(query, limit)
+    }
+}
+
+impl<Q, C, K> KeysetPaginate<C, K> for Q
+where
+    C: ExpressionMethods + Clone,
+    C::SqlType: SqlType,
+    K: AsExpression<C::SqlType> + Clone,
+    Q: FilterDsl<dsl::Gt<C, K>, Output = Q>
+        + FilterDsl<dsl::Lt<C, K>, Output = Q>
+        + OrderDsl<dsl::Asc<C>, Output = Q>
+        + OrderDsl<dsl::Desc<C>, Output = Q>
+        + LimitDsl<Output = Q>,
+{
+}
+
+#[cfg(all(test, feature = "db-postgres-diesel"))]
+mod tests {
+    use super::*;
+    use crate::pagination::{Cursor, Direction};
+    use diesel::{debug_query, pg::Pg, QueryDsl};
+
+    diesel::table! {
+        users (id) {
+            id -> BigInt,
+            name -> Text,
+        }
+    }
+
+    #[test]
+    fn offset_page() {
+        let query = users::table.offset_page(&OffsetParams::new(3, 10));
+        assert_eq!(
+            debug_query::<Pg, _>(&query).to_string(),
+            r#"SELECT "users"."id", "users"."name" FROM "users" LIMIT $1 OFFSET $2 -- binds: [10, 20]"#
+        );
+    }
+
+    #[test]
+    fn keyset_pages() {
+        let sql = |direction: Option<Direction>| {
+            let keyset = Keyset {
+                cursor: direction.map(|direction| Cursor {
+                    key: 5_i64,
+                    direction,
+                }),
+                limit: 2,
+            };
+            let query = users::table.into_boxed::<Pg>().keyset(users::id, &keyset);
+            debug_query::<Pg, _>(&query).to_string()
+        };
+
+        assert_eq!(
+            sql(None),
+            r#"SELECT "users"."id", "users"."name" FROM "users" ORDER BY "users"."id" ASC LIMIT $1 -- binds: [3]"#
+        );
+        assert_eq!(
+            sql(Some(Direction::Next)),
+            r#"SELECT "users"."id", "users"."name" FROM "users" WHERE ("users"."id" > $1) ORDER BY "users"."id" ASC LIMIT $2 -- binds: [5, 3]"#
+        );
+        assert_eq!(
+            sql(Some(Direction::Prev)),
+            r#"SELECT "users"."id", "users"."name" FROM "users" WHERE ("users"."id" < $1) ORDER BY "users"."id" DESC LIMIT $2 -- binds: [5, 3]"#
+        );
+    }
+}
@@ -0,0 +1,137 @@
+//! Applies pagination parameters to SeaORM queries.
+
+use super::{Keyset, OffsetParams, Page};
+use sea_orm::{
+    ColumnTrait, ConnectionTrait, DbErr, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder,
+    QuerySelect, Select, Value,
+};
+
+pub trait Paginate<E>: Sized
+where
+    E: EntityTrait,
+{
+    /// Applies the offset and limit of the page.
+    fn offset_page(self, params: &OffsetParams) -> Self;
+
+    /// Filters the rows after (or before, when going backwards) the cursor, orders them by the column
+    /// and limits them to [Keyset::fetch_limit]. The column must be unique, e.g. the primary key.
+    fn keyset<C, K>(self, column: C, keyset: &Keyset<K>) -> Self
+    where
+        C: ColumnTrait,
+        K: Into<Value> + Clone;
+
+    /// Counts the rows and fetches the page.
+    fn fetch_offset_page<C>(
+        self,
+        db: &C,
+        params: &OffsetParams,
+    ) -> impl std::future::Future<Output = Result<Page<E::Model>, DbErr>> + Send
+    where
+        C: ConnectionTrait;
+}
+
+impl<E> Paginate<E> for Select<E>
+where
+    E: EntityTrait,
+    E::Model: Sync,
+{
+    fn offset_page(self, params: &OffsetParams) -> Self {
+        QuerySelect::offset(self, params.offset()).limit(params.limit())
+    }
+
+    fn keyset<C, K>(self, column: C, keyset: &Keyset<K>) -> Self
+    where
+        C: ColumnTrait,
+        K: Into<Value> + Clone,
+    {
+        let query = match keyset.cursor {
+            Some(ref cursor) if keyset.is_backwards() => self
+                .filter(column.lt(cursor.key.clone()))
+                .order_by_desc(column),
+            Some(ref cursor) => self
+                .filter(column.gt(cursor.key.clone()))
+                .order_by_asc(column),
+            None => self.order_by_asc(column),
+        };
+        query.limit(keyset.fetch_limit())
+    }
+
+    async fn fetch_offset_page<C>(
+        self,
+        db: &C,
+        params: &OffsetParams,
+    ) -> Result<Page<E::Model>, DbErr>
+    where
+        C: ConnectionTrait,
+    {
+        let total = self.clone().count(db).await?;
+        let items = self.offset_page(params).all(db).await?;
+        Ok(Page::offset(items, params, Some(total)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pagination::{Cursor, Direction};
+    use sea_orm::{DbBackend, QueryTrait};
+
+    mod users {
+        use sea_orm::entity::prelude::*;
+
+        #[derive(Debug, Clone, PartialEq, Eq, DeriveEntityModel)]
+        #[sea_orm(table_name = "users")]
+        pub struct Model {
+            #[sea_orm(primary_key)]
+            pub id: i64,
+            pub name: String,
+        }
+
+        #[derive(Debug, Clone, Copy, EnumIter, DeriveRelation)]
+        pub enum Relation {}
+
+        impl ActiveModelBehavior for ActiveModel {}
+    }
+
+    fn keyset(direction: Option<Direction>) -> Keyset<i64> {
+        Keyset {
+            cursor: direction.map(|direction| Cursor { key: 5, direction }),
+            limit: 2,
+        }
+    }
+
+    #[test]
+    fn offset_page() {
+        let sql = users::Entity::find()
+            .offset_page(&OffsetParams::new(3, 10))
+            .build(DbBackend::Postgres)
+            .to_string();
+        assert_eq!(
+            sql,
+            r#"SELECT "users"."id", "users"."name" FROM "users" LIMIT 10 OFFSET 20"#
+        );
+    }
+
+    #[test]
+    fn keyset_pages() {
+        let sql = |direction| {
+            users::Entity::find()
+                .keyset(users::Column::Id, &keyset(direction))
+                .build(DbBackend::Postgres)
+                .to_string()
+        };
+
+        assert_eq!(
+            sql(None),
+            r#"SELECT "users"."id", "users"."name" FROM "users" ORDER BY "users"."id" ASC LIMIT 3"#
+        );
+        assert_eq!(
+            sql(Some(Direction::Next)),
+            r#"SELECT "users"."id", "users"."name" FROM "users" WHERE "users"."id" > 5 ORDER BY "users"."id" ASC LIMIT 3"#
+        );
+        assert_eq!(
+            sql(Some(Direction::Prev)),
+            r#"SELECT "users"."id", "users"."name" FROM "users" WHERE "users"."id" < 5 ORDER BY "users"."id" DESC LIMIT 3"#
+        );
+    }
+}
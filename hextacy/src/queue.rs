@@ -4,8 +4,11 @@
 //! The traits are designed to work on enums, meaning you want to implement the [QueueHandler]
 //! with the `M` as an enum.
 
+use crate::shutdown::{Shutdown, ShutdownError};
+use futures::future::{select, Either};
 use serde::Serialize;
 use std::error::Error;
+use std::sync::Mutex;
 use std::{fmt::Display, marker::PhantomData};
 use tokio::sync::oneshot::{self, Receiver, Sender};
 use tracing::{debug, error, warn};
@@ -23,8 +26,14 @@ where
 }
 
 /// Implement on structs that need to publish messages.
+///
+/// The returned future must be `Send` so publishing can happen from spawned tasks,
+/// e.g. when relaying WebSocket broadcasts with `Broker::with_relay`.
 pub trait Producer {
-    fn publish<M>(&self, message: M) -> impl std::future::Future<Output = Result<(), QueueError>>
+    fn publish<M>(
+        &self,
+        message: M,
+    ) -> impl std::future::Future<Output = Result<(), QueueError>> + Send
     where
         M: Serialize + Send + Sync + 'static;
 }
@@ -40,6 +49,12 @@ where
     ///
     /// When this method returns `Ok(None)` it means the consumer stream is closed
     /// and the whole consumer runtime is dropped.
+    ///
+    /// ### Cancel safety
+    ///
+    /// The started runtime races this future against the stop signal and drops it when the consumer is stopped.
+    /// It must therefore not lose messages when dropped before completing, e.g. by only awaiting the next item of
+    /// a stream and processing it without awaiting anything else.
     fn poll_queue(
         &mut self,
     ) -> impl std::future::Future<Output = Result<Option<M>, QueueError>> + Send;
@@ -52,11 +67,37 @@ where
     }
 }
 
+/// A handle for stopping a started consumer through a shared reference, e.g. when it is a field of the application
+/// state. Obtained by converting the sender returned from [Consumer::start].
+#[derive(Debug)]
+pub struct ConsumerHandle(Mutex<Option<Sender<()>>>);
+
+impl ConsumerHandle {
+    /// Signals the consumer to stop. Returns `false` if it was already stopped.
+    pub fn stop(&self) -> bool {
+        let tx = self.0.lock().unwrap_or_else(|e| e.into_inner()).take();
+        tx.is_some_and(|tx| tx.send(()).is_ok())
+    }
+}
+
+impl From<Sender<()>> for ConsumerHandle {
+    fn from(tx: Sender<()>) -> Self {
+        Self(Mutex::new(Some(tx)))
+    }
+}
+
+impl Shutdown for ConsumerHandle {
+    async fn shutdown(&self) -> Result<(), ShutdownError> {
+        self.stop();
+        Ok(())
+    }
+}
+
 /// A runtime for consumers with a stop channel. The sending end is obtained from calling [Consumer::start].
 struct ConsumerRuntime<C, M, H> {
     consumer: C,
     handler: H,
-    rx: Receiver<()>,
+    rx: Option<Receiver<()>>,
     _m: PhantomData<M>,
 }
 
@@ -65,7 +106,7 @@ impl<C, M, H> ConsumerRuntime<C, M, H> {
         Self {
             consumer,
             handler,
-            rx,
+            rx: Some(rx),
             _m: PhantomData,
         }
     }
@@ -78,9 +119,29 @@ where
     M: Send + 'static,
 {
     async fn run(mut self) -> Result<(), QueueError> {
-        let mut logged = false;
         loop {
-            let message: M = match self.consumer.poll_queue().await {
+            // Listen for the stop signal while waiting on the queue so idle consumers can be stopped.
+            // Dropping the poll is fine since consumers must be cancel safe, see `Consumer::poll_queue`
+            let polled = match self.rx.as_mut() {
+                Some(rx) => {
+                    let poll = std::pin::pin!(self.consumer.poll_queue());
+                    match select(rx, poll).await {
+                        Either::Left((Ok(_), _)) => {
+                            debug!("Consumer stopped");
+                            return Ok(());
+                        }
+                        Either::Left((Err(_), poll)) => {
+                            warn!("Consumer handle dropped! The consumer will keep processing messages, but there is no way to shut it down without exiting the process.");
+                            self.rx = None;
+                            poll.await
+                        }
+                        Either::Right((polled, _)) => polled,
+                    }
+                }
+                None => self.consumer.poll_queue().await,
+            };
+
+            let message: M = match polled {
                 Ok(Some(msg)) => msg,
                 Ok(None) => {
                     debug!("Consumer stream ended");
@@ -95,19 +156,6 @@ where
             if let Err(e) = self.handler.handle(message).await {
                 error!("Error occurred while handling message: {e}")
             }
-
-            match self.rx.try_recv() {
-                Ok(_) => return Ok(()),
-                Err(e) => match e {
-                    oneshot::error::TryRecvError::Empty => continue,
-                    oneshot::error::TryRecvError::Closed => {
-                        if !logged {
-                            logged = true;
-                            warn!("Consumer handle dropped! The consumer will keep processing messages, but there is no way to shut it down without exiting the process.")
-                        }
-                    }
-                },
-            }
         }
     }
 }
@@ -132,3 +180,43 @@ impl From<serde_json::Error> for QueueError {
         Self::Serde(value)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    struct Idle;
+
+    impl Consumer<()> for Idle {
+        async fn poll_queue(&mut self) -> Result<Option<()>, QueueError> {
+            futures::future::pending().await
+        }
+    }
+
+    struct Noop;
+
+    impl QueueHandler<()> for Noop {
+        type Error = QueueError;
+
+        async fn handle(&mut self, _: ()) -> Result<(), QueueError> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn stop_idle_consumer() {
+        let (tx, rx) = oneshot::channel();
+        let runtime = tokio::spawn(ConsumerRuntime::new(Idle, Noop, rx).run());
+
+        let handle = ConsumerHandle::from(tx);
+        assert!(handle.stop());
+        assert!(!handle.stop());
+
+        tokio::time::timeout(Duration::from_secs(1), runtime)
+            .await
+            .expect("consumer did not stop")
+            .unwrap()
+            .unwrap();
+    }
+}
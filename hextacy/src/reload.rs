@@ -0,0 +1,307 @@
+//! Changing settings at runtime without restarting the application.
+//!
+//! Fields of `State` annotated with `#[reload]` are held in a [Reloadable] and get a `reload_{field}` method
+//! which runs the field's loaders again and swaps in the new value. The `reload` method reloads all of them.
+//! A [Reloader] calls it when watched files change or the process receives `SIGHUP`:
+//!
+//! ```ignore
+//! #[derive(State)]
+//! struct AppState {
+//!     #[reload]
+//!     #[config("limits.requests" as u32, "limits.window" as u64)]
+//!     limits: Reloadable<RateLimits>,
+//! }
+//!
+//! let state = Arc::new(AppState::load()?);
+//!
+//! let s = state.clone();
+//! let reloader = Reloader::new()
+//!     .with_file("config/base.toml")
+//!     .with_sighup()
+//!     .spawn(move || {
+//!         let s = s.clone();
+//!         async move { s.reload().await }
+//!     })?;
+//!
+//! let mut limits = state.limits.subscribe();
+//! while limits.changed().await.is_ok() {
+//!     info!("New limits: {:?}", limits.borrow());
+//! }
+//! ```
+
+use crate::shutdown::{Shutdown, ShutdownError};
+use std::{
+    fmt::{Debug, Display},
+    future::Future,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+use tokio::sync::{oneshot, watch};
+
+/// Holds a value which can be atomically replaced while it is being read. Readers get an `Arc` of the value at
+/// the time of reading, and subscribers are notified of every new value.
+pub struct Reloadable<T>(watch::Sender<Arc<T>>);
+
+impl<T> Reloadable<T> {
+    pub fn new(value: T) -> Self {
+        Self(watch::Sender::new(Arc::new(value)))
+    }
+
+    /// Returns the current value.
+    pub fn get(&self) -> Arc<T> {
+        self.0.borrow().clone()
+    }
+
+    /// Replaces the value, notifies the subscribers and returns the previous value.
+    pub fn set(&self, value: T) -> Arc<T> {
+        self.0.send_replace(Arc::new(value))
+    }
+
+    /// Returns a receiver which is notified every time the value is replaced.
+    pub fn subscribe(&self) -> watch::Receiver<Arc<T>> {
+        self.0.subscribe()
+    }
+}
+
+impl<T> Debug for Reloadable<T>
+where
+    T: Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Reloadable").field(&self.get()).finish()
+    }
+}
+
+/// The default interval for checking watched files.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Triggers reloads when files change or the process receives `SIGHUP`.
+#[derive(Debug, Clone)]
+pub struct Reloader {
+    files: Vec<PathBuf>,
+    sighup: bool,
+    interval: Duration,
+}
+
+impl Default for Reloader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Reloader {
+    pub fn new() -> Self {
+        Self {
+            files: vec![],
+            sighup: false,
+            interval: POLL_INTERVAL,
+        }
+    }
+
+    /// Reloads when the file is modified, created or removed.
+    pub fn with_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.files.push(path.into());
+        self
+    }
+
+    /// Reloads when the process receives `SIGHUP`. Has no effect on platforms without signals.
+    pub fn with_sighup(mut self) -> Self {
+        self.sighup = true;
+        self
+    }
+
+    /// How often the watched files are checked for changes, 2 seconds by default.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Spawns a task calling `reload` on every trigger and logging its errors. The task runs until the returned
+    /// handle is stopped, dropping the handle does not stop it. Fails if the `SIGHUP` handler cannot be registered.
+    pub fn spawn<F, Fut, E>(self, reload: F) -> std::io::Result<ReloadHandle>
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: Display,
+    {
+        let mut hangup = Hangup::new(self.sighup)?;
+        let (tx, mut rx) = oneshot::channel();
+
+        let mut versions = self.files.iter().map(version).collect::<Vec<_>>();
+        let mut interval = tokio::time::interval(self.interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        tokio::spawn(async move {
+            loop {
+                let trigger = tokio::select! {
+                    Ok(_) = &mut rx => break,
+                    _ = hangup.recv() => "SIGHUP",
+                    _ = interval.tick() => {
+                        let mut changed = false;
+                        for (path, current) in self.files.iter().zip(versions.iter_mut()) {
+                            let new = version(path);
+                            changed |= new != *current;
+                            *current = new;
+                        }
+                        if !changed {
+                            continue;
+                        }
+                        "file change"
+                    }
+                };
+
+                tracing::info!("Reloading after {trigger}");
+                if let Err(e) = reload().await {
+                    tracing::error!("Error while reloading: {e}");
+                }
+            }
+        });
+
+        Ok(ReloadHandle(Mutex::new(Some(tx))))
+    }
+}
+
+/// The modification time and size of the file, or `None` if it does not exist.
+fn version(path: &PathBuf) -> Option<(Option<SystemTime>, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok(), metadata.len()))
+}
+
+/// Waits for `SIGHUP` if enabled, otherwise never completes.
+struct Hangup {
+    #[cfg(unix)]
+    signal: Option<tokio::signal::unix::Signal>,
+}
+
+impl Hangup {
+    #[cfg(unix)]
+    fn new(enabled: bool) -> std::io::Result<Self> {
+        use tokio::signal::unix::{signal, SignalKind};
+        let signal = enabled.then(|| signal(SignalKind::hangup())).transpose()?;
+        Ok(Self { signal })
+    }
+
+    #[cfg(not(unix))]
+    fn new(_enabled: bool) -> std::io::Result<Self> {
+        Ok(Self {})
+    }
+
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        if let Some(ref mut signal) = self.signal {
+            if signal.recv().await.is_some() {
+                return;
+            }
+        }
+        std::future::pending().await
+    }
+}
+
+/// Stops the task spawned by [Reloader::spawn].
+pub struct ReloadHandle(Mutex<Option<oneshot::Sender<()>>>);
+
+impl ReloadHandle {
+    /// Signals the reloader to stop. Returns `false` if it was already stopped.
+    pub fn stop(&self) -> bool {
+        let tx = self.0.lock().unwrap_or_else(|e| e.into_inner()).take();
+        tx.is_some_and(|tx| tx.send(()).is_ok())
+    }
+}
+
+impl Shutdown for ReloadHandle {
+    async fn shutdown(&self) -> Result<(), ShutdownError> {
+        self.stop();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::State;
+
+    #[derive(Debug, PartialEq)]
+    struct Limits {
+        requests: u32,
+    }
+
+    impl Limits {
+        fn new(requests: u32) -> Self {
+            Self { requests }
+        }
+    }
+
+    #[derive(Debug, State)]
+    struct AppState {
+        #[reload]
+        #[env("HXTC_RELOAD_REQUESTS" as u32)]
+        limits: Reloadable<Arc<Limits>>,
+
+        #[env("HXTC_RELOAD_NAME")]
+        #[load_with(String::from)]
+        name: String,
+    }
+
+    #[tokio::test]
+    async fn explicit() {
+        std::env::set_var("HXTC_RELOAD_REQUESTS", "10");
+        std::env::set_var("HXTC_RELOAD_NAME", "app");
+
+        let state = AppState::load().unwrap();
+        let mut limits = state.limits.subscribe();
+        assert_eq!(state.limits.get().requests, 10);
+        assert_eq!(state.name, "app");
+
+        std::env::set_var("HXTC_RELOAD_REQUESTS", "20");
+        state.reload().await.unwrap();
+        assert!(limits.has_changed().unwrap());
+        assert_eq!(limits.borrow_and_update().requests, 20);
+
+        // Failed reloads keep the current value
+        std::env::set_var("HXTC_RELOAD_REQUESTS", "many");
+        let error = state.reload_limits().await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "limits: could not parse env key `HXTC_RELOAD_REQUESTS` as `u32`"
+        );
+        assert!(!limits.has_changed().unwrap());
+        assert_eq!(state.limits.get().requests, 20);
+    }
+
+    #[tokio::test]
+    async fn file_change() {
+        let path = std::env::temp_dir().join("hextacy-reload-file");
+        std::fs::write(&path, "1").unwrap();
+
+        let value = Arc::new(Reloadable::new(String::new()));
+        let mut rx = value.subscribe();
+
+        let v = value.clone();
+        let handle = Reloader::new()
+            .with_file(&path)
+            .with_interval(Duration::from_millis(10))
+            .spawn(move || {
+                let v = v.clone();
+                let path = path.clone();
+                async move {
+                    v.set(std::fs::read_to_string(&path)?);
+                    Ok::<_, std::io::Error>(())
+                }
+            })
+            .unwrap();
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!rx.has_changed().unwrap());
+
+        std::fs::write(std::env::temp_dir().join("hextacy-reload-file"), "22").unwrap();
+        tokio::time::timeout(Duration::from_secs(1), rx.changed())
+            .await
+            .expect("file change was not detected")
+            .unwrap();
+        assert_eq!(**rx.borrow(), "22");
+
+        assert!(handle.stop());
+        assert!(!handle.stop());
+    }
+}
@@ -0,0 +1,234 @@
+//! Loading secrets from files and secret managers, and keeping them out of logs.
+//!
+//! [resolve] looks up a secret in the following order:
+//!
+//! 1. The file at the path in `<KEY>_FILE`, the convention used by Docker and Kubernetes secrets
+//! 2. The global [SecretProvider], if one is set with [set_secret_provider]
+//! 3. The env variable `<KEY>`
+//!
+//! Resolved secrets are wrapped in [Secret], which never prints its contents. Secrets can also be parsed into it,
+//! e.g. with `#[secret("API_KEY" as Secret<String>)]` in `State`, so its `Debug` output never leaks them.
+
+#[cfg(feature = "secrets-vault")]
+pub mod vault;
+
+use futures::future::BoxFuture;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Deserializer};
+use std::{
+    fmt::{Debug, Display},
+    future::Future,
+    str::FromStr,
+    sync::Arc,
+};
+use thiserror::Error;
+
+/// The suffix of env variables holding paths to secret files.
+const FILE_SUFFIX: &str = "_FILE";
+
+#[derive(Debug, Error)]
+pub enum SecretError {
+    #[error("Could not read secret file {0}: {1}")]
+    File(String, std::io::Error),
+    #[error("Provider: {0}")]
+    Provider(String),
+}
+
+impl SecretError {
+    pub fn provider(error: impl Display) -> Self {
+        Self::Provider(error.to_string())
+    }
+}
+
+/// Wraps a value so it is never printed. The value can only be accessed with [expose][Secret::expose].
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    pub fn expose(&self) -> &T {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Debug for Secret<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Secret").field(&"{ ... }").finish()
+    }
+}
+
+impl<T> From<T> for Secret<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T> FromStr for Secret<T>
+where
+    T: FromStr,
+{
+    type Err = T::Err;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Self)
+    }
+}
+
+impl<'de, T> Deserialize<'de> for Secret<T>
+where
+    T: Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        T::deserialize(deserializer).map(Self)
+    }
+}
+
+/// Implement on secret managers to make them available to [resolve].
+pub trait SecretProvider: Send + Sync {
+    /// Returns `None` if the provider does not hold the secret.
+    fn get(&self, key: &str) -> impl Future<Output = Result<Option<String>, SecretError>> + Send;
+}
+
+impl<T> SecretProvider for Arc<T>
+where
+    T: SecretProvider,
+{
+    fn get(&self, key: &str) -> impl Future<Output = Result<Option<String>, SecretError>> + Send {
+        T::get(self, key)
+    }
+}
+
+/// Object safe [SecretProvider] so it can be stored globally.
+trait DynSecretProvider: Send + Sync {
+    fn get_boxed<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<String>, SecretError>>;
+}
+
+impl<T> DynSecretProvider for T
+where
+    T: SecretProvider,
+{
+    fn get_boxed<'a>(&'a self, key: &'a str) -> BoxFuture<'a, Result<Option<String>, SecretError>> {
+        Box::pin(self.get(key))
+    }
+}
+
+static GLOBAL_PROVIDER: OnceCell<Box<dyn DynSecretProvider>> = OnceCell::new();
+
+/// Sets the provider used by [resolve]. Can only be called once, returns `false` if a provider was already set.
+pub fn set_secret_provider(provider: impl SecretProvider + 'static) -> bool {
+    GLOBAL_PROVIDER.set(Box::new(provider)).is_ok()
+}
+
+/// Looks up the secret for the key in the secret file, the global provider and the env, in that order.
+pub async fn resolve(key: &str) -> Result<Option<Secret<String>>, SecretError> {
+    if let Ok(path) = crate::env::get(&format!("{key}{FILE_SUFFIX}")) {
+        let contents = std::fs::read_to_string(&path).map_err(|e| SecretError::File(path, e))?;
+        // Files usually end with a newline which is not part of the secret
+        let secret = contents.trim_end_matches(['\r', '\n']).to_string();
+        return Ok(Some(Secret(secret)));
+    }
+
+    if let Some(provider) = GLOBAL_PROVIDER.get() {
+        if let Some(secret) = provider.get_boxed(key).await? {
+            return Ok(Some(Secret(secret)));
+        }
+    }
+
+    Ok(crate::env::get(key).ok().map(Secret))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacted() {
+        let secret = Secret::new("hunter2".to_string());
+        assert_eq!(format!("{secret:?}"), r#"Secret("{ ... }")"#);
+        assert_eq!(secret.expose(), "hunter2");
+
+        let secret: Secret<u16> = "42".parse().unwrap();
+        assert_eq!(*secret.expose(), 42);
+
+        let secret: Secret<String> = serde_json::from_str(r#""hunter2""#).unwrap();
+        assert_eq!(secret.into_inner(), "hunter2");
+    }
+
+    #[tokio::test]
+    async fn file_convention() {
+        let path = std::env::temp_dir().join("hextacy-secret-file");
+        std::fs::write(&path, "from-file\n").unwrap();
+
+        std::env::set_var("HXTC_SECRET_FILE_TEST", "from-env");
+        assert_eq!(
+            resolve("HXTC_SECRET_FILE_TEST").await.unwrap().unwrap(),
+            Secret::new("from-env".to_string())
+        );
+
+        std::env::set_var("HXTC_SECRET_FILE_TEST_FILE", &path);
+        assert_eq!(
+            resolve("HXTC_SECRET_FILE_TEST").await.unwrap().unwrap(),
+            Secret::new("from-file".to_string())
+        );
+
+        std::env::set_var("HXTC_SECRET_FILE_TEST_FILE", "/nonexistent/secret");
+        assert!(matches!(
+            resolve("HXTC_SECRET_FILE_TEST").await,
+            Err(SecretError::File(..))
+        ));
+
+        assert!(resolve("HXTC_SECRET_MISSING").await.unwrap().is_none());
+    }
+
+    #[derive(Debug)]
+    struct Redis {
+        host: String,
+        password: Secret<String>,
+    }
+
+    impl Redis {
+        fn new(host: &str, password: Secret<String>) -> Self {
+            Self {
+                host: host.to_string(),
+                password,
+            }
+        }
+    }
+
+    #[derive(Debug, crate::State)]
+    struct AppState {
+        #[secret("HXTC_STATE_RD_HOST", "HXTC_STATE_RD_PASSWORD" as Secret<String>)]
+        cache: Redis,
+
+        #[secret("HXTC_STATE_API_KEY")]
+        #[raw("fallback")]
+        #[load_with(String::from)]
+        api_key: Secret<String>,
+    }
+
+    #[tokio::test]
+    async fn state() {
+        let path = std::env::temp_dir().join("hextacy-secret-state");
+        std::fs::write(&path, "hunter2").unwrap();
+        std::env::set_var("HXTC_STATE_RD_HOST", "localhost");
+        std::env::set_var("HXTC_STATE_RD_PASSWORD_FILE", &path);
+
+        let state = AppState::load().await.unwrap();
+        assert_eq!(state.cache.host, "localhost");
+        assert_eq!(state.cache.password.expose(), "hunter2");
+        // The API key is not set, so the raw loader is used
+        assert_eq!(state.api_key.expose(), "fallback");
+        assert!(!format!("{state:?}").contains("hunter2"));
+        assert!(!format!("{state:?}").contains("fallback"));
+    }
+}
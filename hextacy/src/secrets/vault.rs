@@ -0,0 +1,171 @@
+//! A [SecretProvider] reading from the KV version 2 secrets engine of HashiCorp Vault, or any server
+//! implementing its HTTP API.
+
+use super::{SecretError, SecretProvider};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::fmt::Debug;
+use tokio::sync::Mutex;
+
+#[derive(Debug, Deserialize)]
+struct KvResponse {
+    data: KvData,
+}
+
+#[derive(Debug, Deserialize)]
+struct KvData {
+    data: Map<String, Value>,
+}
+
+/// Reads the keys of a single secret, e.g. `secret/my-app`, from Vault. The secret is fetched once on first use
+/// and cached, since all the state's secrets are usually loaded together.
+pub struct VaultProvider {
+    client: reqwest::Client,
+    address: String,
+    token: String,
+    mount: String,
+    path: String,
+    cache: Mutex<Option<Map<String, Value>>>,
+}
+
+impl Debug for VaultProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VaultProvider")
+            .field("address", &self.address)
+            .field("token", &"{ ... }")
+            .field("mount", &self.mount)
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+impl VaultProvider {
+    /// Creates a provider reading the secret at `path` in the `secret` mount.
+    pub fn new(address: &str, token: &str, path: &str) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            address: address.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+            mount: "secret".to_string(),
+            path: path.to_string(),
+            cache: Mutex::new(None),
+        }
+    }
+
+    /// Creates a provider from `VAULT_ADDR`, `VAULT_TOKEN` and `VAULT_SECRET_PATH`. Returns `None` if any of them
+    /// are missing.
+    pub fn from_env() -> Option<Self> {
+        let params = crate::env::get_multiple(&["VAULT_ADDR", "VAULT_TOKEN", "VAULT_SECRET_PATH"]);
+        Some(Self::new(
+            params.get("VAULT_ADDR")?,
+            params.get("VAULT_TOKEN")?,
+            params.get("VAULT_SECRET_PATH")?,
+        ))
+    }
+
+    /// The mount of the KV engine, `secret` by default.
+    pub fn with_mount(mut self, mount: &str) -> Self {
+        self.mount = mount.to_string();
+        self
+    }
+
+    async fn fetch(&self) -> Result<Map<String, Value>, SecretError> {
+        let url = format!("{}/v1/{}/data/{}", self.address, self.mount, self.path);
+
+        let response = self
+            .client
+            .get(url)
+            .header("X-Vault-Token", &self.token)
+            .send()
+            .await
+            .map_err(SecretError::provider)?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(SecretError::Provider(format!(
+                "Vault responded with {status} for {}",
+                self.path
+            )));
+        }
+
+        let response: KvResponse = response.json().await.map_err(SecretError::provider)?;
+        Ok(response.data.data)
+    }
+}
+
+impl SecretProvider for VaultProvider {
+    async fn get(&self, key: &str) -> Result<Option<String>, SecretError> {
+        let mut cache = self.cache.lock().await;
+
+        if cache.is_none() {
+            *cache = Some(self.fetch().await?);
+        }
+
+        let value = cache.as_ref().and_then(|data| data.get(key));
+        Ok(value.map(|value| match value {
+            Value::String(s) => s.clone(),
+            value => value.to_string(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        extract::Path,
+        http::{HeaderMap, StatusCode},
+        routing::get,
+        Json, Router,
+    };
+    use std::net::SocketAddr;
+
+    /// Serves the KV engine's read endpoint for a single secret.
+    async fn stand_in() -> SocketAddr {
+        async fn read(Path(path): Path<String>, headers: HeaderMap) -> (StatusCode, Json<Value>) {
+            if headers.get("X-Vault-Token").map(|t| t.as_bytes()) != Some(b"root") {
+                return (
+                    StatusCode::FORBIDDEN,
+                    Json(serde_json::json!({ "errors": ["permission denied"] })),
+                );
+            }
+            if path != "my-app" {
+                return (
+                    StatusCode::NOT_FOUND,
+                    Json(serde_json::json!({ "errors": [] })),
+                );
+            }
+            let body = serde_json::json!({
+                "data": {
+                    "data": { "RD_PASSWORD": "hunter2", "RD_DATABASE": 3 },
+                    "metadata": { "version": 1 }
+                }
+            });
+            (StatusCode::OK, Json(body))
+        }
+
+        let app = Router::new().route("/v1/secret/data/*path", get(read));
+        let server =
+            axum::Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+
+    #[tokio::test]
+    async fn kv() {
+        let addr = format!("http://{}", stand_in().await);
+
+        let vault = VaultProvider::new(&addr, "root", "my-app");
+        assert_eq!(vault.get("RD_PASSWORD").await.unwrap().unwrap(), "hunter2");
+        assert_eq!(vault.get("RD_DATABASE").await.unwrap().unwrap(), "3");
+        assert_eq!(vault.get("RD_HOST").await.unwrap(), None);
+        assert!(format!("{vault:?}").contains(r#"token: "{ ... }""#));
+
+        let vault = VaultProvider::new(&addr, "wrong", "my-app");
+        assert!(matches!(
+            vault.get("RD_PASSWORD").await,
+            Err(SecretError::Provider(_))
+        ));
+    }
+}
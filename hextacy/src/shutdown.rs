@@ -0,0 +1,151 @@
+//! Graceful shutdown of the resources held by an application.
+//!
+//! The adapters implement [Shutdown] by closing their pools and connections. Structs deriving `State` get a
+//! `shutdown` method which shuts down every field implementing it in reverse declaration order:
+//!
+//! ```ignore
+//! let state = AppState::load().await?;
+//!
+//! axum::Server::bind(&addr)
+//!     .serve(router.into_make_service())
+//!     .with_graceful_shutdown(signal())
+//!     .await?;
+//!
+//! state.shutdown().await;
+//! ```
+//!
+//! The SMTP mailer, Diesel pools and the Redis message queue hold nothing which needs to be released explicitly
+//! and do not implement it.
+
+use futures::future::BoxFuture;
+use std::{fmt::Display, future::Future, sync::Arc};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+#[error("{0}")]
+pub struct ShutdownError(pub String);
+
+impl ShutdownError {
+    pub fn new(error: impl Display) -> Self {
+        Self(error.to_string())
+    }
+}
+
+/// Implement on drivers and clients which need to release their resources before the application exits.
+pub trait Shutdown: Send + Sync {
+    fn shutdown(&self) -> impl Future<Output = Result<(), ShutdownError>> + Send;
+}
+
+impl<T> Shutdown for Arc<T>
+where
+    T: Shutdown,
+{
+    fn shutdown(&self) -> impl Future<Output = Result<(), ShutdownError>> + Send {
+        T::shutdown(self)
+    }
+}
+
+/// Autoref specialization used by `#[derive(State)]` to only shut down fields implementing [Shutdown].
+#[doc(hidden)]
+pub mod probe {
+    use super::{BoxFuture, Shutdown};
+
+    pub struct Probe<'a, T>(pub &'a T);
+
+    pub trait Closed<'a> {
+        fn shutdown(&self, name: &'static str) -> BoxFuture<'a, ()>;
+    }
+
+    impl<'a, T: Shutdown> Closed<'a> for Probe<'a, T> {
+        fn shutdown(&self, name: &'static str) -> BoxFuture<'a, ()> {
+            let this = self.0;
+            Box::pin(async move {
+                match this.shutdown().await {
+                    Ok(_) => tracing::debug!("Shut down {name}"),
+                    Err(e) => tracing::error!("Error while shutting down {name}: {e}"),
+                }
+            })
+        }
+    }
+
+    pub trait Unclosed<'a> {
+        fn shutdown(&self, _name: &'static str) -> BoxFuture<'a, ()> {
+            Box::pin(async {})
+        }
+    }
+
+    impl<'a, T> Unclosed<'a> for &Probe<'a, T> {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::{HealthCheck, HealthError, Status};
+    use crate::State;
+    use std::sync::Mutex;
+
+    type Log = Arc<Mutex<Vec<&'static str>>>;
+
+    struct Resource {
+        name: &'static str,
+        healthy: bool,
+        log: Log,
+    }
+
+    impl Resource {
+        fn new(name: &'static str, healthy: bool, log: &Log) -> Self {
+            Self {
+                name,
+                healthy,
+                log: log.clone(),
+            }
+        }
+    }
+
+    impl HealthCheck for Resource {
+        async fn check(&self) -> Result<(), HealthError> {
+            if self.healthy {
+                Ok(())
+            } else {
+                Err(HealthError::new("down"))
+            }
+        }
+    }
+
+    impl Shutdown for Resource {
+        async fn shutdown(&self) -> Result<(), ShutdownError> {
+            self.log.lock().unwrap().push(self.name);
+            Err(ShutdownError::new("already closed"))
+        }
+    }
+
+    #[allow(dead_code)]
+    #[derive(State)]
+    struct AppState {
+        db: Resource,
+        #[health(non_critical)]
+        mailer: Arc<Resource>,
+        #[health(skip)]
+        cache: Resource,
+        name: String,
+    }
+
+    #[tokio::test]
+    async fn derived() {
+        let log = Log::default();
+        let state = AppState {
+            db: Resource::new("db", true, &log),
+            mailer: Arc::new(Resource::new("mailer", false, &log)),
+            cache: Resource::new("cache", false, &log),
+            name: "app".to_string(),
+        };
+
+        let report = state.health().await;
+        assert_eq!(report.status, Status::Warn);
+        assert_eq!(report.checks.keys().collect::<Vec<_>>(), ["db", "mailer"]);
+
+        // Errors do not stop the remaining fields from shutting down
+        state.shutdown().await;
+        assert_eq!(*log.lock().unwrap(), ["cache", "mailer", "db"]);
+    }
+}
@@ -1,6 +1,13 @@
 /// Utilities for working with http. The big boy of this module is the the [RestResponse][xhttp::response::RestResponse].
 pub mod xhttp;
 
+#[cfg(feature = "web-ws")]
+pub mod ws;
+
+#[cfg(feature = "web-actix")]
+pub use actix_web;
+#[cfg(feature = "web-axum")]
+pub use axum;
 pub use cookie;
 pub use http;
 pub use mime;
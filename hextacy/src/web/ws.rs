@@ -0,0 +1,76 @@
+//! WebSocket sessions and a room based broker for broadcasting to them.
+//!
+//! Every connection is driven by a [Session], an actor which reads typed JSON messages from the client,
+//! passes them to a [WsHandler] and writes everything sent to it back. Sessions keep the connection alive with
+//! heartbeats and are closed if the client stops responding.
+//!
+//! The [Broker] keeps track of the sessions and the rooms they are subscribed to. Every session has a bounded
+//! outbox, if it fills up because the client is too slow the [Backpressure] policy decides what happens. A broker
+//! can be given a relay, e.g. the Redis pub/sub queue, so broadcasts reach the sessions on all instances.
+//!
+//! The subsystem is framework agnostic, sessions work with any sink and stream of [Frame]s. With axum:
+//!
+//! ```ignore
+//! async fn ws(upgrade: WebSocketUpgrade, State(broker): State<Broker>) -> Response {
+//!     upgrade.on_upgrade(move |socket| async move {
+//!         let (sink, stream) = socket.split();
+//!         let sink = sink.with(|frame: Frame| async move { Ok::<_, axum::Error>(to_axum(frame)) });
+//!         let stream = stream.map(|msg| msg.map(from_axum));
+//!         Session::new(&broker, &SessionConfig::default())
+//!             .run(Box::pin(sink), stream, ChatHandler)
+//!             .await
+//!     })
+//! }
+//! ```
+
+pub mod broker;
+pub mod session;
+
+pub use broker::{Backpressure, Broker, Envelope, SessionId};
+pub use session::{Session, SessionConfig, SessionContext, WsHandler};
+
+use crate::queue::QueueError;
+use thiserror::Error;
+
+/// A WebSocket frame, converted from and to the message type of the used framework.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    Close(Option<CloseFrame>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseFrame {
+    pub code: u16,
+    pub reason: String,
+}
+
+impl CloseFrame {
+    pub fn new(code: u16, reason: impl Into<String>) -> Self {
+        Self {
+            code,
+            reason: reason.into(),
+        }
+    }
+}
+
+/// Close codes used by sessions, see <https://www.rfc-editor.org/rfc/rfc6455#section-7.4.1>.
+pub mod close_code {
+    pub const NORMAL: u16 = 1000;
+    pub const AWAY: u16 = 1001;
+    pub const POLICY: u16 = 1008;
+    pub const AGAIN: u16 = 1013;
+}
+
+#[derive(Debug, Error)]
+pub enum WsError {
+    #[error("Serde: {0}")]
+    Serde(#[from] serde_json::Error),
+    #[error("Relay: {0}")]
+    Relay(QueueError),
+    #[error("Session {0} not found")]
+    SessionNotFound(SessionId),
+}
@@ -0,0 +1,473 @@
+use super::{close_code, CloseFrame, WsError};
+use crate::queue::{Consumer, Producer, QueueError, QueueHandler};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    convert::Infallible,
+    fmt::{Debug, Display},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, warn};
+
+/// Identifies a session within a broker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SessionId(u64);
+
+impl Display for SessionId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// What happens when a message is sent to a session whose outbox is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backpressure {
+    /// The message is dropped for that session. Suitable for updates where only the latest state matters.
+    #[default]
+    DropMessage,
+    /// The session is closed. Suitable when clients must not miss messages and will resync on reconnect.
+    Disconnect,
+}
+
+/// A broadcast relayed between broker instances.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Envelope {
+    /// The instance which published the broadcast, so it does not deliver it twice.
+    pub origin: u64,
+    pub room: String,
+    /// The serialized message.
+    pub payload: String,
+}
+
+/// The broker's end of a session.
+#[derive(Debug)]
+pub(super) struct SessionHandle {
+    tx: mpsc::Sender<Arc<str>>,
+    /// Tells the session to close with the frame.
+    kick: oneshot::Sender<CloseFrame>,
+    rooms: HashSet<String>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    sessions: HashMap<SessionId, SessionHandle>,
+    rooms: HashMap<String, HashSet<SessionId>>,
+}
+
+impl State {
+    fn remove(&mut self, id: SessionId) -> Option<SessionHandle> {
+        let handle = self.sessions.remove(&id)?;
+        for room in handle.rooms.iter() {
+            if let Some(members) = self.rooms.get_mut(room) {
+                members.remove(&id);
+                if members.is_empty() {
+                    self.rooms.remove(room);
+                }
+            }
+        }
+        Some(handle)
+    }
+}
+
+type RelayFn = Arc<dyn Fn(Envelope) -> BoxFuture<'static, Result<(), QueueError>> + Send + Sync>;
+
+/// Keeps track of sessions and their rooms. Cheap to clone, all clones share the same state.
+#[derive(Clone)]
+pub struct Broker {
+    instance: u64,
+    state: Arc<Mutex<State>>,
+    next_id: Arc<AtomicU64>,
+    backpressure: Backpressure,
+    relay: Option<RelayFn>,
+}
+
+impl Debug for Broker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Broker")
+            .field("instance", &self.instance)
+            .field("backpressure", &self.backpressure)
+            .field("relay", &self.relay.as_ref().map(|_| "{ ... }"))
+            .finish()
+    }
+}
+
+impl Default for Broker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Broker {
+    pub fn new() -> Self {
+        Self {
+            instance: rand::random(),
+            state: Arc::default(),
+            next_id: Arc::new(AtomicU64::new(1)),
+            backpressure: Backpressure::default(),
+            relay: None,
+        }
+    }
+
+    pub fn with_backpressure(mut self, backpressure: Backpressure) -> Self {
+        self.backpressure = backpressure;
+        self
+    }
+
+    /// Publishes every broadcast with the producer so other instances can deliver it to their sessions.
+    /// The other instances receive them with [relay_consumer][Self::relay_consumer].
+    pub fn with_relay<P>(mut self, producer: P) -> Self
+    where
+        P: Producer + Clone + Send + Sync + 'static,
+    {
+        self.relay = Some(Arc::new(move |envelope| {
+            let producer = producer.clone();
+            Box::pin(async move { producer.publish(envelope).await })
+        }));
+        self
+    }
+
+    /// Starts delivering broadcasts relayed by other instances to the local sessions.
+    /// Returns the handle for stopping the consumer.
+    pub fn relay_consumer<C>(&self, consumer: C) -> oneshot::Sender<()>
+    where
+        C: Consumer<Envelope>,
+    {
+        consumer.start(RelayHandler {
+            broker: self.clone(),
+        })
+    }
+
+    /// Creates a broker relaying broadcasts through the Redis channel and starts consuming it.
+    #[cfg(feature = "cache-redis")]
+    pub async fn redis(
+        queue: &crate::adapters::queue::redis::RedisMessageQueue,
+        channel: &str,
+    ) -> Result<(Self, oneshot::Sender<()>), deadpool_redis::redis::RedisError> {
+        let broker = Self::new().with_relay(queue.publisher(channel).await?);
+        let stop = broker.relay_consumer(queue.consumer(channel).await?);
+        Ok((broker, stop))
+    }
+
+    /// The ID of this instance, used to recognize its own relayed broadcasts.
+    pub fn instance(&self) -> u64 {
+        self.instance
+    }
+
+    /// Registers a session with an outbox of the given capacity.
+    pub(super) fn register(
+        &self,
+        capacity: usize,
+    ) -> (
+        SessionId,
+        mpsc::Receiver<Arc<str>>,
+        oneshot::Receiver<CloseFrame>,
+    ) {
+        let id = SessionId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let (tx, rx) = mpsc::channel(capacity.max(1));
+        let (kick, kicked) = oneshot::channel();
+        self.state().sessions.insert(
+            id,
+            SessionHandle {
+                tx,
+                kick,
+                rooms: HashSet::new(),
+            },
+        );
+        (id, rx, kicked)
+    }
+
+    /// Removes the session from the broker and all its rooms.
+    pub fn remove(&self, id: SessionId) {
+        self.state().remove(id);
+    }
+
+    /// Removes the session and closes it with the code and reason, e.g. [close_code::POLICY].
+    pub fn disconnect(&self, id: SessionId, code: u16, reason: impl Into<String>) {
+        if let Some(handle) = self.state().remove(id) {
+            let _ = handle.kick.send(CloseFrame::new(code, reason));
+        }
+    }
+
+    pub fn subscribe(&self, id: SessionId, room: &str) -> Result<(), WsError> {
+        let mut state = self.state();
+        let handle = state
+            .sessions
+            .get_mut(&id)
+            .ok_or(WsError::SessionNotFound(id))?;
+        handle.rooms.insert(room.to_string());
+        state.rooms.entry(room.to_string()).or_default().insert(id);
+        Ok(())
+    }
+
+    pub fn unsubscribe(&self, id: SessionId, room: &str) {
+        let mut state = self.state();
+        if let Some(handle) = state.sessions.get_mut(&id) {
+            handle.rooms.remove(room);
+        }
+        if let Some(members) = state.rooms.get_mut(room) {
+            members.remove(&id);
+            if members.is_empty() {
+                state.rooms.remove(room);
+            }
+        }
+    }
+
+    /// The rooms the session is subscribed to.
+    pub fn rooms(&self, id: SessionId) -> Vec<String> {
+        self.state()
+            .sessions
+            .get(&id)
+            .map(|handle| handle.rooms.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// The amount of local sessions subscribed to the room.
+    pub fn room_size(&self, room: &str) -> usize {
+        self.state().rooms.get(room).map_or(0, HashSet::len)
+    }
+
+    pub fn session_count(&self) -> usize {
+        self.state().sessions.len()
+    }
+
+    /// Sends a message to a local session.
+    pub fn send_to<M>(&self, id: SessionId, message: &M) -> Result<bool, WsError>
+    where
+        M: Serialize,
+    {
+        let payload: Arc<str> = serde_json::to_string(message)?.into();
+        let mut state = self.state();
+        if !state.sessions.contains_key(&id) {
+            return Err(WsError::SessionNotFound(id));
+        }
+        Ok(self.deliver_to(&mut state, [id], &payload) == 1)
+    }
+
+    /// Sends a message to every session in the room, on all instances if the broker has a relay.
+    /// Returns the amount of local sessions the message was delivered to.
+    pub async fn broadcast<M>(&self, room: &str, message: &M) -> Result<usize, WsError>
+    where
+        M: Serialize,
+    {
+        let payload = serde_json::to_string(message)?;
+        let delivered = self.deliver(room, payload.as_str().into());
+
+        if let Some(ref relay) = self.relay {
+            let envelope = Envelope {
+                origin: self.instance,
+                room: room.to_string(),
+                payload,
+            };
+            relay(envelope).await.map_err(WsError::Relay)?;
+        }
+
+        Ok(delivered)
+    }
+
+    /// Delivers an already serialized message to the local sessions in the room.
+    pub fn deliver(&self, room: &str, payload: Arc<str>) -> usize {
+        let mut state = self.state();
+        let Some(members) = state.rooms.get(room) else {
+            return 0;
+        };
+        let members = members.iter().copied().collect::<Vec<_>>();
+        self.deliver_to(&mut state, members, &payload)
+    }
+
+    fn deliver_to(
+        &self,
+        state: &mut State,
+        ids: impl IntoIterator<Item = SessionId>,
+        payload: &Arc<str>,
+    ) -> usize {
+        let mut delivered = 0;
+        let mut gone = vec![];
+        let mut kicked = vec![];
+
+        for id in ids {
+            let Some(handle) = state.sessions.get(&id) else {
+                continue;
+            };
+            match handle.tx.try_send(payload.clone()) {
+                Ok(_) => delivered += 1,
+                Err(mpsc::error::TrySendError::Full(_)) => match self.backpressure {
+                    Backpressure::DropMessage => {
+                        debug!("Outbox of session {id} full, dropping message")
+                    }
+                    Backpressure::Disconnect => {
+                        warn!("Outbox of session {id} full, disconnecting");
+                        kicked.push(id);
+                    }
+                },
+                Err(mpsc::error::TrySendError::Closed(_)) => gone.push(id),
+            }
+        }
+
+        for id in gone {
+            state.remove(id);
+        }
+
+        for id in kicked {
+            if let Some(handle) = state.remove(id) {
+                let _ = handle
+                    .kick
+                    .send(CloseFrame::new(close_code::AGAIN, "Too slow"));
+            }
+        }
+
+        delivered
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        // The state is always consistent between operations, so a poisoned lock is safe to use
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Delivers relayed broadcasts from other instances.
+struct RelayHandler {
+    broker: Broker,
+}
+
+impl QueueHandler<Envelope> for RelayHandler {
+    type Error = Infallible;
+
+    async fn handle(&mut self, envelope: Envelope) -> Result<(), Self::Error> {
+        if envelope.origin != self.broker.instance {
+            self.broker.deliver(&envelope.room, envelope.payload.into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn rooms() {
+        let broker = Broker::new();
+        let (a, mut rx_a, _) = broker.register(8);
+        let (b, mut rx_b, _) = broker.register(8);
+
+        broker.subscribe(a, "chat").unwrap();
+        broker.subscribe(b, "chat").unwrap();
+        broker.subscribe(b, "news").unwrap();
+        assert_eq!(broker.room_size("chat"), 2);
+
+        assert_eq!(
+            broker.broadcast("chat", &json!({"hi": 1})).await.unwrap(),
+            2
+        );
+        assert_eq!(broker.broadcast("news", &"extra").await.unwrap(), 1);
+        assert_eq!(broker.broadcast("empty", &"nobody").await.unwrap(), 0);
+
+        assert_eq!(&*rx_a.recv().await.unwrap(), r#"{"hi":1}"#);
+        assert_eq!(&*rx_b.recv().await.unwrap(), r#"{"hi":1}"#);
+        assert_eq!(&*rx_b.recv().await.unwrap(), r#""extra""#);
+        assert!(rx_a.try_recv().is_err());
+
+        assert!(broker.send_to(a, &1).unwrap());
+        assert_eq!(&*rx_a.recv().await.unwrap(), "1");
+
+        broker.unsubscribe(a, "chat");
+        assert_eq!(broker.room_size("chat"), 1);
+
+        broker.remove(b);
+        assert_eq!(broker.room_size("news"), 0);
+        assert_eq!(broker.session_count(), 1);
+        assert!(matches!(
+            broker.subscribe(b, "chat"),
+            Err(WsError::SessionNotFound(_))
+        ));
+
+        // Dropped receivers are cleaned up on delivery
+        broker.subscribe(a, "chat").unwrap();
+        drop(rx_a);
+        assert_eq!(broker.broadcast("chat", &1).await.unwrap(), 0);
+        assert_eq!(broker.session_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn backpressure() {
+        let broker = Broker::new();
+        let (id, mut rx, _) = broker.register(1);
+        broker.subscribe(id, "room").unwrap();
+
+        assert_eq!(broker.broadcast("room", &1).await.unwrap(), 1);
+        assert_eq!(broker.broadcast("room", &2).await.unwrap(), 0);
+        assert_eq!(&*rx.recv().await.unwrap(), "1");
+        assert_eq!(broker.session_count(), 1);
+
+        let broker = Broker::new().with_backpressure(Backpressure::Disconnect);
+        let (id, _rx, kick) = broker.register(1);
+        broker.subscribe(id, "room").unwrap();
+
+        broker.broadcast("room", &1).await.unwrap();
+        broker.broadcast("room", &2).await.unwrap();
+        assert_eq!(broker.session_count(), 0);
+        assert_eq!(
+            kick.await.unwrap(),
+            CloseFrame::new(close_code::AGAIN, "Too slow")
+        );
+    }
+
+    /// A pub/sub channel shared by the brokers, standing in for Redis.
+    #[derive(Clone)]
+    struct Bus(tokio::sync::broadcast::Sender<String>);
+
+    impl Producer for Bus {
+        async fn publish<M>(&self, message: M) -> Result<(), QueueError>
+        where
+            M: Serialize + Send + Sync + 'static,
+        {
+            let _ = self.0.send(serde_json::to_string(&message)?);
+            Ok(())
+        }
+    }
+
+    struct BusConsumer(tokio::sync::broadcast::Receiver<String>);
+
+    impl Consumer<Envelope> for BusConsumer {
+        async fn poll_queue(&mut self) -> Result<Option<Envelope>, QueueError> {
+            match self.0.recv().await {
+                Ok(message) => Ok(Some(serde_json::from_str(&message)?)),
+                Err(_) => Ok(None),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn relay() {
+        let (bus, _) = tokio::sync::broadcast::channel(16);
+        let bus = Bus(bus);
+
+        let one = Broker::new().with_relay(bus.clone());
+        let two = Broker::new().with_relay(bus.clone());
+        let _stop_one = one.relay_consumer(BusConsumer(bus.0.subscribe()));
+        let _stop_two = two.relay_consumer(BusConsumer(bus.0.subscribe()));
+
+        let (a, mut rx_a, _) = one.register(8);
+        let (b, mut rx_b, _) = two.register(8);
+        one.subscribe(a, "room").unwrap();
+        two.subscribe(b, "room").unwrap();
+
+        assert_eq!(one.broadcast("room", &"hello").await.unwrap(), 1);
+
+        assert_eq!(&*rx_a.recv().await.unwrap(), r#""hello""#);
+        assert_eq!(&*rx_b.recv().await.unwrap(), r#""hello""#);
+
+        // The origin does not deliver its own broadcast twice
+        two.broadcast("room", &"bye").await.unwrap();
+        assert_eq!(&*rx_a.recv().await.unwrap(), r#""bye""#);
+        assert_eq!(&*rx_b.recv().await.unwrap(), r#""bye""#);
+        tokio::task::yield_now().await;
+        assert!(rx_b.try_recv().is_err());
+    }
+}
@@ -0,0 +1,464 @@
+use super::{close_code, Broker, CloseFrame, Frame, SessionId, WsError};
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{fmt::Display, future::Future, sync::Arc, time::Duration};
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::{self, Instant, MissedTickBehavior},
+};
+use tracing::{debug, error};
+
+/// Implement on structs that handle the messages of a session. A handler is created per session.
+pub trait WsHandler<M>: Send
+where
+    M: DeserializeOwned + Send,
+{
+    type Error: Display + Send;
+
+    /// Called once the session is registered, e.g. to subscribe to rooms.
+    fn on_connect(&mut self, _ctx: &mut SessionContext) -> impl Future<Output = ()> + Send {
+        async {}
+    }
+
+    fn on_message(
+        &mut self,
+        ctx: &mut SessionContext,
+        message: M,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    /// Called after the session is removed from the broker.
+    fn on_disconnect(&mut self, _ctx: &SessionContext) -> impl Future<Output = ()> + Send {
+        async {}
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SessionConfig {
+    /// How often the client is pinged. A zero heartbeat is raised to 1 ms.
+    pub heartbeat: Duration,
+    /// The session is closed if nothing is received from the client for this long.
+    pub timeout: Duration,
+    /// The capacity of the outbox, see [Backpressure][super::Backpressure].
+    pub buffer: usize,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            heartbeat: Duration::from_secs(30),
+            timeout: Duration::from_secs(75),
+            buffer: 64,
+        }
+    }
+}
+
+impl SessionConfig {
+    pub fn with_heartbeat(mut self, heartbeat: Duration, timeout: Duration) -> Self {
+        self.heartbeat = heartbeat;
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_buffer(mut self, buffer: usize) -> Self {
+        self.buffer = buffer;
+        self
+    }
+}
+
+/// Gives handlers access to their session and the broker.
+#[derive(Debug)]
+pub struct SessionContext {
+    id: SessionId,
+    broker: Broker,
+    /// Replies are written after the handler returns, so they cannot fill up the outbox
+    pending: Vec<Frame>,
+    close: Option<CloseFrame>,
+}
+
+impl SessionContext {
+    pub fn id(&self) -> SessionId {
+        self.id
+    }
+
+    pub fn broker(&self) -> &Broker {
+        &self.broker
+    }
+
+    /// Sends a message to this session's client.
+    pub fn send<M>(&mut self, message: &M) -> Result<(), WsError>
+    where
+        M: Serialize,
+    {
+        self.pending
+            .push(Frame::Text(serde_json::to_string(message)?));
+        Ok(())
+    }
+
+    pub fn subscribe(&self, room: &str) -> Result<(), WsError> {
+        self.broker.subscribe(self.id, room)
+    }
+
+    pub fn unsubscribe(&self, room: &str) {
+        self.broker.unsubscribe(self.id, room)
+    }
+
+    /// Broadcasts a message to the room, including this session if it is subscribed.
+    pub async fn broadcast<M>(&self, room: &str, message: &M) -> Result<usize, WsError>
+    where
+        M: Serialize,
+    {
+        self.broker.broadcast(room, message).await
+    }
+
+    /// Closes the session once the handler returns.
+    pub fn close(&mut self, code: u16, reason: impl Into<String>) {
+        self.close = Some(CloseFrame::new(code, reason));
+    }
+}
+
+/// A connection registered with a broker. Drive it with [run][Session::run].
+#[derive(Debug)]
+pub struct Session {
+    config: SessionConfig,
+    ctx: SessionContext,
+    outbox: mpsc::Receiver<Arc<str>>,
+    kick: oneshot::Receiver<CloseFrame>,
+}
+
+impl Session {
+    pub fn new(broker: &Broker, config: &SessionConfig) -> Self {
+        let (id, outbox, kick) = broker.register(config.buffer);
+        Self {
+            config: config.clone(),
+            ctx: SessionContext {
+                id,
+                broker: broker.clone(),
+                pending: vec![],
+                close: None,
+            },
+            outbox,
+            kick,
+        }
+    }
+
+    pub fn id(&self) -> SessionId {
+        self.ctx.id
+    }
+
+    /// Runs the session until either side closes the connection, the client times out, or the broker disconnects it.
+    ///
+    /// Text and binary frames are deserialized to `M` and passed to the handler. Messages that fail to deserialize
+    /// are ignored.
+    pub async fn run<Si, St, E, H, M>(self, mut sink: Si, mut stream: St, mut handler: H)
+    where
+        Si: Sink<Frame> + Unpin + Send,
+        St: Stream<Item = Result<Frame, E>> + Unpin + Send,
+        E: Display,
+        H: WsHandler<M>,
+        M: DeserializeOwned + Send,
+    {
+        let Self {
+            config,
+            mut ctx,
+            mut outbox,
+            mut kick,
+        } = self;
+
+        let id = ctx.id;
+        debug!("Session {id} connected");
+
+        handler.on_connect(&mut ctx).await;
+
+        let period = config.heartbeat.max(Duration::from_millis(1));
+        let mut heartbeat = time::interval_at(Instant::now() + period, period);
+        heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut last_seen = Instant::now();
+
+        let mut open = flush(&mut sink, &mut ctx).await;
+
+        while open {
+            tokio::select! {
+                // A kicked session's outbox is closed, check the kick first so the client is told why
+                biased;
+
+                close = &mut kick => {
+                    // Removing the session without a reason drops the handle
+                    if let Ok(close) = close {
+                        let _ = sink.send(Frame::Close(Some(close))).await;
+                    }
+                    open = false;
+                }
+                message = outbox.recv() => {
+                    open = match message {
+                        Some(message) => sink.send(Frame::Text(message.to_string())).await.is_ok(),
+                        None => false,
+                    };
+                }
+                frame = stream.next() => {
+                    last_seen = Instant::now();
+                    let frame = match frame {
+                        Some(Ok(frame)) => frame,
+                        Some(Err(e)) => {
+                            debug!("Session {id} stream error: {e}");
+                            break;
+                        }
+                        None => break,
+                    };
+
+                    let message = match frame {
+                        Frame::Text(text) => serde_json::from_str::<M>(&text),
+                        Frame::Binary(bytes) => serde_json::from_slice::<M>(&bytes),
+                        Frame::Ping(payload) => {
+                            open = sink.send(Frame::Pong(payload)).await.is_ok();
+                            continue;
+                        }
+                        Frame::Pong(_) => continue,
+                        Frame::Close(_) => {
+                            let _ = sink.send(Frame::Close(None)).await;
+                            break;
+                        }
+                    };
+
+                    match message {
+                        Ok(message) => {
+                            if let Err(e) = handler.on_message(&mut ctx, message).await {
+                                error!("Session {id} error while handling message: {e}");
+                            }
+                        }
+                        Err(e) => debug!("Session {id} received an invalid message: {e}"),
+                    }
+
+                    open = flush(&mut sink, &mut ctx).await;
+                }
+                _ = heartbeat.tick() => {
+                    if last_seen.elapsed() > config.timeout {
+                        debug!("Session {id} timed out");
+                        let close = CloseFrame::new(close_code::AWAY, "Timed out");
+                        let _ = sink.send(Frame::Close(Some(close))).await;
+                        break;
+                    }
+                    open = sink.send(Frame::Ping(vec![])).await.is_ok();
+                }
+            }
+        }
+
+        ctx.broker.remove(id);
+        handler.on_disconnect(&ctx).await;
+        let _ = sink.close().await;
+        debug!("Session {id} disconnected");
+    }
+}
+
+/// Writes the pending replies and the close frame if the handler requested it.
+/// Returns whether the session is still open.
+async fn flush<Si>(sink: &mut Si, ctx: &mut SessionContext) -> bool
+where
+    Si: Sink<Frame> + Unpin,
+{
+    for frame in ctx.pending.drain(..) {
+        if sink.feed(frame).await.is_err() {
+            return false;
+        }
+    }
+
+    if let Some(close) = ctx.close.take() {
+        let _ = sink.send(Frame::Close(Some(close))).await;
+        return false;
+    }
+
+    sink.flush().await.is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+    use serde::Deserialize;
+    use std::convert::Infallible;
+
+    #[derive(Debug, Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum Incoming {
+        Join { room: String },
+        Say { room: String, text: String },
+        Quit,
+    }
+
+    struct Chat;
+
+    impl WsHandler<Incoming> for Chat {
+        type Error = WsError;
+
+        async fn on_connect(&mut self, ctx: &mut SessionContext) {
+            ctx.send(&"welcome").unwrap();
+        }
+
+        async fn on_message(
+            &mut self,
+            ctx: &mut SessionContext,
+            message: Incoming,
+        ) -> Result<(), Self::Error> {
+            match message {
+                Incoming::Join { room } => {
+                    ctx.subscribe(&room)?;
+                    ctx.send(&format!("joined {room}"))
+                }
+                Incoming::Say { room, text } => ctx.broadcast(&room, &text).await.map(|_| ()),
+                Incoming::Quit => {
+                    ctx.close(close_code::NORMAL, "bye");
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    type Client = (
+        UnboundedSender<Result<Frame, Infallible>>,
+        UnboundedReceiver<Frame>,
+    );
+
+    fn spawn(broker: &Broker, config: &SessionConfig) -> (Client, tokio::task::JoinHandle<()>) {
+        let (to_server, server_in) = unbounded();
+        let (server_out, from_server) = unbounded();
+        let session = Session::new(broker, config);
+        let handle = tokio::spawn(session.run(server_out, server_in, Chat));
+        ((to_server, from_server), handle)
+    }
+
+    fn text(s: &str) -> Result<Frame, Infallible> {
+        Ok(Frame::Text(s.to_string()))
+    }
+
+    #[tokio::test]
+    async fn session() {
+        let broker = Broker::new();
+        let config = SessionConfig::default();
+        let ((tx_a, mut rx_a), session_a) = spawn(&broker, &config);
+        let ((tx_b, mut rx_b), _) = spawn(&broker, &config);
+
+        assert_eq!(rx_a.next().await, Some(Frame::Text(r#""welcome""#.into())));
+        assert_eq!(rx_b.next().await, Some(Frame::Text(r#""welcome""#.into())));
+
+        tx_a.unbounded_send(text(r#"{"type":"join","room":"r"}"#))
+            .unwrap();
+        tx_b.unbounded_send(text(r#"{"type":"join","room":"r"}"#))
+            .unwrap();
+        assert_eq!(rx_a.next().await, Some(Frame::Text(r#""joined r""#.into())));
+        assert_eq!(rx_b.next().await, Some(Frame::Text(r#""joined r""#.into())));
+
+        // Invalid messages are ignored
+        tx_a.unbounded_send(text("nonsense")).unwrap();
+        tx_a.unbounded_send(Ok(Frame::Ping(vec![1]))).unwrap();
+        assert_eq!(rx_a.next().await, Some(Frame::Pong(vec![1])));
+
+        tx_a.unbounded_send(text(r#"{"type":"say","room":"r","text":"hi"}"#))
+            .unwrap();
+        assert_eq!(rx_a.next().await, Some(Frame::Text(r#""hi""#.into())));
+        assert_eq!(rx_b.next().await, Some(Frame::Text(r#""hi""#.into())));
+
+        tx_a.unbounded_send(text(r#"{"type":"quit"}"#)).unwrap();
+        assert_eq!(
+            rx_a.next().await,
+            Some(Frame::Close(Some(CloseFrame::new(
+                close_code::NORMAL,
+                "bye"
+            ))))
+        );
+        session_a.await.unwrap();
+        assert_eq!(rx_a.next().await, None);
+        assert_eq!(broker.room_size("r"), 1);
+
+        // Closing the stream ends the session
+        drop(tx_b);
+        assert_eq!(rx_b.next().await, None);
+        assert_eq!(broker.session_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn heartbeat() {
+        let broker = Broker::new();
+        let config = SessionConfig::default()
+            .with_heartbeat(Duration::from_millis(20), Duration::from_millis(50));
+        let ((_tx, mut rx), session) = spawn(&broker, &config);
+
+        assert_eq!(rx.next().await, Some(Frame::Text(r#""welcome""#.into())));
+        assert_eq!(rx.next().await, Some(Frame::Ping(vec![])));
+
+        // The client never answers
+        let mut last = None;
+        while let Some(frame) = rx.next().await {
+            last = Some(frame);
+        }
+        assert_eq!(
+            last,
+            Some(Frame::Close(Some(CloseFrame::new(
+                close_code::AWAY,
+                "Timed out"
+            ))))
+        );
+        session.await.unwrap();
+        assert_eq!(broker.session_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn zero_heartbeat() {
+        let broker = Broker::new();
+        let config =
+            SessionConfig::default().with_heartbeat(Duration::ZERO, Duration::from_millis(50));
+        let ((_tx, mut rx), session) = spawn(&broker, &config);
+
+        assert_eq!(rx.next().await, Some(Frame::Text(r#""welcome""#.into())));
+        assert_eq!(rx.next().await, Some(Frame::Ping(vec![])));
+        session.await.unwrap();
+        assert_eq!(broker.session_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn disconnect() {
+        let broker = Broker::new();
+        let session = Session::new(&broker, &SessionConfig::default());
+        let id = session.id();
+        let (server_out, from_server) = unbounded();
+        let (_to_server, server_in) = unbounded::<Result<Frame, Infallible>>();
+
+        broker.disconnect(id, close_code::POLICY, "Banned");
+        assert_eq!(broker.session_count(), 0);
+
+        session.run(server_out, server_in, Chat).await;
+
+        let frames = from_server.collect::<Vec<_>>().await;
+        assert_eq!(
+            frames.last(),
+            Some(&Frame::Close(Some(CloseFrame::new(
+                close_code::POLICY,
+                "Banned"
+            ))))
+        );
+    }
+
+    #[tokio::test]
+    async fn slow_client() {
+        let broker = Broker::new().with_backpressure(super::super::Backpressure::Disconnect);
+        let session = Session::new(&broker, &SessionConfig::default().with_buffer(1));
+        let id = session.id();
+        let (server_out, from_server) = unbounded();
+        let (_to_server, server_in) = unbounded::<Result<Frame, Infallible>>();
+
+        broker.subscribe(id, "r").unwrap();
+        broker.broadcast("r", &1).await.unwrap();
+        broker.broadcast("r", &2).await.unwrap();
+        assert_eq!(broker.session_count(), 0);
+        assert!(broker.rooms(id).is_empty());
+
+        session.run(server_out, server_in, Chat).await;
+
+        let frames = from_server.collect::<Vec<_>>().await;
+        assert_eq!(
+            frames.last(),
+            Some(&Frame::Close(Some(CloseFrame::new(
+                close_code::AGAIN,
+                "Too slow"
+            ))))
+        );
+    }
+}
@@ -1,2 +1,13 @@
+pub mod conditional;
+pub mod cookies;
+pub mod cors;
+pub mod csp;
+pub mod csrf;
+#[cfg(feature = "web-negotiation")]
+pub mod negotiation;
+pub mod rate_limit;
+pub mod request;
 pub mod response;
 pub mod security_headers;
+#[cfg(feature = "web-sse")]
+pub mod sse;
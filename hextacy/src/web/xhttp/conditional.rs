@@ -0,0 +1,438 @@
+//! Conditional requests and caching according to [RFC 7232](https://www.rfc-editor.org/rfc/rfc7232)
+//! and [RFC 9111](https://www.rfc-editor.org/rfc/rfc9111).
+//!
+//! The validators of a resource are its [ETag] and last modification time. [evaluate] checks them against
+//! the `If-*` headers of a request. [ResponseBuilder::conditional_json][super::response::ResponseBuilder::conditional_json]
+//! does this automatically, responding with `304 Not Modified` or `412 Precondition Failed` when appropriate:
+//!
+//! ```ignore
+//! user.into_response(StatusCode::OK)
+//!     .with_cache_control(&CacheControl::new().private().max_age(60))?
+//!     .with_last_modified(user.updated_at)?
+//!     .conditional_json(&method, &headers)
+//! ```
+
+use chrono::{DateTime, Datelike, NaiveDateTime, TimeZone, Utc};
+use data_encoding::BASE64URL_NOPAD;
+use http::{header, HeaderMap, HeaderValue, Method};
+use sha2::{Digest, Sha256};
+use std::fmt::Display;
+
+/// An entity tag, a version identifier of a resource.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ETag {
+    pub weak: bool,
+    /// The opaque tag, without quotes.
+    pub tag: String,
+}
+
+impl ETag {
+    /// An ETag for a resource which is byte for byte identical for the same tag.
+    pub fn strong(tag: impl Into<String>) -> Self {
+        Self {
+            weak: false,
+            tag: tag.into(),
+        }
+    }
+
+    /// An ETag for a resource which is semantically equivalent for the same tag, e.g. a version number
+    /// of a resource that can be served in multiple formats.
+    pub fn weak(tag: impl Into<String>) -> Self {
+        Self {
+            weak: true,
+            tag: tag.into(),
+        }
+    }
+
+    /// Creates a strong ETag from the hash of the body.
+    pub fn from_body(body: &[u8]) -> Self {
+        let hash = Sha256::digest(body);
+        // 128 bits are plenty to detect changes
+        Self::strong(BASE64URL_NOPAD.encode(&hash[..16]))
+    }
+
+    /// Parses a single ETag, e.g. `"abc"` or `W/"abc"`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let (weak, s) = match s.strip_prefix("W/") {
+            Some(s) => (true, s),
+            None => (false, s),
+        };
+        let tag = s.strip_prefix('"')?.strip_suffix('"')?;
+        if tag.contains('"') {
+            return None;
+        }
+        Some(Self {
+            weak,
+            tag: tag.to_string(),
+        })
+    }
+
+    /// Both must be strong and have the same tag.
+    pub fn strong_eq(&self, other: &ETag) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+
+    /// The tags must be the same, regardless of weakness.
+    pub fn weak_eq(&self, other: &ETag) -> bool {
+        self.tag == other.tag
+    }
+
+    pub fn header_value(&self) -> HeaderValue {
+        HeaderValue::from_str(&self.to_string()).expect("valid etag")
+    }
+}
+
+impl Display for ETag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.weak {
+            write!(f, "W/")?;
+        }
+        write!(f, "\"{}\"", self.tag)
+    }
+}
+
+/// Formats a time as an HTTP date, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub fn format_http_date(time: DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Parses an HTTP date, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`. The obsolete RFC 850
+/// (`Sunday, 06-Nov-94 08:49:37 GMT`) and asctime (`Sun Nov  6 08:49:37 1994`) formats
+/// are accepted as well, as required by [RFC 7231](https://www.rfc-editor.org/rfc/rfc7231#section-7.1.1.1).
+pub fn parse_http_date(s: &str) -> Option<DateTime<Utc>> {
+    let s = s.trim();
+    if let Ok(dt) = DateTime::parse_from_rfc2822(s) {
+        return Some(dt.with_timezone(&Utc));
+    }
+    if let Ok(dt) = NaiveDateTime::parse_from_str(s, "%A, %d-%b-%y %H:%M:%S GMT") {
+        return rfc850_year(dt).map(|dt| dt.and_utc());
+    }
+    NaiveDateTime::parse_from_str(s, "%a %b %e %H:%M:%S %Y")
+        .ok()
+        .map(|dt| dt.and_utc())
+}
+
+/// Two digit years more than 50 years in the future are in the past century.
+fn rfc850_year(dt: NaiveDateTime) -> Option<NaiveDateTime> {
+    let now = Utc::now().year();
+    let mut year = now - now % 100 + dt.year() % 100;
+    if year > now + 50 {
+        year -= 100;
+    }
+    dt.with_year(year)
+}
+
+/// The outcome of evaluating the preconditions of a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Precondition {
+    /// Process the request normally.
+    Proceed,
+    /// Respond with `304 Not Modified`.
+    NotModified,
+    /// Respond with `412 Precondition Failed`.
+    Failed,
+}
+
+/// Evaluates the `If-Match`, `If-Unmodified-Since`, `If-None-Match` and `If-Modified-Since` headers
+/// against the current validators of the resource, in the order defined by
+/// [RFC 7232](https://www.rfc-editor.org/rfc/rfc7232#section-6).
+///
+/// Use `None` for validators the resource does not have. For state changing requests call this
+/// before performing the change to prevent lost updates.
+pub fn evaluate(
+    method: &Method,
+    headers: &HeaderMap,
+    etag: Option<&ETag>,
+    last_modified: Option<DateTime<Utc>>,
+) -> Precondition {
+    // HTTP dates have a precision of seconds
+    let last_modified = last_modified.and_then(|lm| Utc.timestamp_opt(lm.timestamp(), 0).single());
+
+    if let Some(if_match) = header_str(headers, header::IF_MATCH) {
+        if !matches_any(&if_match, etag, ETag::strong_eq) {
+            return Precondition::Failed;
+        }
+    } else if let Some(since) = header_str(headers, header::IF_UNMODIFIED_SINCE)
+        .as_deref()
+        .and_then(parse_http_date)
+    {
+        if last_modified.is_some_and(|lm| lm > since) {
+            return Precondition::Failed;
+        }
+    }
+
+    let is_read = matches!(*method, Method::GET | Method::HEAD);
+
+    if let Some(if_none_match) = header_str(headers, header::IF_NONE_MATCH) {
+        if matches_any(&if_none_match, etag, ETag::weak_eq) {
+            return if is_read {
+                Precondition::NotModified
+            } else {
+                Precondition::Failed
+            };
+        }
+    } else if is_read {
+        if let Some(since) = header_str(headers, header::IF_MODIFIED_SINCE)
+            .as_deref()
+            .and_then(parse_http_date)
+        {
+            if last_modified.is_some_and(|lm| lm <= since) {
+                return Precondition::NotModified;
+            }
+        }
+    }
+
+    Precondition::Proceed
+}
+
+/// Checks whether the current ETag matches any in the list. `*` matches any existing resource.
+fn matches_any(list: &str, current: Option<&ETag>, eq: fn(&ETag, &ETag) -> bool) -> bool {
+    let Some(current) = current else {
+        return false;
+    };
+    if list.trim() == "*" {
+        return true;
+    }
+    list.split(',')
+        .filter_map(ETag::parse)
+        .any(|tag| eq(&tag, current))
+}
+
+fn header_str(headers: &HeaderMap, name: header::HeaderName) -> Option<String> {
+    let values = headers
+        .get_all(name)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .collect::<Vec<_>>();
+    (!values.is_empty()).then(|| values.join(","))
+}
+
+/// Builder for the `Cache-Control` response header.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheControl {
+    directives: Vec<String>,
+}
+
+impl CacheControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `no-store`, nothing is cached.
+    pub fn no_store() -> Self {
+        Self::new().directive("no-store")
+    }
+
+    /// `no-cache`, responses are cached but always revalidated.
+    pub fn revalidate() -> Self {
+        Self::new().directive("no-cache")
+    }
+
+    /// `public, max-age=31536000, immutable`, for fingerprinted assets.
+    pub fn immutable() -> Self {
+        Self::new()
+            .public()
+            .max_age(31_536_000)
+            .directive("immutable")
+    }
+
+    pub fn public(self) -> Self {
+        self.directive("public")
+    }
+
+    pub fn private(self) -> Self {
+        self.directive("private")
+    }
+
+    pub fn no_cache(self) -> Self {
+        self.directive("no-cache")
+    }
+
+    pub fn must_revalidate(self) -> Self {
+        self.directive("must-revalidate")
+    }
+
+    pub fn no_transform(self) -> Self {
+        self.directive("no-transform")
+    }
+
+    /// In seconds.
+    pub fn max_age(self, seconds: u64) -> Self {
+        self.directive(&format!("max-age={seconds}"))
+    }
+
+    /// Max age for shared caches, in seconds.
+    pub fn s_maxage(self, seconds: u64) -> Self {
+        self.directive(&format!("s-maxage={seconds}"))
+    }
+
+    pub fn stale_while_revalidate(self, seconds: u64) -> Self {
+        self.directive(&format!("stale-while-revalidate={seconds}"))
+    }
+
+    pub fn stale_if_error(self, seconds: u64) -> Self {
+        self.directive(&format!("stale-if-error={seconds}"))
+    }
+
+    /// Adds an arbitrary directive.
+    pub fn directive(mut self, directive: &str) -> Self {
+        if !self.directives.iter().any(|d| d == directive) {
+            self.directives.push(directive.to_string());
+        }
+        self
+    }
+
+    pub fn header_value(&self) -> Result<HeaderValue, header::InvalidHeaderValue> {
+        HeaderValue::from_str(&self.to_string())
+    }
+}
+
+impl Display for CacheControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.directives.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(header::HeaderName, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn etag() {
+        assert_eq!(ETag::parse("\"abc\""), Some(ETag::strong("abc")));
+        assert_eq!(ETag::parse(" W/\"abc\" "), Some(ETag::weak("abc")));
+        assert_eq!(ETag::parse("abc"), None);
+        assert_eq!(ETag::weak("1").to_string(), "W/\"1\"");
+
+        assert!(ETag::strong("1").strong_eq(&ETag::strong("1")));
+        assert!(!ETag::weak("1").strong_eq(&ETag::strong("1")));
+        assert!(ETag::weak("1").weak_eq(&ETag::strong("1")));
+
+        assert_eq!(ETag::from_body(b"body"), ETag::from_body(b"body"));
+        assert_ne!(ETag::from_body(b"body"), ETag::from_body(b"body2"));
+
+        let date = Utc.with_ymd_and_hms(1994, 11, 6, 8, 49, 37).unwrap();
+        assert_eq!(format_http_date(date), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(date));
+        assert_eq!(
+            parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"),
+            Some(date)
+        );
+        assert_eq!(parse_http_date("Sun Nov  6 08:49:37 1994"), Some(date));
+        assert_eq!(parse_http_date("Sun Nov 6 08:49:37 1994"), Some(date));
+        assert_eq!(parse_http_date("6 Nov 1994"), None);
+
+        let recent = Utc.with_ymd_and_hms(2023, 1, 1, 12, 0, 0).unwrap();
+        assert_eq!(
+            parse_http_date("Sunday, 01-Jan-23 12:00:00 GMT"),
+            Some(recent)
+        );
+    }
+
+    #[test]
+    fn preconditions() {
+        let etag = ETag::strong("v2");
+        let modified = Utc.with_ymd_and_hms(2023, 1, 1, 12, 0, 0).unwrap();
+        let eval = |method: Method, pairs: &[(header::HeaderName, &str)]| {
+            evaluate(&method, &headers(pairs), Some(&etag), Some(modified))
+        };
+
+        assert_eq!(eval(Method::GET, &[]), Precondition::Proceed);
+        assert_eq!(
+            eval(Method::GET, &[(header::IF_NONE_MATCH, "\"v1\", W/\"v2\"")]),
+            Precondition::NotModified
+        );
+        assert_eq!(
+            eval(Method::GET, &[(header::IF_NONE_MATCH, "\"v1\"")]),
+            Precondition::Proceed
+        );
+        assert_eq!(
+            eval(Method::PUT, &[(header::IF_NONE_MATCH, "*")]),
+            Precondition::Failed
+        );
+        assert_eq!(
+            evaluate(
+                &Method::PUT,
+                &headers(&[(header::IF_NONE_MATCH, "*")]),
+                None,
+                None
+            ),
+            Precondition::Proceed
+        );
+
+        assert_eq!(
+            eval(Method::PUT, &[(header::IF_MATCH, "\"v2\"")]),
+            Precondition::Proceed
+        );
+        assert_eq!(
+            eval(Method::PUT, &[(header::IF_MATCH, "W/\"v2\"")]),
+            Precondition::Failed
+        );
+        assert_eq!(
+            eval(Method::PUT, &[(header::IF_MATCH, "\"v1\"")]),
+            Precondition::Failed
+        );
+
+        assert_eq!(
+            eval(
+                Method::GET,
+                &[(header::IF_MODIFIED_SINCE, "Sun, 01 Jan 2023 12:00:00 GMT")]
+            ),
+            Precondition::NotModified
+        );
+        assert_eq!(
+            eval(
+                Method::GET,
+                &[(header::IF_MODIFIED_SINCE, "Sat, 31 Dec 2022 12:00:00 GMT")]
+            ),
+            Precondition::Proceed
+        );
+        // If-None-Match takes precedence
+        assert_eq!(
+            eval(
+                Method::GET,
+                &[
+                    (header::IF_NONE_MATCH, "\"v1\""),
+                    (header::IF_MODIFIED_SINCE, "Sun, 01 Jan 2023 12:00:00 GMT")
+                ]
+            ),
+            Precondition::Proceed
+        );
+        assert_eq!(
+            eval(
+                Method::DELETE,
+                &[(header::IF_UNMODIFIED_SINCE, "Sat, 31 Dec 2022 12:00:00 GMT")]
+            ),
+            Precondition::Failed
+        );
+    }
+
+    #[test]
+    fn cache_control() {
+        assert_eq!(
+            CacheControl::new()
+                .private()
+                .max_age(60)
+                .must_revalidate()
+                .to_string(),
+            "private, max-age=60, must-revalidate"
+        );
+        assert_eq!(
+            CacheControl::immutable().to_string(),
+            "public, max-age=31536000, immutable"
+        );
+        assert_eq!(
+            CacheControl::no_store().directive("no-store").to_string(),
+            "no-store"
+        );
+    }
+}
@@ -0,0 +1,236 @@
+//! Helpers for issuing and reading signed and encrypted (private) cookies with secure defaults.
+//!
+//! Signed cookies can be read by the client, but not tampered with. Private cookies are encrypted
+//! and authenticated, so the client can neither read nor modify them.
+
+use cookie::{time::Duration, Cookie, CookieJar, Key, KeyError, SameSite};
+use http::{header, HeaderMap};
+
+/// Prefix which makes browsers reject the cookie unless it is `Secure`, has `Path=/` and no `Domain`.
+/// See <https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Set-Cookie#cookie_prefixes>
+pub const HOST_PREFIX: &str = "__Host-";
+
+/// Creates and verifies signed and private cookies with a configured key.
+///
+/// The default attributes are `HttpOnly`, `Secure`, `SameSite=Lax`, `Path=/` and the `__Host-` name prefix.
+/// Cookie names are always given without the prefix, it is added automatically when configured.
+///
+/// ### Example
+///
+/// ```ignore
+/// let cookies = SecureCookies::from_master(secret.as_bytes())?;
+///
+/// // On login
+/// let session = cookies.private("session", session_id.to_string());
+/// response.with_cookies(&[session])?;
+///
+/// // In the middleware
+/// let session = cookies.read_private(request.headers(), "session");
+/// ```
+#[derive(Clone)]
+pub struct SecureCookies {
+    key: Key,
+    pub http_only: bool,
+    pub secure: bool,
+    pub same_site: SameSite,
+    /// Applied only when `secure` is set since browsers reject prefixed cookies otherwise.
+    pub host_prefix: bool,
+    pub max_age: Option<Duration>,
+}
+
+impl std::fmt::Debug for SecureCookies {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SecureCookies")
+            .field("key", &"{ ... }")
+            .field("http_only", &self.http_only)
+            .field("secure", &self.secure)
+            .field("same_site", &self.same_site)
+            .field("host_prefix", &self.host_prefix)
+            .field("max_age", &self.max_age)
+            .finish()
+    }
+}
+
+impl SecureCookies {
+    pub fn new(key: Key) -> Self {
+        Self {
+            key,
+            http_only: true,
+            secure: true,
+            same_site: SameSite::Lax,
+            host_prefix: true,
+            max_age: None,
+        }
+    }
+
+    /// Creates the key from the given master key. Keys of 64 bytes or longer are used directly, keys
+    /// between 32 and 64 bytes are used to derive the signing and encryption keys. Shorter keys are rejected.
+    pub fn from_master(master: &[u8]) -> Result<Self, KeyError> {
+        let key = if master.len() >= 64 {
+            Key::try_from(master)?
+        } else if master.len() >= 32 {
+            Key::derive_from(master)
+        } else {
+            return Err(KeyError::TooShort(master.len()));
+        };
+        Ok(Self::new(key))
+    }
+
+    pub fn with_same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = same_site;
+        self
+    }
+
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Disables the `Secure` attribute and the `__Host-` prefix. Intended for local development over HTTP.
+    pub fn insecure(mut self) -> Self {
+        self.secure = false;
+        self
+    }
+
+    /// Returns the name with the prefix applied, if configured.
+    pub fn name(&self, name: &str) -> String {
+        if self.secure && self.host_prefix {
+            format!("{HOST_PREFIX}{name}")
+        } else {
+            name.to_string()
+        }
+    }
+
+    /// Creates a cookie whose value is signed with the key.
+    pub fn signed(&self, name: &str, value: impl Into<String>) -> Cookie<'static> {
+        let mut jar = CookieJar::new();
+        jar.signed_mut(&self.key)
+            .add(self.build(name, value.into()));
+        jar.delta().next().cloned().expect("cookie added to jar")
+    }
+
+    /// Creates a cookie whose value is encrypted with the key.
+    pub fn private(&self, name: &str, value: impl Into<String>) -> Cookie<'static> {
+        let mut jar = CookieJar::new();
+        jar.private_mut(&self.key)
+            .add(self.build(name, value.into()));
+        jar.delta().next().cloned().expect("cookie added to jar")
+    }
+
+    /// Creates a cookie which instructs the client to remove the cookie with the given name.
+    pub fn removal(&self, name: &str) -> Cookie<'static> {
+        let mut cookie = self.build(name, String::new());
+        cookie.make_removal();
+        cookie
+    }
+
+    /// Verifies a signed cookie and returns it with the plain value.
+    pub fn verify_signed(&self, cookie: Cookie<'static>) -> Option<Cookie<'static>> {
+        CookieJar::new().signed(&self.key).verify(cookie)
+    }
+
+    /// Decrypts a private cookie and returns it with the plain value.
+    pub fn decrypt_private(&self, cookie: Cookie<'static>) -> Option<Cookie<'static>> {
+        CookieJar::new().private(&self.key).decrypt(cookie)
+    }
+
+    /// Finds the signed cookie with the given name in the request headers and verifies it.
+    /// Returns `None` if the cookie is missing or its signature is invalid.
+    pub fn read_signed(&self, headers: &HeaderMap, name: &str) -> Option<Cookie<'static>> {
+        find_cookie(headers, &self.name(name)).and_then(|c| self.verify_signed(c))
+    }
+
+    /// Finds the private cookie with the given name in the request headers and decrypts it.
+    /// Returns `None` if the cookie is missing or could not be decrypted.
+    pub fn read_private(&self, headers: &HeaderMap, name: &str) -> Option<Cookie<'static>> {
+        find_cookie(headers, &self.name(name)).and_then(|c| self.decrypt_private(c))
+    }
+
+    fn build(&self, name: &str, value: String) -> Cookie<'static> {
+        let mut cookie = Cookie::build(self.name(name), value)
+            .path("/")
+            .http_only(self.http_only)
+            .secure(self.secure)
+            .same_site(self.same_site)
+            .finish();
+
+        if let Some(max_age) = self.max_age {
+            cookie.set_max_age(max_age);
+        }
+
+        cookie
+    }
+}
+
+/// Parses all cookies from the `Cookie` headers of a request. Unparseable cookies are skipped.
+pub fn request_cookies(headers: &HeaderMap) -> Vec<Cookie<'static>> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| Cookie::split_parse(value.to_string()))
+        .filter_map(Result::ok)
+        .collect()
+}
+
+/// Finds the cookie with the given name in the `Cookie` headers of a request.
+pub fn find_cookie(headers: &HeaderMap, name: &str) -> Option<Cookie<'static>> {
+    request_cookies(headers)
+        .into_iter()
+        .find(|cookie| cookie.name() == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    fn request_headers(cookie: &Cookie) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::COOKIE,
+            HeaderValue::from_str(&format!("foo=bar; {}", cookie.stripped())).unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn signed() {
+        let cookies = SecureCookies::new(Key::generate());
+        let cookie = cookies.signed("csrf", "token");
+
+        assert_eq!(cookie.name(), "__Host-csrf");
+        assert_eq!(cookie.http_only(), Some(true));
+        assert_eq!(cookie.secure(), Some(true));
+        assert_eq!(cookie.same_site(), Some(SameSite::Lax));
+        assert_eq!(cookie.path(), Some("/"));
+        assert!(cookie.value().ends_with("token"));
+
+        let headers = request_headers(&cookie);
+        let read = cookies.read_signed(&headers, "csrf").unwrap();
+        assert_eq!(read.value(), "token");
+
+        let other = SecureCookies::new(Key::generate());
+        assert!(other.read_signed(&headers, "csrf").is_none());
+    }
+
+    #[test]
+    fn private() {
+        let cookies = SecureCookies::from_master(&[7; 32]).unwrap().insecure();
+        let cookie = cookies.private("session", "id");
+
+        assert_eq!(cookie.name(), "session");
+        // The value is the nonce and ciphertext, never the plaintext
+        assert_ne!(cookie.value(), "id");
+        assert!(cookie.value().len() > "id".len());
+
+        let headers = request_headers(&cookie);
+        assert_eq!(
+            cookies.read_private(&headers, "session").unwrap().value(),
+            "id"
+        );
+        assert!(cookies.read_signed(&headers, "session").is_none());
+
+        assert!(SecureCookies::from_master(&[7; 16]).is_err());
+    }
+}
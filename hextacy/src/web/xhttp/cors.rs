@@ -0,0 +1,666 @@
+//! Cross origin resource sharing.
+//!
+//! A [CorsConfig] describes which origins may access the resources and how. It can be used directly
+//! with any framework through [CorsConfig::evaluate], or as a tower middleware via the [CorsLayer]:
+//!
+//! ```ignore
+//! let cors = CorsConfig::new()
+//!     .allow_origin(AllowOrigin::exact("https://example.com"))
+//!     .allow_origin(AllowOrigin::wildcard_subdomain("https://*.example.com")?)
+//!     .allow_methods([Method::GET, Method::POST, Method::DELETE])
+//!     .allow_headers([header::CONTENT_TYPE, header::AUTHORIZATION])
+//!     .allow_credentials()
+//!     .max_age(3600);
+//!
+//! let router = Router::new().route(/* ... */).layer(CorsLayer::new(cors)?);
+//! ```
+//!
+//! See <https://developer.mozilla.org/en-US/docs/Web/HTTP/CORS>
+
+use futures::future::BoxFuture;
+use http::{header, HeaderMap, HeaderName, HeaderValue, Method, Request, Response, StatusCode};
+use regex::Regex;
+use std::{
+    sync::Arc,
+    task::{Context, Poll},
+};
+use thiserror::Error;
+use tower_layer::Layer;
+use tower_service::Service;
+use tracing::debug;
+
+/// A rule for matching the `Origin` header of a request.
+#[derive(Clone)]
+pub enum AllowOrigin {
+    /// Any origin. Cannot be combined with credentials.
+    Any,
+    /// An origin in the form of `scheme://host[:port]`.
+    Exact(String),
+    /// Any subdomain of a domain, see [AllowOrigin::wildcard_subdomain].
+    WildcardSubdomain { scheme: String, domain: String },
+    /// Origins matching the expression. Make sure to anchor it with `^` and `$`.
+    Regex(Regex),
+    /// Origins for which the function returns `true`.
+    Predicate(Arc<dyn Fn(&str) -> bool + Send + Sync>),
+}
+
+impl std::fmt::Debug for AllowOrigin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Any => write!(f, "Any"),
+            Self::Exact(origin) => f.debug_tuple("Exact").field(origin).finish(),
+            Self::WildcardSubdomain { scheme, domain } => f
+                .debug_struct("WildcardSubdomain")
+                .field("scheme", scheme)
+                .field("domain", domain)
+                .finish(),
+            Self::Regex(re) => f.debug_tuple("Regex").field(&re.as_str()).finish(),
+            Self::Predicate(_) => f.debug_tuple("Predicate").field(&"{ ... }").finish(),
+        }
+    }
+}
+
+impl AllowOrigin {
+    pub fn exact(origin: &str) -> Self {
+        Self::Exact(origin.trim_end_matches('/').to_string())
+    }
+
+    /// Parses a pattern in the form of `scheme://*.domain[:port]`, e.g. `https://*.example.com`.
+    /// The pattern matches any subdomain, at any depth, but not the domain itself.
+    pub fn wildcard_subdomain(pattern: &str) -> Result<Self, CorsError> {
+        let invalid = || CorsError::InvalidPattern(pattern.to_string());
+
+        let (scheme, domain) = pattern.split_once("://*.").ok_or_else(invalid)?;
+
+        if scheme.is_empty() || domain.is_empty() || domain.contains(['*', '/']) {
+            return Err(invalid());
+        }
+
+        Ok(Self::WildcardSubdomain {
+            scheme: scheme.to_string(),
+            domain: domain.trim_end_matches('/').to_string(),
+        })
+    }
+
+    pub fn regex(pattern: &str) -> Result<Self, CorsError> {
+        Ok(Self::Regex(Regex::new(pattern)?))
+    }
+
+    pub fn predicate(f: impl Fn(&str) -> bool + Send + Sync + 'static) -> Self {
+        Self::Predicate(Arc::new(f))
+    }
+
+    pub fn matches(&self, origin: &str) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Exact(allowed) => allowed == origin,
+            Self::WildcardSubdomain { scheme, domain } => {
+                let Some(host) = origin
+                    .strip_prefix(scheme.as_str())
+                    .and_then(|rest| rest.strip_prefix("://"))
+                else {
+                    return false;
+                };
+                let Some(sub) = host
+                    .strip_suffix(domain.as_str())
+                    .and_then(|sub| sub.strip_suffix('.'))
+                else {
+                    return false;
+                };
+                !sub.is_empty()
+                    && sub
+                        .split('.')
+                        .all(|label| !label.is_empty() && label.chars().all(is_label_char))
+            }
+            Self::Regex(re) => re.is_match(origin),
+            Self::Predicate(f) => f(origin),
+        }
+    }
+}
+
+fn is_label_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-'
+}
+
+/// Either anything or a list of allowed values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Allow<T> {
+    /// Mirrors the values the client requested.
+    Any,
+    List(Vec<T>),
+}
+
+#[derive(Debug, Clone)]
+pub struct CorsConfig {
+    /// If empty, no cross origin requests are allowed.
+    pub origins: Vec<AllowOrigin>,
+    pub methods: Allow<Method>,
+    pub headers: Allow<HeaderName>,
+    /// Headers the client is allowed to read from responses, besides the CORS safelisted ones.
+    pub expose_headers: Vec<HeaderName>,
+    pub credentials: bool,
+    /// How long, in seconds, the client can cache preflight responses.
+    pub max_age: Option<u64>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            origins: vec![],
+            methods: Allow::List(vec![Method::GET, Method::HEAD, Method::POST]),
+            headers: Allow::List(vec![]),
+            expose_headers: vec![],
+            credentials: false,
+            max_age: None,
+        }
+    }
+}
+
+/// The outcome of evaluating a request against a [CorsConfig].
+#[derive(Debug)]
+pub enum CorsDecision {
+    /// An allowed preflight request. It should be answered immediately with `204 No Content` and the headers.
+    Preflight(HeaderMap),
+    /// A preflight request that is not allowed. It should be answered immediately, without reaching the handler.
+    /// The headers contain only `Vary`.
+    Rejected(CorsError, HeaderMap),
+    /// Any other request. It should be forwarded and the headers appended to its response. The headers contain
+    /// only `Vary` if the request is not cross origin or the origin is not allowed, in which case the client
+    /// will not expose the response.
+    Forward(HeaderMap),
+}
+
+impl CorsConfig {
+    /// Creates a configuration which allows no origins, the `GET`, `HEAD` and `POST` methods and no
+    /// additional headers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn allow_origin(mut self, origin: AllowOrigin) -> Self {
+        self.origins.push(origin);
+        self
+    }
+
+    pub fn allow_any_origin(self) -> Self {
+        self.allow_origin(AllowOrigin::Any)
+    }
+
+    pub fn allow_methods(mut self, methods: impl IntoIterator<Item = Method>) -> Self {
+        self.methods = Allow::List(methods.into_iter().collect());
+        self
+    }
+
+    pub fn allow_any_method(mut self) -> Self {
+        self.methods = Allow::Any;
+        self
+    }
+
+    pub fn allow_headers(mut self, headers: impl IntoIterator<Item = HeaderName>) -> Self {
+        self.headers = Allow::List(headers.into_iter().collect());
+        self
+    }
+
+    pub fn allow_any_header(mut self) -> Self {
+        self.headers = Allow::Any;
+        self
+    }
+
+    pub fn expose_headers(mut self, headers: impl IntoIterator<Item = HeaderName>) -> Self {
+        self.expose_headers = headers.into_iter().collect();
+        self
+    }
+
+    pub fn allow_credentials(mut self) -> Self {
+        self.credentials = true;
+        self
+    }
+
+    pub fn max_age(mut self, seconds: u64) -> Self {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    /// Allowing credentials for any origin would let every site make authenticated requests
+    /// on behalf of the user, so the combination is rejected.
+    pub fn validate(&self) -> Result<(), CorsError> {
+        if self.credentials && self.allows_any_origin() {
+            return Err(CorsError::CredentialsWithAnyOrigin);
+        }
+        Ok(())
+    }
+
+    pub fn is_allowed_origin(&self, origin: &str) -> bool {
+        self.origins.iter().any(|o| o.matches(origin))
+    }
+
+    /// Determines how to handle the request.
+    pub fn evaluate<B>(&self, req: &Request<B>) -> CorsDecision {
+        let headers = req.headers();
+        let origin = headers.get(header::ORIGIN);
+
+        let is_preflight = req.method() == Method::OPTIONS
+            && origin.is_some()
+            && headers.contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+
+        let mut res = HeaderMap::new();
+
+        if is_preflight {
+            res.insert(
+                header::VARY,
+                HeaderValue::from_static(
+                    "origin, access-control-request-method, access-control-request-headers",
+                ),
+            );
+        } else if !self.is_wildcard() {
+            // Responses differ per origin so caches must not share them
+            res.insert(header::VARY, HeaderValue::from_static("origin"));
+        }
+
+        let Some(origin) = origin else {
+            return CorsDecision::Forward(res);
+        };
+
+        let allowed_origin = origin
+            .to_str()
+            .ok()
+            .filter(|origin| self.is_allowed_origin(origin));
+
+        let Some(allowed_origin) = allowed_origin else {
+            let origin = String::from_utf8_lossy(origin.as_bytes()).to_string();
+            return if is_preflight {
+                CorsDecision::Rejected(CorsError::OriginNotAllowed(origin), res)
+            } else {
+                debug!("CORS origin not allowed: {origin}");
+                CorsDecision::Forward(res)
+            };
+        };
+
+        if self.is_wildcard() {
+            res.insert(
+                header::ACCESS_CONTROL_ALLOW_ORIGIN,
+                HeaderValue::from_static("*"),
+            );
+        } else {
+            res.insert(
+                header::ACCESS_CONTROL_ALLOW_ORIGIN,
+                HeaderValue::from_str(allowed_origin).expect("valid origin header"),
+            );
+        }
+
+        if self.credentials {
+            res.insert(
+                header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+
+        if !is_preflight {
+            if !self.expose_headers.is_empty() {
+                res.insert(
+                    header::ACCESS_CONTROL_EXPOSE_HEADERS,
+                    join(self.expose_headers.iter().map(HeaderName::as_str)),
+                );
+            }
+            return CorsDecision::Forward(res);
+        }
+
+        if let Err(e) = self.preflight(headers, &mut res) {
+            retain_vary(&mut res);
+            return CorsDecision::Rejected(e, res);
+        }
+
+        CorsDecision::Preflight(res)
+    }
+
+    fn preflight(&self, headers: &HeaderMap, res: &mut HeaderMap) -> Result<(), CorsError> {
+        let requested_method = headers
+            .get(header::ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|m| Method::from_bytes(m.as_bytes()).ok())
+            .ok_or_else(|| CorsError::MethodNotAllowed(String::new()))?;
+
+        match self.methods {
+            Allow::Any => {
+                res.insert(
+                    header::ACCESS_CONTROL_ALLOW_METHODS,
+                    HeaderValue::from_str(requested_method.as_str()).expect("valid method"),
+                );
+            }
+            Allow::List(ref methods) => {
+                if !methods.contains(&requested_method) {
+                    return Err(CorsError::MethodNotAllowed(
+                        requested_method.as_str().to_string(),
+                    ));
+                }
+                res.insert(
+                    header::ACCESS_CONTROL_ALLOW_METHODS,
+                    join(methods.iter().map(Method::as_str)),
+                );
+            }
+        }
+
+        let requested_headers = headers
+            .get_all(header::ACCESS_CONTROL_REQUEST_HEADERS)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(str::trim)
+            .filter(|h| !h.is_empty())
+            .map(|h| {
+                HeaderName::from_bytes(h.as_bytes())
+                    .map_err(|_| CorsError::HeaderNotAllowed(h.to_string()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        match self.headers {
+            // The `*` wildcard is ignored by clients for credentialed requests so the requested headers are mirrored
+            Allow::Any => {
+                if !requested_headers.is_empty() {
+                    res.insert(
+                        header::ACCESS_CONTROL_ALLOW_HEADERS,
+                        join(requested_headers.iter().map(HeaderName::as_str)),
+                    );
+                }
+            }
+            Allow::List(ref allowed) => {
+                if let Some(h) = requested_headers.iter().find(|h| !allowed.contains(h)) {
+                    return Err(CorsError::HeaderNotAllowed(h.to_string()));
+                }
+                if !allowed.is_empty() {
+                    res.insert(
+                        header::ACCESS_CONTROL_ALLOW_HEADERS,
+                        join(allowed.iter().map(HeaderName::as_str)),
+                    );
+                }
+            }
+        }
+
+        if let Some(max_age) = self.max_age {
+            res.insert(header::ACCESS_CONTROL_MAX_AGE, HeaderValue::from(max_age));
+        }
+
+        Ok(())
+    }
+
+    fn allows_any_origin(&self) -> bool {
+        self.origins.iter().any(|o| matches!(o, AllowOrigin::Any))
+    }
+
+    /// Whether `*` can be sent instead of the request origin.
+    fn is_wildcard(&self) -> bool {
+        self.allows_any_origin() && !self.credentials
+    }
+}
+
+/// Removes all headers except `Vary`.
+fn retain_vary(headers: &mut HeaderMap) {
+    let vary = headers.remove(header::VARY);
+    headers.clear();
+    if let Some(vary) = vary {
+        headers.insert(header::VARY, vary);
+    }
+}
+
+fn join<'a>(values: impl Iterator<Item = &'a str>) -> HeaderValue {
+    HeaderValue::from_str(&values.collect::<Vec<_>>().join(", "))
+        .expect("methods and header names are valid header values")
+}
+
+#[derive(Debug, Error)]
+pub enum CorsError {
+    #[error("Origin not allowed: {0}")]
+    OriginNotAllowed(String),
+    #[error("Method not allowed: {0}")]
+    MethodNotAllowed(String),
+    #[error("Header not allowed: {0}")]
+    HeaderNotAllowed(String),
+    #[error("Invalid origin pattern: {0}")]
+    InvalidPattern(String),
+    #[error("Regex: {0}")]
+    Regex(#[from] regex::Error),
+    #[error("Credentials cannot be allowed for any origin")]
+    CredentialsWithAnyOrigin,
+}
+
+/// Applies [CorsConfig::evaluate] to every request. Preflight requests are answered directly with
+/// `204 No Content` if allowed and `403 Forbidden` otherwise.
+#[derive(Debug, Clone)]
+pub struct CorsLayer {
+    config: Arc<CorsConfig>,
+}
+
+impl CorsLayer {
+    pub fn new(config: CorsConfig) -> Result<Self, CorsError> {
+        config.validate()?;
+        Ok(Self {
+            config: Arc::new(config),
+        })
+    }
+}
+
+impl<S> Layer<S> for CorsLayer {
+    type Service = CorsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CorsService {
+            inner,
+            config: self.config.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CorsService<S> {
+    inner: S,
+    config: Arc<CorsConfig>,
+}
+
+impl<S, ReqB, ResB> Service<Request<ReqB>> for CorsService<S>
+where
+    S: Service<Request<ReqB>, Response = Response<ResB>>,
+    S::Future: Send + 'static,
+    ResB: Default + Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqB>) -> Self::Future {
+        let (status, headers) = match self.config.evaluate(&req) {
+            CorsDecision::Preflight(headers) => (StatusCode::NO_CONTENT, headers),
+            CorsDecision::Rejected(e, headers) => {
+                debug!("CORS preflight rejected for {}: {e}", req.uri());
+                (StatusCode::FORBIDDEN, headers)
+            }
+            CorsDecision::Forward(headers) => {
+                let fut = self.inner.call(req);
+                return Box::pin(async move {
+                    let mut res = fut.await?;
+                    let res_headers = res.headers_mut();
+                    for (name, value) in headers {
+                        let Some(name) = name else { continue };
+                        if name == header::VARY {
+                            res_headers.append(name, value);
+                        } else {
+                            res_headers.insert(name, value);
+                        }
+                    }
+                    Ok(res)
+                });
+            }
+        };
+
+        let mut res = Response::new(ResB::default());
+        *res.status_mut() = status;
+        *res.headers_mut() = headers;
+        Box::pin(async move { Ok(res) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower::{service_fn, ServiceExt};
+
+    fn preflight(origin: &str, method: &str, headers: &str) -> Request<()> {
+        Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/resource")
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, method)
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, headers)
+            .body(())
+            .unwrap()
+    }
+
+    #[test]
+    fn origins() {
+        let wildcard = AllowOrigin::wildcard_subdomain("https://*.example.com").unwrap();
+        assert!(wildcard.matches("https://api.example.com"));
+        assert!(wildcard.matches("https://a.b.example.com"));
+        assert!(!wildcard.matches("https://example.com"));
+        assert!(!wildcard.matches("http://api.example.com"));
+        assert!(!wildcard.matches("https://evilexample.com"));
+        assert!(!wildcard.matches("https://api.example.com.evil.com"));
+        assert!(AllowOrigin::wildcard_subdomain("https://example.com").is_err());
+
+        let re = AllowOrigin::regex(r"^https://pr-\d+\.preview\.dev$").unwrap();
+        assert!(re.matches("https://pr-42.preview.dev"));
+        assert!(!re.matches("https://pr-x.preview.dev"));
+
+        let pred = AllowOrigin::predicate(|o| o.ends_with(":3000"));
+        assert!(pred.matches("http://localhost:3000"));
+        assert!(AllowOrigin::exact("https://example.com/").matches("https://example.com"));
+
+        assert!(matches!(
+            CorsConfig::new()
+                .allow_any_origin()
+                .allow_credentials()
+                .validate(),
+            Err(CorsError::CredentialsWithAnyOrigin)
+        ));
+    }
+
+    #[test]
+    fn evaluate() {
+        let config = CorsConfig::new()
+            .allow_origin(AllowOrigin::exact("https://example.com"))
+            .allow_methods([Method::GET, Method::PUT])
+            .allow_headers([header::CONTENT_TYPE, header::AUTHORIZATION])
+            .expose_headers([HeaderName::from_static("x-request-id")])
+            .allow_credentials()
+            .max_age(600);
+
+        let CorsDecision::Preflight(headers) = config.evaluate(&preflight(
+            "https://example.com",
+            "PUT",
+            "Content-Type, authorization",
+        )) else {
+            panic!("expected preflight")
+        };
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://example.com"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_METHODS], "GET, PUT");
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_HEADERS],
+            "content-type, authorization"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert_eq!(headers[header::ACCESS_CONTROL_MAX_AGE], "600");
+        assert!(headers[header::VARY]
+            .to_str()
+            .unwrap()
+            .contains("access-control-request-headers"));
+
+        assert!(matches!(
+            config.evaluate(&preflight("https://example.com", "DELETE", "")),
+            CorsDecision::Rejected(CorsError::MethodNotAllowed(_), _)
+        ));
+        assert!(matches!(
+            config.evaluate(&preflight("https://example.com", "GET", "x-custom")),
+            CorsDecision::Rejected(CorsError::HeaderNotAllowed(_), _)
+        ));
+        let CorsDecision::Rejected(CorsError::OriginNotAllowed(_), headers) =
+            config.evaluate(&preflight("https://evil.com", "GET", ""))
+        else {
+            panic!("expected rejection")
+        };
+        assert_eq!(headers.len(), 1);
+
+        let actual = Request::get("/")
+            .header(header::ORIGIN, "https://example.com")
+            .body(())
+            .unwrap();
+        let CorsDecision::Forward(headers) = config.evaluate(&actual) else {
+            panic!("expected forward")
+        };
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_EXPOSE_HEADERS],
+            "x-request-id"
+        );
+        assert_eq!(headers[header::VARY], "origin");
+
+        let any = CorsConfig::new().allow_any_origin();
+        let CorsDecision::Forward(headers) = any.evaluate(&actual) else {
+            panic!("expected forward")
+        };
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert!(!headers.contains_key(header::VARY));
+    }
+
+    #[tokio::test]
+    async fn layer() {
+        let config = CorsConfig::new()
+            .allow_origin(AllowOrigin::exact("https://example.com"))
+            .allow_any_header();
+        let service = CorsLayer::new(config)
+            .unwrap()
+            .layer(service_fn(|_: Request<()>| async {
+                let mut res = Response::new(());
+                res.headers_mut()
+                    .insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+                Ok::<_, std::convert::Infallible>(res)
+            }));
+
+        let res = service
+            .clone()
+            .oneshot(preflight("https://example.com", "POST", "x-custom"))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            res.headers()[header::ACCESS_CONTROL_ALLOW_HEADERS],
+            "x-custom"
+        );
+
+        let res = service
+            .clone()
+            .oneshot(preflight("https://evil.com", "POST", ""))
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        let res = service
+            .oneshot(
+                Request::post("/")
+                    .header(header::ORIGIN, "https://example.com")
+                    .body(())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let vary = res
+            .headers()
+            .get_all(header::VARY)
+            .iter()
+            .collect::<Vec<_>>();
+        assert_eq!(vary, ["accept-encoding", "origin"]);
+    }
+}
@@ -0,0 +1,545 @@
+//! Typed builder for the `Content-Security-Policy` header.
+//! See <https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Content-Security-Policy>
+//!
+//! ### Example
+//!
+//! ```ignore
+//! let policy = ContentSecurityPolicy::default_policy()
+//!     .directive(Directive::ScriptSrc, [Source::Host("https://cdn.example.com".into())])
+//!     .report_to("csp-endpoint");
+//!
+//! // Per request
+//! let nonce = csp::generate_nonce();
+//! let (name, value) = policy.with_nonce(&nonce).header()?;
+//! ```
+
+use data_encoding::BASE64;
+use http::header::{
+    HeaderName, HeaderValue, InvalidHeaderValue, CONTENT_SECURITY_POLICY,
+    CONTENT_SECURITY_POLICY_REPORT_ONLY,
+};
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+use std::fmt::Display;
+use thiserror::Error;
+
+/// Generates a random base64 nonce with 16 bytes of entropy. A new one should be generated for every response.
+pub fn generate_nonce() -> String {
+    let mut buf = [0_u8; 16];
+    StdRng::from_entropy().fill_bytes(&mut buf);
+    BASE64.encode(&buf)
+}
+
+/// CSP directives.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Directive {
+    DefaultSrc,
+    ScriptSrc,
+    ScriptSrcAttr,
+    ScriptSrcElem,
+    StyleSrc,
+    StyleSrcAttr,
+    StyleSrcElem,
+    ImgSrc,
+    FontSrc,
+    ConnectSrc,
+    MediaSrc,
+    ObjectSrc,
+    FrameSrc,
+    ChildSrc,
+    WorkerSrc,
+    ManifestSrc,
+    BaseUri,
+    FormAction,
+    FrameAncestors,
+    Sandbox,
+    UpgradeInsecureRequests,
+    ReportUri,
+    ReportTo,
+    /// Any directive not covered by the other variants
+    Other(String),
+}
+
+impl Directive {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Directive::DefaultSrc => "default-src",
+            Directive::ScriptSrc => "script-src",
+            Directive::ScriptSrcAttr => "script-src-attr",
+            Directive::ScriptSrcElem => "script-src-elem",
+            Directive::StyleSrc => "style-src",
+            Directive::StyleSrcAttr => "style-src-attr",
+            Directive::StyleSrcElem => "style-src-elem",
+            Directive::ImgSrc => "img-src",
+            Directive::FontSrc => "font-src",
+            Directive::ConnectSrc => "connect-src",
+            Directive::MediaSrc => "media-src",
+            Directive::ObjectSrc => "object-src",
+            Directive::FrameSrc => "frame-src",
+            Directive::ChildSrc => "child-src",
+            Directive::WorkerSrc => "worker-src",
+            Directive::ManifestSrc => "manifest-src",
+            Directive::BaseUri => "base-uri",
+            Directive::FormAction => "form-action",
+            Directive::FrameAncestors => "frame-ancestors",
+            Directive::Sandbox => "sandbox",
+            Directive::UpgradeInsecureRequests => "upgrade-insecure-requests",
+            Directive::ReportUri => "report-uri",
+            Directive::ReportTo => "report-to",
+            Directive::Other(s) => s,
+        }
+    }
+
+    /// Directive names are case insensitive.
+    pub fn parse(name: &str) -> Self {
+        match name.to_ascii_lowercase().as_str() {
+            "default-src" => Directive::DefaultSrc,
+            "script-src" => Directive::ScriptSrc,
+            "script-src-attr" => Directive::ScriptSrcAttr,
+            "script-src-elem" => Directive::ScriptSrcElem,
+            "style-src" => Directive::StyleSrc,
+            "style-src-attr" => Directive::StyleSrcAttr,
+            "style-src-elem" => Directive::StyleSrcElem,
+            "img-src" => Directive::ImgSrc,
+            "font-src" => Directive::FontSrc,
+            "connect-src" => Directive::ConnectSrc,
+            "media-src" => Directive::MediaSrc,
+            "object-src" => Directive::ObjectSrc,
+            "frame-src" => Directive::FrameSrc,
+            "child-src" => Directive::ChildSrc,
+            "worker-src" => Directive::WorkerSrc,
+            "manifest-src" => Directive::ManifestSrc,
+            "base-uri" => Directive::BaseUri,
+            "form-action" => Directive::FormAction,
+            "frame-ancestors" => Directive::FrameAncestors,
+            "sandbox" => Directive::Sandbox,
+            "upgrade-insecure-requests" => Directive::UpgradeInsecureRequests,
+            "report-uri" => Directive::ReportUri,
+            "report-to" => Directive::ReportTo,
+            other => Directive::Other(other.to_string()),
+        }
+    }
+}
+
+impl Display for Directive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Hash algorithms allowed in hash sources.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HashAlgorithm {
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl HashAlgorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha384 => "sha384",
+            HashAlgorithm::Sha512 => "sha512",
+        }
+    }
+}
+
+/// Source expressions. Values that are not sources, e.g. sandbox flags or reporting
+/// endpoints, are represented with [Source::Value].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Source {
+    /// `'none'`
+    None,
+    /// `'self'`
+    SelfOrigin,
+    /// `'unsafe-inline'`
+    UnsafeInline,
+    /// `'unsafe-eval'`
+    UnsafeEval,
+    /// `'unsafe-hashes'`
+    UnsafeHashes,
+    /// `'wasm-unsafe-eval'`
+    WasmUnsafeEval,
+    /// `'strict-dynamic'`
+    StrictDynamic,
+    /// `'report-sample'`
+    ReportSample,
+    /// `'nonce-<base64>'`
+    Nonce(String),
+    /// `'<algorithm>-<base64>'`
+    Hash(HashAlgorithm, String),
+    /// A scheme source, e.g. `https:` or `data:`. Stored without the trailing colon.
+    Scheme(String),
+    /// A host source, e.g. `https://cdn.example.com` or `*.example.com`
+    Host(String),
+    /// Anything else
+    Value(String),
+}
+
+impl Source {
+    pub fn parse(s: &str) -> Self {
+        let lower = s.to_ascii_lowercase();
+        match lower.as_str() {
+            "'none'" => return Source::None,
+            "'self'" => return Source::SelfOrigin,
+            "'unsafe-inline'" => return Source::UnsafeInline,
+            "'unsafe-eval'" => return Source::UnsafeEval,
+            "'unsafe-hashes'" => return Source::UnsafeHashes,
+            "'wasm-unsafe-eval'" => return Source::WasmUnsafeEval,
+            "'strict-dynamic'" => return Source::StrictDynamic,
+            "'report-sample'" => return Source::ReportSample,
+            _ => {}
+        }
+
+        if let Some(quoted) = s.strip_prefix('\'').and_then(|s| s.strip_suffix('\'')) {
+            if let Some((kind, value)) = quoted.split_once('-') {
+                let value = value.to_string();
+                match kind.to_ascii_lowercase().as_str() {
+                    "nonce" => return Source::Nonce(value),
+                    "sha256" => return Source::Hash(HashAlgorithm::Sha256, value),
+                    "sha384" => return Source::Hash(HashAlgorithm::Sha384, value),
+                    "sha512" => return Source::Hash(HashAlgorithm::Sha512, value),
+                    _ => {}
+                }
+            }
+            return Source::Value(s.to_string());
+        }
+
+        if let Some(scheme) = s.strip_suffix(':') {
+            if scheme.chars().all(is_scheme_char) {
+                return Source::Scheme(scheme.to_string());
+            }
+        }
+
+        if s.contains('.') || s.contains("://") || s == "*" || s.starts_with("localhost") {
+            return Source::Host(s.to_string());
+        }
+
+        Source::Value(s.to_string())
+    }
+}
+
+fn is_scheme_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.'
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::None => write!(f, "'none'"),
+            Source::SelfOrigin => write!(f, "'self'"),
+            Source::UnsafeInline => write!(f, "'unsafe-inline'"),
+            Source::UnsafeEval => write!(f, "'unsafe-eval'"),
+            Source::UnsafeHashes => write!(f, "'unsafe-hashes'"),
+            Source::WasmUnsafeEval => write!(f, "'wasm-unsafe-eval'"),
+            Source::StrictDynamic => write!(f, "'strict-dynamic'"),
+            Source::ReportSample => write!(f, "'report-sample'"),
+            Source::Nonce(n) => write!(f, "'nonce-{n}'"),
+            Source::Hash(algo, h) => write!(f, "'{}-{h}'", algo.as_str()),
+            Source::Scheme(s) => write!(f, "{s}:"),
+            Source::Host(h) => write!(f, "{h}"),
+            Source::Value(v) => write!(f, "{v}"),
+        }
+    }
+}
+
+/// A content security policy consisting of directives and their sources. Directives are
+/// kept in insertion order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContentSecurityPolicy {
+    directives: Vec<(Directive, Vec<Source>)>,
+    report_only: bool,
+}
+
+impl ContentSecurityPolicy {
+    /// Creates an empty policy.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A reasonably strict policy allowing resources only from the same origin.
+    ///
+    /// `default-src 'self'; base-uri 'self'; font-src 'self' https: data:; form-action 'self';
+    /// frame-ancestors 'self'; img-src 'self' data:; object-src 'none'; script-src 'self';
+    /// script-src-attr 'none'; style-src 'self' https: 'unsafe-inline'; upgrade-insecure-requests`
+    pub fn default_policy() -> Self {
+        use Directive as D;
+        use Source as S;
+        Self::new()
+            .directive(D::DefaultSrc, [S::SelfOrigin])
+            .directive(D::BaseUri, [S::SelfOrigin])
+            .directive(
+                D::FontSrc,
+                [
+                    S::SelfOrigin,
+                    S::Scheme("https".into()),
+                    S::Scheme("data".into()),
+                ],
+            )
+            .directive(D::FormAction, [S::SelfOrigin])
+            .directive(D::FrameAncestors, [S::SelfOrigin])
+            .directive(D::ImgSrc, [S::SelfOrigin, S::Scheme("data".into())])
+            .directive(D::ObjectSrc, [S::None])
+            .directive(D::ScriptSrc, [S::SelfOrigin])
+            .directive(D::ScriptSrcAttr, [S::None])
+            .directive(
+                D::StyleSrc,
+                [S::SelfOrigin, S::Scheme("https".into()), S::UnsafeInline],
+            )
+            .directive(D::UpgradeInsecureRequests, [])
+    }
+
+    /// Adds the sources to the directive, creating it if it does not exist. Adding sources to a directive
+    /// containing `'none'` removes `'none'` and vice versa.
+    pub fn directive(
+        mut self,
+        directive: Directive,
+        sources: impl IntoIterator<Item = Source>,
+    ) -> Self {
+        let idx = match self.directives.iter().position(|(d, _)| *d == directive) {
+            Some(idx) => idx,
+            None => {
+                self.directives.push((directive, vec![]));
+                self.directives.len() - 1
+            }
+        };
+
+        let existing = &mut self.directives[idx].1;
+        for source in sources {
+            if source == Source::None {
+                existing.clear();
+            } else {
+                existing.retain(|s| *s != Source::None);
+            }
+            if !existing.contains(&source) {
+                existing.push(source);
+            }
+        }
+
+        self
+    }
+
+    /// Replaces the sources of the directive.
+    pub fn set(mut self, directive: Directive, sources: impl IntoIterator<Item = Source>) -> Self {
+        self = self.remove(&directive);
+        self.directive(directive, sources)
+    }
+
+    pub fn remove(mut self, directive: &Directive) -> Self {
+        self.directives.retain(|(d, _)| d != directive);
+        self
+    }
+
+    pub fn get(&self, directive: &Directive) -> Option<&[Source]> {
+        self.directives
+            .iter()
+            .find(|(d, _)| d == directive)
+            .map(|(_, s)| s.as_slice())
+    }
+
+    /// Sets the deprecated `report-uri` directive.
+    pub fn report_uri(self, uri: &str) -> Self {
+        self.set(Directive::ReportUri, [Source::Value(uri.to_string())])
+    }
+
+    /// Sets the `report-to` directive. The group must be defined in the `Reporting-Endpoints` header,
+    /// see [reporting_endpoints].
+    pub fn report_to(self, group: &str) -> Self {
+        self.set(Directive::ReportTo, [Source::Value(group.to_string())])
+    }
+
+    /// When set, the policy is sent in the `Content-Security-Policy-Report-Only` header and violations are
+    /// only reported, not enforced.
+    pub fn report_only(mut self, report_only: bool) -> Self {
+        self.report_only = report_only;
+        self
+    }
+
+    pub fn is_report_only(&self) -> bool {
+        self.report_only
+    }
+
+    /// Returns a copy of the policy with the nonce added to `script-src` and `style-src`. If either of them
+    /// is missing, it is created with the sources of `default-src` so the nonce does not restrict the policy.
+    pub fn with_nonce(&self, nonce: &str) -> Self {
+        let defaults = self
+            .get(&Directive::DefaultSrc)
+            .map(<[Source]>::to_vec)
+            .unwrap_or_default();
+
+        let mut policy = self.clone();
+        for directive in [Directive::ScriptSrc, Directive::StyleSrc] {
+            if policy.get(&directive).is_none() {
+                policy = policy.directive(directive.clone(), defaults.clone());
+            }
+            // 'none' cannot be combined with other sources so the nonce would be dropped by it
+            policy = policy.directive(directive, [Source::Nonce(nonce.to_string())]);
+        }
+        policy
+    }
+
+    /// Merges the other policy into this one by taking the union of the sources of each directive.
+    pub fn merge(mut self, other: &ContentSecurityPolicy) -> Self {
+        for (directive, sources) in other.directives.iter() {
+            self = self.directive(directive.clone(), sources.iter().cloned());
+        }
+        self
+    }
+
+    /// Parses a policy from a header value. Directives are separated with `;` and sources with whitespace.
+    /// As per the spec, only the first occurrence of a directive is used.
+    pub fn parse(policy: &str) -> Result<Self, CspError> {
+        let mut this = Self::new();
+
+        for directive in policy.split(';') {
+            let mut tokens = directive.split_ascii_whitespace();
+            let Some(name) = tokens.next() else {
+                continue;
+            };
+
+            if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+                return Err(CspError::InvalidDirective(name.to_string()));
+            }
+
+            let directive = Directive::parse(name);
+            if this.get(&directive).is_some() {
+                continue;
+            }
+
+            this = this.directive(directive, tokens.map(Source::parse));
+        }
+
+        Ok(this)
+    }
+
+    /// Returns the header name and value for this policy.
+    pub fn header(&self) -> Result<(HeaderName, HeaderValue), InvalidHeaderValue> {
+        let name = if self.report_only {
+            CONTENT_SECURITY_POLICY_REPORT_ONLY
+        } else {
+            CONTENT_SECURITY_POLICY
+        };
+        Ok((name, HeaderValue::from_str(&self.to_string())?))
+    }
+}
+
+impl Display for ContentSecurityPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let directives = self
+            .directives
+            .iter()
+            .map(|(directive, sources)| {
+                let mut s = directive.to_string();
+                for source in sources {
+                    s.push(' ');
+                    s.push_str(&source.to_string());
+                }
+                s
+            })
+            .collect::<Vec<_>>();
+        write!(f, "{}", directives.join("; "))
+    }
+}
+
+/// Creates the `Reporting-Endpoints` header from the given `(group, url)` pairs.
+/// See <https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Reporting-Endpoints>
+pub fn reporting_endpoints(
+    endpoints: &[(&str, &str)],
+) -> Result<(HeaderName, HeaderValue), InvalidHeaderValue> {
+    let value = endpoints
+        .iter()
+        .map(|(group, url)| format!("{group}=\"{url}\""))
+        .collect::<Vec<_>>()
+        .join(", ");
+    Ok((
+        HeaderName::from_static("reporting-endpoints"),
+        HeaderValue::from_str(&value)?,
+    ))
+}
+
+#[derive(Debug, Error)]
+pub enum CspError {
+    #[error("Invalid directive: {0}")]
+    InvalidDirective(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEFAULT: &str = "default-src 'self'; base-uri 'self'; font-src 'self' https: data:; form-action 'self'; frame-ancestors 'self'; img-src 'self' data:; object-src 'none'; script-src 'self'; script-src-attr 'none'; style-src 'self' https: 'unsafe-inline'; upgrade-insecure-requests";
+
+    #[test]
+    fn build() {
+        assert_eq!(ContentSecurityPolicy::default_policy().to_string(), DEFAULT);
+
+        let policy = ContentSecurityPolicy::new()
+            .directive(Directive::DefaultSrc, [Source::None])
+            .directive(
+                Directive::ScriptSrc,
+                [
+                    Source::SelfOrigin,
+                    Source::Host("https://cdn.example.com".into()),
+                    Source::Hash(HashAlgorithm::Sha256, "abc=".into()),
+                ],
+            )
+            .report_uri("/csp-reports")
+            .report_only(true);
+
+        let (name, value) = policy.header().unwrap();
+        assert_eq!(name, CONTENT_SECURITY_POLICY_REPORT_ONLY);
+        assert_eq!(
+            value.to_str().unwrap(),
+            "default-src 'none'; script-src 'self' https://cdn.example.com 'sha256-abc='; report-uri /csp-reports"
+        );
+    }
+
+    #[test]
+    fn nonce() {
+        let policy = ContentSecurityPolicy::new()
+            .directive(Directive::DefaultSrc, [Source::SelfOrigin])
+            .directive(Directive::ScriptSrc, [Source::None]);
+
+        let with_nonce = policy.with_nonce("abc");
+        assert_eq!(
+            with_nonce.to_string(),
+            "default-src 'self'; script-src 'nonce-abc'; style-src 'self' 'nonce-abc'"
+        );
+        // The original is unchanged
+        assert_eq!(policy.get(&Directive::StyleSrc), None);
+        assert_ne!(generate_nonce(), generate_nonce());
+    }
+
+    #[test]
+    fn parse_and_merge() {
+        let parsed = ContentSecurityPolicy::parse(DEFAULT).unwrap();
+        assert_eq!(parsed, ContentSecurityPolicy::default_policy());
+
+        let cdn = ContentSecurityPolicy::parse(
+            "script-src https://cdn.example.com 'nonce-xyz' ; img-src *.example.com; script-src 'none'",
+        )
+        .unwrap();
+        assert_eq!(
+            cdn.get(&Directive::ScriptSrc).unwrap(),
+            &[
+                Source::Host("https://cdn.example.com".into()),
+                Source::Nonce("xyz".into())
+            ]
+        );
+
+        let merged = ContentSecurityPolicy::default_policy().merge(&cdn);
+        assert_eq!(
+            merged.get(&Directive::ImgSrc).unwrap(),
+            &[
+                Source::SelfOrigin,
+                Source::Scheme("data".into()),
+                Source::Host("*.example.com".into())
+            ]
+        );
+        assert!(merged
+            .to_string()
+            .contains("script-src 'self' https://cdn.example.com 'nonce-xyz';"));
+
+        assert!(ContentSecurityPolicy::parse("script_src 'self'").is_err());
+    }
+}
@@ -0,0 +1,413 @@
+//! Cross site request forgery protection.
+//!
+//! Two strategies are supported:
+//!
+//! - [CsrfStrategy::DoubleSubmit] - The token is issued in a signed cookie readable by the client which must
+//!   echo it back in a header. Stateless, no server side storage needed.
+//! - [CsrfStrategy::Synchronizer] - The token is stored server side, e.g. in the user's session, and the
+//!   client must send it in a header. The expected token is obtained via a [CsrfTokenSource].
+//!
+//! For unsafe methods the `Origin` (or `Referer` if missing) header is additionally checked against the
+//! list of allowed origins, if any are configured.
+//!
+//! The [CsrfLayer] can be used to enable the protection for any tower based framework:
+//!
+//! ```ignore
+//! let csrf = CsrfConfig::double_submit(SecureCookies::from_master(key)?)
+//!     .with_allowed_origin("https://example.com");
+//!
+//! let router = Router::new().route(/* ... */).layer(CsrfLayer::new(csrf));
+//! ```
+
+use super::cookies::{find_cookie, SecureCookies};
+use cookie::Cookie;
+use data_encoding::BASE64URL_NOPAD;
+use futures::future::BoxFuture;
+use http::{header, request::Parts, HeaderName, Method, Request, Response, StatusCode};
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+use std::{
+    future::Future,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use subtle::ConstantTimeEq;
+use thiserror::Error;
+use tower_layer::Layer;
+use tower_service::Service;
+use tracing::debug;
+
+/// The default header in which clients send the token.
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+/// The default name of the double submit cookie, prefixed according to the [SecureCookies] configuration.
+pub const CSRF_COOKIE: &str = "csrf";
+
+/// Generates a random URL safe token with 32 bytes of entropy.
+pub fn generate_token() -> String {
+    let mut buf = [0_u8; 32];
+    StdRng::from_entropy().fill_bytes(&mut buf);
+    BASE64URL_NOPAD.encode(&buf)
+}
+
+/// Provides the expected token for the [synchronizer][CsrfStrategy::Synchronizer] strategy,
+/// usually by looking up the session of the request.
+///
+/// Implemented for closures taking in the request parts and returning a future.
+pub trait CsrfTokenSource: Send + Sync {
+    fn expected_token(&self, parts: &Parts) -> BoxFuture<'static, Option<String>>;
+}
+
+impl<F, Fut> CsrfTokenSource for F
+where
+    F: Fn(&Parts) -> Fut + Send + Sync,
+    Fut: Future<Output = Option<String>> + Send + 'static,
+{
+    fn expected_token(&self, parts: &Parts) -> BoxFuture<'static, Option<String>> {
+        Box::pin(self(parts))
+    }
+}
+
+#[derive(Clone)]
+pub enum CsrfStrategy {
+    DoubleSubmit(SecureCookies),
+    Synchronizer(Arc<dyn CsrfTokenSource>),
+}
+
+impl std::fmt::Debug for CsrfStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DoubleSubmit(cookies) => f.debug_tuple("DoubleSubmit").field(cookies).finish(),
+            Self::Synchronizer(_) => f.debug_tuple("Synchronizer").field(&"{ ... }").finish(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CsrfConfig {
+    pub strategy: CsrfStrategy,
+    /// The header the token is expected in.
+    pub header: HeaderName,
+    /// Used only by the double submit strategy.
+    pub cookie_name: String,
+    /// Origins in the form of `scheme://host[:port]`. If empty, origins are not checked.
+    pub allowed_origins: Vec<String>,
+    /// Reject unsafe requests without an `Origin` or `Referer` header when origins are checked.
+    pub require_origin: bool,
+    /// Paths for which the checks are skipped, e.g. webhooks.
+    pub exempt_paths: Vec<String>,
+}
+
+impl CsrfConfig {
+    pub fn new(strategy: CsrfStrategy) -> Self {
+        Self {
+            strategy,
+            header: HeaderName::from_static(CSRF_HEADER),
+            cookie_name: CSRF_COOKIE.to_string(),
+            allowed_origins: vec![],
+            require_origin: false,
+            exempt_paths: vec![],
+        }
+    }
+
+    /// The cookie must be readable by the client so `HttpOnly` is always disabled.
+    pub fn double_submit(mut cookies: SecureCookies) -> Self {
+        cookies.http_only = false;
+        Self::new(CsrfStrategy::DoubleSubmit(cookies))
+    }
+
+    pub fn synchronizer(source: impl CsrfTokenSource + 'static) -> Self {
+        Self::new(CsrfStrategy::Synchronizer(Arc::new(source)))
+    }
+
+    pub fn with_allowed_origin(mut self, origin: &str) -> Self {
+        self.allowed_origins
+            .push(origin.trim_end_matches('/').to_string());
+        self
+    }
+
+    pub fn with_exempt_path(mut self, path: &str) -> Self {
+        self.exempt_paths.push(path.to_string());
+        self
+    }
+
+    /// Generates a token and the cookie containing it for the double submit strategy.
+    /// Returns `None` if the strategy is not double submit.
+    ///
+    /// The cookie value is what the client must send back in the header.
+    pub fn issue_cookie(&self) -> Option<Cookie<'static>> {
+        let CsrfStrategy::DoubleSubmit(ref cookies) = self.strategy else {
+            return None;
+        };
+        Some(cookies.signed(&self.cookie_name, generate_token()))
+    }
+
+    /// Performs the CSRF checks on the request. Requests with safe methods (`GET`, `HEAD`, `OPTIONS`, `TRACE`)
+    /// and requests to exempt paths always pass.
+    pub async fn verify(&self, parts: &Parts) -> Result<(), CsrfError> {
+        if is_safe(&parts.method) || self.exempt_paths.iter().any(|p| p == parts.uri.path()) {
+            return Ok(());
+        }
+
+        self.verify_origin(parts)?;
+
+        let token = parts
+            .headers
+            .get(&self.header)
+            .and_then(|v| v.to_str().ok())
+            .ok_or(CsrfError::MissingToken)?;
+
+        let expected = match self.strategy {
+            CsrfStrategy::DoubleSubmit(ref cookies) => {
+                let cookie = find_cookie(&parts.headers, &cookies.name(&self.cookie_name))
+                    .ok_or(CsrfError::MissingToken)?;
+                let value = cookie.value().to_string();
+                // Ensures the cookie was issued by us and not planted by a sibling domain
+                cookies
+                    .verify_signed(cookie)
+                    .ok_or(CsrfError::TokenMismatch)?;
+                value
+            }
+            CsrfStrategy::Synchronizer(ref source) => source
+                .expected_token(parts)
+                .await
+                .ok_or(CsrfError::MissingToken)?,
+        };
+
+        if !bool::from(token.as_bytes().ct_eq(expected.as_bytes())) {
+            return Err(CsrfError::TokenMismatch);
+        }
+
+        Ok(())
+    }
+
+    fn verify_origin(&self, parts: &Parts) -> Result<(), CsrfError> {
+        if self.allowed_origins.is_empty() {
+            return Ok(());
+        }
+
+        let origin = parts
+            .headers
+            .get(header::ORIGIN)
+            .and_then(|v| v.to_str().ok())
+            .map(ToString::to_string)
+            .or_else(|| {
+                let referer = parts.headers.get(header::REFERER)?.to_str().ok()?;
+                referer_origin(referer)
+            });
+
+        match origin {
+            Some(origin) if self.allowed_origins.contains(&origin) => Ok(()),
+            Some(origin) => Err(CsrfError::InvalidOrigin(origin)),
+            None if self.require_origin => Err(CsrfError::MissingOrigin),
+            None => Ok(()),
+        }
+    }
+}
+
+fn is_safe(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+    )
+}
+
+/// Extracts `scheme://authority` from a referer URL.
+fn referer_origin(referer: &str) -> Option<String> {
+    let uri = referer.parse::<http::Uri>().ok()?;
+    Some(format!("{}://{}", uri.scheme_str()?, uri.authority()?))
+}
+
+#[derive(Debug, Error)]
+pub enum CsrfError {
+    #[error("CSRF token missing")]
+    MissingToken,
+    #[error("CSRF token mismatch")]
+    TokenMismatch,
+    #[error("Origin or referer missing")]
+    MissingOrigin,
+    #[error("Origin not allowed: {0}")]
+    InvalidOrigin(String),
+}
+
+/// Applies [CsrfConfig::verify] to every request and responds with `403 Forbidden` and an empty body
+/// if the checks fail.
+#[derive(Debug, Clone)]
+pub struct CsrfLayer {
+    config: Arc<CsrfConfig>,
+}
+
+impl CsrfLayer {
+    pub fn new(config: CsrfConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+        }
+    }
+}
+
+impl<S> Layer<S> for CsrfLayer {
+    type Service = CsrfService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CsrfService {
+            inner,
+            config: self.config.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CsrfService<S> {
+    inner: S,
+    config: Arc<CsrfConfig>,
+}
+
+impl<S, ReqB, ResB> Service<Request<ReqB>> for CsrfService<S>
+where
+    S: Service<Request<ReqB>, Response = Response<ResB>> + Clone + Send + 'static,
+    S::Future: Send,
+    ReqB: Send + 'static,
+    ResB: Default,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqB>) -> Self::Future {
+        // Take the service that was driven to readiness and leave a clone in its place
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let config = self.config.clone();
+
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+
+            if let Err(e) = config.verify(&parts).await {
+                debug!("CSRF check failed for {} {}: {e}", parts.method, parts.uri);
+                let mut res = Response::new(ResB::default());
+                *res.status_mut() = StatusCode::FORBIDDEN;
+                return Ok(res);
+            }
+
+            inner.call(Request::from_parts(parts, body)).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cookie::Key;
+    use tower::{service_fn, ServiceExt};
+
+    fn parts(method: Method, headers: &[(&str, &str)]) -> Parts {
+        let mut req = Request::builder().method(method).uri("/resource");
+        for (k, v) in headers {
+            req = req.header(*k, *v);
+        }
+        req.body(()).unwrap().into_parts().0
+    }
+
+    #[tokio::test]
+    async fn double_submit() {
+        let config = CsrfConfig::double_submit(SecureCookies::new(Key::generate()));
+        let cookie = config.issue_cookie().unwrap();
+        assert_eq!(cookie.http_only(), Some(false));
+
+        let cookie_header = cookie.stripped().to_string();
+        let token = cookie.value();
+
+        let ok = parts(
+            Method::POST,
+            &[("cookie", &cookie_header), (CSRF_HEADER, token)],
+        );
+        assert!(config.verify(&ok).await.is_ok());
+
+        let mismatch = parts(
+            Method::POST,
+            &[("cookie", &cookie_header), (CSRF_HEADER, "nope")],
+        );
+        assert!(matches!(
+            config.verify(&mismatch).await,
+            Err(CsrfError::TokenMismatch)
+        ));
+
+        // Unsigned cookie planted by an attacker
+        let planted = parts(
+            Method::POST,
+            &[("cookie", "__Host-csrf=evil"), (CSRF_HEADER, "evil")],
+        );
+        assert!(matches!(
+            config.verify(&planted).await,
+            Err(CsrfError::TokenMismatch)
+        ));
+
+        let missing = parts(Method::DELETE, &[]);
+        assert!(matches!(
+            config.verify(&missing).await,
+            Err(CsrfError::MissingToken)
+        ));
+
+        assert!(config.verify(&parts(Method::GET, &[])).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn synchronizer_and_origin() {
+        let config =
+            CsrfConfig::synchronizer(|_: &Parts| async { Some("session-token".to_string()) })
+                .with_allowed_origin("https://example.com/");
+
+        let ok = parts(
+            Method::POST,
+            &[
+                ("origin", "https://example.com"),
+                (CSRF_HEADER, "session-token"),
+            ],
+        );
+        assert!(config.verify(&ok).await.is_ok());
+
+        let referer = parts(
+            Method::POST,
+            &[
+                ("referer", "https://example.com/some/page?q=1"),
+                (CSRF_HEADER, "session-token"),
+            ],
+        );
+        assert!(config.verify(&referer).await.is_ok());
+
+        let cross = parts(
+            Method::PUT,
+            &[
+                ("origin", "https://evil.com"),
+                (CSRF_HEADER, "session-token"),
+            ],
+        );
+        assert!(matches!(
+            config.verify(&cross).await,
+            Err(CsrfError::InvalidOrigin(o)) if o == "https://evil.com"
+        ));
+    }
+
+    #[tokio::test]
+    async fn layer() {
+        let config = CsrfConfig::synchronizer(|_: &Parts| async { Some("token".to_string()) });
+        let service = CsrfLayer::new(config).layer(service_fn(|_: Request<()>| async {
+            Ok::<_, std::convert::Infallible>(Response::new(String::from("ok")))
+        }));
+
+        let req = Request::post("/").body(()).unwrap();
+        let res = service.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        let req = Request::post("/")
+            .header(CSRF_HEADER, "token")
+            .body(())
+            .unwrap();
+        let res = service.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.body(), "ok");
+    }
+}
@@ -0,0 +1,425 @@
+//! Content negotiation based on the `Accept` and `Accept-Encoding` request headers.
+//!
+//! Used through [ResponseBuilder::negotiate][super::response::ResponseBuilder::negotiate]:
+//!
+//! ```ignore
+//! let response = user
+//!     .into_response(StatusCode::OK)
+//!     .negotiate(request.headers())?;
+//! ```
+//!
+//! Bodies are serialized to the [Format] the client prefers the most and compressed with the preferred [Encoding].
+//! If the client accepts none of the formats, the response is `406 Not Acceptable`.
+
+use super::response::ResponseError;
+use http::{header, HeaderMap};
+use serde::Serialize;
+use std::io::Write;
+
+/// A format the body can be serialized to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    MessagePack,
+    Cbor,
+    Xml,
+    /// Strings and primitives are written as is, anything else as JSON.
+    Text,
+}
+
+impl Format {
+    /// The `Content-Type` of the format.
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::MessagePack => "application/msgpack",
+            Self::Cbor => "application/cbor",
+            Self::Xml => "application/xml",
+            Self::Text => "text/plain; charset=utf-8",
+        }
+    }
+
+    /// The media types the format is served for.
+    pub fn media_types(&self) -> &'static [&'static str] {
+        match self {
+            Self::Json => &["application/json"],
+            Self::MessagePack => &[
+                "application/msgpack",
+                "application/x-msgpack",
+                "application/vnd.msgpack",
+            ],
+            Self::Cbor => &["application/cbor"],
+            Self::Xml => &["application/xml", "text/xml"],
+            Self::Text => &["text/plain"],
+        }
+    }
+
+    /// The `Content-Type` sent when the format was negotiated for one of its [media types][Format::media_types],
+    /// so the response is always of a type the client accepts.
+    pub fn content_type_for(&self, media_type: &'static str) -> &'static str {
+        match (self, media_type) {
+            // Without a charset, text/xml would default to US-ASCII in older parsers
+            (Self::Xml, "text/xml") => "text/xml; charset=utf-8",
+            _ if self.media_types().first() == Some(&media_type) => self.content_type(),
+            _ => media_type,
+        }
+    }
+
+    pub fn serialize<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, ResponseError> {
+        match self {
+            Self::Json => Ok(serde_json::to_vec(value)?),
+            Self::MessagePack => {
+                rmp_serde::to_vec_named(value).map_err(|e| ResponseError::Encode(e.to_string()))
+            }
+            Self::Cbor => {
+                let mut buf = vec![];
+                ciborium::into_writer(value, &mut buf)
+                    .map_err(|e| ResponseError::Encode(e.to_string()))?;
+                Ok(buf)
+            }
+            Self::Xml => {
+                // Only structs and enums can be written without a root element
+                let xml = quick_xml::se::to_string(value).or_else(|_| {
+                    quick_xml::se::to_string_with_root("response", value)
+                        .map_err(|e| ResponseError::Encode(e.to_string()))
+                })?;
+                Ok(xml.into_bytes())
+            }
+            Self::Text => match serde_json::to_value(value)? {
+                serde_json::Value::String(s) => Ok(s.into_bytes()),
+                serde_json::Value::Null => Ok(vec![]),
+                other => Ok(other.to_string().into_bytes()),
+            },
+        }
+    }
+}
+
+/// A compression algorithm for the body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    /// The token used in `Accept-Encoding` and `Content-Encoding`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Brotli => "br",
+            Self::Gzip => "gzip",
+        }
+    }
+
+    pub fn compress(&self, body: &[u8]) -> Result<Vec<u8>, ResponseError> {
+        match self {
+            Self::Brotli => {
+                let mut out = vec![];
+                {
+                    let mut writer = brotli::CompressorWriter::new(&mut out, 4096, 5, 22);
+                    writer.write_all(body)?;
+                }
+                Ok(out)
+            }
+            Self::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+                encoder.write_all(body)?;
+                Ok(encoder.finish()?)
+            }
+        }
+    }
+}
+
+/// Configures which formats and encodings are offered to clients. The order of the formats and encodings
+/// is the server's preference, used when the client prefers several equally.
+#[derive(Debug, Clone)]
+pub struct Negotiation {
+    pub formats: Vec<Format>,
+    /// If empty, bodies are never compressed.
+    pub encodings: Vec<Encoding>,
+    /// Bodies smaller than this, in bytes, are not compressed since the overhead outweighs the gain.
+    pub min_compress_size: usize,
+}
+
+impl Default for Negotiation {
+    fn default() -> Self {
+        Self {
+            formats: vec![
+                Format::Json,
+                Format::MessagePack,
+                Format::Cbor,
+                Format::Xml,
+                Format::Text,
+            ],
+            encodings: vec![Encoding::Brotli, Encoding::Gzip],
+            min_compress_size: 1024,
+        }
+    }
+}
+
+impl Negotiation {
+    pub fn new(formats: impl IntoIterator<Item = Format>) -> Self {
+        Self {
+            formats: formats.into_iter().collect(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_encodings(mut self, encodings: impl IntoIterator<Item = Encoding>) -> Self {
+        self.encodings = encodings.into_iter().collect();
+        self
+    }
+
+    pub fn without_compression(mut self) -> Self {
+        self.encodings.clear();
+        self
+    }
+
+    pub fn with_min_compress_size(mut self, size: usize) -> Self {
+        self.min_compress_size = size;
+        self
+    }
+
+    /// Returns the format the client prefers, or `None` if none of the formats are acceptable.
+    /// If the request has no `Accept` header, the first format is used.
+    pub fn format(&self, headers: &HeaderMap) -> Option<Format> {
+        self.content_type(headers).map(|(format, _)| format)
+    }
+
+    /// Same as [format][Negotiation::format], along with the `Content-Type` of the media type the format
+    /// was matched for, e.g. `text/xml` when the client only accepts `text/*`.
+    pub fn content_type(&self, headers: &HeaderMap) -> Option<(Format, &'static str)> {
+        let accept = header_list(headers, header::ACCEPT);
+
+        if accept.is_empty() {
+            return self
+                .formats
+                .first()
+                .map(|format| (*format, format.content_type()));
+        }
+
+        let ranges = parse_weighted(&accept);
+
+        let candidates = self
+            .formats
+            .iter()
+            .flat_map(|format| format.media_types().iter().map(|ty| (*format, *ty)))
+            .collect::<Vec<_>>();
+
+        best(&candidates, |(_, media_type)| {
+            media_type_quality(&ranges, media_type)
+        })
+        .map(|(format, media_type)| (format, format.content_type_for(media_type)))
+    }
+
+    /// Returns the encoding the client prefers, or `None` if the body should not be compressed.
+    pub fn encoding(&self, headers: &HeaderMap) -> Option<Encoding> {
+        let accept = header_list(headers, header::ACCEPT_ENCODING);
+        let codings = parse_weighted(&accept);
+
+        best(&self.encodings, |encoding| {
+            codings
+                .iter()
+                .find(|(c, _)| c.eq_ignore_ascii_case(encoding.as_str()))
+                .or_else(|| codings.iter().find(|(c, _)| c == "*"))
+                .map(|(_, q)| *q)
+        })
+    }
+}
+
+/// Returns the first item with the highest quality above 0.
+fn best<T: Copy>(items: &[T], quality: impl Fn(&T) -> Option<f32>) -> Option<T> {
+    let mut best: Option<(T, f32)> = None;
+    for item in items {
+        let Some(q) = quality(item) else { continue };
+        if q > 0. && !best.is_some_and(|(_, best)| q <= best) {
+            best = Some((*item, q));
+        }
+    }
+    best.map(|(item, _)| item)
+}
+
+/// Returns the quality of the most specific range matching the media type.
+fn media_type_quality(ranges: &[(String, f32)], media_type: &str) -> Option<f32> {
+    let (ty, _) = media_type.split_once('/')?;
+    ranges
+        .iter()
+        .filter_map(|(range, q)| {
+            let specificity = if range.eq_ignore_ascii_case(media_type) {
+                2
+            } else if range
+                .strip_suffix("/*")
+                .is_some_and(|r| r.eq_ignore_ascii_case(ty))
+            {
+                1
+            } else if range == "*/*" {
+                0
+            } else {
+                return None;
+            };
+            Some((specificity, *q))
+        })
+        .max_by_key(|(specificity, _)| *specificity)
+        .map(|(_, q)| q)
+}
+
+fn header_list(headers: &HeaderMap, name: header::HeaderName) -> String {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Parses a list of values with optional `q` parameters, e.g. `text/html, application/json;q=0.9`.
+/// Other parameters are ignored.
+fn parse_weighted(list: &str) -> Vec<(String, f32)> {
+    list.split(',')
+        .filter_map(|item| {
+            let mut params = item.split(';');
+            let value = params.next()?.trim();
+            if value.is_empty() {
+                return None;
+            }
+            let q = params
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.)
+                .clamp(0., 1.);
+            Some((value.to_ascii_lowercase(), q))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+    use std::io::Read;
+
+    fn headers(name: header::HeaderName, value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn content_type() {
+        let negotiation = Negotiation::default();
+        let content_type = |accept| negotiation.content_type(&headers(header::ACCEPT, accept));
+
+        assert_eq!(
+            negotiation.content_type(&HeaderMap::new()),
+            Some((Format::Json, "application/json"))
+        );
+        // The media type the client accepted is sent, not the format's default
+        assert_eq!(
+            content_type("text/html, text/*;q=0.8"),
+            Some((Format::Xml, "text/xml; charset=utf-8"))
+        );
+        assert_eq!(
+            content_type("application/xml, text/xml"),
+            Some((Format::Xml, "application/xml"))
+        );
+        assert_eq!(
+            content_type("application/x-msgpack"),
+            Some((Format::MessagePack, "application/x-msgpack"))
+        );
+        assert_eq!(
+            content_type("text/plain"),
+            Some((Format::Text, "text/plain; charset=utf-8"))
+        );
+    }
+
+    #[test]
+    fn format() {
+        let negotiation = Negotiation::default();
+        let format = |accept| negotiation.format(&headers(header::ACCEPT, accept));
+
+        assert_eq!(negotiation.format(&HeaderMap::new()), Some(Format::Json));
+        assert_eq!(format("*/*"), Some(Format::Json));
+        assert_eq!(format("application/x-msgpack"), Some(Format::MessagePack));
+        assert_eq!(
+            format("application/json;q=0.5, application/cbor"),
+            Some(Format::Cbor)
+        );
+        assert_eq!(format("text/*, application/xml;q=0.1"), Some(Format::Xml));
+        assert_eq!(format("text/html, text/*;q=0.8"), Some(Format::Xml));
+        assert_eq!(format("text/plain, */*;q=0.1"), Some(Format::Text));
+        assert_eq!(
+            format("application/json;q=0, */*"),
+            Some(Format::MessagePack)
+        );
+        assert_eq!(format("image/png"), None);
+        assert_eq!(
+            Negotiation::new([Format::Json]).format(&headers(header::ACCEPT, "text/plain")),
+            None
+        );
+    }
+
+    #[test]
+    fn encoding() {
+        let negotiation = Negotiation::default();
+        let encoding = |accept| negotiation.encoding(&headers(header::ACCEPT_ENCODING, accept));
+
+        assert_eq!(negotiation.encoding(&HeaderMap::new()), None);
+        assert_eq!(encoding("gzip, deflate, br"), Some(Encoding::Brotli));
+        assert_eq!(encoding("gzip, br;q=0.5"), Some(Encoding::Gzip));
+        assert_eq!(encoding("*"), Some(Encoding::Brotli));
+        assert_eq!(encoding("br;q=0, *"), Some(Encoding::Gzip));
+        assert_eq!(encoding("identity"), None);
+        assert_eq!(
+            negotiation
+                .clone()
+                .without_compression()
+                .encoding(&headers(header::ACCEPT_ENCODING, "gzip")),
+            None
+        );
+    }
+
+    #[test]
+    fn serialize() {
+        #[derive(Serialize)]
+        struct User {
+            id: u64,
+            name: &'static str,
+        }
+
+        let user = User { id: 1, name: "Bob" };
+
+        assert_eq!(
+            Format::Json.serialize(&user).unwrap(),
+            br#"{"id":1,"name":"Bob"}"#
+        );
+        assert_eq!(
+            Format::Xml.serialize(&user).unwrap(),
+            b"<User><id>1</id><name>Bob</name></User>"
+        );
+        assert_eq!(Format::Text.serialize(&"plain").unwrap(), b"plain");
+        assert_eq!(Format::Text.serialize(&42).unwrap(), b"42");
+
+        let msgpack = Format::MessagePack.serialize(&user).unwrap();
+        let value: serde_json::Value = rmp_serde::from_slice(&msgpack).unwrap();
+        assert_eq!(value["name"], "Bob");
+
+        let cbor = Format::Cbor.serialize(&user).unwrap();
+        let value: serde_json::Value = ciborium::from_reader(cbor.as_slice()).unwrap();
+        assert_eq!(value["id"], 1);
+
+        let body = "hextacy ".repeat(100);
+        let gzip = Encoding::Gzip.compress(body.as_bytes()).unwrap();
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(gzip.as_slice())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, body);
+
+        let br = Encoding::Brotli.compress(body.as_bytes()).unwrap();
+        let mut decoded = String::new();
+        brotli::Decompressor::new(br.as_slice(), 4096)
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, body);
+    }
+}
@@ -0,0 +1,871 @@
+//! Rate limiting for login throttling, API quotas and the like.
+//!
+//! A [RateLimiter] counts hits per key according to an [Algorithm] and stores the counters in a [RateLimitStore].
+//! Redis is supported via the `cache-redis` feature, where every algorithm runs as a single Lua script so the limits hold
+//! across instances. The `InMemCache` adapter can be used via the `cache-inmem` feature
+//! for prototyping and testing.
+//!
+//! The outcome of a check is a [RateLimit] which can be turned into the standard `RateLimit-*` and `Retry-After` headers,
+//! either by hand via [ResponseBuilder::with_rate_limit][super::response::ResponseBuilder::with_rate_limit] or automatically
+//! by the [RateLimitLayer]:
+//!
+//! ```ignore
+//! let limiter = RateLimiter::new(pool, Algorithm::sliding_window(100, Duration::from_secs(60)));
+//! let router = Router::new().route(/* ... */).layer(RateLimitLayer::new(limiter, KeyExtractor::ip()));
+//!
+//! // Or by hand
+//! let limit = login_limiter.check(&email).await?;
+//! if !limit.allowed {
+//!     return ErrorBody::too_many_requests().into_response(StatusCode::TOO_MANY_REQUESTS).with_rate_limit(&limit).json();
+//! }
+//! ```
+//!
+//! See <https://datatracker.ietf.org/doc/draft-ietf-httpapi-ratelimit-headers/>
+
+use super::request::TrustedProxies;
+use futures::future::BoxFuture;
+use http::{
+    header, request::Parts, HeaderMap, HeaderName, HeaderValue, Request, Response, StatusCode,
+};
+use std::{
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use thiserror::Error;
+use tower_layer::Layer;
+use tower_service::Service;
+use tracing::{debug, warn};
+
+pub const RATELIMIT_LIMIT: &str = "ratelimit-limit";
+pub const RATELIMIT_REMAINING: &str = "ratelimit-remaining";
+pub const RATELIMIT_RESET: &str = "ratelimit-reset";
+pub const RATELIMIT_POLICY: &str = "ratelimit-policy";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    /// Allows `limit` hits per window. The window starts with the first hit. Cheap, but allows bursts
+    /// of up to twice the limit around window boundaries.
+    FixedWindow { limit: u64, window: Duration },
+    /// Approximates a rolling window by weighing the count of the previous window by how much
+    /// of it still overlaps with the rolling one. Smooths out the bursts of the fixed window.
+    SlidingWindow { limit: u64, window: Duration },
+    /// A bucket holding up to `capacity` tokens, refilled with `refill` tokens every `period`. Every hit takes a token.
+    /// Allows bursts of up to `capacity` hits while enforcing an average rate.
+    TokenBucket {
+        capacity: u64,
+        refill: u64,
+        period: Duration,
+    },
+}
+
+impl Algorithm {
+    pub fn fixed_window(limit: u64, window: Duration) -> Self {
+        Self::FixedWindow { limit, window }
+    }
+
+    pub fn sliding_window(limit: u64, window: Duration) -> Self {
+        Self::SlidingWindow { limit, window }
+    }
+
+    pub fn token_bucket(capacity: u64, refill: u64, period: Duration) -> Self {
+        Self::TokenBucket {
+            capacity,
+            refill,
+            period,
+        }
+    }
+
+    /// The maximum amount of hits allowed at once.
+    pub fn limit(&self) -> u64 {
+        match self {
+            Self::FixedWindow { limit, .. } | Self::SlidingWindow { limit, .. } => *limit,
+            Self::TokenBucket { capacity, .. } => *capacity,
+        }
+    }
+
+    /// The value of the `RateLimit-Policy` header, e.g. `100;w=60`.
+    pub fn policy(&self) -> String {
+        match self {
+            Self::FixedWindow { limit, window } | Self::SlidingWindow { limit, window } => {
+                format!("{limit};w={}", window.as_secs().max(1))
+            }
+            Self::TokenBucket {
+                capacity,
+                refill,
+                period,
+            } => {
+                // The window in which the whole bucket is refilled
+                let window = (period.as_secs_f64() * *capacity as f64 / (*refill).max(1) as f64)
+                    .ceil() as u64;
+                format!("{capacity};w={}", window.max(1))
+            }
+        }
+    }
+
+    /// Applies a hit to the given state at `now` (in milliseconds) and returns the new state.
+    /// The Redis scripts implement the same logic.
+    #[cfg_attr(not(feature = "cache-inmem"), allow(dead_code))]
+    fn apply(&self, state: Option<State>, now: u64) -> (State, Outcome) {
+        match *self {
+            Self::FixedWindow { limit, window } => {
+                let window = millis(window);
+                let (start, count) = match state {
+                    Some(State::Fixed { start, count }) if now < start + window => (start, count),
+                    _ => (now, 0),
+                };
+                let count = count + 1;
+                let reset = start + window - now;
+                let outcome = if count > limit {
+                    Outcome::denied(reset, reset)
+                } else {
+                    Outcome::allowed(limit - count, reset)
+                };
+                (State::Fixed { start, count }, outcome)
+            }
+            Self::SlidingWindow { limit, window } => {
+                let window = millis(window);
+                let idx = now / window;
+                let (current, previous) = match state {
+                    Some(State::Sliding {
+                        idx: i,
+                        current,
+                        previous,
+                    }) => {
+                        if i == idx {
+                            (current, previous)
+                        } else if i + 1 == idx {
+                            (0, current)
+                        } else {
+                            (0, 0)
+                        }
+                    }
+                    _ => (0, 0),
+                };
+
+                let elapsed = now - idx * window;
+                let reset = window - elapsed;
+                let estimate = previous as f64 * reset as f64 / window as f64 + current as f64;
+
+                if estimate + 1. > limit as f64 {
+                    let retry = sliding_retry(limit, window, elapsed, current, previous);
+                    let state = State::Sliding {
+                        idx,
+                        current,
+                        previous,
+                    };
+                    return (state, Outcome::denied(reset, retry));
+                }
+
+                let remaining = (limit as f64 - estimate - 1.).floor() as u64;
+                let state = State::Sliding {
+                    idx,
+                    current: current + 1,
+                    previous,
+                };
+                (state, Outcome::allowed(remaining, reset))
+            }
+            Self::TokenBucket {
+                capacity,
+                refill,
+                period,
+            } => {
+                // Tokens per millisecond
+                let rate = refill as f64 / millis(period) as f64;
+                let tokens = match state {
+                    Some(State::Bucket { tokens, updated }) => {
+                        (tokens + now.saturating_sub(updated) as f64 * rate).min(capacity as f64)
+                    }
+                    _ => capacity as f64,
+                };
+
+                if tokens < 1. {
+                    let retry = ((1. - tokens) / rate).ceil() as u64;
+                    let reset = ((capacity as f64 - tokens) / rate).ceil() as u64;
+                    let state = State::Bucket {
+                        tokens,
+                        updated: now,
+                    };
+                    return (state, Outcome::denied(reset, retry));
+                }
+
+                let tokens = tokens - 1.;
+                let reset = ((capacity as f64 - tokens) / rate).ceil() as u64;
+                let state = State::Bucket {
+                    tokens,
+                    updated: now,
+                };
+                (state, Outcome::allowed(tokens.floor() as u64, reset))
+            }
+        }
+    }
+}
+
+/// Time in milliseconds until the sliding window estimate drops enough to allow another hit.
+fn sliding_retry(limit: u64, window: u64, elapsed: u64, current: u64, previous: u64) -> u64 {
+    let limit = limit as f64;
+    let window = window as f64;
+    let elapsed = elapsed as f64;
+
+    // The current window alone exceeds the limit, wait for the next one in which it becomes the previous
+    if current as f64 + 1. > limit {
+        let next = if current == 0 {
+            0.
+        } else {
+            window * (1. - (limit - 1.) / current as f64)
+        };
+        return (window - elapsed + next.max(0.)).ceil() as u64;
+    }
+
+    let needed = window * (1. - (limit - current as f64 - 1.) / previous as f64);
+    (needed - elapsed).max(1.).ceil() as u64
+}
+
+fn millis(d: Duration) -> u64 {
+    (d.as_millis() as u64).max(1)
+}
+
+/// The counters of a single key.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(not(feature = "cache-inmem"), allow(dead_code))]
+enum State {
+    Fixed {
+        start: u64,
+        count: u64,
+    },
+    Sliding {
+        idx: u64,
+        current: u64,
+        previous: u64,
+    },
+    Bucket {
+        tokens: f64,
+        updated: u64,
+    },
+}
+
+/// The raw result of a hit, times are in milliseconds.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(
+    not(any(feature = "cache-redis", feature = "cache-inmem")),
+    allow(dead_code)
+)]
+struct Outcome {
+    allowed: bool,
+    remaining: u64,
+    reset: u64,
+    retry_after: u64,
+}
+
+impl Outcome {
+    fn allowed(remaining: u64, reset: u64) -> Self {
+        Self {
+            allowed: true,
+            remaining,
+            reset,
+            retry_after: 0,
+        }
+    }
+
+    fn denied(reset: u64, retry_after: u64) -> Self {
+        Self {
+            allowed: false,
+            remaining: 0,
+            reset,
+            retry_after,
+        }
+    }
+
+    #[cfg_attr(
+        not(any(feature = "cache-redis", feature = "cache-inmem")),
+        allow(dead_code)
+    )]
+    fn into_rate_limit(self, algorithm: &Algorithm) -> RateLimit {
+        RateLimit {
+            allowed: self.allowed,
+            limit: algorithm.limit(),
+            remaining: self.remaining,
+            reset: Duration::from_millis(self.reset),
+            retry_after: (!self.allowed).then(|| Duration::from_millis(self.retry_after)),
+            policy: algorithm.policy(),
+        }
+    }
+}
+
+/// The outcome of a rate limit check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimit {
+    pub allowed: bool,
+    pub limit: u64,
+    pub remaining: u64,
+    /// Time until the quota is fully restored.
+    pub reset: Duration,
+    /// Set only when the hit was not allowed.
+    pub retry_after: Option<Duration>,
+    pub policy: String,
+}
+
+impl RateLimit {
+    /// Returns the `RateLimit-*` headers, and `Retry-After` if the hit was not allowed. Durations are rounded up
+    /// to whole seconds.
+    pub fn headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            HeaderName::from_static(RATELIMIT_LIMIT),
+            HeaderValue::from(self.limit),
+        );
+        headers.insert(
+            HeaderName::from_static(RATELIMIT_REMAINING),
+            HeaderValue::from(self.remaining),
+        );
+        headers.insert(
+            HeaderName::from_static(RATELIMIT_RESET),
+            HeaderValue::from(ceil_secs(self.reset)),
+        );
+        if let Ok(policy) = HeaderValue::from_str(&self.policy) {
+            headers.insert(HeaderName::from_static(RATELIMIT_POLICY), policy);
+        }
+        if let Some(retry_after) = self.retry_after {
+            headers.insert(
+                header::RETRY_AFTER,
+                HeaderValue::from(ceil_secs(retry_after).max(1)),
+            );
+        }
+        headers
+    }
+}
+
+fn ceil_secs(d: Duration) -> u64 {
+    d.as_millis().div_ceil(1000) as u64
+}
+
+/// Persists the counters. Implementations must apply a hit atomically.
+pub trait RateLimitStore {
+    fn hit(
+        &self,
+        key: &str,
+        algorithm: &Algorithm,
+    ) -> impl Future<Output = Result<RateLimit, RateLimitError>> + Send;
+}
+
+/// Each script returns `{allowed, remaining, reset, retry_after}` with times in milliseconds.
+/// The server time is used so the limits are consistent across instances.
+#[cfg(feature = "cache-redis")]
+mod scripts {
+    pub const FIXED_WINDOW: &str = r#"
+local limit, window = tonumber(ARGV[1]), tonumber(ARGV[2])
+local count = redis.call('INCR', KEYS[1])
+local ttl = redis.call('PTTL', KEYS[1])
+if ttl < 0 then
+  redis.call('PEXPIRE', KEYS[1], window)
+  ttl = window
+end
+if count > limit then
+  return {0, 0, ttl, ttl}
+end
+return {1, limit - count, ttl, 0}
+"#;
+
+    pub const SLIDING_WINDOW: &str = r#"
+local limit, window = tonumber(ARGV[1]), tonumber(ARGV[2])
+local t = redis.call('TIME')
+local now = tonumber(t[1]) * 1000 + math.floor(tonumber(t[2]) / 1000)
+local idx = math.floor(now / window)
+local data = redis.call('HMGET', KEYS[1], 'i', 'c', 'p')
+local i, current, previous = tonumber(data[1]), tonumber(data[2]) or 0, tonumber(data[3]) or 0
+if i ~= idx then
+  if i == idx - 1 then previous = current else previous = 0 end
+  current = 0
+end
+local elapsed = now - idx * window
+local reset = window - elapsed
+local estimate = previous * reset / window + current
+local allowed, remaining, retry = 1, 0, 0
+if estimate + 1 > limit then
+  allowed = 0
+  if current + 1 > limit then
+    local next = 0
+    if current > 0 then next = math.max(0, window * (1 - (limit - 1) / current)) end
+    retry = math.ceil(reset + next)
+  else
+    retry = math.ceil(math.max(1, window * (1 - (limit - current - 1) / previous) - elapsed))
+  end
+else
+  remaining = math.floor(limit - estimate - 1)
+  current = current + 1
+end
+redis.call('HSET', KEYS[1], 'i', idx, 'c', current, 'p', previous)
+redis.call('PEXPIRE', KEYS[1], window * 2)
+return {allowed, remaining, reset, retry}
+"#;
+
+    pub const TOKEN_BUCKET: &str = r#"
+local capacity, rate = tonumber(ARGV[1]), tonumber(ARGV[2])
+local t = redis.call('TIME')
+local now = tonumber(t[1]) * 1000 + math.floor(tonumber(t[2]) / 1000)
+local data = redis.call('HMGET', KEYS[1], 't', 'u')
+local tokens = tonumber(data[1])
+if tokens == nil then
+  tokens = capacity
+else
+  tokens = math.min(capacity, tokens + math.max(0, now - tonumber(data[2])) * rate)
+end
+local allowed, retry = 1, 0
+if tokens < 1 then
+  allowed = 0
+  retry = math.ceil((1 - tokens) / rate)
+else
+  tokens = tokens - 1
+end
+local reset = math.ceil((capacity - tokens) / rate)
+redis.call('HSET', KEYS[1], 't', tostring(tokens), 'u', now)
+redis.call('PEXPIRE', KEYS[1], math.max(1, math.ceil(capacity / rate)))
+return {allowed, math.floor(tokens), reset, retry}
+"#;
+}
+
+#[cfg(feature = "cache-redis")]
+impl RateLimitStore for deadpool_redis::Pool {
+    async fn hit(&self, key: &str, algorithm: &Algorithm) -> Result<RateLimit, RateLimitError> {
+        let mut conn = self
+            .get()
+            .await
+            .map_err(|e| RateLimitError::Store(e.to_string()))?;
+
+        let mut cmd = deadpool_redis::redis::cmd("EVAL");
+        match *algorithm {
+            Algorithm::FixedWindow { limit, window } => cmd
+                .arg(scripts::FIXED_WINDOW)
+                .arg(1)
+                .arg(key)
+                .arg(limit)
+                .arg(millis(window)),
+            Algorithm::SlidingWindow { limit, window } => cmd
+                .arg(scripts::SLIDING_WINDOW)
+                .arg(1)
+                .arg(key)
+                .arg(limit)
+                .arg(millis(window)),
+            Algorithm::TokenBucket {
+                capacity,
+                refill,
+                period,
+            } => cmd
+                .arg(scripts::TOKEN_BUCKET)
+                .arg(1)
+                .arg(key)
+                .arg(capacity)
+                .arg(refill as f64 / millis(period) as f64),
+        };
+
+        let result: Vec<i64> = cmd
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| RateLimitError::Store(e.to_string()))?;
+
+        let [allowed, remaining, reset, retry_after] = result[..] else {
+            return Err(RateLimitError::Store(format!(
+                "unexpected script result: {result:?}"
+            )));
+        };
+
+        let outcome = Outcome {
+            allowed: allowed == 1,
+            remaining: remaining.max(0) as u64,
+            reset: reset.max(0) as u64,
+            retry_after: retry_after.max(0) as u64,
+        };
+
+        Ok(outcome.into_rate_limit(algorithm))
+    }
+}
+
+/// Counters are never evicted, use only for prototyping and testing.
+#[cfg(feature = "cache-inmem")]
+impl RateLimitStore for crate::adapters::cache::in_mem::InMemCache {
+    async fn hit(&self, key: &str, algorithm: &Algorithm) -> Result<RateLimit, RateLimitError> {
+        use crate::driver::Driver;
+
+        let Ok(mut conn) = self.connect().await;
+        let now = chrono::Utc::now().timestamp_millis() as u64;
+        let outcome = conn.update(key, |state| algorithm.apply(state, now));
+        Ok(outcome.into_rate_limit(algorithm))
+    }
+}
+
+/// Checks hits against an [Algorithm] using a [RateLimitStore].
+#[derive(Debug, Clone)]
+pub struct RateLimiter<S> {
+    store: S,
+    algorithm: Algorithm,
+    prefix: String,
+}
+
+impl<S> RateLimiter<S>
+where
+    S: RateLimitStore,
+{
+    /// Keys are prefixed with `hextacy:ratelimit:` by default.
+    pub fn new(store: S, algorithm: Algorithm) -> Self {
+        Self {
+            store,
+            algorithm,
+            prefix: "hextacy:ratelimit:".to_string(),
+        }
+    }
+
+    /// Use a different prefix for every limiter sharing a store, e.g. `login:` and `api:`.
+    pub fn with_prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    pub fn algorithm(&self) -> &Algorithm {
+        &self.algorithm
+    }
+
+    /// Registers a hit for the key and returns whether it is allowed.
+    pub async fn check(&self, key: &str) -> Result<RateLimit, RateLimitError> {
+        self.store
+            .hit(&format!("{}{key}", self.prefix), &self.algorithm)
+            .await
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum RateLimitError {
+    #[error("Rate limit store: {0}")]
+    Store(String),
+}
+
+/// Determines the key requests are limited by.
+#[derive(Clone)]
+pub enum KeyExtractor {
+    /// The client IP. When a header is given, e.g. `x-real-ip`, the IP is read from it first. Only use a header
+    /// that is set by a trusted proxy, otherwise clients can spoof it. Falls back to a [SocketAddr] or [IpAddr]
+    /// found in the request extensions.
+    Ip(Option<HeaderName>),
+    /// The value of a header, e.g. an API key.
+    Header(HeaderName),
+    Custom(KeyFn),
+}
+
+type KeyFn = Arc<dyn Fn(&Parts) -> Option<String> + Send + Sync>;
+
+impl std::fmt::Debug for KeyExtractor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ip(header) => f.debug_tuple("Ip").field(header).finish(),
+            Self::Header(header) => f.debug_tuple("Header").field(header).finish(),
+            Self::Custom(_) => f.debug_tuple("Custom").field(&"{ ... }").finish(),
+        }
+    }
+}
+
+impl KeyExtractor {
+    pub fn ip() -> Self {
+        Self::Ip(None)
+    }
+
+    pub fn ip_from_header(header: HeaderName) -> Self {
+        Self::Ip(Some(header))
+    }
+
+    /// The client IP resolved through the forwarding headers set by trusted proxies.
+    pub fn client_ip(proxies: TrustedProxies) -> Self {
+        Self::custom(move |parts| {
+            proxies
+                .client_ip_from_parts(parts)
+                .map(|ip| format!("ip:{ip}"))
+        })
+    }
+
+    pub fn header(header: HeaderName) -> Self {
+        Self::Header(header)
+    }
+
+    /// Uses an extension inserted by a preceding middleware, e.g. the ID of the authenticated user.
+    pub fn extension<T>() -> Self
+    where
+        T: ToString + Send + Sync + 'static,
+    {
+        Self::custom(|parts| parts.extensions.get::<T>().map(ToString::to_string))
+    }
+
+    pub fn custom(f: impl Fn(&Parts) -> Option<String> + Send + Sync + 'static) -> Self {
+        Self::Custom(Arc::new(f))
+    }
+
+    pub fn extract(&self, parts: &Parts) -> Option<String> {
+        match self {
+            Self::Ip(header) => {
+                let from_header = header
+                    .as_ref()
+                    .and_then(|h| parts.headers.get(h))
+                    .and_then(|v| v.to_str().ok())
+                    // X-Forwarded-For style lists contain the client first
+                    .and_then(|v| v.split(',').next())
+                    .and_then(|ip| ip.trim().parse::<IpAddr>().ok());
+
+                from_header
+                    .or_else(|| parts.extensions.get::<SocketAddr>().map(SocketAddr::ip))
+                    .or_else(|| parts.extensions.get::<IpAddr>().copied())
+                    .map(|ip| format!("ip:{ip}"))
+            }
+            Self::Header(header) => parts
+                .headers
+                .get(header)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| format!("{header}:{v}")),
+            Self::Custom(f) => f(parts),
+        }
+    }
+}
+
+/// Applies a [RateLimiter] to every request. Requests over the limit are answered with `429 Too Many Requests`
+/// and an empty body, all other responses get the `RateLimit-*` headers.
+///
+/// Requests without a key, as well as requests for which the store fails, are let through.
+#[derive(Debug)]
+pub struct RateLimitLayer<S> {
+    limiter: Arc<RateLimiter<S>>,
+    key: KeyExtractor,
+}
+
+impl<S> Clone for RateLimitLayer<S> {
+    fn clone(&self) -> Self {
+        Self {
+            limiter: self.limiter.clone(),
+            key: self.key.clone(),
+        }
+    }
+}
+
+impl<S> RateLimitLayer<S> {
+    pub fn new(limiter: RateLimiter<S>, key: KeyExtractor) -> Self {
+        Self {
+            limiter: Arc::new(limiter),
+            key,
+        }
+    }
+}
+
+impl<St, S> Layer<S> for RateLimitLayer<St> {
+    type Service = RateLimitService<S, St>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService {
+            inner,
+            limiter: self.limiter.clone(),
+            key: self.key.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RateLimitService<S, St> {
+    inner: S,
+    limiter: Arc<RateLimiter<St>>,
+    key: KeyExtractor,
+}
+
+impl<S: Clone, St> Clone for RateLimitService<S, St> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            limiter: self.limiter.clone(),
+            key: self.key.clone(),
+        }
+    }
+}
+
+impl<S, St, ReqB, ResB> Service<Request<ReqB>> for RateLimitService<S, St>
+where
+    S: Service<Request<ReqB>, Response = Response<ResB>> + Clone + Send + 'static,
+    S::Future: Send,
+    St: RateLimitStore + Send + Sync + 'static,
+    ReqB: Send + 'static,
+    ResB: Default,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqB>) -> Self::Future {
+        // Take the service that was driven to readiness and leave a clone in its place
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let limiter = self.limiter.clone();
+        let key = self.key.clone();
+
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+
+            let Some(key) = key.extract(&parts) else {
+                debug!("No rate limit key for {} {}", parts.method, parts.uri);
+                return inner.call(Request::from_parts(parts, body)).await;
+            };
+
+            let limit = match limiter.check(&key).await {
+                Ok(limit) => limit,
+                Err(e) => {
+                    warn!("Rate limit check failed, letting request through: {e}");
+                    return inner.call(Request::from_parts(parts, body)).await;
+                }
+            };
+
+            if !limit.allowed {
+                debug!("Rate limit exceeded for {key}");
+                let mut res = Response::new(ResB::default());
+                *res.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+                *res.headers_mut() = limit.headers();
+                return Ok(res);
+            }
+
+            let mut res = inner.call(Request::from_parts(parts, body)).await?;
+            res.headers_mut().extend(limit.headers());
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEC: Duration = Duration::from_secs(1);
+
+    fn run(algorithm: Algorithm, hits: &[u64]) -> Vec<Outcome> {
+        let mut state = None;
+        hits.iter()
+            .map(|now| {
+                let (s, outcome) = algorithm.apply(state, *now);
+                state = Some(s);
+                outcome
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fixed_window() {
+        let outcomes = run(Algorithm::fixed_window(2, SEC), &[0, 100, 200, 999, 1000]);
+        let allowed = outcomes.iter().map(|o| o.allowed).collect::<Vec<_>>();
+        assert_eq!(allowed, [true, true, false, false, true]);
+        assert_eq!(outcomes[1].remaining, 0);
+        assert_eq!(outcomes[2].retry_after, 800);
+        assert_eq!(outcomes[4].remaining, 1);
+    }
+
+    #[test]
+    fn sliding_window() {
+        let algorithm = Algorithm::sliding_window(4, SEC);
+        let outcomes = run(algorithm, &[0, 100, 200, 300, 400, 1250, 1260, 1750]);
+        let allowed = outcomes.iter().map(|o| o.allowed).collect::<Vec<_>>();
+        // At 1250 the previous window weighs 75% so the estimate is 3,
+        // at 1260 it is 3.96 and at 1750 it is 2
+        assert_eq!(allowed, [true, true, true, true, false, true, false, true]);
+        assert_eq!(outcomes[4].retry_after, 850);
+        assert_eq!(outcomes[6].retry_after, 240);
+    }
+
+    #[test]
+    fn token_bucket() {
+        // 1 token every 100ms
+        let algorithm = Algorithm::token_bucket(3, 1, Duration::from_millis(100));
+        let outcomes = run(algorithm, &[0, 0, 0, 0, 50, 100, 100]);
+        let allowed = outcomes.iter().map(|o| o.allowed).collect::<Vec<_>>();
+        assert_eq!(allowed, [true, true, true, false, false, true, false]);
+        assert_eq!(outcomes[3].retry_after, 100);
+        assert_eq!(outcomes[4].retry_after, 50);
+        assert_eq!(outcomes[0].remaining, 2);
+        assert_eq!(algorithm.policy(), "3;w=1");
+    }
+
+    #[test]
+    fn headers() {
+        let denied =
+            Outcome::denied(1500, 200).into_rate_limit(&Algorithm::fixed_window(10, SEC * 60));
+        let headers = denied.headers();
+        assert_eq!(headers[RATELIMIT_LIMIT], "10");
+        assert_eq!(headers[RATELIMIT_REMAINING], "0");
+        assert_eq!(headers[RATELIMIT_RESET], "2");
+        assert_eq!(headers[RATELIMIT_POLICY], "10;w=60");
+        assert_eq!(headers[header::RETRY_AFTER], "1");
+
+        let allowed = Outcome::allowed(3, 500).into_rate_limit(&Algorithm::fixed_window(10, SEC));
+        assert!(!allowed.headers().contains_key(header::RETRY_AFTER));
+    }
+
+    #[test]
+    fn keys() {
+        let mut req = Request::get("/")
+            .header("x-real-ip", "10.0.0.1, 10.0.0.2")
+            .header("x-api-key", "key")
+            .body(())
+            .unwrap();
+        req.extensions_mut()
+            .insert(SocketAddr::from(([127, 0, 0, 1], 8080)));
+        req.extensions_mut().insert(42_u64);
+        let (parts, _) = req.into_parts();
+
+        assert_eq!(KeyExtractor::ip().extract(&parts).unwrap(), "ip:127.0.0.1");
+        assert_eq!(
+            KeyExtractor::ip_from_header(HeaderName::from_static("x-real-ip"))
+                .extract(&parts)
+                .unwrap(),
+            "ip:10.0.0.1"
+        );
+        assert_eq!(
+            KeyExtractor::header(HeaderName::from_static("x-api-key"))
+                .extract(&parts)
+                .unwrap(),
+            "x-api-key:key"
+        );
+        assert_eq!(
+            KeyExtractor::extension::<u64>().extract(&parts).unwrap(),
+            "42"
+        );
+        assert!(KeyExtractor::extension::<String>()
+            .extract(&parts)
+            .is_none());
+    }
+
+    #[cfg(feature = "cache-inmem")]
+    #[tokio::test]
+    async fn layer() {
+        use crate::adapters::cache::in_mem::InMemCache;
+        use tower::{service_fn, ServiceExt};
+
+        let limiter = RateLimiter::new(InMemCache::new(), Algorithm::fixed_window(1, SEC * 60));
+        let service = RateLimitLayer::new(limiter, KeyExtractor::ip()).layer(service_fn(
+            |_: Request<()>| async { Ok::<_, std::convert::Infallible>(Response::new(())) },
+        ));
+
+        let request = || {
+            let mut req = Request::get("/").body(()).unwrap();
+            req.extensions_mut().insert(IpAddr::from([127, 0, 0, 1]));
+            req
+        };
+
+        let res = service.clone().oneshot(request()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[RATELIMIT_REMAINING], "0");
+
+        let res = service.clone().oneshot(request()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(res.headers().contains_key(header::RETRY_AFTER));
+
+        // No key
+        let res = service
+            .oneshot(Request::get("/").body(()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,444 @@
+//! Framework agnostic helpers for reading requests.
+//!
+//! Bodies are parsed from bytes already read by the framework, checking the `Content-Type` and the size.
+//! Every [RequestError] maps to a [Problem][super::response::Problem] so handlers can return them directly:
+//!
+//! ```ignore
+//! let token = bearer_token(request.headers())?;
+//! let body: CreateUser = json(request.headers(), &bytes, 64 * 1024)?;
+//! let ip = proxies.client_ip(request.headers(), Some(peer.ip()));
+//! ```
+
+use super::cookies::find_cookie;
+use super::response::{IntoProblem, Problem};
+use cookie::Cookie;
+use data_encoding::BASE64;
+use http::{header, request::Parts, HeaderMap, StatusCode, Uri};
+use ipnet::IpNet;
+use serde::de::DeserializeOwned;
+use std::net::{IpAddr, SocketAddr};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum RequestError {
+    #[error("Payload exceeds the limit of {0} bytes")]
+    PayloadTooLarge(usize),
+    #[error("Expected content type {0}")]
+    UnsupportedMediaType(&'static str),
+    #[error("Json: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Form: {0}")]
+    Form(#[from] serde_urlencoded::de::Error),
+    #[error("Missing cookie: {0}")]
+    MissingCookie(String),
+    #[error("Missing credentials")]
+    MissingCredentials,
+    #[error("Malformed credentials")]
+    MalformedCredentials,
+}
+
+impl IntoProblem for RequestError {
+    fn status(&self) -> StatusCode {
+        match self {
+            Self::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::Json(_) | Self::Form(_) | Self::MissingCookie(_) => StatusCode::BAD_REQUEST,
+            Self::MissingCredentials | Self::MalformedCredentials => StatusCode::UNAUTHORIZED,
+        }
+    }
+
+    /// Includes the error message as the detail, except for parsing errors whose messages
+    /// can reveal the structure of the expected types.
+    fn problem(&self) -> Problem {
+        let problem = Problem::new(self.status());
+        match self {
+            Self::Json(_) | Self::Form(_) => problem.with_detail("Malformed body"),
+            _ => problem.with_detail(self.to_string()),
+        }
+    }
+}
+
+/// Returns an error if the `Content-Length` of the request exceeds the limit. Use before reading the body.
+pub fn check_content_length(headers: &HeaderMap, limit: usize) -> Result<(), RequestError> {
+    let length = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok());
+
+    match length {
+        Some(length) if length > limit as u64 => Err(RequestError::PayloadTooLarge(limit)),
+        _ => Ok(()),
+    }
+}
+
+/// Parses a JSON body. The content type must be `application/json` or end with `+json`.
+pub fn json<T>(headers: &HeaderMap, body: &[u8], limit: usize) -> Result<T, RequestError>
+where
+    T: DeserializeOwned,
+{
+    let is_json = media_type(headers)
+        .is_some_and(|mt| mt == mime::APPLICATION_JSON.essence_str() || mt.ends_with("+json"));
+    if !is_json {
+        return Err(RequestError::UnsupportedMediaType(
+            mime::APPLICATION_JSON.essence_str(),
+        ));
+    }
+    check_size(headers, body, limit)?;
+    Ok(serde_json::from_slice(body)?)
+}
+
+/// Parses an `application/x-www-form-urlencoded` body.
+pub fn form<T>(headers: &HeaderMap, body: &[u8], limit: usize) -> Result<T, RequestError>
+where
+    T: DeserializeOwned,
+{
+    let form = mime::APPLICATION_WWW_FORM_URLENCODED.essence_str();
+    if media_type(headers).as_deref() != Some(form) {
+        return Err(RequestError::UnsupportedMediaType(form));
+    }
+    check_size(headers, body, limit)?;
+    Ok(serde_urlencoded::from_bytes(body)?)
+}
+
+/// Parses the query string of the URI. A missing query is parsed as an empty one.
+pub fn query<T>(uri: &Uri) -> Result<T, RequestError>
+where
+    T: DeserializeOwned,
+{
+    Ok(serde_urlencoded::from_str(uri.query().unwrap_or_default())?)
+}
+
+/// Finds the cookie with the given name.
+pub fn cookie(headers: &HeaderMap, name: &str) -> Result<Cookie<'static>, RequestError> {
+    find_cookie(headers, name).ok_or_else(|| RequestError::MissingCookie(name.to_string()))
+}
+
+/// Returns the token from an `Authorization: Bearer <token>` header.
+pub fn bearer_token(headers: &HeaderMap) -> Result<&str, RequestError> {
+    let token = authorization(headers, "Bearer")?.trim();
+    if token.is_empty() {
+        return Err(RequestError::MalformedCredentials);
+    }
+    Ok(token)
+}
+
+/// Credentials of the `Basic` authentication scheme.
+#[derive(Clone, PartialEq, Eq)]
+pub struct BasicAuth {
+    pub username: String,
+    pub password: String,
+}
+
+impl std::fmt::Debug for BasicAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BasicAuth")
+            .field("username", &self.username)
+            .field("password", &"{ ... }")
+            .finish()
+    }
+}
+
+/// Returns the credentials from an `Authorization: Basic <base64(username:password)>` header.
+pub fn basic_auth(headers: &HeaderMap) -> Result<BasicAuth, RequestError> {
+    let encoded = authorization(headers, "Basic")?.trim();
+
+    let decoded = BASE64
+        .decode(encoded.as_bytes())
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .ok_or(RequestError::MalformedCredentials)?;
+
+    let (username, password) = decoded
+        .split_once(':')
+        .ok_or(RequestError::MalformedCredentials)?;
+
+    Ok(BasicAuth {
+        username: username.to_string(),
+        password: password.to_string(),
+    })
+}
+
+/// Returns the credentials of the `Authorization` header if it uses the scheme. Schemes are case insensitive.
+fn authorization<'a>(headers: &'a HeaderMap, scheme: &str) -> Result<&'a str, RequestError> {
+    let value = headers
+        .get(header::AUTHORIZATION)
+        .ok_or(RequestError::MissingCredentials)?
+        .to_str()
+        .map_err(|_| RequestError::MalformedCredentials)?;
+
+    let (s, credentials) = value
+        .split_once(' ')
+        .ok_or(RequestError::MalformedCredentials)?;
+
+    if !s.eq_ignore_ascii_case(scheme) {
+        return Err(RequestError::MissingCredentials);
+    }
+
+    Ok(credentials)
+}
+
+/// The essence of the `Content-Type`, lowercased and without parameters.
+fn media_type(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(header::CONTENT_TYPE)?.to_str().ok()?;
+    let essence = value.split(';').next()?.trim();
+    Some(essence.to_ascii_lowercase())
+}
+
+fn check_size(headers: &HeaderMap, body: &[u8], limit: usize) -> Result<(), RequestError> {
+    check_content_length(headers, limit)?;
+    if body.len() > limit {
+        return Err(RequestError::PayloadTooLarge(limit));
+    }
+    Ok(())
+}
+
+/// Resolves the client IP of requests passing through reverse proxies.
+///
+/// The forwarding headers are honored only if the peer is a trusted proxy. The `Forwarded` header is read
+/// if present, otherwise `X-Forwarded-For`. The list is walked from the right, skipping trusted proxies,
+/// and the first untrusted address is the client, since anything to its left can be spoofed by the client.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    networks: Vec<IpNet>,
+}
+
+impl TrustedProxies {
+    /// Trusts no proxies, the client IP is always the peer address.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trusts loopback and private network addresses, e.g. when running behind a load balancer in the same network.
+    pub fn private() -> Self {
+        let networks = [
+            "127.0.0.0/8",
+            "10.0.0.0/8",
+            "172.16.0.0/12",
+            "192.168.0.0/16",
+            "::1/128",
+            "fc00::/7",
+        ];
+        Self {
+            networks: networks
+                .iter()
+                .map(|n| n.parse().expect("valid network"))
+                .collect(),
+        }
+    }
+
+    /// Trusts an address, e.g. `10.0.0.1`, or a network in CIDR notation, e.g. `10.0.0.0/8`.
+    pub fn trust(mut self, network: &str) -> Result<Self, ipnet::AddrParseError> {
+        let network = match network.parse::<IpAddr>() {
+            Ok(ip) => IpNet::from(ip),
+            Err(_) => network.parse()?,
+        };
+        self.networks.push(network);
+        Ok(self)
+    }
+
+    pub fn is_trusted(&self, ip: &IpAddr) -> bool {
+        self.networks.iter().any(|net| net.contains(ip))
+    }
+
+    /// Returns the client IP, or `None` if it cannot be determined, e.g. when a proxy obfuscates
+    /// the addresses or the peer is unknown.
+    pub fn client_ip(&self, headers: &HeaderMap, peer: Option<IpAddr>) -> Option<IpAddr> {
+        let peer = peer?;
+        if !self.is_trusted(&peer) {
+            return Some(peer);
+        }
+
+        let chain = forwarded_for(headers);
+        let mut client = peer;
+
+        for ip in chain.iter().rev() {
+            // An unparseable address hides the client, we cannot trust anything to its left
+            let ip = (*ip)?;
+            client = ip;
+            if !self.is_trusted(&ip) {
+                break;
+            }
+        }
+
+        Some(client)
+    }
+
+    /// Uses the [SocketAddr] or [IpAddr] found in the request extensions as the peer.
+    pub fn client_ip_from_parts(&self, parts: &Parts) -> Option<IpAddr> {
+        let peer = parts
+            .extensions
+            .get::<SocketAddr>()
+            .map(SocketAddr::ip)
+            .or_else(|| parts.extensions.get::<IpAddr>().copied());
+        self.client_ip(&parts.headers, peer)
+    }
+}
+
+/// The addresses in the `Forwarded` header, or `X-Forwarded-For` if it is missing, in order of appearance.
+fn forwarded_for(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let values = |name| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .collect::<Vec<_>>()
+    };
+
+    let forwarded = values(header::FORWARDED);
+    if !forwarded.is_empty() {
+        return forwarded
+            .into_iter()
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.trim().split_once('='))
+                    .find(|(key, _)| key.eq_ignore_ascii_case("for"))
+                    .and_then(|(_, node)| parse_node(node))
+            })
+            .collect();
+    }
+
+    values(header::HeaderName::from_static("x-forwarded-for"))
+        .into_iter()
+        .map(parse_node)
+        .collect()
+}
+
+/// Parses a node, e.g. `192.0.2.1`, `192.0.2.1:8080`, `"[2001:db8::1]:4711"` or `2001:db8::1`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip);
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    node.strip_prefix('[')
+        .and_then(|n| n.strip_suffix(']'))
+        .and_then(|n| n.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+    use serde::Deserialize;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Login {
+        email: String,
+        remember: Option<bool>,
+    }
+
+    #[test]
+    fn bodies() {
+        let json_headers = headers(&[("content-type", "application/json; charset=utf-8")]);
+        let login: Login = json(&json_headers, br#"{"email":"a@b.c"}"#, 64).unwrap();
+        assert_eq!(login.email, "a@b.c");
+
+        let problem_json = headers(&[("content-type", "application/merge-patch+json")]);
+        assert!(json::<Login>(&problem_json, br#"{"email":"a"}"#, 64).is_ok());
+
+        let err = json::<Login>(&json_headers, br#"{"email":"a@b.c"}"#, 8).unwrap_err();
+        assert_eq!(err.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let err = json::<Login>(&HeaderMap::new(), b"{}", 64).unwrap_err();
+        assert_eq!(err.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let err = json::<Login>(&json_headers, b"{}", 64).unwrap_err();
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(err.problem().detail.as_deref(), Some("Malformed body"));
+
+        let too_long = headers(&[("content-length", "1000")]);
+        assert!(check_content_length(&too_long, 100).is_err());
+        assert!(check_content_length(&too_long, 1000).is_ok());
+
+        let form_headers = headers(&[("content-type", "application/x-www-form-urlencoded")]);
+        let login: Login = form(&form_headers, b"email=a%40b.c&remember=true", 64).unwrap();
+        assert_eq!(login.email, "a@b.c");
+        assert_eq!(login.remember, Some(true));
+
+        let uri: Uri = "/login?email=x%40y.z".parse().unwrap();
+        let login: Login = query(&uri).unwrap();
+        assert_eq!(login.email, "x@y.z");
+        assert!(query::<Login>(&"/login".parse().unwrap()).is_err());
+    }
+
+    #[test]
+    fn credentials() {
+        let auth = headers(&[("authorization", "bearer abc.def")]);
+        assert_eq!(bearer_token(&auth).unwrap(), "abc.def");
+        assert!(matches!(
+            bearer_token(&HeaderMap::new()),
+            Err(RequestError::MissingCredentials)
+        ));
+
+        // "user:pa:ss"
+        let auth = headers(&[("authorization", "Basic dXNlcjpwYTpzcw==")]);
+        let basic = basic_auth(&auth).unwrap();
+        assert_eq!(basic.username, "user");
+        assert_eq!(basic.password, "pa:ss");
+        assert!(!format!("{basic:?}").contains("pa:ss"));
+        assert!(matches!(
+            bearer_token(&auth),
+            Err(RequestError::MissingCredentials)
+        ));
+
+        let auth = headers(&[("authorization", "Basic !!!")]);
+        assert!(matches!(
+            basic_auth(&auth),
+            Err(RequestError::MalformedCredentials)
+        ));
+
+        let cookies = headers(&[("cookie", "a=1; session=xyz")]);
+        assert_eq!(cookie(&cookies, "session").unwrap().value(), "xyz");
+        assert_eq!(
+            cookie(&cookies, "missing").unwrap_err().status(),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[test]
+    fn client_ip() {
+        let proxies = TrustedProxies::new().trust("10.0.0.0/8").unwrap();
+        let peer = Some("10.0.0.2".parse().unwrap());
+        let ip = |s: &str| Some(s.parse::<IpAddr>().unwrap());
+
+        // Untrusted peers cannot forward
+        let xff = headers(&[("x-forwarded-for", "1.1.1.1")]);
+        assert_eq!(proxies.client_ip(&xff, ip("9.9.9.9")), ip("9.9.9.9"));
+        assert_eq!(proxies.client_ip(&xff, None), None);
+
+        // The client prepends a spoofed address, the proxies append the real ones
+        let xff = headers(&[("x-forwarded-for", "6.6.6.6, 2.2.2.2, 10.0.0.1")]);
+        assert_eq!(proxies.client_ip(&xff, peer), ip("2.2.2.2"));
+
+        let forwarded = headers(&[
+            ("forwarded", "for=6.6.6.6"),
+            (
+                "forwarded",
+                "for=\"[2001:db8::1]:4711\";proto=https, for=10.0.0.1",
+            ),
+            ("x-forwarded-for", "3.3.3.3"),
+        ]);
+        assert_eq!(proxies.client_ip(&forwarded, peer), ip("2001:db8::1"));
+
+        let hidden = headers(&[("forwarded", "for=1.1.1.1, for=_hidden")]);
+        assert_eq!(proxies.client_ip(&hidden, peer), None);
+
+        assert_eq!(proxies.client_ip(&HeaderMap::new(), peer), peer);
+        assert!(TrustedProxies::private().is_trusted(&"192.168.1.1".parse().unwrap()));
+        assert!(TrustedProxies::new().trust("nonsense").is_err());
+    }
+}
@@ -1,3 +1,12 @@
+use super::conditional::{
+    evaluate, format_http_date, parse_http_date, CacheControl, ETag, Precondition,
+};
+#[cfg(feature = "web-negotiation")]
+use super::negotiation::{Format, Negotiation};
+use super::rate_limit::RateLimit;
+#[cfg(feature = "pagination")]
+use crate::pagination::Page;
+use chrono::{DateTime, Utc};
 use cookie::Cookie;
 use http::header;
 use http::{
@@ -5,7 +14,7 @@ use http::{
     response::Builder,
     Response, StatusCode,
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -16,6 +25,10 @@ pub enum ResponseError {
     Http(#[from] http::Error),
     #[error("Serde: {0}")]
     Serde(#[from] serde_json::Error),
+    #[error("Encode: {0}")]
+    Encode(String),
+    #[error("IO: {0}")]
+    Io(#[from] std::io::Error),
 }
 
 pub struct ResponseBuilder<T> {
@@ -52,6 +65,56 @@ impl<T> ResponseBuilder<T> {
         self
     }
 
+    /// Sets the `RateLimit-*` headers, and `Retry-After` if the limit was exceeded.
+    pub fn with_rate_limit(mut self, limit: &RateLimit) -> ResponseBuilder<T> {
+        for (key, value) in limit.headers().iter() {
+            self.builder = self.builder.header(key, value);
+        }
+
+        self
+    }
+
+    /// Sets the `Link` header to the related pages of `page`, relative to the request URI.
+    #[cfg(feature = "pagination")]
+    pub fn with_links<P>(
+        mut self,
+        page: &Page<P>,
+        uri: &http::Uri,
+    ) -> Result<ResponseBuilder<T>, ResponseError> {
+        if let Some(links) = page.link_header(uri) {
+            self.builder = self
+                .builder
+                .header(header::LINK, HeaderValue::try_from(links)?);
+        }
+        Ok(self)
+    }
+
+    pub fn with_etag(mut self, etag: &ETag) -> ResponseBuilder<T> {
+        self.builder = self.builder.header(header::ETAG, etag.header_value());
+        self
+    }
+
+    pub fn with_last_modified(
+        mut self,
+        time: DateTime<Utc>,
+    ) -> Result<ResponseBuilder<T>, ResponseError> {
+        self.builder = self.builder.header(
+            header::LAST_MODIFIED,
+            HeaderValue::try_from(format_http_date(time))?,
+        );
+        Ok(self)
+    }
+
+    pub fn with_cache_control(
+        mut self,
+        cache_control: &CacheControl,
+    ) -> Result<ResponseBuilder<T>, ResponseError> {
+        self.builder = self
+            .builder
+            .header(header::CACHE_CONTROL, cache_control.header_value()?);
+        Ok(self)
+    }
+
     pub fn finish(self) -> Result<Response<T>, ResponseError> {
         Ok(self.builder.body(self.body)?)
     }
@@ -77,6 +140,136 @@ where
     }
 }
 
+impl<T> ResponseBuilder<T>
+where
+    T: Serialize,
+{
+    /// Finish the response with a JSON body, evaluating the request's preconditions.
+    ///
+    /// The validators are the ETag and Last-Modified headers set on the builder. If no ETag was set, a strong one
+    /// is computed from the body. Responds with `304 Not Modified` or `412 Precondition Failed` and an empty body
+    /// if the preconditions say so, see [evaluate].
+    pub fn conditional_json(
+        mut self,
+        method: &http::Method,
+        request: &http::HeaderMap,
+    ) -> Result<Response<String>, ResponseError> {
+        let json = serde_json::to_string(&self.body)?;
+
+        let Some(headers) = self.builder.headers_mut() else {
+            // The builder has an error which will be returned
+            return self.builder.body(json).map_err(ResponseError::Http);
+        };
+
+        let etag = match headers
+            .get(header::ETAG)
+            .and_then(|v| v.to_str().ok())
+            .and_then(ETag::parse)
+        {
+            Some(etag) => etag,
+            None => {
+                let etag = ETag::from_body(json.as_bytes());
+                headers.insert(header::ETAG, etag.header_value());
+                etag
+            }
+        };
+
+        let last_modified = headers
+            .get(header::LAST_MODIFIED)
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_http_date);
+
+        match evaluate(method, request, Some(&etag), last_modified) {
+            Precondition::Proceed => {
+                if !headers.contains_key(header::CONTENT_TYPE) {
+                    headers.insert(
+                        header::CONTENT_TYPE,
+                        HeaderValue::from_static(mime::APPLICATION_JSON.essence_str()),
+                    );
+                }
+                self.builder.body(json).map_err(ResponseError::Http)
+            }
+            Precondition::NotModified => {
+                headers.remove(header::CONTENT_TYPE);
+                self.builder
+                    .status(StatusCode::NOT_MODIFIED)
+                    .body(String::new())
+                    .map_err(ResponseError::Http)
+            }
+            Precondition::Failed => {
+                headers.remove(header::CONTENT_TYPE);
+                self.builder
+                    .status(StatusCode::PRECONDITION_FAILED)
+                    .body(String::new())
+                    .map_err(ResponseError::Http)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "web-negotiation")]
+impl<T> ResponseBuilder<T>
+where
+    T: Serialize,
+{
+    /// Finish the response with the body in the format preferred by the request, compressing it if the
+    /// request allows it. Uses the [default][Negotiation::default] negotiation.
+    ///
+    /// Responds with `406 Not Acceptable` and the available types in the body if the request accepts none of them.
+    pub fn negotiate(self, request: &http::HeaderMap) -> Result<Response<Vec<u8>>, ResponseError> {
+        self.negotiate_with(request, &Negotiation::default())
+    }
+
+    /// Same as [negotiate][ResponseBuilder::negotiate], with a custom configuration.
+    pub fn negotiate_with(
+        mut self,
+        request: &http::HeaderMap,
+        negotiation: &Negotiation,
+    ) -> Result<Response<Vec<u8>>, ResponseError> {
+        let vary = if negotiation.encodings.is_empty() {
+            "accept"
+        } else {
+            "accept, accept-encoding"
+        };
+        self.builder = self.builder.header(header::VARY, vary);
+
+        let Some((format, content_type)) = negotiation.content_type(request) else {
+            let available = negotiation
+                .formats
+                .iter()
+                .map(Format::content_type)
+                .collect::<Vec<_>>()
+                .join(", ");
+            return Ok(self
+                .builder
+                .status(StatusCode::NOT_ACCEPTABLE)
+                .header(header::CONTENT_TYPE, mime::TEXT_PLAIN_UTF_8.as_ref())
+                .body(available.into_bytes())?);
+        };
+
+        let mut body = format.serialize(&self.body)?;
+
+        if let Some(headers) = self.builder.headers_mut() {
+            // Keeps more specific JSON types, e.g. problem+json
+            if format != Format::Json || !headers.contains_key(header::CONTENT_TYPE) {
+                headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+            }
+
+            if body.len() >= negotiation.min_compress_size {
+                if let Some(encoding) = negotiation.encoding(request) {
+                    body = encoding.compress(&body)?;
+                    headers.insert(
+                        header::CONTENT_ENCODING,
+                        HeaderValue::from_static(encoding.as_str()),
+                    );
+                }
+            }
+        }
+
+        self.builder.body(body).map_err(ResponseError::Http)
+    }
+}
+
 /// Utility containing default methods for quickly converting a struct to an HTTP response.
 pub trait RestResponse<'a>
 where
@@ -89,4 +282,542 @@ where
             body: self,
         }
     }
+
+    /// The status used by [into_default_response][RestResponse::into_default_response]. Set with `#[code]` when derived.
+    fn default_status(&self) -> StatusCode {
+        StatusCode::OK
+    }
+
+    /// Headers added by [into_default_response][RestResponse::into_default_response]. Set with `#[header]` when derived.
+    fn default_headers(&self) -> Vec<(HeaderName, HeaderValue)> {
+        vec![]
+    }
+
+    /// Cookies added by [into_default_response][RestResponse::into_default_response]. Set with `#[cookie]` when derived.
+    fn cookies(&self) -> Vec<Cookie<'static>> {
+        vec![]
+    }
+
+    /// Creates a builder with the default status, headers and cookies of the type.
+    fn into_default_response(self) -> Result<ResponseBuilder<Self>, ResponseError> {
+        let status = self.default_status();
+        let headers = self.default_headers();
+        let cookies = self.cookies();
+
+        let mut response = self.into_response(status);
+        for (key, value) in headers {
+            response.builder = response.builder.header(key, value);
+        }
+
+        response.with_cookies(&cookies)
+    }
+
+    /// Finishes the [default response][RestResponse::into_default_response] with a JSON body.
+    fn into_json_response(self) -> Result<Response<String>, ResponseError> {
+        self.into_default_response()?.json()
+    }
+}
+
+/// The content type of [Problem] responses.
+pub const APPLICATION_PROBLEM_JSON: &str = "application/problem+json";
+
+/// Members of a [Problem] which cannot be used as extensions.
+const PROBLEM_MEMBERS: [&str; 5] = ["type", "title", "status", "detail", "instance"];
+
+/// An error response according to [RFC 7807](https://www.rfc-editor.org/rfc/rfc7807).
+///
+/// Any additional members are stored in `extensions` and serialized at the top level of the object.
+///
+/// ### Example
+///
+/// ```ignore
+/// Problem::new(StatusCode::FORBIDDEN)
+///     .with_type("https://example.com/probs/out-of-credit")
+///     .with_detail("Your current balance is 30, but that costs 50.")
+///     .with_extension("balance", 30)
+///     .response()
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Problem {
+    /// A URI identifying the problem type. When absent, it is assumed to be `about:blank`.
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none", default)]
+    pub r#type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub title: Option<String>,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub detail: Option<String>,
+    /// A URI identifying this occurrence of the problem.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub instance: Option<String>,
+    #[serde(flatten)]
+    pub extensions: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Problem {
+    /// Creates a problem with the status' canonical reason as the title.
+    pub fn new(status: StatusCode) -> Self {
+        Self {
+            r#type: None,
+            title: status.canonical_reason().map(ToString::to_string),
+            status: status.as_u16(),
+            detail: None,
+            instance: None,
+            extensions: serde_json::Map::new(),
+        }
+    }
+
+    pub fn with_type(mut self, r#type: impl Into<String>) -> Self {
+        self.r#type = Some(r#type.into());
+        self
+    }
+
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn with_instance(mut self, instance: impl Into<String>) -> Self {
+        self.instance = Some(instance.into());
+        self
+    }
+
+    /// Adds an extension member. Keys of the standard members are ignored.
+    pub fn with_extension(mut self, key: &str, value: impl Into<serde_json::Value>) -> Self {
+        if !PROBLEM_MEMBERS.contains(&key) {
+            self.extensions.insert(key.to_string(), value.into());
+        }
+        self
+    }
+
+    /// Returns the status, or `500` if it is not a valid status code.
+    pub fn status_code(&self) -> StatusCode {
+        StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    /// Creates a builder with the problem's status and the `application/problem+json` content type so
+    /// headers and cookies can be added before finishing it with [json][ResponseBuilder::json].
+    pub fn into_builder(self) -> ResponseBuilder<Self> {
+        let status = self.status_code();
+        self.into_response(status)
+            .with_headers([(header::CONTENT_TYPE, APPLICATION_PROBLEM_JSON)])
+    }
+
+    /// Finishes the problem as an `application/problem+json` response.
+    pub fn response(self) -> Result<Response<String>, ResponseError> {
+        self.into_json_response()
+    }
+}
+
+impl RestResponse<'_> for Problem {
+    fn default_status(&self) -> StatusCode {
+        self.status_code()
+    }
+
+    fn default_headers(&self) -> Vec<(HeaderName, HeaderValue)> {
+        vec![(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(APPLICATION_PROBLEM_JSON),
+        )]
+    }
+}
+
+#[cfg(feature = "pagination")]
+impl<T> RestResponse<'_> for Page<T> where T: Serialize {}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.status)?;
+        if let Some(ref title) = self.title {
+            write!(f, " {title}")?;
+        }
+        if let Some(ref detail) = self.detail {
+            write!(f, ": {detail}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Maps errors to [Problem]s. Can be derived for error enums with `#[derive(IntoProblem)]`, see the
+/// [macro][crate::IntoProblem] for the available attributes.
+pub trait IntoProblem {
+    fn status(&self) -> StatusCode;
+
+    /// By default, the problem contains only the status and its canonical reason so internal
+    /// error messages are not exposed.
+    fn problem(&self) -> Problem {
+        Problem::new(self.status())
+    }
+}
+
+impl IntoProblem for Problem {
+    fn status(&self) -> StatusCode {
+        self.status_code()
+    }
+
+    fn problem(&self) -> Problem {
+        self.clone()
+    }
+}
+
+/// Turns the result of a handler into a response. Successes are serialized as JSON with the given status
+/// and errors as `application/problem+json` with the status of their [Problem].
+pub fn respond<'a, T, E>(
+    result: Result<T, E>,
+    code: StatusCode,
+) -> Result<Response<String>, ResponseError>
+where
+    T: RestResponse<'a>,
+    E: IntoProblem,
+{
+    match result {
+        Ok(body) => body.into_response(code).json(),
+        Err(e) => e.problem().response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IntoProblem, RestResponse};
+    use serde_json::json;
+    use thiserror::Error;
+
+    #[derive(Debug, Error, IntoProblem)]
+    enum AuthError {
+        #[error("Invalid credentials")]
+        #[problem(status = 401, detail)]
+        Credentials,
+    }
+
+    #[derive(Debug, Error, IntoProblem)]
+    enum Error {
+        #[error("User {0} not found")]
+        #[problem(status = 404, type = "https://example.com/probs/not-found", detail)]
+        NotFound(u64),
+        #[error("Too many attempts")]
+        #[problem(status = 429, title = "Slow down", detail = "Try again later")]
+        Throttled { attempts: u32 },
+        #[error("{0}")]
+        #[problem(transparent)]
+        Auth(#[from] AuthError),
+        #[error("Database: {0}")]
+        Database(String),
+    }
+
+    #[derive(Debug, Clone, Serialize, RestResponse)]
+    struct User {
+        id: u64,
+    }
+
+    #[derive(Debug, Serialize, RestResponse)]
+    #[code(201)]
+    #[header("Cache-Control", "no-store")]
+    #[header("x-custom", "value")]
+    struct Session {
+        id: u64,
+        #[cookie]
+        #[serde(skip)]
+        cookie: Cookie<'static>,
+        #[cookie]
+        #[serde(skip)]
+        refresh: Option<Cookie<'static>>,
+    }
+
+    #[derive(Debug, Serialize, RestResponse)]
+    #[header("x-custom", "value")]
+    enum Outcome {
+        #[code(CREATED)]
+        Created {
+            id: u64,
+        },
+        #[code(202)]
+        #[header("location", "/jobs/1")]
+        Accepted(u64),
+        Noop,
+    }
+
+    #[test]
+    fn problem() {
+        let problem = Problem::new(StatusCode::FORBIDDEN)
+            .with_type("https://example.com/probs/out-of-credit")
+            .with_detail("Your current balance is 30, but that costs 50.")
+            .with_instance("/account/12345/msgs/abc")
+            .with_extension("balance", 30)
+            .with_extension("status", 200);
+
+        let value = serde_json::to_value(&problem).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "type": "https://example.com/probs/out-of-credit",
+                "title": "Forbidden",
+                "status": 403,
+                "detail": "Your current balance is 30, but that costs 50.",
+                "instance": "/account/12345/msgs/abc",
+                "balance": 30
+            })
+        );
+        assert_eq!(serde_json::from_value::<Problem>(value).unwrap(), problem);
+
+        let response = problem.response().unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            APPLICATION_PROBLEM_JSON
+        );
+    }
+
+    #[test]
+    fn derive() {
+        let problem = Error::NotFound(1).problem();
+        assert_eq!(problem.status, 404);
+        assert_eq!(problem.title.as_deref(), Some("Not Found"));
+        assert_eq!(
+            problem.r#type.as_deref(),
+            Some("https://example.com/probs/not-found")
+        );
+        assert_eq!(problem.detail.as_deref(), Some("User 1 not found"));
+
+        let problem = Error::Throttled { attempts: 5 }.problem();
+        assert_eq!(problem.title.as_deref(), Some("Slow down"));
+        assert_eq!(problem.detail.as_deref(), Some("Try again later"));
+
+        let problem = Error::from(AuthError::Credentials).problem();
+        assert_eq!(problem.status, 401);
+        assert_eq!(problem.detail.as_deref(), Some("Invalid credentials"));
+
+        let problem = Error::Database("connection refused".to_string()).problem();
+        assert_eq!(problem.status, 500);
+        assert!(problem.detail.is_none());
+    }
+
+    #[test]
+    fn pipeline() {
+        let ok = respond::<_, Error>(Ok(User { id: 1 }), StatusCode::CREATED).unwrap();
+        assert_eq!(ok.status(), StatusCode::CREATED);
+        assert_eq!(ok.headers()[header::CONTENT_TYPE], "application/json");
+        assert_eq!(ok.body(), r#"{"id":1}"#);
+
+        let err = respond::<User, _>(Err(Error::NotFound(2)), StatusCode::OK).unwrap();
+        assert_eq!(err.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            err.headers()[header::CONTENT_TYPE],
+            APPLICATION_PROBLEM_JSON
+        );
+    }
+
+    #[cfg(feature = "pagination")]
+    #[test]
+    fn paginated() {
+        use crate::pagination::{OffsetParams, Page};
+
+        let params = OffsetParams::new(1, 2);
+        let page = Page::offset(vec![User { id: 1 }, User { id: 2 }], &params, Some(3));
+        let uri: http::Uri = "/users?page=1".parse().unwrap();
+        let res = page
+            .clone()
+            .into_response(StatusCode::OK)
+            .with_links(&page, &uri)
+            .unwrap()
+            .json()
+            .unwrap();
+
+        assert_eq!(
+            res.headers()[header::LINK],
+            "</users?page=1&per_page=2>; rel=\"first\", </users?page=2&per_page=2>; rel=\"next\", \
+             </users?page=2&per_page=2>; rel=\"last\""
+        );
+        assert_eq!(
+            res.body(),
+            r#"{"items":[{"id":1},{"id":2}],"page":1,"per_page":2,"total":3}"#
+        );
+    }
+
+    #[test]
+    fn derive_response() {
+        let session = Session {
+            id: 1,
+            cookie: Cookie::new("session", "abc"),
+            refresh: None,
+        };
+        let res = session.into_json_response().unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(res.headers()[header::CACHE_CONTROL], "no-store");
+        assert_eq!(res.headers()["x-custom"], "value");
+        assert_eq!(
+            res.headers()
+                .get_all(header::SET_COOKIE)
+                .iter()
+                .collect::<Vec<_>>(),
+            ["session=abc"]
+        );
+        assert_eq!(res.body(), r#"{"id":1}"#);
+
+        let res = Outcome::Created { id: 1 }.into_json_response().unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(res.headers()["x-custom"], "value");
+
+        let res = Outcome::Accepted(1).into_json_response().unwrap();
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        assert_eq!(res.headers()[header::LOCATION], "/jobs/1");
+
+        let res = Outcome::Noop.into_json_response().unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // Explicit codes still take precedence
+        let res = User { id: 1 }
+            .into_response(StatusCode::ACCEPTED)
+            .json()
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+    }
+
+    #[cfg(feature = "web-axum")]
+    #[test]
+    fn axum_responder() {
+        let res = axum::response::IntoResponse::into_response(Outcome::Accepted(1));
+        assert_eq!(res.status(), axum::http::StatusCode::ACCEPTED);
+        assert_eq!(res.headers()["location"], "/jobs/1");
+        assert_eq!(res.headers()["content-type"], "application/json");
+        assert_eq!(res.headers().get_all("content-type").iter().count(), 1);
+    }
+
+    #[cfg(feature = "web-actix")]
+    #[test]
+    fn actix_responder() {
+        use actix_web::Responder;
+
+        let req = actix_web::test::TestRequest::default().to_http_request();
+        let res = Outcome::Accepted(1).respond_to(&req);
+        assert_eq!(res.status(), actix_web::http::StatusCode::ACCEPTED);
+        assert_eq!(res.headers().get("location").unwrap(), "/jobs/1");
+        assert_eq!(
+            res.headers().get("content-type").unwrap(),
+            "application/json"
+        );
+        assert_eq!(res.headers().get_all("content-type").count(), 1);
+    }
+
+    #[cfg(feature = "web-negotiation")]
+    #[test]
+    fn negotiate() {
+        use http::HeaderMap;
+
+        let mut request = HeaderMap::new();
+        request.insert(
+            header::ACCEPT,
+            HeaderValue::from_static("application/cbor, application/json;q=0.5"),
+        );
+        request.insert(header::ACCEPT_ENCODING, HeaderValue::from_static("gzip"));
+
+        let res = User { id: 1 }
+            .into_response(StatusCode::OK)
+            .negotiate(&request)
+            .unwrap();
+        assert_eq!(res.headers()[header::CONTENT_TYPE], "application/cbor");
+        assert_eq!(res.headers()[header::VARY], "accept, accept-encoding");
+        // Too small to compress
+        assert!(!res.headers().contains_key(header::CONTENT_ENCODING));
+
+        #[derive(Serialize, RestResponse)]
+        struct Users {
+            users: Vec<User>,
+        }
+
+        let users = Users {
+            users: (0..200).map(|id| User { id }).collect(),
+        };
+        request.insert(header::ACCEPT, HeaderValue::from_static("application/*"));
+        let res = users
+            .into_response(StatusCode::OK)
+            .negotiate(&request)
+            .unwrap();
+        assert_eq!(res.headers()[header::CONTENT_TYPE], "application/json");
+        assert_eq!(res.headers()[header::CONTENT_ENCODING], "gzip");
+
+        let res = Problem::new(StatusCode::NOT_FOUND)
+            .into_builder()
+            .negotiate(&request)
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            res.headers()[header::CONTENT_TYPE],
+            APPLICATION_PROBLEM_JSON
+        );
+
+        request.insert(
+            header::ACCEPT,
+            HeaderValue::from_static("text/html, text/*;q=0.8"),
+        );
+        let res = User { id: 1 }
+            .into_response(StatusCode::OK)
+            .negotiate(&request)
+            .unwrap();
+        assert_eq!(
+            res.headers()[header::CONTENT_TYPE],
+            "text/xml; charset=utf-8"
+        );
+
+        request.insert(header::ACCEPT, HeaderValue::from_static("image/png"));
+        let res = User { id: 1 }
+            .into_response(StatusCode::OK)
+            .negotiate(&request)
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_ACCEPTABLE);
+    }
+
+    #[test]
+    fn conditional() {
+        use super::super::conditional::{CacheControl, ETag};
+        use chrono::TimeZone;
+        use http::{HeaderMap, Method};
+
+        let res = User { id: 1 }
+            .into_response(StatusCode::OK)
+            .with_cache_control(&CacheControl::revalidate())
+            .unwrap()
+            .conditional_json(&Method::GET, &HeaderMap::new())
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::CACHE_CONTROL], "no-cache");
+        let etag = res.headers()[header::ETAG].clone();
+
+        let mut request = HeaderMap::new();
+        request.insert(header::IF_NONE_MATCH, etag.clone());
+        let res = User { id: 1 }
+            .into_response(StatusCode::OK)
+            .conditional_json(&Method::GET, &request)
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(res.headers()[header::ETAG], etag);
+        assert!(res.body().is_empty());
+
+        let res = User { id: 2 }
+            .into_response(StatusCode::OK)
+            .conditional_json(&Method::GET, &request)
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let modified = Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap();
+        let mut request = HeaderMap::new();
+        request.insert(header::IF_MATCH, HeaderValue::from_static("\"v1\""));
+        request.insert(
+            header::IF_MODIFIED_SINCE,
+            HeaderValue::from_static("Sun, 01 Jan 2023 00:00:00 GMT"),
+        );
+        let res = User { id: 1 }
+            .into_response(StatusCode::OK)
+            .with_etag(&ETag::weak("v1"))
+            .with_last_modified(modified)
+            .unwrap()
+            .conditional_json(&Method::PUT, &request)
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+    }
 }
@@ -1,65 +1,62 @@
+use super::csp::ContentSecurityPolicy;
+use futures::future::BoxFuture;
 use http::header::*;
+use http::{Request, Response};
+use std::{
+    sync::Arc,
+    task::{Context, Poll},
+};
+use thiserror::Error;
+use tower_layer::Layer;
+use tower_service::Service;
 
-//TODO : Create with params
+/// Returns the [default policy][ContentSecurityPolicy::default_policy]. Use [ContentSecurityPolicy]
+/// to customise it.
+///
 /// See <https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Content-Security-Policy>
 pub fn default_content_security_policy() -> (HeaderName, HeaderValue) {
-    (
-        CONTENT_SECURITY_POLICY,
-        HeaderValue::from_str(&format!(
-            "{}{}{}{}{}{}{}{}{}{}{}",
-            "default-src 'self';",
-            "base-uri 'self';",
-            "font-src 'self' https: data;",
-            "form-action 'self';",
-            "frame-ancestors 'self';",
-            "img-src 'self' data:;",
-            "object-src 'none';",
-            "script-src 'self';",
-            "script-src-attr 'none';",
-            "style-src 'self' https: 'unsafe-inline';",
-            "upgrade-insecure-requests"
-        ))
-        .expect("Couldn't construct CSP header"),
-    )
+    ContentSecurityPolicy::default_policy()
+        .header()
+        .expect("Couldn't construct CSP header")
 }
 
 /// See <https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Cross-Origin-Embedder-Policy>
 ///
-/// Accepts: `"require-corp" | "unsafe-none"`
-pub fn cross_origin_embedder_policy(policy: &'static str) -> (HeaderName, HeaderValue) {
-    (
+/// Accepts: `"require-corp" | "credentialless" | "unsafe-none"`
+pub fn cross_origin_embedder_policy(
+    policy: &str,
+) -> Result<(HeaderName, HeaderValue), SecurityHeaderError> {
+    let policy: CrossOriginEmbedderPolicy = policy.parse()?;
+    Ok((
         HeaderName::from_static("cross-origin-embedder-policy"),
-        HeaderValue::from_static(match policy {
-            "require-corp" | "unsafe-none" => policy,
-            _ => panic!("Invalid value given for cross-origin-embedder-policy header"),
-        }),
-    )
+        HeaderValue::from_static(policy.as_str()),
+    ))
 }
 
 /// See <https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Cross-Origin-Opener-Policy>
 ///
 /// Accepts: `"same-origin" | "same-origin-allow-popups" | "unsafe-none"`
-pub fn cross_origin_opener_policy(policy: &'static str) -> (HeaderName, HeaderValue) {
-    (
+pub fn cross_origin_opener_policy(
+    policy: &str,
+) -> Result<(HeaderName, HeaderValue), SecurityHeaderError> {
+    let policy: CrossOriginOpenerPolicy = policy.parse()?;
+    Ok((
         HeaderName::from_static("cross-origin-opener-policy"),
-        HeaderValue::from_static(match policy {
-            "same-origin" | "same-origin-allow-popups" | "unsafe-none" => policy,
-            _ => panic!("Invalid value given for cross-origin-opener-policy header"),
-        }),
-    )
+        HeaderValue::from_static(policy.as_str()),
+    ))
 }
 
 /// See <https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Cross-Origin-Resource-Policy>
 ///
 /// Accepts: `"same-site" | "same-origin" | "cross-origin"`
-pub fn cross_origin_resource_policy(policy: &'static str) -> (HeaderName, HeaderValue) {
-    (
+pub fn cross_origin_resource_policy(
+    policy: &str,
+) -> Result<(HeaderName, HeaderValue), SecurityHeaderError> {
+    let policy: CrossOriginResourcePolicy = policy.parse()?;
+    Ok((
         HeaderName::from_static("cross-origin-resource-policy"),
-        HeaderValue::from_static(match policy {
-            "same-site" | "same-origin" | "cross-origin" => policy,
-            _ => panic!("Invalid value given for cross-origin-resource-policy header"),
-        }),
-    )
+        HeaderValue::from_static(policy.as_str()),
+    ))
 }
 
 /// If given a slice with 1 element the header will be set to the given element if it is valid.
@@ -68,33 +65,21 @@ pub fn cross_origin_resource_policy(policy: &'static str) -> (HeaderName, Header
 /// See <https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Referrer-Policy>
 ///
 /// Accepts: `&["no-referrer",
-/// "no-referrer-when-downgrage",
+/// "no-referrer-when-downgrade",
 /// "origin",
 /// "origin-when-cross-origin",
 /// "same-origin",
 /// "strict-origin",
 /// "strict-origin-when-cross-origin",
 /// "unsafe-url"]`
-pub fn referrer_policy(policies: &[&'static str]) -> (HeaderName, HeaderValue) {
-    const POLICIES: &[&str] = &[
-        "no-referrer",
-        "no-referrer-when-downgrade",
-        "origin",
-        "origin-when-cross-origin",
-        "same-origin",
-        "strict-origin",
-        "strict-origin-when-cross-origin",
-        "unsafe-url",
-    ];
-    for p in policies {
-        if !POLICIES.contains(p) {
-            panic!("Invalid value given for referrer-policy header")
-        }
-    }
-    (
-        REFERRER_POLICY,
-        HeaderValue::from_str(&policies.join(", ")).unwrap(),
-    )
+pub fn referrer_policy(
+    policies: &[&str],
+) -> Result<(HeaderName, HeaderValue), SecurityHeaderError> {
+    let policies = policies
+        .iter()
+        .map(|p| p.parse::<ReferrerPolicy>().map(|p| p.as_str()))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((REFERRER_POLICY, HeaderValue::try_from(policies.join(", "))?))
 }
 
 /// See <https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Strict-Transport-Security#preloading_strict_transport_security>
@@ -103,19 +88,23 @@ pub fn referrer_policy(policies: &[&'static str]) -> (HeaderName, HeaderValue) {
 pub fn strict_transport_security(
     max_age: usize,
     option: Option<&str>,
-) -> (HeaderName, HeaderValue) {
-    let value = if let Some(option) = option {
-        match option {
-            "includeSubDomains" | "preload" => format!("max-age={}; {}", max_age, option),
-            _ => panic!("Invalid value given for strict-transport-security header"),
-        }
-    } else {
-        format!("max-age={}", max_age)
-    };
-    (
+) -> Result<(HeaderName, HeaderValue), SecurityHeaderError> {
+    let mut hsts = StrictTransportSecurity::new(max_age as u64);
+    match option {
+        Some("includeSubDomains") => hsts.include_subdomains = true,
+        Some("preload") => hsts.preload = true,
+        Some(option) => {
+            return Err(SecurityHeaderError::InvalidValue {
+                header: "strict-transport-security",
+                value: option.to_string(),
+            })
+        }
+        None => {}
+    }
+    Ok((
         STRICT_TRANSPORT_SECURITY,
-        HeaderValue::from_str(&value).unwrap(),
-    )
+        HeaderValue::try_from(hsts.to_string())?,
+    ))
 }
 
 /// Sets the `x-content-type-options` header to `nosniff`
@@ -155,14 +144,14 @@ pub fn frame_options(sameorigin: bool) -> (HeaderName, HeaderValue) {
 /// See <https://owasp.org/www-project-secure-headers/#x-permitted-cross-domain-policies>
 ///
 /// Accepts: `"none" | "master-only" | "by-content-type" | "all"`
-pub fn cross_domain_policies(policy: &'static str) -> (HeaderName, HeaderValue) {
-    (
+pub fn cross_domain_policies(
+    policy: &str,
+) -> Result<(HeaderName, HeaderValue), SecurityHeaderError> {
+    let policy: CrossDomainPolicies = policy.parse()?;
+    Ok((
         HeaderName::from_static("x-permitted-cross-domain-policies"),
-        HeaderValue::from_static(match policy {
-            "none" | "master-only" | "by-content-type" | "all" => policy,
-            _ => panic!("Invalid value for x-permitted-cross-domain-policies header"),
-        }),
-    )
+        HeaderValue::from_static(policy.as_str()),
+    ))
 }
 
 /// See <https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/X-XSS-Protection>
@@ -175,30 +164,535 @@ pub fn xss_filter(on: bool) -> (HeaderName, HeaderValue) {
     )
 }
 
+/// Generates an enum of the allowed values for a header with conversions from and to strings.
+macro_rules! header_enum {
+    ($(#[$meta:meta])* $name:ident, $header:literal, { $($variant:ident => $value:literal),* $(,)? }) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum $name {
+            $($variant),*
+        }
+
+        impl $name {
+            pub fn as_str(&self) -> &'static str {
+                match self {
+                    $(Self::$variant => $value),*
+                }
+            }
+        }
+
+        impl std::str::FromStr for $name {
+            type Err = SecurityHeaderError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                match s {
+                    $($value => Ok(Self::$variant),)*
+                    _ => Err(SecurityHeaderError::InvalidValue {
+                        header: $header,
+                        value: s.to_string(),
+                    }),
+                }
+            }
+        }
+
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "{}", self.as_str())
+            }
+        }
+    };
+}
+
+header_enum!(
+    /// See <https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Cross-Origin-Embedder-Policy>
+    CrossOriginEmbedderPolicy, "cross-origin-embedder-policy", {
+        RequireCorp => "require-corp",
+        Credentialless => "credentialless",
+        UnsafeNone => "unsafe-none",
+    }
+);
+
+header_enum!(
+    /// See <https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Cross-Origin-Opener-Policy>
+    CrossOriginOpenerPolicy, "cross-origin-opener-policy", {
+        SameOrigin => "same-origin",
+        SameOriginAllowPopups => "same-origin-allow-popups",
+        UnsafeNone => "unsafe-none",
+    }
+);
+
+header_enum!(
+    /// See <https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Cross-Origin-Resource-Policy>
+    CrossOriginResourcePolicy, "cross-origin-resource-policy", {
+        SameSite => "same-site",
+        SameOrigin => "same-origin",
+        CrossOrigin => "cross-origin",
+    }
+);
+
+header_enum!(
+    /// See <https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Referrer-Policy>
+    ReferrerPolicy, "referrer-policy", {
+        NoReferrer => "no-referrer",
+        NoReferrerWhenDowngrade => "no-referrer-when-downgrade",
+        Origin => "origin",
+        OriginWhenCrossOrigin => "origin-when-cross-origin",
+        SameOrigin => "same-origin",
+        StrictOrigin => "strict-origin",
+        StrictOriginWhenCrossOrigin => "strict-origin-when-cross-origin",
+        UnsafeUrl => "unsafe-url",
+    }
+);
+
+header_enum!(
+    /// See <https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/X-Frame-Options>
+    FrameOptions, "x-frame-options", {
+        Deny => "deny",
+        SameOrigin => "sameorigin",
+    }
+);
+
+header_enum!(
+    /// See <https://owasp.org/www-project-secure-headers/#x-permitted-cross-domain-policies>
+    CrossDomainPolicies, "x-permitted-cross-domain-policies", {
+        None => "none",
+        MasterOnly => "master-only",
+        ByContentType => "by-content-type",
+        All => "all",
+    }
+);
+
+/// See <https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Strict-Transport-Security>
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StrictTransportSecurity {
+    /// In seconds
+    pub max_age: u64,
+    pub include_subdomains: bool,
+    pub preload: bool,
+}
+
+impl StrictTransportSecurity {
+    pub fn new(max_age: u64) -> Self {
+        Self {
+            max_age,
+            include_subdomains: false,
+            preload: false,
+        }
+    }
+
+    pub fn include_subdomains(mut self) -> Self {
+        self.include_subdomains = true;
+        self
+    }
+
+    /// Preloading requires `includeSubDomains` and a `max-age` of at least 1 year.
+    /// See <https://hstspreload.org/#submission-requirements>
+    pub fn preload(mut self) -> Self {
+        self.preload = true;
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), SecurityHeaderError> {
+        if self.preload && (!self.include_subdomains || self.max_age < 31_536_000) {
+            return Err(SecurityHeaderError::InvalidValue {
+                header: "strict-transport-security",
+                value: "preload requires includeSubDomains and a max-age of at least 31536000"
+                    .to_string(),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for StrictTransportSecurity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "max-age={}", self.max_age)?;
+        if self.include_subdomains {
+            write!(f, "; includeSubDomains")?;
+        }
+        if self.preload {
+            write!(f, "; preload")?;
+        }
+        Ok(())
+    }
+}
+
+/// An allowlist entry of the `Permissions-Policy` header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PermissionsOrigin {
+    /// `*`
+    Any,
+    /// `self`
+    SelfOrigin,
+    /// A quoted origin, e.g. `"https://example.com"`
+    Origin(String),
+}
+
+impl std::fmt::Display for PermissionsOrigin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PermissionsOrigin::Any => write!(f, "*"),
+            PermissionsOrigin::SelfOrigin => write!(f, "self"),
+            PermissionsOrigin::Origin(o) => write!(f, "\"{o}\""),
+        }
+    }
+}
+
+/// See <https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Permissions-Policy>
+///
+/// Features with an empty allowlist are disabled entirely.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PermissionsPolicy {
+    features: Vec<(String, Vec<PermissionsOrigin>)>,
+}
+
+impl PermissionsPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Disables the features for all origins, e.g. `camera=()`.
+    pub fn deny<'a>(mut self, features: impl IntoIterator<Item = &'a str>) -> Self {
+        for feature in features {
+            self = self.allow(feature, []);
+        }
+        self
+    }
+
+    /// Allows the feature only for the given origins. Replaces the previous allowlist for the feature.
+    pub fn allow(
+        mut self,
+        feature: &str,
+        origins: impl IntoIterator<Item = PermissionsOrigin>,
+    ) -> Self {
+        self.features.retain(|(f, _)| f != feature);
+        self.features
+            .push((feature.to_string(), origins.into_iter().collect()));
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), SecurityHeaderError> {
+        for (feature, _) in self.features.iter() {
+            if feature.is_empty()
+                || !feature
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+            {
+                return Err(SecurityHeaderError::InvalidValue {
+                    header: "permissions-policy",
+                    value: feature.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for PermissionsPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let features = self
+            .features
+            .iter()
+            .map(|(feature, origins)| match origins.as_slice() {
+                [PermissionsOrigin::Any] => format!("{feature}=*"),
+                origins => format!(
+                    "{feature}=({})",
+                    origins
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                ),
+            })
+            .collect::<Vec<_>>();
+        write!(f, "{}", features.join(", "))
+    }
+}
+
+/// Typed configuration of all the security headers in this module. Headers set to `None` (or `false`) are not sent.
+///
+/// [Default] is a preset equal to the defaults of [helmet](https://helmetjs.github.io/), [strict][SecurityHeaders::strict]
+/// additionally isolates the document and disables common browser features. Use [headers][SecurityHeaders::headers] to
+/// obtain the validated headers or [SecurityHeadersLayer] to apply them to every response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecurityHeaders {
+    pub content_security_policy: Option<ContentSecurityPolicy>,
+    pub cross_origin_embedder_policy: Option<CrossOriginEmbedderPolicy>,
+    pub cross_origin_opener_policy: Option<CrossOriginOpenerPolicy>,
+    pub cross_origin_resource_policy: Option<CrossOriginResourcePolicy>,
+    /// When multiple policies are given, every preceding one is a fallback for the next.
+    pub referrer_policy: Vec<ReferrerPolicy>,
+    pub strict_transport_security: Option<StrictTransportSecurity>,
+    pub no_sniff: bool,
+    pub dns_prefetch_control: Option<bool>,
+    pub ie_no_open: bool,
+    pub frame_options: Option<FrameOptions>,
+    pub cross_domain_policies: Option<CrossDomainPolicies>,
+    pub xss_filter: Option<bool>,
+    pub permissions_policy: Option<PermissionsPolicy>,
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        Self {
+            content_security_policy: Some(ContentSecurityPolicy::default_policy()),
+            cross_origin_embedder_policy: None,
+            cross_origin_opener_policy: Some(CrossOriginOpenerPolicy::SameOrigin),
+            cross_origin_resource_policy: Some(CrossOriginResourcePolicy::SameOrigin),
+            referrer_policy: vec![ReferrerPolicy::NoReferrer],
+            strict_transport_security: Some(
+                StrictTransportSecurity::new(15_552_000).include_subdomains(),
+            ),
+            no_sniff: true,
+            dns_prefetch_control: Some(false),
+            ie_no_open: true,
+            frame_options: Some(FrameOptions::SameOrigin),
+            cross_domain_policies: Some(CrossDomainPolicies::None),
+            // Auditors of old browsers create vulnerabilities so helmet disables them
+            xss_filter: Some(false),
+            permissions_policy: None,
+        }
+    }
+}
+
+impl SecurityHeaders {
+    /// A configuration which sends no headers.
+    pub fn none() -> Self {
+        Self {
+            content_security_policy: None,
+            cross_origin_embedder_policy: None,
+            cross_origin_opener_policy: None,
+            cross_origin_resource_policy: None,
+            referrer_policy: vec![],
+            strict_transport_security: None,
+            no_sniff: false,
+            dns_prefetch_control: None,
+            ie_no_open: false,
+            frame_options: None,
+            cross_domain_policies: None,
+            xss_filter: None,
+            permissions_policy: None,
+        }
+    }
+
+    /// The default preset with cross origin isolation, denied framing and common browser features disabled.
+    pub fn strict() -> Self {
+        Self {
+            cross_origin_embedder_policy: Some(CrossOriginEmbedderPolicy::RequireCorp),
+            frame_options: Some(FrameOptions::Deny),
+            strict_transport_security: Some(
+                StrictTransportSecurity::new(31_536_000).include_subdomains(),
+            ),
+            permissions_policy: Some(PermissionsPolicy::new().deny([
+                "accelerometer",
+                "camera",
+                "geolocation",
+                "gyroscope",
+                "magnetometer",
+                "microphone",
+                "payment",
+                "usb",
+            ])),
+            ..Default::default()
+        }
+    }
+
+    /// Validates the configuration and returns the headers.
+    pub fn headers(&self) -> Result<HeaderMap, SecurityHeaderError> {
+        let mut headers = HeaderMap::new();
+
+        if let Some(ref csp) = self.content_security_policy {
+            let (name, value) = csp.header()?;
+            headers.insert(name, value);
+        }
+
+        let mut insert = |name: HeaderName, value: String| -> Result<(), SecurityHeaderError> {
+            headers.insert(name, HeaderValue::try_from(value)?);
+            Ok(())
+        };
+
+        if let Some(coep) = self.cross_origin_embedder_policy {
+            insert(
+                HeaderName::from_static("cross-origin-embedder-policy"),
+                coep.to_string(),
+            )?;
+        }
+
+        if let Some(coop) = self.cross_origin_opener_policy {
+            insert(
+                HeaderName::from_static("cross-origin-opener-policy"),
+                coop.to_string(),
+            )?;
+        }
+
+        if let Some(corp) = self.cross_origin_resource_policy {
+            insert(
+                HeaderName::from_static("cross-origin-resource-policy"),
+                corp.to_string(),
+            )?;
+        }
+
+        if !self.referrer_policy.is_empty() {
+            let value = self
+                .referrer_policy
+                .iter()
+                .map(ReferrerPolicy::as_str)
+                .collect::<Vec<_>>()
+                .join(", ");
+            insert(REFERRER_POLICY, value)?;
+        }
+
+        if let Some(hsts) = self.strict_transport_security {
+            hsts.validate()?;
+            insert(STRICT_TRANSPORT_SECURITY, hsts.to_string())?;
+        }
+
+        if self.no_sniff {
+            insert(X_CONTENT_TYPE_OPTIONS, "nosniff".to_string())?;
+        }
+
+        if let Some(on) = self.dns_prefetch_control {
+            insert(
+                X_DNS_PREFETCH_CONTROL,
+                if on { "on" } else { "off" }.to_string(),
+            )?;
+        }
+
+        if self.ie_no_open {
+            insert(
+                HeaderName::from_static("x-download-options"),
+                "noopen".to_string(),
+            )?;
+        }
+
+        if let Some(frame_options) = self.frame_options {
+            insert(X_FRAME_OPTIONS, frame_options.to_string())?;
+        }
+
+        if let Some(policies) = self.cross_domain_policies {
+            insert(
+                HeaderName::from_static("x-permitted-cross-domain-policies"),
+                policies.to_string(),
+            )?;
+        }
+
+        if let Some(on) = self.xss_filter {
+            insert(
+                X_XSS_PROTECTION,
+                if on { "1; mode=block" } else { "0" }.to_string(),
+            )?;
+        }
+
+        if let Some(ref permissions) = self.permissions_policy {
+            permissions.validate()?;
+            insert(
+                HeaderName::from_static("permissions-policy"),
+                permissions.to_string(),
+            )?;
+        }
+
+        Ok(headers)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SecurityHeaderError {
+    #[error("Invalid value for {header}: {value}")]
+    InvalidValue { header: &'static str, value: String },
+    #[error("Invalid header: {0}")]
+    Header(#[from] InvalidHeaderValue),
+}
+
+/// Applies the configured [SecurityHeaders] to every response.
+///
+/// Headers already present on the response are left untouched, so routes can override any
+/// of them, either by setting them in the handler or by applying another layer closer to the route.
+#[derive(Debug, Clone)]
+pub struct SecurityHeadersLayer {
+    headers: Arc<HeaderMap>,
+}
+
+impl SecurityHeadersLayer {
+    /// Validates the configuration and computes the headers once.
+    pub fn new(config: &SecurityHeaders) -> Result<Self, SecurityHeaderError> {
+        Ok(Self {
+            headers: Arc::new(config.headers()?),
+        })
+    }
+}
+
+impl<S> Layer<S> for SecurityHeadersLayer {
+    type Service = SecurityHeadersService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        SecurityHeadersService {
+            inner,
+            headers: self.headers.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SecurityHeadersService<S> {
+    inner: S,
+    headers: Arc<HeaderMap>,
+}
+
+impl<S, ReqB, ResB> Service<Request<ReqB>> for SecurityHeadersService<S>
+where
+    S: Service<Request<ReqB>, Response = Response<ResB>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqB>) -> Self::Future {
+        let headers = self.headers.clone();
+        let fut = self.inner.call(req);
+
+        Box::pin(async move {
+            let mut res = fut.await?;
+            let res_headers = res.headers_mut();
+            for (name, value) in headers.iter() {
+                if !res_headers.contains_key(name) {
+                    res_headers.insert(name, value.clone());
+                }
+            }
+            Ok(res)
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     #[test]
     fn headers() {
-        let (name, value) = cross_origin_embedder_policy("require-corp");
+        let (name, value) = cross_origin_embedder_policy("require-corp").unwrap();
         assert_eq!(name.to_string(), "cross-origin-embedder-policy");
         assert_eq!(value.to_str().unwrap(), "require-corp");
 
-        let (name, value) = cross_origin_opener_policy("same-origin");
+        let (name, value) = cross_origin_opener_policy("same-origin").unwrap();
         assert_eq!(name.to_string(), "cross-origin-opener-policy");
         assert_eq!(value.to_str().unwrap(), "same-origin");
 
-        let (name, value) = cross_origin_resource_policy("same-site");
+        let (name, value) = cross_origin_resource_policy("same-site").unwrap();
         assert_eq!(name.to_string(), "cross-origin-resource-policy");
         assert_eq!(value.to_str().unwrap(), "same-site");
 
-        let (name, value) = referrer_policy(&["no-referrer", "origin"]);
+        let (name, value) = referrer_policy(&["no-referrer", "origin"]).unwrap();
         assert_eq!(value.to_str().unwrap(), "no-referrer, origin");
         assert_eq!(name.to_string(), "referrer-policy");
-        let (_, value) = referrer_policy(&["origin"]);
+        let (_, value) = referrer_policy(&["origin"]).unwrap();
         assert_eq!(value.to_str().unwrap(), "origin");
 
-        let (name, value) = strict_transport_security(31_536_000, Some("includeSubDomains"));
+        let (name, value) =
+            strict_transport_security(31_536_000, Some("includeSubDomains")).unwrap();
         assert_eq!(name.to_string(), "strict-transport-security");
         assert_eq!(
             value.to_str().unwrap(),
@@ -221,7 +715,7 @@ mod tests {
         assert_eq!(name.to_string(), "x-frame-options");
         assert_eq!(value.to_str().unwrap(), "sameorigin");
 
-        let (name, value) = cross_domain_policies("none");
+        let (name, value) = cross_domain_policies("none").unwrap();
         assert_eq!(name.to_string(), "x-permitted-cross-domain-policies");
         assert_eq!(value.to_str().unwrap(), "none");
 
@@ -229,4 +723,114 @@ mod tests {
         assert_eq!(name.to_string(), "x-xss-protection");
         assert_eq!(value.to_str().unwrap(), "1; mode=block");
     }
+
+    #[test]
+    fn invalid_values() {
+        assert!(matches!(
+            cross_origin_embedder_policy("require-cors"),
+            Err(SecurityHeaderError::InvalidValue {
+                header: "cross-origin-embedder-policy",
+                ..
+            })
+        ));
+        assert!(cross_origin_opener_policy("same").is_err());
+        assert!(cross_origin_resource_policy("").is_err());
+        assert!(referrer_policy(&["origin", "no-referer"]).is_err());
+        assert!(strict_transport_security(60, Some("includeSubdomains")).is_err());
+        assert!(cross_domain_policies("some").is_err());
+    }
+
+    #[test]
+    fn config() {
+        let headers = SecurityHeaders::default().headers().unwrap();
+        assert_eq!(headers.len(), 11);
+        assert_eq!(
+            headers[STRICT_TRANSPORT_SECURITY],
+            "max-age=15552000; includeSubDomains"
+        );
+        assert_eq!(headers[X_XSS_PROTECTION], "0");
+        assert!(headers.contains_key(CONTENT_SECURITY_POLICY));
+
+        let headers = SecurityHeaders::strict().headers().unwrap();
+        assert_eq!(headers["cross-origin-embedder-policy"], "require-corp");
+        assert_eq!(headers[X_FRAME_OPTIONS], "deny");
+        assert!(headers["permissions-policy"]
+            .to_str()
+            .unwrap()
+            .starts_with("accelerometer=(), camera=()"));
+
+        assert!(SecurityHeaders::none().headers().unwrap().is_empty());
+
+        let policy = PermissionsPolicy::new()
+            .deny(["camera"])
+            .allow("geolocation", [PermissionsOrigin::SelfOrigin])
+            .allow(
+                "payment",
+                [
+                    PermissionsOrigin::SelfOrigin,
+                    PermissionsOrigin::Origin("https://pay.example.com".into()),
+                ],
+            )
+            .allow("fullscreen", [PermissionsOrigin::Any]);
+        assert_eq!(
+            policy.to_string(),
+            r#"camera=(), geolocation=(self), payment=(self "https://pay.example.com"), fullscreen=*"#
+        );
+    }
+
+    #[test]
+    fn validation() {
+        assert!(matches!(
+            "require-corp".parse::<CrossOriginEmbedderPolicy>(),
+            Ok(CrossOriginEmbedderPolicy::RequireCorp)
+        ));
+        assert!(matches!(
+            "invalid".parse::<ReferrerPolicy>(),
+            Err(SecurityHeaderError::InvalidValue {
+                header: "referrer-policy",
+                ..
+            })
+        ));
+
+        let config = SecurityHeaders {
+            strict_transport_security: Some(StrictTransportSecurity::new(300).preload()),
+            ..SecurityHeaders::none()
+        };
+        assert!(config.headers().is_err());
+
+        let config = SecurityHeaders {
+            permissions_policy: Some(PermissionsPolicy::new().deny(["Camera=()"])),
+            ..SecurityHeaders::none()
+        };
+        assert!(config.headers().is_err());
+    }
+
+    #[tokio::test]
+    async fn layer() {
+        use tower::{service_fn, ServiceExt};
+
+        let layer = SecurityHeadersLayer::new(&SecurityHeaders::default()).unwrap();
+        let service = layer.layer(service_fn(|req: Request<()>| async move {
+            let mut res = Response::new(());
+            if req.uri().path() == "/embed" {
+                res.headers_mut()
+                    .insert(X_FRAME_OPTIONS, HeaderValue::from_static("deny"));
+            }
+            Ok::<_, std::convert::Infallible>(res)
+        }));
+
+        let res = service
+            .clone()
+            .oneshot(Request::get("/").body(()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(res.headers()[X_FRAME_OPTIONS], "sameorigin");
+        assert_eq!(res.headers()[X_CONTENT_TYPE_OPTIONS], "nosniff");
+
+        let res = service
+            .oneshot(Request::get("/embed").body(()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(res.headers()[X_FRAME_OPTIONS], "deny");
+    }
 }
@@ -0,0 +1,433 @@
+//! Server-Sent Events, see <https://html.spec.whatwg.org/multipage/server-sent-events.html>.
+//!
+//! An [SseStream] turns a stream of [Event]s into the text of the response body and interleaves keep-alive
+//! comments so proxies do not close idle connections. Events published through an [EventChannel] are kept in a
+//! bounded buffer, so clients reconnecting with `Last-Event-ID` receive what they missed:
+//!
+//! ```ignore
+//! async fn events(headers: HeaderMap, State(channel): State<EventChannel>) -> impl IntoResponse {
+//!     let events = channel.subscribe(last_event_id(&headers));
+//!     let body = SseStream::new(events, Duration::from_secs(15)).map(Ok::<_, Infallible>);
+//!     (sse_headers(), StreamBody::new(body))
+//! }
+//!
+//! channel.publish(Event::json(&notification)?.with_event("notification"));
+//! ```
+
+use crate::queue::Consumer;
+use futures::{Stream, StreamExt};
+use http::{header, HeaderMap, HeaderName, HeaderValue};
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::sync::broadcast;
+use tokio::time::{Interval, MissedTickBehavior};
+use tracing::warn;
+
+pub const TEXT_EVENT_STREAM: &str = "text/event-stream";
+
+pub const LAST_EVENT_ID: &str = "last-event-id";
+
+/// A single event.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Event {
+    pub id: Option<String>,
+    /// The event type. Clients listen with `addEventListener(type)`, or `onmessage` if not set.
+    pub event: Option<String>,
+    pub data: String,
+    /// How long the client waits before reconnecting.
+    pub retry: Option<Duration>,
+}
+
+impl Event {
+    pub fn data(data: impl Into<String>) -> Self {
+        Self {
+            data: data.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn json<T: Serialize>(data: &T) -> Result<Self, serde_json::Error> {
+        Ok(Self::data(serde_json::to_string(data)?))
+    }
+
+    pub fn with_id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    pub fn with_event(mut self, event: impl Into<String>) -> Self {
+        self.event = Some(event.into());
+        self
+    }
+
+    pub fn with_retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Formats the event for the response body. Multiline data is split into multiple `data` fields.
+    /// Line breaks in the ID and type would end the field early and are removed.
+    pub fn encode(&self) -> String {
+        let mut out = String::new();
+
+        if let Some(ref id) = self.id {
+            out.push_str(&format!("id: {}\n", single_line(id)));
+        }
+        if let Some(ref event) = self.event {
+            out.push_str(&format!("event: {}\n", single_line(event)));
+        }
+        if let Some(retry) = self.retry {
+            out.push_str(&format!("retry: {}\n", retry.as_millis()));
+        }
+        // Clients end lines on CRLF, CR and LF alike
+        for line in self.data.replace("\r\n", "\n").split(['\r', '\n']) {
+            out.push_str(&format!("data: {line}\n"));
+        }
+
+        out.push('\n');
+        out
+    }
+}
+
+fn single_line(s: &str) -> String {
+    s.replace(['\r', '\n'], "")
+}
+
+/// Formats a comment, ignored by clients. Used for keep-alives.
+pub fn comment(text: &str) -> String {
+    format!(": {}\n\n", single_line(text))
+}
+
+/// The response headers of an event stream.
+pub fn sse_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(TEXT_EVENT_STREAM),
+    );
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    // Disables response buffering in nginx
+    headers.insert(
+        HeaderName::from_static("x-accel-buffering"),
+        HeaderValue::from_static("no"),
+    );
+    headers
+}
+
+/// The `Last-Event-ID` sent by reconnecting clients.
+pub fn last_event_id(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(LAST_EVENT_ID)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+}
+
+/// Encodes the events of a stream and sends a keep-alive comment whenever no event was sent for the interval.
+/// Ends when the event stream ends.
+pub struct SseStream<S> {
+    events: S,
+    keep_alive: Interval,
+}
+
+impl<S> std::fmt::Debug for SseStream<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SseStream")
+            .field("events", &"{ ... }")
+            .field("keep_alive", &self.keep_alive.period())
+            .finish()
+    }
+}
+
+impl<S> SseStream<S>
+where
+    S: Stream<Item = Event> + Unpin,
+{
+    /// A zero `keep_alive` is raised to 1 ms.
+    pub fn new(events: S, keep_alive: Duration) -> Self {
+        let keep_alive = keep_alive.max(Duration::from_millis(1));
+        let mut keep_alive =
+            tokio::time::interval_at(tokio::time::Instant::now() + keep_alive, keep_alive);
+        keep_alive.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        Self { events, keep_alive }
+    }
+}
+
+impl<S> Stream for SseStream<S>
+where
+    S: Stream<Item = Event> + Unpin,
+{
+    type Item = String;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.events.poll_next_unpin(cx) {
+            Poll::Ready(Some(event)) => {
+                self.keep_alive.reset();
+                Poll::Ready(Some(event.encode()))
+            }
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => match self.keep_alive.poll_tick(cx) {
+                Poll::Ready(_) => Poll::Ready(Some(comment("keep-alive"))),
+                Poll::Pending => Poll::Pending,
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Buffer {
+    next_id: u64,
+    events: VecDeque<(u64, Event)>,
+    capacity: usize,
+}
+
+/// Publishes events to all subscribers and keeps the last `capacity` of them for replaying to
+/// reconnecting clients. Event IDs are assigned sequentially. Cheap to clone.
+#[derive(Debug, Clone)]
+pub struct EventChannel {
+    buffer: Arc<Mutex<Buffer>>,
+    tx: broadcast::Sender<(u64, Event)>,
+}
+
+impl EventChannel {
+    pub fn new(capacity: usize) -> Self {
+        let (tx, _) = broadcast::channel(capacity.max(1));
+        Self {
+            buffer: Arc::new(Mutex::new(Buffer {
+                next_id: 1,
+                events: VecDeque::with_capacity(capacity),
+                capacity,
+            })),
+            tx,
+        }
+    }
+
+    /// Assigns the next ID to the event and sends it to all subscribers. Returns the ID.
+    pub fn publish(&self, event: Event) -> u64 {
+        let mut buffer = self.buffer();
+        let id = buffer.next_id;
+        buffer.next_id += 1;
+
+        let event = event.with_id(id.to_string());
+        if buffer.capacity > 0 {
+            if buffer.events.len() == buffer.capacity {
+                buffer.events.pop_front();
+            }
+            buffer.events.push_back((id, event.clone()));
+        }
+
+        // Sent while holding the lock so subscribers see the events in the same order as the buffer
+        let _ = self.tx.send((id, event));
+        id
+    }
+
+    /// The buffered events after the given ID.
+    pub fn since(&self, last_event_id: u64) -> Vec<Event> {
+        self.buffer()
+            .events
+            .iter()
+            .filter(|(id, _)| *id > last_event_id)
+            .map(|(_, event)| event.clone())
+            .collect()
+    }
+
+    /// Streams the buffered events after `last_event_id`, followed by the live events. Unknown IDs are
+    /// treated as a new subscription. Subscribers lagging behind by more than the capacity skip the
+    /// events they missed.
+    pub fn subscribe(
+        &self,
+        last_event_id: Option<&str>,
+    ) -> impl Stream<Item = Event> + Send + Unpin {
+        let last_event_id = last_event_id.and_then(|id| id.parse::<u64>().ok());
+
+        // Subscribe and snapshot under the lock so no event is missed or duplicated
+        let (rx, replay, last) = {
+            let buffer = self.buffer();
+            let rx = self.tx.subscribe();
+            let replay = match last_event_id {
+                Some(last) => buffer
+                    .events
+                    .iter()
+                    .filter(|(id, _)| *id > last)
+                    .map(|(_, event)| event.clone())
+                    .collect(),
+                None => vec![],
+            };
+            (rx, replay, buffer.next_id - 1)
+        };
+
+        let live = futures::stream::unfold(rx, move |mut rx| async move {
+            loop {
+                match rx.recv().await {
+                    Ok((id, _)) if id <= last => continue,
+                    Ok((_, event)) => return Some((event, rx)),
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("SSE subscriber lagged behind, skipped {n} events");
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        });
+
+        futures::stream::iter(replay).chain(live).boxed()
+    }
+
+    fn buffer(&self) -> std::sync::MutexGuard<'_, Buffer> {
+        self.buffer.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Turns the messages of a queue consumer into events. Errors while polling the queue are logged
+/// and skipped, the stream ends when the consumer does.
+pub fn consumer_events<C, M, F>(consumer: C, mut f: F) -> impl Stream<Item = Event> + Send
+where
+    C: Consumer<M>,
+    M: Send + 'static,
+    F: FnMut(M) -> Option<Event> + Send + 'static,
+{
+    futures::stream::unfold(consumer, |mut consumer| async move {
+        loop {
+            match consumer.poll_queue().await {
+                Ok(Some(message)) => return Some((message, consumer)),
+                Ok(None) => return None,
+                Err(e) => warn!("Error occurred while polling queue: {e}"),
+            }
+        }
+    })
+    .filter_map(move |message| std::future::ready(f(message)))
+}
+
+/// Turns the messages of a queue consumer into JSON events.
+pub fn consumer_json_events<C, M>(consumer: C) -> impl Stream<Item = Event> + Send
+where
+    C: Consumer<M>,
+    M: Serialize + Send + 'static,
+{
+    consumer_events(consumer, |message: M| match Event::json(&message) {
+        Ok(event) => Some(event),
+        Err(e) => {
+            warn!("Could not serialize event: {e}");
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue::QueueError;
+
+    #[test]
+    fn encode() {
+        let event = Event::data("line 1\nline 2")
+            .with_id("7")
+            .with_event("update\nevil")
+            .with_retry(Duration::from_secs(3));
+        assert_eq!(
+            event.encode(),
+            "id: 7\nevent: updateevil\nretry: 3000\ndata: line 1\ndata: line 2\n\n"
+        );
+        assert_eq!(Event::data("").encode(), "data: \n\n");
+        assert_eq!(
+            Event::data("a\r\nb\rc\n\rd").encode(),
+            "data: a\ndata: b\ndata: c\ndata: \ndata: d\n\n"
+        );
+        assert_eq!(
+            Event::json(&serde_json::json!({"a": 1})).unwrap().encode(),
+            "data: {\"a\":1}\n\n"
+        );
+        assert_eq!(comment("keep-alive"), ": keep-alive\n\n");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(LAST_EVENT_ID, HeaderValue::from_static(" 42 "));
+        assert_eq!(last_event_id(&headers), Some("42"));
+        assert_eq!(sse_headers()[header::CONTENT_TYPE], TEXT_EVENT_STREAM);
+    }
+
+    #[tokio::test]
+    async fn replay() {
+        let channel = EventChannel::new(3);
+        for i in 1..=5 {
+            channel.publish(Event::data(i.to_string()));
+        }
+
+        let ids = |events: Vec<Event>| events.into_iter().filter_map(|e| e.id).collect::<Vec<_>>();
+        assert_eq!(ids(channel.since(0)), ["3", "4", "5"]);
+        assert_eq!(ids(channel.since(4)), ["5"]);
+
+        let mut stream = channel.subscribe(Some("3"));
+        channel.publish(Event::data("6"));
+        let received = (&mut stream).take(3).collect::<Vec<_>>().await;
+        assert_eq!(ids(received), ["4", "5", "6"]);
+
+        // New subscribers only get live events
+        let mut fresh = channel.subscribe(None);
+        channel.publish(Event::data("7"));
+        assert_eq!(fresh.next().await.unwrap().data, "7");
+        assert_eq!(stream.next().await.unwrap().data, "7");
+    }
+
+    #[tokio::test]
+    async fn keep_alive() {
+        let (tx, rx) = futures::channel::mpsc::unbounded();
+        let mut sse = SseStream::new(rx, Duration::from_millis(20));
+
+        tx.unbounded_send(Event::data("hello")).unwrap();
+        assert_eq!(sse.next().await.unwrap(), "data: hello\n\n");
+        assert_eq!(sse.next().await.unwrap(), ": keep-alive\n\n");
+
+        drop(tx);
+        assert_eq!(sse.next().await, None);
+    }
+
+    #[tokio::test]
+    async fn zero_keep_alive() {
+        let (tx, rx) = futures::channel::mpsc::unbounded::<Event>();
+        let mut sse = SseStream::new(rx, Duration::ZERO);
+
+        assert_eq!(sse.next().await.unwrap(), ": keep-alive\n\n");
+        drop(tx);
+    }
+
+    struct Numbers(Vec<Result<u32, QueueError>>);
+
+    impl Consumer<u32> for Numbers {
+        async fn poll_queue(&mut self) -> Result<Option<u32>, QueueError> {
+            match self.0.pop() {
+                Some(Ok(n)) => Ok(Some(n)),
+                Some(Err(e)) => Err(e),
+                None => Ok(None),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn consumer() {
+        let consumer = Numbers(vec![
+            Ok(3),
+            Err(QueueError::Serde(
+                serde_json::from_str::<u32>("x").unwrap_err(),
+            )),
+            Ok(2),
+            Ok(1),
+        ]);
+        let events = consumer_json_events(consumer).collect::<Vec<_>>().await;
+        let data = events.into_iter().map(|e| e.data).collect::<Vec<_>>();
+        assert_eq!(data, ["1", "2", "3"]);
+
+        let events = consumer_events(Numbers(vec![Ok(2), Ok(1)]), |n: u32| {
+            n.is_multiple_of(2)
+                .then(|| Event::data(n.to_string()).with_event("even"))
+        })
+        .collect::<Vec<_>>()
+        .await;
+        assert_eq!(events, [Event::data("2").with_event("even")]);
+    }
+}
@@ -0,0 +1,141 @@
+use hextacy::secrets::Secret;
+use hextacy::State;
+
+#[allow(dead_code)]
+#[derive(Debug)]
+struct Redis {
+    host: String,
+    password: Secret<String>,
+}
+
+impl Redis {
+    fn new(host: &str, password: Secret<String>) -> Self {
+        Self {
+            host: host.to_string(),
+            password,
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug)]
+struct Port(u16);
+
+impl Port {
+    fn new(port: u16) -> Self {
+        Self(port)
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, State)]
+struct BrokenState {
+    #[env("HXTC_BROKEN_HOST", "HXTC_BROKEN_PASSWORD" as Secret<String>)]
+    cache: Redis,
+
+    #[env("HXTC_BROKEN_PORT" as u16)]
+    #[secret("HXTC_BROKEN_PORT" as u16)]
+    port: Port,
+}
+
+#[tokio::test]
+async fn load_errors() {
+    std::env::set_var("HXTC_BROKEN_PORT", "eighty");
+
+    let error = BrokenState::load().await.unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "4 errors occurred while loading BrokenState:\n  \
+        - cache: env key `HXTC_BROKEN_HOST` not found\n  \
+        - cache: env key `HXTC_BROKEN_PASSWORD` not found\n  \
+        - port: could not parse env key `HXTC_BROKEN_PORT` as `u16`\n  \
+        - port: could not parse secret key `HXTC_BROKEN_PORT` as `u16`"
+    );
+    assert_eq!(error.into_errors().len(), 4);
+}
+
+#[derive(Debug, PartialEq)]
+struct Database {
+    url: String,
+    kind: String,
+}
+
+impl Database {
+    fn new(url: &str, kind: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            kind: kind.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, State)]
+struct KeyState {
+    // Keys which are not valid identifiers, keywords or the same when lowercased
+    #[env("HXTC_KEYS-URL", "TYPE")]
+    env: Database,
+
+    #[secret("HXTC_KEYS-URL", "type")]
+    secret: Database,
+}
+
+#[tokio::test]
+async fn keys() {
+    std::env::set_var("HXTC_KEYS-URL", "postgres://localhost");
+    std::env::set_var("TYPE", "postgres");
+    std::env::set_var("type", "mysql");
+
+    let state = KeyState::load().await.unwrap();
+    assert_eq!(state.env, Database::new("postgres://localhost", "postgres"));
+    assert_eq!(state.secret, Database::new("postgres://localhost", "mysql"));
+}
+
+// Field values are bound to variables named after the fields, which must not clash with the derive's own
+#[derive(Debug, State)]
+struct ReservedState {
+    #[env("HXTC_RESERVED_ERRORS")]
+    #[load_with(String::from)]
+    errors: String,
+
+    #[raw("none")]
+    #[load_with(String::from)]
+    field_errors: String,
+}
+
+#[test]
+fn reserved_names() {
+    std::env::set_var("HXTC_RESERVED_ERRORS", "some");
+
+    let state = ReservedState::load().unwrap();
+    assert_eq!(state.errors, "some");
+    assert_eq!(state.field_errors, "none");
+}
+
+#[cfg(feature = "reload")]
+use hextacy::reload::Reloadable;
+
+#[cfg(feature = "reload")]
+#[derive(Debug, State)]
+struct ReservedReloadState {
+    #[reload]
+    #[env("HXTC_RESERVED_RELOAD" as u32)]
+    #[load_with(u32::from)]
+    errors: Reloadable<u32>,
+}
+
+#[cfg(feature = "reload")]
+#[tokio::test]
+async fn reserved_names_reload() {
+    std::env::set_var("HXTC_RESERVED_RELOAD", "1");
+    let state = ReservedReloadState::load().unwrap();
+
+    std::env::set_var("HXTC_RESERVED_RELOAD", "2");
+    state.reload_errors().await.unwrap();
+    assert_eq!(*state.errors.get(), 2);
+}
+
+#[test]
+fn compile_errors() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,11 @@
+use hextacy::State;
+
+#[derive(State)]
+struct AppState {
+    #[health(optional)]
+    #[raw("localhost")]
+    #[load_with(String::from)]
+    host: String,
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use hextacy::State;
+
+#[derive(State)]
+struct AppState {
+    #[env = "HOST"]
+    #[load_with(String::from)]
+    host: String,
+}
+
+fn main() {}
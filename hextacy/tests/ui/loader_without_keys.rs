@@ -0,0 +1,10 @@
+use hextacy::State;
+
+#[derive(State)]
+struct AppState {
+    #[secret()]
+    #[load_with(String::from)]
+    password: String,
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+use hextacy::State;
+
+#[derive(State)]
+struct AppState {
+    #[reload]
+    #[env("LIMIT" as u32)]
+    #[load_with(u32::from)]
+    limit: u32,
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use hextacy::State;
+
+#[derive(State)]
+struct AppState {
+    #[reload]
+    limits: Reloadable<u32>,
+}
+
+fn main() {}
@@ -1,7 +1,6 @@
 use std::collections::HashMap;
 
-use proc_macro2::{Span, TokenStream};
-use proc_macro_error::abort;
+use proc_macro2::TokenStream;
 use quote::{format_ident, quote, ToTokens, TokenStreamExt};
 use syn::{
     parse::Parse, punctuated::Punctuated, spanned::Spanned, DeriveInput, Expr, Field, Ident,
@@ -10,42 +9,44 @@ use syn::{
 
 pub fn impl_state(input: DeriveInput) -> Result<proc_macro2::TokenStream, syn::Error> {
     let syn::Data::Struct(strct) = input.data else {
-        abort!(
-            input.span(),
-            "State derive only works for structs with named fields"
-        );
+        return Err(syn::Error::new(
+            input.ident.span(),
+            "State derive only works for structs with named fields",
+        ));
     };
 
     let mut field_loaders = vec![];
+    let mut lifecycle_fields = vec![];
 
     let field_len = strct.fields.len();
 
     for field in strct.fields {
-        let field_info = FieldInfo::new(&field, &input.ident);
+        let field = &field;
+        let field_info = FieldInfo::new(field, &input.ident)?;
         let field_id = field_info.id.clone();
         let mut field_loader = FieldLoader::new(field_info);
+        let mut health = HealthAttr::Critical;
+        let mut reload = None;
 
         // Parse attributes
         let mut priority = 0;
-        for attr in field.attrs {
-            // Parse env
-            if attr.meta.path().is_ident("env") {
-                let mut env_loader = EnvLoader::from(&attr.meta);
-                env_loader.priority = priority;
-                let loader = Box::new(env_loader);
-                field_loader
-                    .loaders
-                    .entry(field_id.clone())
-                    .or_default()
-                    .push(loader);
-                priority += 1;
-            }
+        for attr in &field.attrs {
+            let path = attr.meta.path();
+
+            let loader: Option<Box<dyn Loader>> = if path.is_ident("env") {
+                Some(Box::new(EnvLoader::try_from(&attr.meta)?))
+            } else if path.is_ident("config") {
+                Some(Box::new(ConfigLoader::try_from(&attr.meta)?))
+            } else if path.is_ident("secret") {
+                Some(Box::new(SecretLoader::try_from(&attr.meta)?))
+            } else if path.is_ident("raw") {
+                Some(Box::new(RawLoader::try_from(&attr.meta)?))
+            } else {
+                None
+            };
 
-            // Parse raw
-            if attr.meta.path().is_ident("raw") {
-                let mut raw_loader = RawLoader::from(&attr.meta);
-                raw_loader.priority = priority;
-                let loader = Box::new(raw_loader);
+            if let Some(mut loader) = loader {
+                loader.set_priority(priority);
                 field_loader
                     .loaders
                     .entry(field_id.clone())
@@ -56,16 +57,45 @@ pub fn impl_state(input: DeriveInput) -> Result<proc_macro2::TokenStream, syn::E
 
             // Parse helpers
 
-            if attr.meta.path().is_ident("load_async") {
+            if path.is_ident("load_async") {
+                attr.meta.require_path_only()?;
                 field_loader.is_async = true;
             }
 
-            if attr.meta.path().is_ident("load_with") {
+            if path.is_ident("load_with") {
                 let list = attr.meta.require_list()?;
                 field_loader.load_with = Some(list.parse_args::<syn::Path>()?);
             }
+
+            if path.is_ident("health") {
+                health = HealthAttr::try_from(&attr.meta)?;
+            }
+
+            if path.is_ident("reload") {
+                attr.meta.require_path_only()?;
+                reload = Some(attr.meta.span());
+            }
         }
 
+        if let Some(span) = reload {
+            if field_loader.loaders.is_empty() {
+                return Err(syn::Error::new(
+                    span,
+                    "`reload` fields need at least one loader to reload from",
+                ));
+            }
+            let reloadable =
+                matches!(field_loader.field.wrappers.last(), Some(w) if w == "Reloadable");
+            if !reloadable {
+                return Err(syn::Error::new(
+                    field.ty.span(),
+                    "`reload` fields must be wrapped in `Reloadable`, e.g. `Reloadable<RateLimits>`",
+                ));
+            }
+            field_loader.reload = true;
+        }
+
+        lifecycle_fields.push((field_id, health));
         field_loaders.push(field_loader);
     }
 
@@ -90,10 +120,18 @@ pub fn impl_state(input: DeriveInput) -> Result<proc_macro2::TokenStream, syn::E
 
     let asyncness = field_loaders
         .iter()
-        .any(|l| l.is_async)
+        .any(|l| l.is_async())
         .then_some(quote!(async));
 
-    let loader_calls = quote_loader_calls(&field_loaders);
+    let error_id = format_ident!("{config_struct}ConfigurationError");
+    let error = quote_error(&error_id, config_struct);
+
+    let loader_calls = quote_loader_calls(&field_loaders, &error_id);
+
+    let field_ids = field_loaders
+        .iter()
+        .map(|el| &el.field.id)
+        .collect::<Vec<_>>();
 
     // Self fields will be the same as the variables returned from the loaders
     let self_fields = field_loaders
@@ -113,23 +151,33 @@ pub fn impl_state(input: DeriveInput) -> Result<proc_macro2::TokenStream, syn::E
         })
         .collect::<Vec<_>>();
 
-    let error_id = format_ident!("{config_struct}ConfigurationError");
-    let error = quote_error(&error_id);
-
     let configure_fn = quote!(
         impl #imp #config_struct #ty #wher {
             /// Initialises the struct by calling all functions generated by `Configure`.
+            ///
+            /// Every field is loaded even if one fails, so the error contains everything that is missing
+            /// or could not be parsed.
             pub #asyncness fn load() -> Result<Self, #error_id> {
-                #(#loader_calls ; )*
-                Ok(Self {
-                    #(#self_fields),*
-                })
+                let mut __errors: Vec<#error_id> = vec![];
+                #(#loader_calls)*
+                match (#(#field_ids,)*) {
+                    (#(Some(#field_ids),)*) => Ok(Self {
+                        #(#self_fields),*
+                    }),
+                    _ => Err(#error_id::from_errors(__errors)),
+                }
             }
         }
     );
 
     tokens.extend(error);
 
+    let reload = quote_reload(&field_loaders, &error_id);
+    tokens.extend(quote!(impl #imp #config_struct #ty #wher { #reload }));
+
+    let lifecycle = quote_lifecycle(&lifecycle_fields);
+    tokens.extend(quote!(impl #imp #config_struct #ty #wher { #lifecycle }));
+
     if field_len == field_loaders.len() {
         tokens.extend(configure_fn);
     }
@@ -137,88 +185,268 @@ pub fn impl_state(input: DeriveInput) -> Result<proc_macro2::TokenStream, syn::E
     Ok(tokens)
 }
 
-fn quote_error(error_id: &Ident) -> TokenStream {
+fn quote_error(error_id: &Ident, config_struct: &Ident) -> TokenStream {
+    let name = config_struct.to_string();
     quote!(
         /// Autogenerated with `#[derive(State)]`
         #[derive(Debug)]
         pub enum #error_id {
-            Env(String),
-            Raw
+            /// A required key was not found by the loader.
+            Missing {
+                field: &'static str,
+                loader: &'static str,
+                key: &'static str,
+            },
+            /// A key was found, but could not be parsed to the expected type.
+            Parse {
+                field: &'static str,
+                loader: &'static str,
+                key: &'static str,
+                expected: &'static str,
+            },
+            /// The loader could not access its source, e.g. an unreadable config or secret file.
+            Source {
+                field: &'static str,
+                loader: &'static str,
+                message: String,
+            },
+            /// All the errors that occurred while loading.
+            Multiple(Vec<Self>),
+        }
+
+        impl #error_id {
+            #[allow(dead_code)]
+            fn from_errors(mut errors: Vec<Self>) -> Self {
+                if errors.len() == 1 {
+                    errors.remove(0)
+                } else {
+                    Self::Multiple(errors)
+                }
+            }
+
+            /// Flattens the error into the individual errors.
+            pub fn into_errors(self) -> Vec<Self> {
+                match self {
+                    Self::Multiple(errors) => errors.into_iter().flat_map(Self::into_errors).collect(),
+                    e => vec![e],
+                }
+            }
         }
 
         impl std::fmt::Display for #error_id {
             fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
                 match self {
-                    Self::Env(s) => write!(f, "{s}"),
-                    Self::Raw => unreachable!(),
+                    Self::Missing { field, loader, key } => {
+                        write!(f, "{field}: {loader} key `{key}` not found")
+                    }
+                    Self::Parse { field, loader, key, expected } => {
+                        write!(f, "{field}: could not parse {loader} key `{key}` as `{expected}`")
+                    }
+                    Self::Source { field, loader, message } => {
+                        write!(f, "{field}: {loader}: {message}")
+                    }
+                    Self::Multiple(errors) => {
+                        write!(f, "{} errors occurred while loading {}:", errors.len(), #name)?;
+                        for error in errors {
+                            write!(f, "\n  - {error}")?;
+                        }
+                        Ok(())
+                    }
                 }
             }
         }
+
+        impl std::error::Error for #error_id {}
     )
 }
 
-fn quote_loader_calls(field_loaders: &[FieldLoader]) -> Vec<TokenStream> {
+/// Quotes the `health` and `shutdown` methods. Fields which do not implement the respective trait are skipped
+/// through the probes in `hextacy::health` and `hextacy::shutdown`.
+fn quote_lifecycle(fields: &[(Ident, HealthAttr)]) -> TokenStream {
+    let checks = fields
+        .iter()
+        .filter_map(|(id, health)| {
+            let critical = match health {
+                HealthAttr::Critical => true,
+                HealthAttr::NonCritical => false,
+                HealthAttr::Skip => return None,
+            };
+            let name = id.to_string();
+            Some(quote!(
+                (&::hextacy::health::probe::Probe(&self.#id)).health_check(#name, #critical)
+            ))
+        })
+        .collect::<Vec<_>>();
+    let check_len = checks.len();
+
+    // Resources are released in reverse order since later fields usually depend on earlier ones
+    let shutdowns = fields.iter().rev().map(|(id, _)| {
+        let name = id.to_string();
+        quote!((&::hextacy::shutdown::probe::Probe(&self.#id)).shutdown(#name).await;)
+    });
+
+    quote!(
+        /// Runs the health checks of all fields implementing `HealthCheck`.
+        #[allow(unused_imports)]
+        pub async fn health(&self) -> ::hextacy::health::HealthReport {
+            use ::hextacy::health::probe::{Checked as _, Unchecked as _};
+            let checks: [Option<::hextacy::health::BorrowedCheck<'_>>; #check_len] = [#(#checks),*];
+            let runner = ::hextacy::health::HealthChecks::new();
+            runner.run_with(checks.into_iter().flatten()).await
+        }
+
+        /// Shuts down all fields implementing `Shutdown` in reverse declaration order.
+        #[allow(unused_imports)]
+        pub async fn shutdown(&self) {
+            use ::hextacy::shutdown::probe::{Closed as _, Unclosed as _};
+            #(#shutdowns)*
+        }
+    )
+}
+
+/// Options of the `health` attribute.
+#[derive(Debug)]
+enum HealthAttr {
+    Critical,
+    NonCritical,
+    Skip,
+}
+
+impl TryFrom<&Meta> for HealthAttr {
+    type Error = syn::Error;
+
+    fn try_from(meta: &Meta) -> Result<Self, Self::Error> {
+        let option = meta.require_list()?.parse_args::<Ident>()?;
+        match option.to_string().as_str() {
+            "non_critical" => Ok(Self::NonCritical),
+            "skip" => Ok(Self::Skip),
+            _ => Err(syn::Error::new(
+                option.span(),
+                "Expected `non_critical` or `skip`",
+            )),
+        }
+    }
+}
+
+/// Quotes the loader chain of each field. A field is `Some` if any of its loaders succeeded, otherwise the errors
+/// of all of them are collected in `errors`.
+fn quote_loader_calls(field_loaders: &[FieldLoader], error_id: &Ident) -> Vec<TokenStream> {
     field_loaders
         .iter()
+        .map(|l| quote_loader_call(l, error_id))
+        .collect()
+}
+
+fn quote_loader_call(l: &FieldLoader, error_id: &Ident) -> TokenStream {
+    let var = &l.field.id;
+    let loaders = l.loaders.get(var).unwrap();
+    let len = loaders.len();
+
+    let calls = loaders.iter().enumerate().map(|(i, loader)| {
+        let loader_fn = loader.fn_ident(var);
+        let call = if l.is_async || loader.is_async() {
+            quote!(Self::#loader_fn().await)
+        } else {
+            quote!(Self::#loader_fn())
+        };
+
+        // Errors are only logged when falling back to the next loader
+        let log_err = (i < len - 1).then(|| loader.error_log());
+
+        quote!(
+            match #call {
+                Ok(v) => break 'load Some(v),
+                Err(e) => {
+                    #log_err;
+                    __field_errors.push(e);
+                }
+            }
+        )
+    });
+
+    // The locals are reserved since the field values are bound to variables named after the fields
+    quote!(
+        let #var = 'load: {
+            let mut __field_errors = vec![];
+            #(#calls)*
+            __errors.extend(__field_errors.into_iter().flat_map(#error_id::into_errors));
+            None
+        };
+    )
+}
+
+/// Quotes a `reload_{field}` method for each `#[reload]` field, which runs its loader chain again and swaps the
+/// new value into its `Reloadable`, and a `reload` method calling all of them.
+fn quote_reload(field_loaders: &[FieldLoader], error_id: &Ident) -> TokenStream {
+    let reloads = field_loaders
+        .iter()
+        .filter(|l| l.reload)
         .map(|l| {
             let var = &l.field.id;
-            let loaders = l.loaders.get(var).unwrap();
-
-            let mut last_err = quote!();
-            let len = loaders.len();
-
-            if l.is_async {
-                let mut quoted = quote!();
-                for (i, loader) in loaders.iter().enumerate() {
-                    let loader_fn = loader.fn_ident(var);
-                    let log_err = loader.error_log();
-                    if i == len - 1 {
-                        quoted.extend(quote!(
-                            let #var = Self::#loader_fn().await?;
-                        ));
-                        break;
-                    }
-                    quoted.extend(quote!(
-                        let #var = Self::#loader_fn().await;
-                        if let Err(e) = #var {
-                            #log_err;
-                        }
-                    ));
-                }
+            let reload_fn = format_ident!("reload_{var}");
+            let doc = format!(
+                " Loads `{var}` again and notifies its subscribers. The current value is kept if loading fails."
+            );
+
+            let prepare = l
+                .loaders
+                .get(var)
+                .unwrap()
+                .iter()
+                .filter_map(|loader| loader.prepare_reload(&l.field));
+            let call = quote_loader_call(l, error_id);
 
-                return quoted;
+            // The outermost wrapper is the `Reloadable` itself
+            let (_, wrappers) = l.field.wrappers.split_last().unwrap();
+            let mut value = quote!(#var);
+            for wrapper in wrappers {
+                value = quote!(#wrapper::new(#value));
             }
 
-            let mut quoted = quote!( let #var = );
-            for (i, loader) in loaders.iter().enumerate() {
-                let loader_fn = loader.fn_ident(var);
-
-                if i == 0 {
-                    last_err = loader.error_log();
-                    quoted.extend(quote!( Self::#loader_fn() ));
-                    if len == 1 {
-                        quoted.extend(quote!(?;));
+            let method = quote!(
+                #[doc = #doc]
+                pub async fn #reload_fn(&self) -> Result<(), #error_id> {
+                    let mut __errors: Vec<#error_id> = vec![];
+                    #(#prepare)*
+                    #call
+                    match #var {
+                        Some(#var) => {
+                            self.#var.set(#value);
+                            Ok(())
+                        }
+                        None => Err(#error_id::from_errors(__errors)),
                     }
-                    continue;
                 }
+            );
 
-                quoted.extend(quote!(
-                    .or_else(|e| {
-                        #last_err;
-                        Self::#loader_fn()
-                    })
-                ));
+            (reload_fn, method)
+        })
+        .collect::<Vec<_>>();
+
+    if reloads.is_empty() {
+        return quote!();
+    }
 
-                last_err = loader.error_log();
+    let (reload_fns, methods): (Vec<_>, Vec<_>) = reloads.into_iter().unzip();
 
-                if i == len - 1 {
-                    quoted.extend(quote!(?;));
+    quote!(
+        #(#methods)*
+
+        /// Reloads all the fields annotated with `#[reload]`. Fields which fail to load keep their current value.
+        pub async fn reload(&self) -> Result<(), #error_id> {
+            let mut __errors: Vec<#error_id> = vec![];
+            #(
+                if let Err(e) = self.#reload_fns().await {
+                    __errors.extend(e.into_errors());
                 }
+            )*
+            if __errors.is_empty() {
+                Ok(())
+            } else {
+                Err(#error_id::from_errors(__errors))
             }
-
-            quoted
-        })
-        .collect()
+        }
+    )
 }
 
 trait Loader: std::fmt::Debug {
@@ -226,6 +454,8 @@ trait Loader: std::fmt::Debug {
 
     fn priority(&self) -> usize;
 
+    fn set_priority(&mut self, priority: usize);
+
     fn extend_tokens(
         &self,
         field: &FieldInfo,
@@ -234,9 +464,17 @@ trait Loader: std::fmt::Debug {
         tokens: &mut TokenStream,
     );
 
-    fn error_variant(&self, config_id: &Ident) -> TokenStream;
-
     fn error_log(&self) -> TokenStream;
+
+    /// Quotes what needs to run before the loader is called again in `reload_{field}`.
+    fn prepare_reload(&self, _field: &FieldInfo) -> Option<TokenStream> {
+        None
+    }
+
+    /// Whether the generated function is async regardless of the constructor.
+    fn is_async(&self) -> bool {
+        false
+    }
 }
 
 /// Top level loader that collects config loaders on a per field basis.
@@ -246,6 +484,7 @@ struct FieldLoader {
     loaders: HashMap<Ident, Vec<Box<dyn Loader>>>,
     is_async: bool,
     load_with: Option<syn::Path>,
+    reload: bool,
 }
 
 impl FieldLoader {
@@ -255,8 +494,14 @@ impl FieldLoader {
             loaders: HashMap::new(),
             is_async: false,
             load_with: None,
+            reload: false,
         }
     }
+
+    /// The loader chain is async if the constructor or any of the loaders are.
+    fn is_async(&self) -> bool {
+        self.is_async || self.loaders.values().flatten().any(|l| l.is_async())
+    }
 }
 
 #[derive(Debug)]
@@ -268,35 +513,38 @@ struct FieldInfo {
 }
 
 impl FieldInfo {
-    fn new(field: &Field, config_struct: &Ident) -> Self {
-        let field_id = field
-            .ident
-            .as_ref()
-            .unwrap_or_else(|| abort!(field.span(), "State macro must be used on named structs"));
+    fn new(field: &Field, config_struct: &Ident) -> syn::Result<Self> {
+        let field_id = field.ident.as_ref().ok_or_else(|| {
+            syn::Error::new(field.span(), "State macro must be used on named structs")
+        })?;
 
         let mut wrappers = vec![];
 
         let Type::Path(ref p) = field.ty else {
-            abort!(
+            return Err(syn::Error::new(
                 field.ty.span(),
-                "`env` loader cannot be implemented for type"
-            )
+                "State fields must be paths to types, e.g. `Arc<Adapter>`",
+            ));
         };
 
         let seg = p
             .path
             .segments
             .last()
-            .unwrap_or_else(|| abort!(p.path.segments.span(), "Wrapper not supported"));
+            .ok_or_else(|| syn::Error::new(p.path.segments.span(), "Wrapper not supported"))?;
 
-        let original = find_original(seg, &mut wrappers);
+        let original = find_original(seg, &mut wrappers)?;
 
-        Self {
+        Ok(Self {
             id: field_id.clone(),
             strct: original,
             wrappers,
             config_struct: config_struct.clone(),
-        }
+        })
+    }
+
+    fn error_id(&self) -> Ident {
+        format_ident!("{}ConfigurationError", self.config_struct)
     }
 }
 
@@ -307,6 +555,7 @@ impl ToTokens for FieldLoader {
             loaders,
             is_async,
             load_with,
+            ..
         } = &self;
 
         let loaders = loaders.get(&field.id).unwrap();
@@ -316,6 +565,177 @@ impl ToTokens for FieldLoader {
     }
 }
 
+/// Parses the list of keys of the `env`, `config` and `secret` loaders.
+fn parse_keys(meta: &Meta, loader: &str) -> syn::Result<Vec<EnvVar>> {
+    let list = meta.require_list().map_err(|_| {
+        syn::Error::new(
+            meta.span(),
+            format!("`{loader}` loader must be a list of keys, e.g. `#[{loader}(\"HOST\", \"PORT\" as u16)]`"),
+        )
+    })?;
+
+    let keys = list.parse_args_with(Punctuated::<EnvVar, Token![,]>::parse_terminated)?;
+
+    if keys.is_empty() {
+        return Err(syn::Error::new(
+            list.span(),
+            format!("`{loader}` loader must specify at least one key"),
+        ));
+    }
+
+    Ok(keys.into_iter().collect())
+}
+
+/// Quotes the errors a loader function can push for a field.
+struct ErrorContext<'a> {
+    error_id: Ident,
+    field: String,
+    loader: &'a str,
+}
+
+impl<'a> ErrorContext<'a> {
+    fn new(field: &FieldInfo, loader: &'a str) -> Self {
+        Self {
+            error_id: field.error_id(),
+            field: field.id.to_string(),
+            loader,
+        }
+    }
+
+    fn missing(&self, key: &str) -> TokenStream {
+        let Self {
+            error_id,
+            field,
+            loader,
+        } = self;
+        quote!(#error_id::Missing { field: #field, loader: #loader, key: #key })
+    }
+
+    fn parse(&self, key: &str, expected: &str) -> TokenStream {
+        let Self {
+            error_id,
+            field,
+            loader,
+        } = self;
+        quote!(#error_id::Parse { field: #field, loader: #loader, key: #key, expected: #expected })
+    }
+
+    fn source(&self, message: TokenStream) -> TokenStream {
+        let Self {
+            error_id,
+            field,
+            loader,
+        } = self;
+        quote!(#error_id::Source { field: #field, loader: #loader, message: #message })
+    }
+}
+
+/// The name of the type for error messages.
+fn type_name(ty: &Type) -> String {
+    ty.to_token_stream().to_string().replace(' ', "")
+}
+
+/// The variables holding the values of the keys. Keys are not necessarily valid identifiers, e.g. `DB-URL`, dotted
+/// config paths or keywords such as `TYPE`, so they are sanitized and prefixed with their position.
+fn var_idents(keys: &[EnvVar]) -> Vec<Ident> {
+    keys.iter()
+        .enumerate()
+        .map(|(i, key)| {
+            let name = key
+                .lit
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() {
+                        c.to_ascii_lowercase()
+                    } else {
+                        '_'
+                    }
+                })
+                .collect::<String>();
+            format_ident!("__{i}_{name}")
+        })
+        .collect()
+}
+
+/// Quotes the conversion of a key shared by the `env`, `config` and `secret` loaders. `lookup` must evaluate to an
+/// `Option<Option<&str>>`, where the outer `None` means the lookup failed and its error was already pushed.
+///
+/// Every problem is pushed to `errors` instead of returning early so all of them are reported at once.
+fn quote_key(key: &EnvVar, id: &Ident, lookup: TokenStream, ctx: &ErrorContext) -> TokenStream {
+    let missing = ctx.missing(&key.lit);
+
+    match (key.optional, key.parse_to.as_ref()) {
+        (true, Some(to)) => {
+            let parse = ctx.parse(&key.lit, &type_name(to));
+            quote!(
+                let #id = match #lookup {
+                    Some(Some(v)) => match v.parse::<#to>() {
+                        Ok(v) => Some(v),
+                        Err(_) => {
+                            errors.push(#parse);
+                            None
+                        }
+                    },
+                    _ => None,
+                };
+            )
+        }
+        (true, None) => quote!(let #id = #lookup.flatten();),
+        (false, Some(to)) => {
+            let parse = ctx.parse(&key.lit, &type_name(to));
+            quote!(
+                let #id = match #lookup {
+                    Some(Some(v)) => match v.parse::<#to>() {
+                        Ok(v) => Some(v),
+                        Err(_) => {
+                            errors.push(#parse);
+                            None
+                        }
+                    },
+                    Some(None) => {
+                        errors.push(#missing);
+                        None
+                    }
+                    None => None,
+                };
+            )
+        }
+        (false, None) => quote!(
+            let #id = match #lookup {
+                Some(Some(v)) => Some(v),
+                Some(None) => {
+                    errors.push(#missing);
+                    None
+                }
+                None => None,
+            };
+        ),
+    }
+}
+
+/// Quotes the constructor call of a loader function, which is only made if all required keys were found and
+/// no errors occurred.
+fn quote_construct(
+    keys: &[EnvVar],
+    vars: &[Ident],
+    constructor: TokenStream,
+    error_id: &Ident,
+) -> TokenStream {
+    let required = keys
+        .iter()
+        .zip(vars)
+        .filter(|(key, _)| !key.optional)
+        .map(|(_, var)| var)
+        .collect::<Vec<_>>();
+
+    quote!(
+        match (#(#required,)*) {
+            (#(Some(#required),)*) if errors.is_empty() => Ok(#constructor),
+            _ => Err(#error_id::from_errors(errors)),
+        }
+    )
+}
+
 /// Loading strategy for the `env` attribute.
 #[derive(Debug)]
 struct EnvLoader {
@@ -323,28 +743,14 @@ struct EnvLoader {
     keys: Vec<EnvVar>,
 }
 
-impl From<&Meta> for EnvLoader {
-    fn from(meta: &Meta) -> Self {
-        let mut loader = Self {
-            keys: vec![],
-            priority: 0,
-        };
+impl TryFrom<&Meta> for EnvLoader {
+    type Error = syn::Error;
 
-        let list = meta.require_list().unwrap_or_else(|_| {
-            abort!(
-                meta.span(),
-                "`env` loader must be a list of variables to load from the env"
-            )
-        });
-
-        loader.keys.extend(
-            list.parse_args_with(Punctuated::<EnvVar, Token![,]>::parse_terminated)
-                .unwrap()
-                .into_pairs()
-                .map(|pair| pair.into_value()),
-        );
-
-        loader
+    fn try_from(meta: &Meta) -> Result<Self, Self::Error> {
+        Ok(Self {
+            keys: parse_keys(meta, "env")?,
+            priority: 0,
+        })
     }
 }
 
@@ -357,6 +763,10 @@ impl Loader for EnvLoader {
         self.priority
     }
 
+    fn set_priority(&mut self, priority: usize) {
+        self.priority = priority;
+    }
+
     fn extend_tokens(
         &self,
         field: &FieldInfo,
@@ -364,16 +774,11 @@ impl Loader for EnvLoader {
         load_with: Option<&syn::Path>,
         tokens: &mut TokenStream,
     ) {
-        let FieldInfo {
-            id,
-            strct,
-            config_struct,
-            ..
-        } = field;
+        let FieldInfo { id, strct, .. } = field;
         let id = self.fn_ident(id);
         let env_keys = &self.keys;
-
-        let to_var_ident = |var: &EnvVar| Ident::new(&var.lit.to_lowercase(), Span::call_site());
+        let ctx = ErrorContext::new(field, "env");
+        let error_id = field.error_id();
 
         // Account for asyncness
         let (async_fn, async_constr) = if is_async {
@@ -386,67 +791,38 @@ impl Loader for EnvLoader {
         let constructor_fn = load_with.map(|p| quote!(#p)).unwrap_or(quote!(#strct::new));
 
         // For the call to strct::new
-        let constructor_vars = env_keys.iter().map(to_var_ident).collect::<Vec<_>>();
+        let constructor_vars = var_idents(env_keys);
 
         // The variable names and their conversion
         let variables = env_keys
             .iter()
-            .map(|env_key| {
-                let id = to_var_ident(env_key);
+            .zip(constructor_vars.iter())
+            .map(|(env_key, id)| {
                 let env_var = &env_key.lit;
-
-                let err_variant = self.error_variant(config_struct);
-
-                let required_msg = format!("Required variable {env_var} not found in env");
-                let required_err = quote!(#err_variant(#required_msg.to_string()));
-
-                let parse_msg = format!("Could not parse {env_var} to specified type");
-                let parse_err = quote!(#err_variant(#parse_msg.to_string()));
-
-                let convert = match (env_key.optional, env_key.parse_to.as_ref()) {
-                    (true, Some(to)) => quote!(
-                        // This ensures an error is returned on unparseable values
-                        ; if let Some(val) = #id {
-                             if val.parse::<#to>().is_err() {
-                                 return Err(#parse_err)
-                                }
-                            }
-                        let #id = #id.map(|x|x.parse::<#to>().unwrap());
-                    ),
-                    (true, None) => quote!(.map(|x| x.as_str());),
-                    (false, Some(to)) => {
-                        quote!(.ok_or(#required_err)?.parse::<#to>().map_err(|_|#parse_err)?;)
-                    }
-                    (false, None) => quote!(.map(|x| x.as_str()).ok_or(#required_err)?;),
-                };
-
-                quote!(let #id = params.get(#env_var) #convert)
+                let lookup = quote!(Some(params.get(#env_var).map(|v| v.as_str())));
+                quote_key(env_key, id, lookup, &ctx)
             })
             .collect::<Vec<_>>();
 
-        // For collecting the vars with get_multiple
-        let env_keys = env_keys.iter().map(|k| k.lit.clone()).collect::<Vec<_>>();
-
         let constructor = quote!( #constructor_fn ( #( #constructor_vars ),* ) #async_constr);
+        let construct = quote_construct(env_keys, &constructor_vars, constructor, &error_id);
 
-        let config_err = format_ident!("{config_struct}ConfigurationError");
+        // For collecting the vars with get_multiple
+        let env_keys = env_keys.iter().map(|k| k.lit.clone()).collect::<Vec<_>>();
 
         let quoted = quote!(
-            #async_fn fn #id () -> Result<#strct, #config_err> {
+            #async_fn fn #id () -> Result<#strct, #error_id> {
                 let params = ::hextacy::env::get_multiple(&[#( #env_keys ),*]);
+                #[allow(unused_mut)]
+                let mut errors: Vec<#error_id> = vec![];
                 #(#variables)*
-                Ok(#constructor)
+                #construct
             }
         );
 
         tokens.extend(quoted)
     }
 
-    fn error_variant(&self, config_id: &Ident) -> TokenStream {
-        let err = format_ident!("{config_id}ConfigurationError");
-        quote!(#err::Env)
-    }
-
     fn error_log(&self) -> TokenStream {
         quote!(tracing::error!(
             "Error occurred while loading from env: {e}"
@@ -531,6 +907,237 @@ impl Parse for EnvVar {
     }
 }
 
+/// Loading strategy for the `config` attribute. Reads values from `hextacy::config::global_config`.
+#[derive(Debug)]
+struct ConfigLoader {
+    priority: usize,
+    keys: Vec<EnvVar>,
+}
+
+impl TryFrom<&Meta> for ConfigLoader {
+    type Error = syn::Error;
+
+    fn try_from(meta: &Meta) -> Result<Self, Self::Error> {
+        Ok(Self {
+            keys: parse_keys(meta, "config")?,
+            priority: 0,
+        })
+    }
+}
+
+impl Loader for ConfigLoader {
+    fn fn_ident(&self, field_id: &Ident) -> Ident {
+        format_ident!("load_{field_id}_config")
+    }
+
+    fn priority(&self) -> usize {
+        self.priority
+    }
+
+    fn set_priority(&mut self, priority: usize) {
+        self.priority = priority;
+    }
+
+    fn extend_tokens(
+        &self,
+        field: &FieldInfo,
+        is_async: bool,
+        load_with: Option<&syn::Path>,
+        tokens: &mut TokenStream,
+    ) {
+        let FieldInfo { id, strct, .. } = field;
+        let id = self.fn_ident(id);
+        let ctx = ErrorContext::new(field, "config");
+        let error_id = field.error_id();
+
+        let (async_fn, async_constr) = if is_async {
+            (quote!(async), quote!(.await))
+        } else {
+            (quote!(), quote!())
+        };
+
+        let constructor_fn = load_with.map(|p| quote!(#p)).unwrap_or(quote!(#strct::new));
+
+        let constructor_vars = var_idents(&self.keys);
+
+        let variables = self
+            .keys
+            .iter()
+            .zip(constructor_vars.iter())
+            .map(|(key, id)| {
+                let lit = &key.lit;
+                let missing = ctx.missing(lit);
+
+                let Some(to) = key.parse_to.as_ref() else {
+                    // Unparsed values are passed as `&str`s, the same as with `env`, so scalars are stringified
+                    let parse = ctx.parse(lit, "String");
+                    let value = format_ident!("{id}_value");
+                    let lookup = quote!(#value.as_ref().map(|v| v.as_deref()));
+                    let convert = quote_key(key, id, lookup, &ctx);
+                    return quote!(
+                        let #value = config.get_string(#lit).map_err(|_| errors.push(#parse)).ok();
+                        #convert
+                    );
+                };
+
+                // Config values are typed, so they are deserialized instead of parsed
+                let parse = ctx.parse(lit, &type_name(to));
+                let missing = if key.optional {
+                    quote!(None)
+                } else {
+                    quote!({
+                        errors.push(#missing);
+                        None
+                    })
+                };
+                quote!(
+                    let #id = match config.get_opt::<#to>(#lit) {
+                        Ok(Some(v)) => Some(v),
+                        Ok(None) => #missing,
+                        Err(_) => {
+                            errors.push(#parse);
+                            None
+                        }
+                    };
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let constructor = quote!( #constructor_fn ( #( #constructor_vars ),* ) #async_constr);
+        let construct = quote_construct(&self.keys, &constructor_vars, constructor, &error_id);
+
+        let source = ctx.source(quote!(e.to_string()));
+
+        let quoted = quote!(
+            #async_fn fn #id () -> Result<#strct, #error_id> {
+                let config = match ::hextacy::config::global_config() {
+                    Ok(config) => config,
+                    Err(e) => return Err(#source),
+                };
+                #[allow(unused_mut)]
+                let mut errors: Vec<#error_id> = vec![];
+                #(#variables)*
+                #construct
+            }
+        );
+
+        tokens.extend(quoted)
+    }
+
+    fn error_log(&self) -> TokenStream {
+        quote!(tracing::error!(
+            "Error occurred while loading from config: {e}"
+        ))
+    }
+
+    /// The config files are read again so the field sees their changes.
+    fn prepare_reload(&self, field: &FieldInfo) -> Option<TokenStream> {
+        let source = ErrorContext::new(field, "config").source(quote!(e.to_string()));
+        Some(quote!(
+            if let Err(e) = ::hextacy::config::reload_global_config() {
+                return Err(#source);
+            }
+        ))
+    }
+}
+
+/// Loading strategy for the `secret` attribute. Resolves values with `hextacy::secrets::resolve`, which is async,
+/// so the generated function is always async.
+#[derive(Debug)]
+struct SecretLoader {
+    priority: usize,
+    keys: Vec<EnvVar>,
+}
+
+impl TryFrom<&Meta> for SecretLoader {
+    type Error = syn::Error;
+
+    fn try_from(meta: &Meta) -> Result<Self, Self::Error> {
+        Ok(Self {
+            keys: parse_keys(meta, "secret")?,
+            priority: 0,
+        })
+    }
+}
+
+impl Loader for SecretLoader {
+    fn fn_ident(&self, field_id: &Ident) -> Ident {
+        format_ident!("load_{field_id}_secret")
+    }
+
+    fn priority(&self) -> usize {
+        self.priority
+    }
+
+    fn set_priority(&mut self, priority: usize) {
+        self.priority = priority;
+    }
+
+    fn extend_tokens(
+        &self,
+        field: &FieldInfo,
+        is_async: bool,
+        load_with: Option<&syn::Path>,
+        tokens: &mut TokenStream,
+    ) {
+        let FieldInfo { id, strct, .. } = field;
+        let id = self.fn_ident(id);
+        let ctx = ErrorContext::new(field, "secret");
+        let error_id = field.error_id();
+
+        let async_constr = if is_async { quote!(.await) } else { quote!() };
+
+        let constructor_fn = load_with.map(|p| quote!(#p)).unwrap_or(quote!(#strct::new));
+
+        let constructor_vars = var_idents(&self.keys);
+
+        let variables = self
+            .keys
+            .iter()
+            .zip(constructor_vars.iter())
+            .map(|(key, id)| {
+                let lit = &key.lit;
+                let secret = format_ident!("{id}_secret");
+                let source = ctx.source(quote!(format!("{}: {e}", #lit)));
+
+                // The same conversions as `env`, the resolved values are only exposed to the constructor
+                let lookup =
+                    quote!(#secret.as_ref().map(|s| s.as_ref().map(|s| s.expose().as_str())));
+                let convert = quote_key(key, id, lookup, &ctx);
+
+                quote!(
+                    let #secret = ::hextacy::secrets::resolve(#lit)
+                        .await
+                        .map_err(|e| errors.push(#source))
+                        .ok();
+                    #convert
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let constructor = quote!( #constructor_fn ( #( #constructor_vars ),* ) #async_constr);
+        let construct = quote_construct(&self.keys, &constructor_vars, constructor, &error_id);
+
+        let quoted = quote!(
+            async fn #id () -> Result<#strct, #error_id> {
+                let mut errors: Vec<#error_id> = vec![];
+                #(#variables)*
+                #construct
+            }
+        );
+
+        tokens.extend(quoted)
+    }
+
+    fn error_log(&self) -> TokenStream {
+        quote!(tracing::error!("Error occurred while loading secret: {e}"))
+    }
+
+    fn is_async(&self) -> bool {
+        true
+    }
+}
+
 /// Loading strategy for the `raw` attribute. Parses all valid `Expr`s.
 #[derive(Debug)]
 struct RawLoader {
@@ -538,28 +1145,23 @@ struct RawLoader {
     values: Vec<Expr>,
 }
 
-impl From<&Meta> for RawLoader {
-    fn from(meta: &Meta) -> Self {
-        let mut loader = Self {
-            values: vec![],
-            priority: 0,
-        };
+impl TryFrom<&Meta> for RawLoader {
+    type Error = syn::Error;
 
-        let list = meta.require_list().unwrap_or_else(|_| {
-            abort!(
+    fn try_from(meta: &Meta) -> Result<Self, Self::Error> {
+        let list = meta.require_list().map_err(|_| {
+            syn::Error::new(
                 meta.span(),
-                "`env` loader must be a list of variables to load from the env"
+                "`raw` loader must be a list of arguments for the constructor",
             )
-        });
+        })?;
 
-        loader.values.extend(
-            list.parse_args_with(Punctuated::<Expr, Token![,]>::parse_terminated)
-                .unwrap()
-                .into_pairs()
-                .map(|pair| pair.into_value()),
-        );
+        let values = list.parse_args_with(Punctuated::<Expr, Token![,]>::parse_terminated)?;
 
-        loader
+        Ok(Self {
+            values: values.into_iter().collect(),
+            priority: 0,
+        })
     }
 }
 
@@ -572,6 +1174,10 @@ impl Loader for RawLoader {
         self.priority
     }
 
+    fn set_priority(&mut self, priority: usize) {
+        self.priority = priority;
+    }
+
     fn extend_tokens(
         &self,
         field: &FieldInfo,
@@ -579,12 +1185,7 @@ impl Loader for RawLoader {
         load_with: Option<&syn::Path>,
         tokens: &mut TokenStream,
     ) {
-        let FieldInfo {
-            id,
-            strct,
-            config_struct,
-            ..
-        } = field;
+        let FieldInfo { id, strct, .. } = field;
         let id = self.fn_ident(id);
         let args = &self.values;
 
@@ -599,11 +1200,12 @@ impl Loader for RawLoader {
         let return_ty = quote!(#strct);
         let constructor = quote!(#constructor_fn ( #( #args ),* ) #async_constr);
 
-        let config_err = format_ident!("{config_struct}ConfigurationError");
+        let error_id = field.error_id();
 
+        // Raw loaders can never error since an invalid configuration will be stopped at compile time
         let quoted = quote!(
             /// This function will never error
-            #async_fn fn #id () -> Result<#return_ty, #config_err> {
+            #async_fn fn #id () -> Result<#return_ty, #error_id> {
                 Ok(#constructor)
             }
         );
@@ -611,12 +1213,6 @@ impl Loader for RawLoader {
         tokens.extend(quoted)
     }
 
-    // Raw loaders can never error since an invalid configuration will be stopped at compile time
-    fn error_variant(&self, config_id: &Ident) -> TokenStream {
-        let err = format_ident!("{config_id}ConfigurationError");
-        quote!(#err::Raw)
-    }
-
     fn error_log(&self) -> TokenStream {
         quote!(tracing::error!("Error occurred while loading raw: {e}"))
     }
@@ -624,30 +1220,58 @@ impl Loader for RawLoader {
 
 /// Recursively goes through wrappers until it finds one with no AB args and returns it, this will usually be the
 /// struct in question.
-fn find_original(seg: &PathSegment, wrappers: &mut Vec<Ident>) -> PathSegment {
+fn find_original(seg: &PathSegment, wrappers: &mut Vec<Ident>) -> syn::Result<PathSegment> {
     match seg.arguments {
-        PathArguments::None => seg.clone(),
+        PathArguments::None => Ok(seg.clone()),
         PathArguments::AngleBracketed(ref ab) => {
             wrappers.insert(0, seg.ident.clone());
             let arg = ab
                 .args
                 .last()
-                .unwrap_or_else(|| abort!(ab.args.span(), "Wrapper not supported"));
+                .ok_or_else(|| syn::Error::new(ab.args.span(), "Wrapper not supported"))?;
             match arg {
                 syn::GenericArgument::Type(Type::Path(p)) => {
-                    let s = p.path.segments.last().unwrap_or_else(|| {
-                        abort!(
-                            p.path.segments.span(),
-                            "`env` loader not supported for type"
-                        )
-                    });
+                    let s = p.path.segments.last().ok_or_else(|| {
+                        syn::Error::new(p.path.segments.span(), "Type not supported by State")
+                    })?;
                     find_original(s, wrappers)
                 }
-                _ => abort!(arg.span(), "`env` loader not supported for type"),
+                _ => Err(syn::Error::new(
+                    arg.span(),
+                    "Wrappers must have a single type argument, e.g. `Arc<Adapter>`",
+                )),
             }
         }
         PathArguments::Parenthesized(ref p) => {
-            abort!(p.span(), "`env` loader not supported for type")
+            Err(syn::Error::new(p.span(), "Type not supported by State"))
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_variables() {
+        let keys = ["DB-URL", "TYPE", "type", "database.pool_size", "é"]
+            .into_iter()
+            .map(|key| syn::parse_str::<EnvVar>(&format!("{key:?}")).unwrap())
+            .collect::<Vec<_>>();
+
+        let idents = var_idents(&keys)
+            .iter()
+            .map(Ident::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            idents,
+            [
+                "__0_db_url",
+                "__1_type",
+                "__2_type",
+                "__3_database_pool_size",
+                "__4__"
+            ]
+        );
+    }
+}
@@ -2,6 +2,7 @@ use proc_macro_error::{abort, proc_macro_error};
 
 mod component;
 mod configuration;
+mod problem;
 mod response;
 
 /// Intended to be used on configuration/state structs that need to instantiate themselves using variables obtained
@@ -26,6 +27,10 @@ mod response;
 /// If a field constructor is async, the field must be annotated with `#[load_async]` to support it. If any of the
 /// constructors are async, the resulting `configure()` function will be async as well.
 ///
+/// `load()` loads every field even if some of them fail. The returned `<Struct>ConfigurationError` lists every key
+/// that was missing or could not be parsed, along with the field and loader it belongs to, so all of them can be
+/// fixed at once.
+///
 /// ## Field annotations
 ///
 /// The order of field annotations specifies the priority of loading the variables. Each subsequent annotation will be a fallback
@@ -79,6 +84,57 @@ mod response;
 /// }
 /// ```
 ///
+/// ### `config`
+///
+/// - Requires the `config` feature of `hextacy`
+/// - Loads values from the global config, see `hextacy::config::global_config`, which is by default layered from
+///   `config/base.toml`, the file of the profile set in `APP_PROFILE` and overrides such as `APP__DATABASE__URL`
+/// - Keys are dotted paths and support the same conversions as `env`. Unconverted numbers and booleans are passed
+///   as strings, e.g. `"8080"`
+///
+/// #### Example
+///
+/// ```ignore
+/// #[derive(Debug, State)]
+/// struct MyAppState {
+///     #[config(
+///         "postgres.host",
+///         "postgres.port" as u16,
+///         "postgres.pool_size" as Option<u16>
+///     )]
+///     // Falls back to the env if the config does not contain the keys
+///     #[env(
+///         "HOST",
+///         "PORT" as u16,
+///         "POOL_SIZE" as Option<u16>
+///     )]
+///     pub postgres: Arc<DummyAdapter>
+/// }
+/// ```
+///
+/// ### `secret`
+///
+/// - Resolves secrets with `hextacy::secrets::resolve`, i.e. from the file in `<KEY>_FILE`, the global secret
+///   provider, e.g. Vault, or the env, in that order
+/// - Supports the same conversions as `env`, parse secrets to `hextacy::secrets::Secret` to keep them out of
+///   `Debug` output
+/// - Resolving is async, so `load()` becomes async as well
+///
+/// #### Example
+///
+/// ```ignore
+/// #[derive(Debug, State)]
+/// struct MyAppState {
+///     #[secret("RD_HOST", "RD_PASSWORD" as Secret<String>)]
+///     pub cache: RedisDriver,
+///
+///     // Wrappers are applied after loading, so the secret is loaded as a `String`
+///     #[secret("API_KEY")]
+///     #[load_with(String::from)]
+///     pub api_key: Secret<String>,
+/// }
+/// ```
+///
 /// ### `raw`
 ///
 /// - Call constructor with the specified values
@@ -121,12 +177,74 @@ mod response;
 ///     pub postgres: Arc<DummyAdapter>
 /// }
 /// ````
-#[proc_macro_derive(State, attributes(env, raw, load_async, load_with))]
+///
+/// ## Lifecycle
+///
+/// The derive also generates two methods for managing the state's resources:
+///
+/// - `health`, which runs the checks of all fields implementing `hextacy::health::HealthCheck` and returns a
+///   `HealthReport`. Fields are critical by default, annotate them with `#[health(non_critical)]` to only
+///   degrade the report when they fail, or with `#[health(skip)]` to not check them at all.
+/// - `shutdown`, which shuts down all fields implementing `hextacy::shutdown::Shutdown` in reverse declaration
+///   order. Errors are logged and do not stop the remaining fields from shutting down.
+///
+/// Not every adapter implements `Shutdown`. `SimpleTemplateMailer` sends synchronously so there is nothing to
+/// flush, `DieselPool` cannot be closed explicitly and closes its connections when the last clone is dropped,
+/// and `RedisMessageQueue` holds no connections of its own. Stop its consumers through a `ConsumerHandle`.
+///
+/// #### Example
+///
+/// ```ignore
+/// #[derive(Debug, State)]
+/// struct MyAppState {
+///     #[env("DATABASE_URL")]
+///     #[load_async]
+///     pub postgres: DatabaseConnection,
+///
+///     #[health(non_critical)]
+///     #[raw("smtp.example.com", "user", "password", "sender@example.com", "Sender")]
+///     pub mailer: SimpleTemplateMailer,
+/// }
+///
+/// let state = MyAppState::load().await?;
+/// let report = state.health().await;
+/// // ...
+/// state.shutdown().await;
+/// ```
+///
+/// ## Reloading
+///
+/// Fields annotated with `#[reload]` must be wrapped in `hextacy::reload::Reloadable`, which requires the
+/// `reload` feature. For each of them an async `reload_<field_name>()` method is generated, which runs the
+/// field's loaders again and swaps in the new value, notifying its subscribers. `reload()` reloads all of them.
+/// Fields loaded from `config` read the config files again before reloading.
+///
+/// If the loaders fail, the field keeps its current value. Reloads can be triggered on file changes or `SIGHUP`
+/// with `hextacy::reload::Reloader`.
+///
+/// #### Example
+///
+/// ```ignore
+/// #[derive(Debug, State)]
+/// struct MyAppState {
+///     #[reload]
+///     #[config("limits.requests" as u32, "limits.window" as u64)]
+///     pub limits: Reloadable<RateLimits>,
+/// }
+///
+/// let state = MyAppState::load()?;
+/// state.reload().await?;
+/// let limits = state.limits.get();
+/// ```
+#[proc_macro_derive(
+    State,
+    attributes(env, config, secret, raw, load_async, load_with, health, reload)
+)]
 #[proc_macro_error]
 pub fn derive_state(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
-    let input: syn::DeriveInput = syn::parse(input).unwrap();
+    let input = syn::parse_macro_input!(input as syn::DeriveInput);
     configuration::state::impl_state(input)
-        .expect("Error while parsing State")
+        .unwrap_or_else(syn::Error::into_compile_error)
         .into()
 }
 
@@ -148,12 +266,69 @@ pub fn derive_constructor(input: proc_macro::TokenStream) -> proc_macro::TokenSt
         .into()
 }
 
-#[proc_macro_derive(RestResponse, attributes(code))]
+/// Implements `RestResponse` for the type.
+///
+/// ## Attributes
+///
+/// - `#[code(201)]` or `#[code(CREATED)]` - The status of the response, `200` by default. On enums it can be set
+///   on the enum and on every variant, the latter taking precedence.
+/// - `#[header("name", "value")]` - A header added to the response. Can be repeated and set on enums and their variants.
+/// - `#[cookie]` - On struct fields of type `Cookie` or `Option<Cookie>` that are sent as `Set-Cookie`. The fields must
+///   be annotated with `#[serde(skip)]`.
+///
+/// With the `web-axum` or `web-actix` features of hextacy, axum's `IntoResponse` or actix's `Responder` are implemented
+/// as well so the type can be returned from handlers directly.
+///
+/// ```ignore
+/// #[derive(Serialize, RestResponse)]
+/// #[code(CREATED)]
+/// #[header("cache-control", "no-store")]
+/// struct Session {
+///     id: Uuid,
+///     #[cookie]
+///     #[serde(skip)]
+///     cookie: Cookie<'static>,
+/// }
+/// ```
+#[proc_macro_derive(RestResponse, attributes(code, header, cookie))]
 #[proc_macro_error]
 pub fn derive_response(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let input: syn::DeriveInput = syn::parse(input).unwrap();
     response::impl_response(input)
-        .expect("Error while parsing HttpResponse")
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+/// Implements `IntoProblem` for error enums (or structs), mapping each variant to an RFC 7807 `Problem`.
+///
+/// Variants are configured with `#[problem(..)]`:
+///
+/// - `status = 404` - The status code of the problem. Defaults to `500`.
+/// - `title = "..."` - Defaults to the canonical reason of the status.
+/// - `type = "..."` - A URI identifying the problem type.
+/// - `detail` - Use the `Display` implementation of the error as the detail. Off by default so internal messages are
+///   not exposed. `detail = "..."` sets a fixed detail instead.
+/// - `transparent` - Delegate to the single field of the variant, which must implement `IntoProblem`.
+///
+/// ```ignore
+/// #[derive(Debug, Error, IntoProblem)]
+/// pub enum UserError {
+///     #[error("User {0} not found")]
+///     #[problem(status = 404, type = "https://example.com/probs/user-not-found", detail)]
+///     NotFound(Uuid),
+///     #[error("Auth: {0}")]
+///     #[problem(transparent)]
+///     Auth(AuthError),
+///     #[error("Database: {0}")]
+///     Database(#[from] DbErr), // 500, no detail
+/// }
+/// ```
+#[proc_macro_derive(IntoProblem, attributes(problem))]
+#[proc_macro_error]
+pub fn derive_into_problem(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input: syn::DeriveInput = syn::parse(input).unwrap();
+    problem::impl_into_problem(input)
+        .unwrap_or_else(|e| e.to_compile_error())
         .into()
 }
 
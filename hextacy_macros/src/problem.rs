@@ -0,0 +1,190 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{spanned::Spanned, Attribute, Data, DeriveInput, Fields, LitInt, LitStr};
+
+/// The parsed `#[problem(..)]` attribute of a variant or struct.
+#[derive(Default)]
+struct ProblemAttr {
+    status: Option<LitInt>,
+    title: Option<LitStr>,
+    r#type: Option<LitStr>,
+    detail: Option<Detail>,
+    transparent: bool,
+}
+
+enum Detail {
+    /// Use the `Display` implementation
+    Display,
+    Literal(LitStr),
+}
+
+impl ProblemAttr {
+    fn parse(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut parsed = Self::default();
+
+        for attr in attrs.iter().filter(|a| a.path().is_ident("problem")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("status") {
+                    let status: LitInt = meta.value()?.parse()?;
+                    let code = status.base10_parse::<u16>()?;
+                    if !(100..=999).contains(&code) {
+                        return Err(syn::Error::new(status.span(), "invalid status code"));
+                    }
+                    parsed.status = Some(status);
+                } else if meta.path.is_ident("title") {
+                    parsed.title = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("type") {
+                    parsed.r#type = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("detail") {
+                    parsed.detail = Some(if meta.input.peek(syn::Token![=]) {
+                        Detail::Literal(meta.value()?.parse()?)
+                    } else {
+                        Detail::Display
+                    });
+                } else if meta.path.is_ident("transparent") {
+                    parsed.transparent = true;
+                } else {
+                    return Err(meta.error(
+                        "expected one of `status`, `title`, `type`, `detail` or `transparent`",
+                    ));
+                }
+                Ok(())
+            })?;
+        }
+
+        if parsed.transparent
+            && (parsed.status.is_some()
+                || parsed.title.is_some()
+                || parsed.r#type.is_some()
+                || parsed.detail.is_some())
+        {
+            return Err(syn::Error::new(
+                attrs[0].span(),
+                "`transparent` cannot be combined with other options",
+            ));
+        }
+
+        Ok(parsed)
+    }
+
+    fn status(&self) -> TokenStream {
+        match self.status {
+            Some(ref status) => quote!(
+                ::hextacy::web::http::StatusCode::from_u16(#status)
+                    .unwrap_or(::hextacy::web::http::StatusCode::INTERNAL_SERVER_ERROR)
+            ),
+            None => quote!(::hextacy::web::http::StatusCode::INTERNAL_SERVER_ERROR),
+        }
+    }
+
+    fn problem(&self) -> TokenStream {
+        let title = self
+            .title
+            .as_ref()
+            .map(|title| quote!(problem.title = Some(#title.to_string());));
+        let r#type = self
+            .r#type
+            .as_ref()
+            .map(|ty| quote!(problem.r#type = Some(#ty.to_string());));
+        let detail = self.detail.as_ref().map(|detail| match detail {
+            Detail::Display => quote!(problem.detail = Some(self.to_string());),
+            Detail::Literal(detail) => quote!(problem.detail = Some(#detail.to_string());),
+        });
+
+        quote!({
+            let mut problem = ::hextacy::web::xhttp::response::Problem::new(
+                ::hextacy::web::xhttp::response::IntoProblem::status(self),
+            );
+            #title
+            #r#type
+            #detail
+            problem
+        })
+    }
+}
+
+pub fn impl_into_problem(input: DeriveInput) -> syn::Result<TokenStream> {
+    let ident = &input.ident;
+    let (im, ty, wh) = input.generics.split_for_impl();
+
+    let (status_arms, problem_arms) = match input.data {
+        Data::Enum(ref data) => {
+            let mut status_arms = vec![];
+            let mut problem_arms = vec![];
+
+            for variant in data.variants.iter() {
+                let attr = ProblemAttr::parse(&variant.attrs)?;
+                let v_ident = &variant.ident;
+
+                if attr.transparent {
+                    let pattern = match variant.fields {
+                        Fields::Unnamed(ref fields) if fields.unnamed.len() == 1 => {
+                            quote!(Self::#v_ident(inner))
+                        }
+                        Fields::Named(ref fields) if fields.named.len() == 1 => {
+                            let field = fields.named[0].ident.as_ref();
+                            quote!(Self::#v_ident { #field: inner })
+                        }
+                        _ => {
+                            return Err(syn::Error::new(
+                                variant.span(),
+                                "`transparent` variants must have exactly one field",
+                            ))
+                        }
+                    };
+                    status_arms.push(quote!(
+                        #pattern => ::hextacy::web::xhttp::response::IntoProblem::status(inner)
+                    ));
+                    problem_arms.push(quote!(
+                        #pattern => ::hextacy::web::xhttp::response::IntoProblem::problem(inner)
+                    ));
+                    continue;
+                }
+
+                let status = attr.status();
+                let problem = attr.problem();
+                status_arms.push(quote!(Self::#v_ident { .. } => #status));
+                problem_arms.push(quote!(Self::#v_ident { .. } => #problem));
+            }
+
+            (status_arms, problem_arms)
+        }
+        Data::Struct(_) => {
+            let attr = ProblemAttr::parse(&input.attrs)?;
+            if attr.transparent {
+                return Err(syn::Error::new(
+                    ident.span(),
+                    "`transparent` is supported only on enum variants",
+                ));
+            }
+            let status = attr.status();
+            let problem = attr.problem();
+            (
+                vec![quote!(Self { .. } => #status)],
+                vec![quote!(Self { .. } => #problem)],
+            )
+        }
+        Data::Union(_) => {
+            return Err(syn::Error::new(
+                ident.span(),
+                "IntoProblem cannot be derived for unions",
+            ))
+        }
+    };
+
+    Ok(quote!(
+        impl #im ::hextacy::web::xhttp::response::IntoProblem for #ident #ty #wh {
+            fn status(&self) -> ::hextacy::web::http::StatusCode {
+                match self {
+                    #(#status_arms),*
+                }
+            }
+
+            fn problem(&self) -> ::hextacy::web::xhttp::response::Problem {
+                match self {
+                    #(#problem_arms),*
+                }
+            }
+        }
+    ))
+}
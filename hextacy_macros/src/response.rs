@@ -1,15 +1,302 @@
+use proc_macro2::TokenStream;
 use quote::quote;
-use syn::DeriveInput;
+use syn::{
+    punctuated::Punctuated, spanned::Spanned, Attribute, Data, DeriveInput, Expr, Fields, Ident,
+    Lit, LitStr, Token, Type,
+};
 
-pub fn impl_response(input: DeriveInput) -> Result<proc_macro2::TokenStream, syn::Error> {
+pub fn impl_response(input: DeriveInput) -> Result<TokenStream, syn::Error> {
     let ident = &input.ident;
     let (im, ty, wh) = input.generics.split_for_impl();
 
-    for attr in input.attrs.iter() {
-        attr.meta.path().is_ident(ident);
-    }
+    let code = parse_code(&input.attrs)?;
+    let headers = parse_headers(&input.attrs)?;
+
+    let (status_fn, headers_fn, cookies_fn) = match input.data {
+        Data::Struct(ref data) => {
+            let status_fn = code.map(|code| {
+                quote!(
+                    fn default_status(&self) -> ::hextacy::web::http::StatusCode {
+                        #code
+                    }
+                )
+            });
+
+            let headers_fn = (!headers.is_empty()).then(|| {
+                quote!(
+                    fn default_headers(
+                        &self,
+                    ) -> Vec<(
+                        ::hextacy::web::http::HeaderName,
+                        ::hextacy::web::http::HeaderValue,
+                    )> {
+                        vec![#(#headers),*]
+                    }
+                )
+            });
+
+            let cookies = parse_cookies(&data.fields)?;
+            let cookies_fn = (!cookies.is_empty()).then(|| {
+                quote!(
+                    fn cookies(&self) -> Vec<::hextacy::web::cookie::Cookie<'static>> {
+                        let mut cookies = vec![];
+                        #(#cookies)*
+                        cookies
+                    }
+                )
+            });
+
+            (status_fn, headers_fn, cookies_fn)
+        }
+        Data::Enum(ref data) => {
+            let default_code = code.unwrap_or_else(|| quote!(::hextacy::web::http::StatusCode::OK));
+
+            let mut status_arms = vec![];
+            let mut header_arms = vec![];
+
+            for variant in data.variants.iter() {
+                let v_ident = &variant.ident;
+
+                for field in variant.fields.iter() {
+                    if let Some(attr) = field.attrs.iter().find(|a| a.path().is_ident("cookie")) {
+                        return Err(syn::Error::new(
+                            attr.span(),
+                            "`#[cookie]` is supported only on struct fields",
+                        ));
+                    }
+                }
+
+                let code = parse_code(&variant.attrs)?.unwrap_or_else(|| default_code.clone());
+                status_arms.push(quote!(Self::#v_ident { .. } => #code));
+
+                let variant_headers = parse_headers(&variant.attrs)?;
+                header_arms.push(
+                    quote!(Self::#v_ident { .. } => vec![#(#headers,)* #(#variant_headers),*]),
+                );
+            }
+
+            let status_fn = quote!(
+                fn default_status(&self) -> ::hextacy::web::http::StatusCode {
+                    match self {
+                        #(#status_arms),*
+                    }
+                }
+            );
+
+            let headers_fn = quote!(
+                fn default_headers(&self) -> Vec<(::hextacy::web::http::HeaderName, ::hextacy::web::http::HeaderValue)> {
+                    match self {
+                        #(#header_arms),*
+                    }
+                }
+            );
+
+            (Some(status_fn), Some(headers_fn), None)
+        }
+        Data::Union(_) => {
+            return Err(syn::Error::new(
+                ident.span(),
+                "RestResponse cannot be derived for unions",
+            ))
+        }
+    };
+
+    let axum = cfg!(feature = "axum").then(|| {
+        quote!(
+            impl #im ::hextacy::web::axum::response::IntoResponse for #ident #ty #wh {
+                fn into_response(self) -> ::hextacy::web::axum::response::Response {
+                    let Ok(response) = ::hextacy::web::xhttp::response::RestResponse::into_json_response(self) else {
+                        return ::hextacy::web::axum::response::IntoResponse::into_response(::hextacy::web::axum::http::StatusCode::INTERNAL_SERVER_ERROR);
+                    };
+
+                    // Converted by hand so the version of `http` used by axum does not matter
+                    let (parts, body) = response.into_parts();
+                    let mut res = ::hextacy::web::axum::response::IntoResponse::into_response(body);
+                    *res.status_mut() = ::hextacy::web::axum::http::StatusCode::from_u16(parts.status.as_u16())
+                        .unwrap_or(::hextacy::web::axum::http::StatusCode::INTERNAL_SERVER_ERROR);
+
+                    let headers = res.headers_mut();
+                    headers.clear();
+                    for (name, value) in parts.headers.iter() {
+                        if let (Ok(name), Ok(value)) = (
+                            ::hextacy::web::axum::http::HeaderName::from_bytes(name.as_str().as_bytes()),
+                            ::hextacy::web::axum::http::HeaderValue::from_bytes(value.as_bytes()),
+                        ) {
+                            headers.append(name, value);
+                        }
+                    }
+
+                    res
+                }
+            }
+        )
+    });
+
+    let actix = cfg!(feature = "actix").then(|| {
+        quote!(
+            impl #im ::hextacy::web::actix_web::Responder for #ident #ty #wh {
+                type Body = ::hextacy::web::actix_web::body::BoxBody;
+
+                fn respond_to(self, _: &::hextacy::web::actix_web::HttpRequest) -> ::hextacy::web::actix_web::HttpResponse<Self::Body> {
+                    let Ok(response) = ::hextacy::web::xhttp::response::RestResponse::into_json_response(self) else {
+                        return ::hextacy::web::actix_web::HttpResponse::InternalServerError().finish();
+                    };
+
+                    // Converted by hand so the version of `http` used by actix does not matter
+                    let (parts, body) = response.into_parts();
+                    let status = ::hextacy::web::actix_web::http::StatusCode::from_u16(parts.status.as_u16())
+                        .unwrap_or(::hextacy::web::actix_web::http::StatusCode::INTERNAL_SERVER_ERROR);
+                    let mut res = ::hextacy::web::actix_web::HttpResponse::with_body(status, body).map_into_boxed_body();
+
+                    let headers = res.headers_mut();
+                    for (name, value) in parts.headers.iter() {
+                        if let (Ok(name), Ok(value)) = (
+                            ::hextacy::web::actix_web::http::header::HeaderName::from_bytes(name.as_str().as_bytes()),
+                            ::hextacy::web::actix_web::http::header::HeaderValue::from_bytes(value.as_bytes()),
+                        ) {
+                            headers.append(name, value);
+                        }
+                    }
+
+                    res
+                }
+            }
+        )
+    });
 
     Ok(quote!(
-        impl #im hextacy::web::xhttp::response::RestResponse<'_> for #ident #ty #wh {}
+        impl #im ::hextacy::web::xhttp::response::RestResponse<'_> for #ident #ty #wh {
+            #status_fn
+            #headers_fn
+            #cookies_fn
+        }
+
+        #axum
+        #actix
     ))
 }
+
+/// Parses `#[code(201)]` or `#[code(CREATED)]`.
+fn parse_code(attrs: &[Attribute]) -> syn::Result<Option<TokenStream>> {
+    let Some(attr) = attrs.iter().find(|a| a.path().is_ident("code")) else {
+        return Ok(None);
+    };
+
+    let expr: Expr = attr.parse_args()?;
+
+    match expr {
+        Expr::Lit(ref lit) => {
+            let Lit::Int(ref code) = lit.lit else {
+                return Err(syn::Error::new(expr.span(), "expected a status code"));
+            };
+            let value = code.base10_parse::<u16>()?;
+            if !(100..=999).contains(&value) {
+                return Err(syn::Error::new(code.span(), "invalid status code"));
+            }
+            Ok(Some(quote!(
+                ::hextacy::web::http::StatusCode::from_u16(#code).expect("valid status code")
+            )))
+        }
+        Expr::Path(ref path) if path.path.get_ident().is_some() => {
+            let ident = path.path.get_ident();
+            Ok(Some(quote!(::hextacy::web::http::StatusCode::#ident)))
+        }
+        _ => Err(syn::Error::new(
+            expr.span(),
+            "expected a status code, e.g. `201` or `CREATED`",
+        )),
+    }
+}
+
+/// Parses all `#[header("name", "value")]` attributes.
+fn parse_headers(attrs: &[Attribute]) -> syn::Result<Vec<TokenStream>> {
+    let mut headers = vec![];
+
+    for attr in attrs.iter().filter(|a| a.path().is_ident("header")) {
+        let args = attr.parse_args_with(Punctuated::<LitStr, Token![,]>::parse_terminated)?;
+
+        let [name, value] = args.iter().collect::<Vec<_>>()[..] else {
+            return Err(syn::Error::new(
+                attr.span(),
+                "expected `#[header(\"name\", \"value\")]`",
+            ));
+        };
+
+        let name_str = name.value().to_lowercase();
+        if name_str.is_empty()
+            || !name_str
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c))
+        {
+            return Err(syn::Error::new(name.span(), "invalid header name"));
+        }
+
+        if !value
+            .value()
+            .chars()
+            .all(|c| c == '\t' || (' '..='~').contains(&c))
+        {
+            return Err(syn::Error::new(value.span(), "invalid header value"));
+        }
+
+        headers.push(quote!((
+            ::hextacy::web::http::HeaderName::from_static(#name_str),
+            ::hextacy::web::http::HeaderValue::from_static(#value)
+        )));
+    }
+
+    Ok(headers)
+}
+
+/// Creates the statements pushing the `#[cookie]` fields to `cookies`. The fields must be either
+/// a `Cookie` or an `Option<Cookie>` and must be skipped by serde.
+fn parse_cookies(fields: &Fields) -> syn::Result<Vec<TokenStream>> {
+    let mut cookies = vec![];
+
+    for (i, field) in fields.iter().enumerate() {
+        let Some(attr) = field.attrs.iter().find(|a| a.path().is_ident("cookie")) else {
+            continue;
+        };
+
+        let skipped = field.attrs.iter().any(|a| {
+            a.path().is_ident("serde")
+                && a.parse_args_with(Punctuated::<Ident, Token![,]>::parse_terminated)
+                    .is_ok_and(|args| {
+                        args.iter()
+                            .any(|arg| arg == "skip" || arg == "skip_serializing")
+                    })
+        });
+
+        if !skipped {
+            return Err(syn::Error::new(
+                attr.span(),
+                "`#[cookie]` fields must be annotated with `#[serde(skip)]`",
+            ));
+        }
+
+        let access = match field.ident {
+            Some(ref ident) => quote!(self.#ident),
+            None => {
+                let index = syn::Index::from(i);
+                quote!(self.#index)
+            }
+        };
+
+        let is_option = matches!(
+            field.ty,
+            Type::Path(ref path) if path.path.segments.last().is_some_and(|s| s.ident == "Option")
+        );
+
+        if is_option {
+            cookies.push(quote!(
+                if let Some(ref cookie) = #access {
+                    cookies.push(cookie.clone().into_owned());
+                }
+            ));
+        } else {
+            cookies.push(quote!(cookies.push(#access.clone().into_owned());));
+        }
+    }
+
+    Ok(cookies)
+}